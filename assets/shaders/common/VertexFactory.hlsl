@@ -6,4 +6,50 @@ struct VertexIn
 #if VERTEX_FACTORY_USE_COLOR
     float4 color: Color;
 #endif
-};
\ No newline at end of file
+#if VERTEX_FACTORY_USE_NORMAL
+#if VERTEX_FACTORY_NORMAL_OCTAHEDRAL
+    // OctahedralSnorm16x2, use GetVertexNormal.
+    float2 normal: Normal;
+#else
+    float3 normal: Normal;
+#endif
+#endif
+#if VERTEX_FACTORY_USE_TANGENT
+    float4 tangent: Tangent;
+#endif
+#if VERTEX_FACTORY_USE_TEXCOORD
+    float2 uv0: TexCoord0;
+#endif
+#if VERTEX_FACTORY_USE_TEXCOORD1
+    float2 uv1: TexCoord1;
+#endif
+#if VERTEX_FACTORY_USE_BLENDINDICES
+    float4 bone_indices: BlendIndices;
+#endif
+#if VERTEX_FACTORY_USE_BLENDWEIGHT
+    float4 bone_weights: BlendWeight;
+#endif
+};
+
+// Inverse of encode_octahedral in vertex_format.rs, the lower hemisphere is unfolded over the diagonals.
+float3 DecodeOctahedral(float2 encoded)
+{
+    float3 normal = float3(encoded, 1.0 - abs(encoded.x) - abs(encoded.y));
+    if (normal.z < 0.0)
+    {
+        float2 signs = float2(encoded.x >= 0.0 ? 1.0 : -1.0, encoded.y >= 0.0 ? 1.0 : -1.0);
+        normal.xy = (1.0 - abs(encoded.yx)) * signs;
+    }
+    return normalize(normal);
+}
+
+#if VERTEX_FACTORY_USE_NORMAL
+float3 GetVertexNormal(VertexIn input)
+{
+#if VERTEX_FACTORY_NORMAL_OCTAHEDRAL
+    return DecodeOctahedral(input.normal);
+#else
+    return input.normal;
+#endif
+}
+#endif
\ No newline at end of file
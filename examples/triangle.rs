@@ -6,6 +6,8 @@ use log::{debug, error, trace, warn};
 use memoffset::offset_of;
 
 use RustDX::static_mesh::StaticMesh;
+use RustDX::scene_proxy::SceneProxy;
+use RustDX::mesh_draw_command::MeshDrawCommand;
 use RustDX::vertex_factory::get_vertex_input_layout;
 use RustDX::*;
 use crate::d3d12_common::*;
@@ -148,7 +150,7 @@ impl HelloTriangleSample<> {
         triangle.add_channel_data(mesh::MeshDataChannel::Color, vec![0., 1., 0., 1.]);
         triangle.add_channel_data(mesh::MeshDataChannel::Color, vec![1., 0., 1., 1.]);
         triangle.set_index_buffer(vec![0, 1, 2]);
-        triangle.generate_gpu_resource(&device);
+        triangle.generate_gpu_resource(&device).expect("Cannot upload triangle mesh");
     
         let meshes = vec![triangle];
         let mut renderer = HelloTriangleSample {
@@ -227,7 +229,9 @@ float4 PS(VertexOut input) : SV_Target
 
         renderer.root_signature = Some(root_signature);
 
-        let vertex_desc = get_vertex_input_layout("CommonVertexFactory_");
+        let (vertex_desc, layout_macros) = get_vertex_input_layout("CommonVertexFactory_", &renderer.static_meshes[0].get_vertex_layout());
+        // The vertex shader above reads float inputs, it has no permutations for packed formats.
+        assert!(layout_macros.is_empty(), "the triangle mesh needs a vertex shader compiled with {:?}", layout_macros);
         let mut input_layout = InputLayoutDesc::default();
         input_layout.0.pInputElementDescs = vertex_desc.as_ptr() as *const D3D12_INPUT_ELEMENT_DESC;
         input_layout.0.NumElements = vertex_desc.len() as u32;
@@ -316,15 +320,18 @@ float4 PS(VertexOut input) : SV_Target
         self.command_list
             .set_render_targets(&mut [rtv_handle], false, None);
 
-        let (_,_,vertex_view, index_view) = self.static_meshes[0].get_gpu_resource();
+        let (_,_,vertex_views, index_view) = self.static_meshes[0].get_gpu_resource();
         self.command_list
-            .set_vertex_buffers(0, &[vertex_view.clone()]);
+            .set_vertex_buffers(0, vertex_views);
 
         self.command_list
             .set_index_buffer(&index_view.clone());
         self.command_list
             .set_primitive_topology(PrimitiveTopology::TriangleList);
-        self.command_list.draw_indexed_instanced(3, 1, 0, 0, 0);
+        for mesh_batch in self.static_meshes[0].generate_mesh_batches()
+        {
+            MeshDrawCommand::new(&mesh_batch).draw(&self.command_list, 1);
+        }
 
         HelloTriangleSample::add_transition(
             &self.command_list,
@@ -32,6 +32,7 @@ use lazy_static::lazy_static;
 use std::sync::Mutex;
 
 use crate::d3d12_pso::InputElementDesc;
+use crate::vertex_factory::{get_layout_macro_permutations, get_vertex_factory_permutation_name};
 
 pub fn compile_shader(
     name : &str,
@@ -326,19 +327,25 @@ impl ShaderManager
             let data = fs::read_to_string(path_name).expect("Can't Open File.");
             let ps_entry_point = "PSMain";
             let vs_entry_point: &str = "VSMain";
-            let mut macros = vec![];
+            let macros = vec![];
             if entry.file_name().to_str().unwrap().ends_with("VS.hlsl")
             {
+                // One permutation per combination of layout macros, so that every vertex layout has a matching shader.
                 for vf_entry in &self.vertex_factory_infos
                 {
-                    for open_define in &vf_entry.macros
+                    for layout_macros in get_layout_macro_permutations(&vf_entry.macros)
                     {
-                        macros.push("-D");
-                        macros.push(open_define);
+                        let mut macros = vec![];
+                        for open_define in vf_entry.macros.iter().chain(&layout_macros)
+                        {
+                            macros.push("-D");
+                            macros.push(*open_define);
+                        }
+                        let compiled_code = compile_shader(path_name, &data, vs_entry_point, "vs_6_0", false, &macros);
+                        let shader_reflection = get_shader_reflection(path_name, &data, vs_entry_point, "vs_6_0", &macros);
+                        let permutation_name = get_vertex_factory_permutation_name(vf_entry.name, &layout_macros);
+                        self.cache_compiled_result_to_file(entry, compiled_code, shader_reflection, &permutation_name);
                     }
-                    let compiled_code = compile_shader(path_name, &data, vs_entry_point, "vs_6_0", false, &macros);
-                    let shader_reflection = get_shader_reflection(path_name, &data, vs_entry_point, "vs_6_0", &macros);
-                    self.cache_compiled_result_to_file(entry, compiled_code, shader_reflection, vf_entry.name);    
                 }
             }
             else if entry.file_name().to_str().unwrap().ends_with("PS.hlsl")
@@ -5,14 +5,41 @@
 use std::ffi::CString;
 
 use crate::d3d12_enum::Format;
-use crate::mesh::get_vertex_attribute_offset;
 use crate::mesh::MeshChannelData;
-use crate::mesh::MeshDataChannel;
+use crate::mesh::get_mesh_channel_desc;
+use crate::mesh::vertex_format::*;
 use crate::shader::*;
 use crate::d3d12_pso::*;
 use crate::D3D12_INPUT_ELEMENT_DESC;
 use crate::D3D12_INPUT_LAYOUT_DESC;
 
+// Switches the normal input of VertexFactory.hlsl to the two components of OctahedralSnorm16x2, GetVertexNormal
+// decodes them.
+pub const VERTEX_FACTORY_NORMAL_OCTAHEDRAL: &str = "VERTEX_FACTORY_NORMAL_OCTAHEDRAL";
+
+// Macros that change the inputs of a vertex factory to match the layout of a mesh, each with the channel macro it
+// needs. ShaderManager compiles every vertex shader once per combination that applies to a vertex factory.
+const VERTEX_FACTORY_LAYOUT_MACROS: [(&str, &str); 1] = [(VERTEX_FACTORY_NORMAL_OCTAHEDRAL, "VERTEX_FACTORY_USE_NORMAL")];
+
+// Every combination of the layout macros that apply to a vertex factory with the given macros, each in the order of
+// VERTEX_FACTORY_LAYOUT_MACROS like the ones of get_vertex_input_layout.
+pub fn get_layout_macro_permutations(vf_macros: &[&str]) -> Vec<Vec<&'static str>>
+{
+    let layout_macros: Vec<&'static str> = VERTEX_FACTORY_LAYOUT_MACROS.iter()
+        .filter(|(_, channel_macro)| vf_macros.contains(channel_macro))
+        .map(|(layout_macro, _)| *layout_macro)
+        .collect();
+    (0..1usize << layout_macros.len())
+        .map(|mask| layout_macros.iter().enumerate().filter(|(bit, _)| mask & (1 << bit) != 0).map(|(_, layout_macro)| *layout_macro).collect())
+        .collect()
+}
+
+// File prefix of the vertex shaders a vertex factory compiled with the layout macros of get_vertex_input_layout.
+pub fn get_vertex_factory_permutation_name(vf_name: &str, layout_macros: &[&str]) -> String
+{
+    layout_macros.iter().fold(vf_name.to_string(), |name, layout_macro| name + *layout_macro + "_")
+}
+
 macro_rules! impl_vertex_factory {
     ($vf_name:ident) => {{
         $vf_name::add_to_shader_manager()
@@ -53,35 +80,70 @@ impl VertexFactory for CommonVertexFactory
     add_vertex_factory!("CommonVertexFactory", "VERTEX_FACTORY_USE_POSITION", "VERTEX_FACTORY_USE_COLOR");
 }
 
-pub fn get_vertex_input_layout<'a>(vf_name: &str) -> Vec<InputElementDesc<'a>>
+pub fn get_vertex_element_format(element: &VertexElement) -> Format
+{
+    match (element.format, element.packed_component_count)
+    {
+        (VertexFormat::Float32, 1) => Format::R32Float,
+        (VertexFormat::Float32, 2) => Format::R32G32Float,
+        (VertexFormat::Float32, 3) => Format::R32G32B32Float,
+        (VertexFormat::Float32, _) => Format::R32G32B32A32Float,
+        (VertexFormat::Float16, 1) => Format::R16Float,
+        (VertexFormat::Float16, 2) => Format::R16G16Float,
+        (VertexFormat::Float16, _) => Format::R16G16B16A16Float,
+        (VertexFormat::Unorm16, 1) => Format::R16Unorm,
+        (VertexFormat::Unorm16, 2) => Format::R16G16Unorm,
+        (VertexFormat::Unorm16, _) => Format::R16G16B16A16Unorm,
+        (VertexFormat::Snorm16, 1) => Format::R16Snorm,
+        (VertexFormat::Snorm16, 2) => Format::R16G16Snorm,
+        (VertexFormat::Snorm16, _) => Format::R16G16B16A16Snorm,
+        (VertexFormat::Unorm8x4, _) => Format::R8G8B8A8Unorm,
+        (VertexFormat::Snorm8x4, _) => Format::R8G8B8A8Snorm,
+        (VertexFormat::OctahedralSnorm16x2, _) => Format::R16G16Snorm
+    }
+}
+
+// Formats and offsets come from the vertex layout of the mesh that is drawn with the vertex factory, a channel is
+// read when the vertex factory lists its macro, see MeshChannelDesc::get_vertex_factory_macro. Also returns the
+// layout macros the vertex shader was compiled with on top of the ones of the vertex factory, so that its inputs
+// match the formats of the layout. get_vertex_factory_permutation_name turns them into the shader to draw with.
+pub fn get_vertex_input_layout<'a>(vf_name: &str, vertex_layout: &VertexLayout) -> (Vec<InputElementDesc<'a>>, Vec<&'static str>)
 {
     let mut element_vec = vec![]; 
+    let mut used_layout_macros = vec![];
     let shader_manager = G_SHADER_MANAGER.lock().unwrap();
     let macros_res = shader_manager.get_vf_macros(vf_name);
     if macros_res.is_none()
     {
-        return element_vec;
+        return (element_vec, used_layout_macros);
     }
     let macros = macros_res.unwrap();
-    if macros.contains(&"VERTEX_FACTORY_USE_POSITION")
-    {
-        let mut position_element_desc = InputElementDesc::default();
-        position_element_desc.0.SemanticName = CString::new("Position").unwrap().into_raw() as *const i8;
-        position_element_desc.0.Format = Format::R32G32B32Float as i32;
-        position_element_desc.0.InputSlot = 0;
-        position_element_desc.0.AlignedByteOffset = get_vertex_attribute_offset(MeshDataChannel::Position as u32);
-        element_vec.push(position_element_desc);
-    }
-    if macros.contains(&"VERTEX_FACTORY_USE_COLOR")
+    for element in &vertex_layout.elements
     {
-        let mut color_element_desc = InputElementDesc::default();
-        color_element_desc.0.SemanticName = CString::new("Color").unwrap().into_raw() as *const i8;
-        color_element_desc.0.Format = Format::R32G32B32A32Float as i32;
-        color_element_desc.0.InputSlot = 0;
-        color_element_desc.0.AlignedByteOffset = get_vertex_attribute_offset(MeshDataChannel::Color as u32);
-        element_vec.push(color_element_desc);
+        let channel_desc = get_mesh_channel_desc(element.channel).unwrap();
+        let channel_macro = channel_desc.get_vertex_factory_macro();
+        if !macros.iter().any(|name| *name == channel_macro)
+        {
+            continue;
+        }
+        // Mesh validation only allows octahedral encoding for normals.
+        if element.format == VertexFormat::OctahedralSnorm16x2
+        {
+            used_layout_macros.push(VERTEX_FACTORY_NORMAL_OCTAHEDRAL);
+        }
+        let mut element_desc = InputElementDesc::default();
+        element_desc.0.SemanticName = CString::new(channel_desc.semantic_name).unwrap().into_raw() as *const i8;
+        element_desc.0.SemanticIndex = channel_desc.semantic_index;
+        element_desc.0.Format = get_vertex_element_format(element) as i32;
+        element_desc.0.InputSlot = element.slot;
+        element_desc.0.AlignedByteOffset = element.offset;
+        element_vec.push(element_desc);
     }
-    element_vec        
+    let layout_macros = VERTEX_FACTORY_LAYOUT_MACROS.iter()
+        .map(|(layout_macro, _)| *layout_macro)
+        .filter(|layout_macro| used_layout_macros.contains(layout_macro))
+        .collect();
+    (element_vec, layout_macros)
 }
 pub struct VertexFactoryInitializer;
 impl VertexFactoryInitializer
@@ -1,8 +1,37 @@
+use cgmath::Matrix4;
+
 use crate::scene::mesh::*;
 use crate::scene::scene_proxy::*;
+use crate::d3d12_wrapper::d3d12_command::*;
 
 pub struct MeshDrawCommand<'a>
 {
     pub mesh: &'a Mesh,
-    pub mesh_index_in_gpu_scene: u32
+    pub first_index: u32,
+    pub index_count: u32,
+    pub base_vertex: i32,
+    pub mesh_index_in_gpu_scene: u32,
+    pub world_transform: Matrix4<f32>
+}
+
+impl<'a> MeshDrawCommand<'a>
+{
+    pub fn new(mesh_batch: &MeshBatch<'a>) -> Self
+    {
+        MeshDrawCommand
+        {
+            mesh: mesh_batch.mesh,
+            first_index: mesh_batch.section.first_index,
+            index_count: mesh_batch.section.index_count,
+            base_vertex: mesh_batch.section.base_vertex,
+            mesh_index_in_gpu_scene: mesh_batch.mesh_index_in_gpu_scene,
+            world_transform: mesh_batch.world_transform
+        }
+    }
+
+    // Vertex and index buffers of the mesh have to be bound already.
+    pub fn draw(&self, command_list: &CommandList, instance_count: u32)
+    {
+        command_list.draw_indexed_instanced(self.index_count, instance_count, self.first_index, self.base_vertex, 0);
+    }
 }
\ No newline at end of file
@@ -1,9 +1,11 @@
 use crate::scene_proxy::MeshBatch;
 use crate::scene::scene::{Scene};
+use crate::scene::culling::{ViewVisibility};
 use crate::mesh_draw_command::{MeshDrawCommand};
 
 pub trait RenderPass
 {
-    fn generate_mesh_batch_from_scene(scene: &Scene) -> Vec<MeshBatch<'_>>;
+    // Only the proxies visible in the view become batches.
+    fn generate_mesh_batch_from_scene<'a>(scene: &'a Scene, visibility: &ViewVisibility) -> Vec<MeshBatch<'a>>;
     fn cache_mesh_draw_commands(mesh_batches: Vec<MeshBatch<'_>>) -> Vec<MeshDrawCommand<'_>>;
 }
\ No newline at end of file
@@ -0,0 +1,327 @@
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, One, Quaternion, Rad, SquareMatrix, Vector2, Vector3, Vector4};
+
+use crate::scene::mesh::bounds::Ray;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection
+{
+    Perspective { vertical_fov: Rad<f32>, near: f32, far: f32 },
+    // Far plane at infinity, avoids clipping distant geometry and works best with reverse Z.
+    InfinitePerspective { vertical_fov: Rad<f32>, near: f32 },
+    // Height of the view volume, the width follows from the aspect ratio.
+    Orthographic { height: f32, near: f32, far: f32 }
+}
+
+/// Constants of one view as a render pass uploads them, matrices are column major for mul(matrix, vector).
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ViewConstants
+{
+    pub view: [[f32; 4]; 4],
+    pub projection: [[f32; 4]; 4],
+    pub view_projection: [[f32; 4]; 4],
+    pub inverse_view_projection: [[f32; 4]; 4],
+    // xyz, w is 1.
+    pub camera_position: [f32; 4]
+}
+
+/// Left handed camera with D3D clip space: x right, y up and z forward in view space, depth 0 at the near plane and
+/// 1 at the far plane, the other way around with reverse Z.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera
+{
+    pub position: Vector3<f32>,
+    // View space to world space.
+    pub rotation: Quaternion<f32>,
+    pub projection: Projection,
+    // Width over height.
+    pub aspect_ratio: f32,
+    pub is_reverse_z: bool
+}
+
+impl Camera
+{
+    pub fn new(projection: Projection, aspect_ratio: f32) -> Self
+    {
+        Camera { position: Vector3::new(0.0, 0.0, 0.0), rotation: Quaternion::one(), projection, aspect_ratio, is_reverse_z: false }
+    }
+
+    /// Places the camera at eye looking at target. Up must not be parallel to the view direction.
+    pub fn look_at(&mut self, eye: Vector3<f32>, target: Vector3<f32>, up: Vector3<f32>)
+    {
+        let forward = (target - eye).normalize();
+        let right = up.cross(forward).normalize();
+        let up = forward.cross(right);
+        self.position = eye;
+        self.rotation = Quaternion::from(Matrix3::from_cols(right, up, forward)).normalize();
+    }
+
+    pub fn get_forward(&self) -> Vector3<f32>
+    {
+        self.rotation * Vector3::unit_z()
+    }
+
+    pub fn get_right(&self) -> Vector3<f32>
+    {
+        self.rotation * Vector3::unit_x()
+    }
+
+    pub fn get_up(&self) -> Vector3<f32>
+    {
+        self.rotation * Vector3::unit_y()
+    }
+
+    pub fn get_view_matrix(&self) -> Matrix4<f32>
+    {
+        Matrix4::from(self.rotation.conjugate()) * Matrix4::from_translation(-self.position)
+    }
+
+    pub fn get_projection_matrix(&self) -> Matrix4<f32>
+    {
+        // Depth is z_scale + z_offset / z for perspective and z_scale * z + z_offset for orthographic projections.
+        let (x_scale, y_scale, z_scale, z_offset, is_perspective) = match self.projection
+        {
+            Projection::Perspective { vertical_fov, near, far } =>
+            {
+                let y_scale = 1.0 / (vertical_fov.0 * 0.5).tan();
+                let (z_scale, z_offset) = if self.is_reverse_z
+                {
+                    (near / (near - far), near * far / (far - near))
+                }
+                else
+                {
+                    (far / (far - near), -near * far / (far - near))
+                };
+                (y_scale / self.aspect_ratio, y_scale, z_scale, z_offset, true)
+            }
+            Projection::InfinitePerspective { vertical_fov, near } =>
+            {
+                let y_scale = 1.0 / (vertical_fov.0 * 0.5).tan();
+                let (z_scale, z_offset) = if self.is_reverse_z { (0.0, near) } else { (1.0, -near) };
+                (y_scale / self.aspect_ratio, y_scale, z_scale, z_offset, true)
+            }
+            Projection::Orthographic { height, near, far } =>
+            {
+                let (z_scale, z_offset) = if self.is_reverse_z
+                {
+                    (-1.0 / (far - near), far / (far - near))
+                }
+                else
+                {
+                    (1.0 / (far - near), -near / (far - near))
+                };
+                (2.0 / (height * self.aspect_ratio), 2.0 / height, z_scale, z_offset, false)
+            }
+        };
+        let w = if is_perspective { 1.0 } else { 0.0 };
+        Matrix4::from_cols(
+            Vector4::new(x_scale, 0.0, 0.0, 0.0),
+            Vector4::new(0.0, y_scale, 0.0, 0.0),
+            Vector4::new(0.0, 0.0, z_scale, w),
+            Vector4::new(0.0, 0.0, z_offset, 1.0 - w))
+    }
+
+    pub fn get_view_projection_matrix(&self) -> Matrix4<f32>
+    {
+        self.get_projection_matrix() * self.get_view_matrix()
+    }
+
+    pub fn get_view_constants(&self) -> ViewConstants
+    {
+        let view_projection = self.get_view_projection_matrix();
+        ViewConstants
+        {
+            view: self.get_view_matrix().into(),
+            projection: self.get_projection_matrix().into(),
+            view_projection: view_projection.into(),
+            inverse_view_projection: view_projection.invert().unwrap_or(Matrix4::identity()).into(),
+            camera_position: self.position.extend(1.0).into()
+        }
+    }
+
+    /// World space planes left, right, bottom, top, near and far as (normal, distance) with normals pointing inside,
+    /// as MeshletBounds::is_sphere_culled expects them. The far plane of an infinite projection is (0, 0, 0, d) with
+    /// a positive d and never culls anything.
+    pub fn get_frustum_planes(&self) -> [Vector4<f32>; 6]
+    {
+        let matrix = self.get_view_projection_matrix();
+        let rows = [matrix.row(0), matrix.row(1), matrix.row(2), matrix.row(3)];
+        // Inside is 0 <= z <= w, reverse Z only swaps which of the two is the near plane.
+        let (near, far) = if self.is_reverse_z { (rows[3] - rows[2], rows[2]) } else { (rows[2], rows[3] - rows[2]) };
+        let mut planes = [rows[3] + rows[0], rows[3] - rows[0], rows[3] + rows[1], rows[3] - rows[1], near, far];
+        for plane in planes.iter_mut()
+        {
+            let length = plane.truncate().magnitude();
+            if length > 0.0
+            {
+                *plane /= length;
+            }
+        }
+        planes
+    }
+
+    /// Pixel position with the origin at the top left corner, and the depth of a world space point. None for points
+    /// behind the camera.
+    pub fn world_to_screen(&self, point: Vector3<f32>, viewport_size: Vector2<f32>) -> Option<Vector3<f32>>
+    {
+        let clip = self.get_view_projection_matrix() * point.extend(1.0);
+        if clip.w <= 0.0
+        {
+            return None;
+        }
+        let ndc = clip.truncate() / clip.w;
+        Some(Vector3::new((ndc.x + 1.0) * 0.5 * viewport_size.x, (1.0 - ndc.y) * 0.5 * viewport_size.y, ndc.z))
+    }
+
+    /// Ray from the near plane through a pixel position, for picking.
+    pub fn screen_to_world_ray(&self, pixel: Vector2<f32>, viewport_size: Vector2<f32>) -> Ray
+    {
+        let inverse_view_projection = self.get_view_projection_matrix().invert().unwrap_or(Matrix4::identity());
+        let ndc_x = pixel.x / viewport_size.x * 2.0 - 1.0;
+        let ndc_y = 1.0 - pixel.y / viewport_size.y * 2.0;
+        let unproject = |depth: f32| {
+            let point = inverse_view_projection * Vector4::new(ndc_x, ndc_y, depth, 1.0);
+            point.truncate() / point.w
+        };
+        // Depth 0.5 stays finite with an infinite far plane.
+        let origin = unproject(if self.is_reverse_z { 1.0 } else { 0.0 });
+        Ray::new(origin, unproject(0.5) - origin)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const VIEWPORT_SIZE: Vector2<f32> = Vector2::new(800.0, 600.0);
+
+    // Every projection with and without reverse Z, looking from (1, 2, 3) towards (4, 2, -1).
+    fn get_test_cameras() -> Vec<Camera>
+    {
+        let projections = [
+            Projection::Perspective { vertical_fov: Rad(1.0), near: 0.1, far: 100.0 },
+            Projection::InfinitePerspective { vertical_fov: Rad(1.0), near: 0.1 },
+            Projection::Orthographic { height: 10.0, near: 0.5, far: 50.0 }
+        ];
+        let mut cameras = vec![];
+        for projection in projections
+        {
+            for is_reverse_z in [false, true]
+            {
+                let mut camera = Camera { is_reverse_z, ..Camera::new(projection, 4.0 / 3.0) };
+                camera.look_at(Vector3::new(1.0, 2.0, 3.0), Vector3::new(4.0, 2.0, -1.0), Vector3::unit_y());
+                cameras.push(camera);
+            }
+        }
+        cameras
+    }
+
+    fn get_near_far(camera: &Camera) -> (f32, Option<f32>)
+    {
+        match camera.projection
+        {
+            Projection::Perspective { near, far, .. } | Projection::Orthographic { near, far, .. } => (near, Some(far)),
+            Projection::InfinitePerspective { near, .. } => (near, None)
+        }
+    }
+
+    fn unproject(camera: &Camera, ndc: Vector3<f32>) -> Vector3<f32>
+    {
+        let point = camera.get_view_projection_matrix().invert().unwrap() * ndc.extend(1.0);
+        point.truncate() / point.w
+    }
+
+    fn get_plane_distance(plane: Vector4<f32>, point: Vector3<f32>) -> f32
+    {
+        plane.truncate().dot(point) + plane.w
+    }
+
+    #[test]
+    fn near_and_far_map_to_the_depth_range()
+    {
+        for camera in get_test_cameras()
+        {
+            let (near, far) = get_near_far(&camera);
+            let get_depth = |distance: f32| camera.world_to_screen(camera.position + camera.get_forward() * distance, VIEWPORT_SIZE).unwrap().z;
+            // The infinite far plane is only approached.
+            let (near_depth, far_depth) = (get_depth(near), get_depth(far.unwrap_or(1.0e6)));
+            let (expected_near, expected_far) = if camera.is_reverse_z { (1.0, 0.0) } else { (0.0, 1.0) };
+            assert!((near_depth - expected_near).abs() < 1e-5, "{:?} maps near to {}", camera.projection, near_depth);
+            assert!((far_depth - expected_far).abs() < 1e-5, "{:?} maps far to {}", camera.projection, far_depth);
+            let middle_depth = get_depth(near * 2.0);
+            assert!(middle_depth > 0.0 && middle_depth < 1.0);
+            assert_eq!(middle_depth > near_depth, !camera.is_reverse_z);
+        }
+    }
+
+    #[test]
+    fn frustum_planes_point_inside()
+    {
+        for camera in get_test_cameras()
+        {
+            let planes = camera.get_frustum_planes();
+            for ndc in [Vector3::new(0.0, 0.0, 0.5), Vector3::new(0.9, -0.9, 0.2), Vector3::new(-0.9, 0.9, 0.8)]
+            {
+                let point = unproject(&camera, ndc);
+                assert!(planes.iter().all(|plane| get_plane_distance(*plane, point) > 0.0), "{:?} is outside of {:?}", point, camera.projection);
+            }
+
+            // Points beyond each side and in front of the near plane.
+            let before_near = if camera.is_reverse_z { 1.1 } else { -0.1 };
+            let outside = [
+                (0, Vector3::new(-1.2, 0.0, 0.5)),
+                (1, Vector3::new(1.2, 0.0, 0.5)),
+                (2, Vector3::new(0.0, -1.2, 0.5)),
+                (3, Vector3::new(0.0, 1.2, 0.5)),
+                (4, Vector3::new(0.0, 0.0, before_near))
+            ];
+            for (plane, ndc) in outside
+            {
+                assert!(get_plane_distance(planes[plane], unproject(&camera, ndc)) < 0.0, "plane {} of {:?}", plane, camera.projection);
+            }
+            match get_near_far(&camera)
+            {
+                (_, Some(far)) => assert!(get_plane_distance(planes[5], camera.position + camera.get_forward() * far * 1.1) < 0.0),
+                (_, None) => assert!(planes[5].truncate().magnitude() == 0.0 && planes[5].w > 0.0)
+            }
+        }
+    }
+
+    #[test]
+    fn screen_rays_project_back_onto_their_pixel()
+    {
+        for camera in get_test_cameras()
+        {
+            for pixel in [Vector2::new(400.0, 300.0), Vector2::new(10.0, 590.0), Vector2::new(700.0, 20.0)]
+            {
+                let ray = camera.screen_to_world_ray(pixel, VIEWPORT_SIZE);
+                assert!((ray.direction.magnitude() - 1.0).abs() < 1e-5);
+                assert!(ray.direction.dot(camera.get_forward()) > 0.0);
+                for distance in [0.0, 1.0, 20.0]
+                {
+                    let screen = camera.world_to_screen(ray.origin + ray.direction * distance, VIEWPORT_SIZE).unwrap();
+                    assert!((screen.truncate() - pixel).magnitude() < 1e-2, "{:?} projects to {:?} with {:?}", pixel, screen, camera.projection);
+                }
+                // The ray starts on the near plane.
+                let origin_depth = camera.world_to_screen(ray.origin, VIEWPORT_SIZE).unwrap().z;
+                assert!((origin_depth - if camera.is_reverse_z { 1.0 } else { 0.0 }).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn look_at_faces_the_target()
+    {
+        let mut camera = Camera::new(Projection::Perspective { vertical_fov: Rad(1.0), near: 0.1, far: 100.0 }, 1.0);
+        camera.look_at(Vector3::new(1.0, 2.0, 3.0), Vector3::new(4.0, 2.0, -1.0), Vector3::unit_y());
+        assert!((camera.get_forward() - Vector3::new(0.6, 0.0, -0.8)).magnitude() < 1e-6);
+        assert!((camera.get_up() - Vector3::unit_y()).magnitude() < 1e-6);
+        // Left handed, x right of the view direction.
+        assert!((camera.get_right() - Vector3::unit_y().cross(camera.get_forward())).magnitude() < 1e-6);
+        let target = camera.get_view_matrix() * Vector4::new(4.0, 2.0, -1.0, 1.0);
+        assert!((target - Vector4::new(0.0, 0.0, 5.0, 1.0)).magnitude() < 1e-5);
+        assert!(camera.world_to_screen(Vector3::new(-2.0, 2.0, 7.0), VIEWPORT_SIZE).is_none());
+    }
+}
+
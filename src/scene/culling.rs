@@ -0,0 +1,253 @@
+use cgmath::{Vector3, Vector4};
+
+use crate::scene::camera::Camera;
+use crate::scene::mesh::bounds::BoundingBox;
+use crate::scene::scene::*;
+use crate::scene::scene_proxy::MeshBatch;
+
+/// Planes of a view frustum as (normal, distance) with normals pointing inside, split into components so that the
+/// tests run one plane at a time over many boxes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ViewFrustum
+{
+    normal_x: [f32; 6],
+    normal_y: [f32; 6],
+    normal_z: [f32; 6],
+    distance: [f32; 6]
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CullingStatistics
+{
+    pub tested_count: u32,
+    pub visible_count: u32,
+    pub culled_count: u32
+}
+
+/// Scene proxies that passed culling for one view, shared by every pass that renders the view.
+#[derive(Clone, Debug, Default)]
+pub struct ViewVisibility
+{
+    pub visible_proxies: Vec<SceneProxyHandle>,
+    pub statistics: CullingStatistics
+}
+
+// World space boxes as centers and half extents, one array per component.
+#[derive(Default)]
+struct CullingBoxes
+{
+    center_x: Vec<f32>,
+    center_y: Vec<f32>,
+    center_z: Vec<f32>,
+    extent_x: Vec<f32>,
+    extent_y: Vec<f32>,
+    extent_z: Vec<f32>
+}
+
+impl CullingBoxes
+{
+    fn push(&mut self, center: Vector3<f32>, half_extents: Vector3<f32>)
+    {
+        self.center_x.push(center.x);
+        self.center_y.push(center.y);
+        self.center_z.push(center.z);
+        self.extent_x.push(half_extents.x);
+        self.extent_y.push(half_extents.y);
+        self.extent_z.push(half_extents.z);
+    }
+}
+
+impl ViewFrustum
+{
+    pub fn from_planes(planes: &[Vector4<f32>; 6]) -> Self
+    {
+        ViewFrustum
+        {
+            normal_x: planes.map(|plane| plane.x),
+            normal_y: planes.map(|plane| plane.y),
+            normal_z: planes.map(|plane| plane.z),
+            distance: planes.map(|plane| plane.w)
+        }
+    }
+
+    pub fn from_camera(camera: &Camera) -> Self
+    {
+        ViewFrustum::from_planes(&camera.get_frustum_planes())
+    }
+
+    // Signed distance of the box center to a plane, and the extent of the box along the plane normal.
+    fn get_box_plane_distance(&self, plane: usize, bounding_box: &BoundingBox) -> (f32, f32)
+    {
+        let center = bounding_box.get_center();
+        let half_extents = bounding_box.get_half_extents();
+        let distance = self.normal_x[plane] * center.x + self.normal_y[plane] * center.y + self.normal_z[plane] * center.z + self.distance[plane];
+        let radius = self.normal_x[plane].abs() * half_extents.x + self.normal_y[plane].abs() * half_extents.y + self.normal_z[plane].abs() * half_extents.z;
+        (distance, radius)
+    }
+
+    /// Conservative, a box outside the frustum but not completely outside any single plane counts as visible.
+    pub fn is_box_visible(&self, bounding_box: &BoundingBox) -> bool
+    {
+        if bounding_box.is_empty()
+        {
+            return false;
+        }
+        (0..6).all(|plane| {
+            let (distance, radius) = self.get_box_plane_distance(plane, bounding_box);
+            distance + radius >= 0.0
+        })
+    }
+
+    /// True when the box is completely inside, everything in it is visible without further tests.
+    pub fn contains_box(&self, bounding_box: &BoundingBox) -> bool
+    {
+        if bounding_box.is_empty()
+        {
+            return false;
+        }
+        (0..6).all(|plane| {
+            let (distance, radius) = self.get_box_plane_distance(plane, bounding_box);
+            distance - radius >= 0.0
+        })
+    }
+
+    // Clears the flag of every box completely outside a plane. The inner loop has no branches and vectorizes.
+    fn cull_boxes(&self, boxes: &CullingBoxes, is_visible: &mut [bool])
+    {
+        for plane in 0..6
+        {
+            let (normal_x, normal_y, normal_z, distance) = (self.normal_x[plane], self.normal_y[plane], self.normal_z[plane], self.distance[plane]);
+            let (abs_x, abs_y, abs_z) = (normal_x.abs(), normal_y.abs(), normal_z.abs());
+            let centers = boxes.center_x.iter().zip(&boxes.center_y).zip(&boxes.center_z);
+            let extents = boxes.extent_x.iter().zip(&boxes.extent_y).zip(&boxes.extent_z);
+            for ((visible, ((center_x, center_y), center_z)), ((extent_x, extent_y), extent_z)) in is_visible.iter_mut().zip(centers).zip(extents)
+            {
+                let center_distance = normal_x * center_x + normal_y * center_y + normal_z * center_z + distance;
+                let radius = abs_x * extent_x + abs_y * extent_y + abs_z * extent_z;
+                *visible &= center_distance + radius >= 0.0;
+            }
+        }
+    }
+}
+
+impl Scene
+{
+    /// Tests the world space box of every scene proxy against the frustum. Proxies with empty bounds are culled.
+    pub fn cull_scene_proxies(&self, frustum: &ViewFrustum) -> ViewVisibility
+    {
+        let mut proxies = vec![];
+        let mut boxes = CullingBoxes::default();
+        let mut empty_count = 0;
+        for (proxy, _) in self.get_scene_proxies()
+        {
+            let bounding_box = self.get_scene_proxy_world_bounds(proxy).unwrap().bounding_box;
+            if bounding_box.is_empty()
+            {
+                empty_count += 1;
+                continue;
+            }
+            proxies.push(proxy);
+            boxes.push(bounding_box.get_center(), bounding_box.get_half_extents());
+        }
+
+        let mut is_visible = vec![true; proxies.len()];
+        frustum.cull_boxes(&boxes, &mut is_visible);
+        let visible_proxies: Vec<SceneProxyHandle> = proxies.into_iter().zip(is_visible).filter(|(_, visible)| *visible).map(|(proxy, _)| proxy).collect();
+        let tested_count = (boxes.center_x.len() + empty_count) as u32;
+        let visible_count = visible_proxies.len() as u32;
+        ViewVisibility { visible_proxies, statistics: CullingStatistics { tested_count, visible_count, culled_count: tested_count - visible_count } }
+    }
+
+    pub fn cull_scene_proxies_for_camera(&self, camera: &Camera) -> ViewVisibility
+    {
+        self.cull_scene_proxies(&ViewFrustum::from_camera(camera))
+    }
+
+    /// Batches of the visible scene proxies, with the world transforms of their nodes.
+    pub fn generate_visible_mesh_batches(&self, visibility: &ViewVisibility) -> Vec<MeshBatch<'_>>
+    {
+        visibility.visible_proxies.iter().flat_map(|proxy| self.generate_scene_proxy_mesh_batches(*proxy)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::scene::mesh::Mesh;
+    use crate::scene::static_mesh::StaticMesh;
+    use crate::scene::transform::Transform;
+
+    // The box -1 <= x <= 1, -1 <= y <= 1, 0 <= z <= 10.
+    fn create_frustum() -> ViewFrustum
+    {
+        ViewFrustum::from_planes(&[
+            Vector4::new(1.0, 0.0, 0.0, 1.0),
+            Vector4::new(-1.0, 0.0, 0.0, 1.0),
+            Vector4::new(0.0, 1.0, 0.0, 1.0),
+            Vector4::new(0.0, -1.0, 0.0, 1.0),
+            Vector4::new(0.0, 0.0, 1.0, 0.0),
+            Vector4::new(0.0, 0.0, -1.0, 10.0)
+        ])
+    }
+
+    fn create_box(center: Vector3<f32>, half_extent: f32) -> BoundingBox
+    {
+        let half_extents = Vector3::new(half_extent, half_extent, half_extent);
+        BoundingBox { min: center - half_extents, max: center + half_extents }
+    }
+
+    fn add_unit_box(scene: &mut Scene, center: Vector3<f32>) -> SceneProxyHandle
+    {
+        let node = scene.create_node("box", None).unwrap();
+        scene.set_local_transform(node, Transform::from_translation(center)).unwrap();
+        let mut static_mesh = StaticMesh::new("box");
+        *static_mesh.get_mesh_mut() = Mesh::create_box(Vector3::new(1.0, 1.0, 1.0), 1);
+        scene.add_scene_proxy(node, Box::new(static_mesh)).unwrap()
+    }
+
+    #[test]
+    fn boxes_are_tested_against_every_plane()
+    {
+        let frustum = create_frustum();
+        let inside = create_box(Vector3::new(0.0, 0.0, 5.0), 0.5);
+        assert!(frustum.is_box_visible(&inside) && frustum.contains_box(&inside));
+        for straddling in [create_box(Vector3::new(1.0, 0.0, 5.0), 0.5), create_box(Vector3::new(0.0, 0.0, 10.0), 0.5), create_box(Vector3::new(0.0, 0.0, 5.0), 20.0)]
+        {
+            assert!(frustum.is_box_visible(&straddling) && !frustum.contains_box(&straddling));
+        }
+        for outside in [create_box(Vector3::new(2.0, 0.0, 5.0), 0.5), create_box(Vector3::new(0.0, -3.0, 5.0), 0.5), create_box(Vector3::new(0.0, 0.0, -1.0), 0.5), create_box(Vector3::new(0.0, 0.0, 11.0), 0.5)]
+        {
+            assert!(!frustum.is_box_visible(&outside) && !frustum.contains_box(&outside));
+        }
+        // Touching a plane counts as visible.
+        assert!(frustum.is_box_visible(&create_box(Vector3::new(1.5, 0.0, 5.0), 0.5)));
+        assert!(!frustum.is_box_visible(&BoundingBox::default()) && !frustum.contains_box(&BoundingBox::default()));
+    }
+
+    #[test]
+    fn scene_proxies_outside_or_without_bounds_are_culled()
+    {
+        let mut scene = Scene::new();
+        let inside = add_unit_box(&mut scene, Vector3::new(0.0, 0.0, 5.0));
+        let straddling = add_unit_box(&mut scene, Vector3::new(-1.0, 1.0, 0.0));
+        add_unit_box(&mut scene, Vector3::new(3.0, 0.0, 5.0));
+        add_unit_box(&mut scene, Vector3::new(0.0, 0.0, -5.0));
+        add_unit_box(&mut scene, Vector3::new(0.0, 0.0, 12.0));
+        let empty_node = scene.create_node("empty", None).unwrap();
+        scene.add_scene_proxy(empty_node, Box::new(StaticMesh::new("empty"))).unwrap();
+
+        let frustum = create_frustum();
+        let visibility = scene.cull_scene_proxies(&frustum);
+        assert_eq!(visibility.visible_proxies, vec![inside, straddling]);
+        assert_eq!(visibility.statistics, CullingStatistics { tested_count: 6, visible_count: 2, culled_count: 4 });
+        // The batched test agrees with testing one box at a time.
+        for (proxy, _) in scene.get_scene_proxies()
+        {
+            let bounding_box = scene.get_scene_proxy_world_bounds(proxy).unwrap().bounding_box;
+            assert_eq!(frustum.is_box_visible(&bounding_box), visibility.visible_proxies.contains(&proxy));
+        }
+        assert_eq!(scene.generate_visible_mesh_batches(&visibility).len(), 2);
+    }
+}
+
@@ -0,0 +1,1254 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use cgmath::{Matrix4, Quaternion, SquareMatrix, Vector3};
+use log::warn;
+use serde::Deserialize;
+
+use crate::scene::importer::ImportError;
+use crate::scene::mesh::*;
+use crate::scene::mesh::morph::*;
+use crate::scene::mesh::normals::*;
+use crate::scene::mesh::skinning::*;
+use crate::scene::mesh_material::*;
+use crate::scene::scene::*;
+use crate::scene::skeletal_mesh::*;
+use crate::scene::static_mesh::*;
+use crate::scene::transform::Transform;
+
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const GLB_CHUNK_JSON: u32 = 0x4E4F_534A;
+const GLB_CHUNK_BIN: u32 = 0x004E_4942;
+
+const COMPONENT_TYPE_BYTE: u32 = 5120;
+const COMPONENT_TYPE_UNSIGNED_BYTE: u32 = 5121;
+const COMPONENT_TYPE_SHORT: u32 = 5122;
+const COMPONENT_TYPE_UNSIGNED_SHORT: u32 = 5123;
+const COMPONENT_TYPE_UNSIGNED_INT: u32 = 5125;
+const COMPONENT_TYPE_FLOAT: u32 = 5126;
+
+const PRIMITIVE_MODE_TRIANGLES: u32 = 4;
+const PRIMITIVE_MODE_TRIANGLE_STRIP: u32 = 5;
+const PRIMITIVE_MODE_TRIANGLE_FAN: u32 = 6;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GltfRoot
+{
+    #[serde(default)]
+    accessors: Vec<GltfAccessor>,
+    #[serde(default)]
+    buffer_views: Vec<GltfBufferView>,
+    #[serde(default)]
+    buffers: Vec<GltfBuffer>,
+    #[serde(default)]
+    meshes: Vec<GltfMesh>,
+    #[serde(default)]
+    nodes: Vec<GltfNode>,
+    #[serde(default)]
+    scenes: Vec<GltfScene>,
+    scene: Option<usize>,
+    #[serde(default)]
+    materials: Vec<GltfMaterial>,
+    #[serde(default)]
+    textures: Vec<GltfTexture>,
+    #[serde(default)]
+    images: Vec<GltfImage>,
+    #[serde(default)]
+    skins: Vec<GltfSkin>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GltfAccessor
+{
+    buffer_view: Option<usize>,
+    #[serde(default)]
+    byte_offset: usize,
+    component_type: u32,
+    #[serde(default)]
+    normalized: bool,
+    count: usize,
+    #[serde(rename = "type")]
+    accessor_type: String,
+    sparse: Option<GltfSparse>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GltfSparse
+{
+    count: usize,
+    indices: GltfSparseIndices,
+    values: GltfSparseValues
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GltfSparseIndices
+{
+    buffer_view: usize,
+    #[serde(default)]
+    byte_offset: usize,
+    component_type: u32
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GltfSparseValues
+{
+    buffer_view: usize,
+    #[serde(default)]
+    byte_offset: usize
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GltfBufferView
+{
+    buffer: usize,
+    #[serde(default)]
+    byte_offset: usize,
+    byte_length: usize,
+    byte_stride: Option<usize>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GltfBuffer
+{
+    uri: Option<String>,
+    byte_length: usize
+}
+
+#[derive(Deserialize)]
+struct GltfMesh
+{
+    name: Option<String>,
+    primitives: Vec<GltfPrimitive>,
+    weights: Option<Vec<f32>>,
+    extras: Option<GltfMeshExtras>
+}
+
+// Morph target names are not part of the spec, exporters agree on this extra.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GltfMeshExtras
+{
+    #[serde(default)]
+    target_names: Vec<String>
+}
+
+#[derive(Deserialize)]
+struct GltfPrimitive
+{
+    attributes: BTreeMap<String, usize>,
+    indices: Option<usize>,
+    material: Option<usize>,
+    mode: Option<u32>,
+    #[serde(default)]
+    targets: Vec<BTreeMap<String, usize>>
+}
+
+#[derive(Deserialize)]
+struct GltfNode
+{
+    name: Option<String>,
+    #[serde(default)]
+    children: Vec<usize>,
+    mesh: Option<usize>,
+    skin: Option<usize>,
+    weights: Option<Vec<f32>>,
+    matrix: Option<[f32; 16]>,
+    translation: Option<[f32; 3]>,
+    rotation: Option<[f32; 4]>,
+    scale: Option<[f32; 3]>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GltfSkin
+{
+    inverse_bind_matrices: Option<usize>,
+    joints: Vec<usize>
+}
+
+#[derive(Deserialize)]
+struct GltfScene
+{
+    #[serde(default)]
+    nodes: Vec<usize>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GltfMaterial
+{
+    name: Option<String>,
+    pbr_metallic_roughness: Option<GltfPbrMetallicRoughness>,
+    normal_texture: Option<GltfTextureInfo>
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GltfPbrMetallicRoughness
+{
+    base_color_factor: Option<[f32; 4]>,
+    base_color_texture: Option<GltfTextureInfo>
+}
+
+#[derive(Deserialize)]
+struct GltfTextureInfo
+{
+    index: usize
+}
+
+#[derive(Deserialize)]
+struct GltfTexture
+{
+    source: Option<usize>
+}
+
+#[derive(Deserialize)]
+struct GltfImage
+{
+    uri: Option<String>
+}
+
+fn get_component_size(component_type: u32) -> Result<usize, ImportError>
+{
+    match component_type
+    {
+        COMPONENT_TYPE_BYTE | COMPONENT_TYPE_UNSIGNED_BYTE => Ok(1),
+        COMPONENT_TYPE_SHORT | COMPONENT_TYPE_UNSIGNED_SHORT => Ok(2),
+        COMPONENT_TYPE_UNSIGNED_INT | COMPONENT_TYPE_FLOAT => Ok(4),
+        _ => Err(ImportError::invalid_data("gltf", format!("unknown component type {}", component_type)))
+    }
+}
+
+fn get_component_count(accessor_type: &str) -> Result<usize, ImportError>
+{
+    match accessor_type
+    {
+        "SCALAR" => Ok(1),
+        "VEC2" => Ok(2),
+        "VEC3" => Ok(3),
+        "VEC4" => Ok(4),
+        "MAT2" => Ok(4),
+        "MAT3" => Ok(9),
+        "MAT4" => Ok(16),
+        _ => Err(ImportError::invalid_data("gltf", format!("unknown accessor type {}", accessor_type)))
+    }
+}
+
+// Reads one component and applies the normalization rules of the glTF spec for integer types.
+fn read_component(data: &[u8], component_type: u32, normalized: bool) -> f32
+{
+    match component_type
+    {
+        COMPONENT_TYPE_BYTE =>
+        {
+            let value = data[0] as i8 as f32;
+            if normalized { (value / 127.0).max(-1.0) } else { value }
+        }
+        COMPONENT_TYPE_UNSIGNED_BYTE =>
+        {
+            let value = data[0] as f32;
+            if normalized { value / 255.0 } else { value }
+        }
+        COMPONENT_TYPE_SHORT =>
+        {
+            let value = i16::from_le_bytes([data[0], data[1]]) as f32;
+            if normalized { (value / 32767.0).max(-1.0) } else { value }
+        }
+        COMPONENT_TYPE_UNSIGNED_SHORT =>
+        {
+            let value = u16::from_le_bytes([data[0], data[1]]) as f32;
+            if normalized { value / 65535.0 } else { value }
+        }
+        COMPONENT_TYPE_UNSIGNED_INT => u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as f32,
+        _ => f32::from_le_bytes([data[0], data[1], data[2], data[3]])
+    }
+}
+
+fn read_index(data: &[u8], component_type: u32) -> u32
+{
+    match component_type
+    {
+        COMPONENT_TYPE_UNSIGNED_BYTE => data[0] as u32,
+        COMPONENT_TYPE_UNSIGNED_SHORT => u16::from_le_bytes([data[0], data[1]]) as u32,
+        _ => u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+    }
+}
+
+fn decode_base64(data: &str) -> Result<Vec<u8>, ImportError>
+{
+    let mut result = Vec::with_capacity(data.len() * 3 / 4);
+    let mut accumulator = 0u32;
+    let mut bit_count = 0;
+    for byte in data.bytes()
+    {
+        let value = match byte
+        {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            b'\r' | b'\n' | b' ' => continue,
+            _ => return Err(ImportError::invalid_data("gltf", "invalid base64 data uri"))
+        };
+        accumulator = (accumulator << 6) | value as u32;
+        bit_count += 6;
+        if bit_count >= 8
+        {
+            bit_count -= 8;
+            result.push((accumulator >> bit_count) as u8);
+        }
+    }
+    Ok(result)
+}
+
+fn load_uri(uri: &str, base_dir: Option<&Path>) -> Result<Vec<u8>, ImportError>
+{
+    if uri.starts_with("data:")
+    {
+        let (_, payload) = uri.split_once(";base64,")
+            .ok_or_else(|| ImportError::invalid_data("gltf", "only base64 data uris are supported"))?;
+        return decode_base64(payload);
+    }
+    let base_dir = base_dir.ok_or_else(|| ImportError::invalid_data("gltf", format!("cannot resolve external uri {} without a base directory", uri)))?;
+    Ok(std::fs::read(base_dir.join(uri))?)
+}
+
+struct GltfDocument
+{
+    root: GltfRoot,
+    buffers: Vec<Vec<u8>>,
+    base_dir: Option<PathBuf>
+}
+
+impl GltfDocument
+{
+    fn parse(data: &[u8], base_dir: Option<&Path>) -> Result<Self, ImportError>
+    {
+        let mut json_chunk = data;
+        let mut bin_chunk = None;
+
+        if data.len() >= 12 && u32::from_le_bytes([data[0], data[1], data[2], data[3]]) == GLB_MAGIC
+        {
+            json_chunk = &[];
+            let mut offset = 12;
+            while offset + 8 <= data.len()
+            {
+                let chunk_length = u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]) as usize;
+                let chunk_type = u32::from_le_bytes([data[offset + 4], data[offset + 5], data[offset + 6], data[offset + 7]]);
+                let chunk_data = data.get(offset + 8..offset + 8 + chunk_length)
+                    .ok_or_else(|| ImportError::invalid_data("glb", "chunk exceeds file length"))?;
+                match chunk_type
+                {
+                    GLB_CHUNK_JSON => json_chunk = chunk_data,
+                    GLB_CHUNK_BIN => bin_chunk = Some(chunk_data),
+                    _ => {}
+                }
+                offset += 8 + chunk_length;
+            }
+        }
+
+        let root: GltfRoot = serde_json::from_slice(json_chunk)?;
+        let mut buffers = vec![];
+        for (buffer_index, buffer) in root.buffers.iter().enumerate()
+        {
+            let mut buffer_data = match (&buffer.uri, bin_chunk)
+            {
+                (Some(uri), _) => load_uri(uri, base_dir)?,
+                (None, Some(bin)) if buffer_index == 0 => bin.to_vec(),
+                (None, _) => return Err(ImportError::invalid_data("gltf", format!("buffer {} has no data", buffer_index)))
+            };
+            if buffer_data.len() < buffer.byte_length
+            {
+                return Err(ImportError::invalid_data("gltf", format!("buffer {} is shorter than its byteLength", buffer_index)));
+            }
+            buffer_data.truncate(buffer.byte_length);
+            buffers.push(buffer_data);
+        }
+
+        Ok(GltfDocument { root, buffers, base_dir: base_dir.map(Path::to_path_buf) })
+    }
+
+    fn get_buffer_view_data(&self, buffer_view_index: usize) -> Result<(&[u8], Option<usize>), ImportError>
+    {
+        let buffer_view = self.root.buffer_views.get(buffer_view_index)
+            .ok_or_else(|| ImportError::invalid_data("gltf", format!("buffer view {} does not exist", buffer_view_index)))?;
+        let data = self.buffers.get(buffer_view.buffer)
+            .and_then(|buffer| buffer.get(buffer_view.byte_offset..buffer_view.byte_offset + buffer_view.byte_length))
+            .ok_or_else(|| ImportError::invalid_data("gltf", format!("buffer view {} is out of range", buffer_view_index)))?;
+        Ok((data, buffer_view.byte_stride))
+    }
+
+    // Decodes an accessor of any component type into tightly packed floats.
+    fn read_accessor(&self, accessor_index: usize) -> Result<(Vec<f32>, usize), ImportError>
+    {
+        let accessor = self.root.accessors.get(accessor_index)
+            .ok_or_else(|| ImportError::invalid_data("gltf", format!("accessor {} does not exist", accessor_index)))?;
+        let component_size = get_component_size(accessor.component_type)?;
+        let component_count = get_component_count(&accessor.accessor_type)?;
+        let element_size = component_size * component_count;
+
+        let mut values = vec![0.0; accessor.count * component_count];
+        if let Some(buffer_view_index) = accessor.buffer_view
+        {
+            let (data, byte_stride) = self.get_buffer_view_data(buffer_view_index)?;
+            let stride = byte_stride.unwrap_or(element_size);
+            for element in 0..accessor.count
+            {
+                let element_offset = accessor.byte_offset + element * stride;
+                let element_data = data.get(element_offset..element_offset + element_size)
+                    .ok_or_else(|| ImportError::invalid_data("gltf", format!("accessor {} is out of range", accessor_index)))?;
+                for component in 0..component_count
+                {
+                    values[element * component_count + component] =
+                        read_component(&element_data[component * component_size..], accessor.component_type, accessor.normalized);
+                }
+            }
+        }
+
+        if let Some(sparse) = &accessor.sparse
+        {
+            let index_size = get_component_size(sparse.indices.component_type)?;
+            let (index_data, _) = self.get_buffer_view_data(sparse.indices.buffer_view)?;
+            let (value_data, _) = self.get_buffer_view_data(sparse.values.buffer_view)?;
+            for sparse_element in 0..sparse.count
+            {
+                let index_offset = sparse.indices.byte_offset + sparse_element * index_size;
+                let value_offset = sparse.values.byte_offset + sparse_element * element_size;
+                let (Some(index_bytes), Some(value_bytes)) =
+                    (index_data.get(index_offset..index_offset + index_size), value_data.get(value_offset..value_offset + element_size))
+                else
+                {
+                    return Err(ImportError::invalid_data("gltf", format!("sparse data of accessor {} is out of range", accessor_index)));
+                };
+                let element = read_index(index_bytes, sparse.indices.component_type) as usize;
+                if element >= accessor.count
+                {
+                    return Err(ImportError::invalid_data("gltf", format!("sparse index {} of accessor {} is out of range", element, accessor_index)));
+                }
+                for component in 0..component_count
+                {
+                    values[element * component_count + component] =
+                        read_component(&value_bytes[component * component_size..], accessor.component_type, accessor.normalized);
+                }
+            }
+        }
+
+        Ok((values, component_count))
+    }
+
+    fn read_indices(&self, accessor_index: usize) -> Result<Vec<u32>, ImportError>
+    {
+        let accessor = self.root.accessors.get(accessor_index)
+            .ok_or_else(|| ImportError::invalid_data("gltf", format!("accessor {} does not exist", accessor_index)))?;
+        if accessor.accessor_type != "SCALAR" || accessor.sparse.is_some()
+        {
+            return Err(ImportError::invalid_data("gltf", format!("accessor {} cannot be used as index data", accessor_index)));
+        }
+        let index_size = get_component_size(accessor.component_type)?;
+        let buffer_view_index = accessor.buffer_view
+            .ok_or_else(|| ImportError::invalid_data("gltf", format!("index accessor {} has no buffer view", accessor_index)))?;
+        let (data, byte_stride) = self.get_buffer_view_data(buffer_view_index)?;
+        let stride = byte_stride.unwrap_or(index_size);
+
+        let mut indices = Vec::with_capacity(accessor.count);
+        for element in 0..accessor.count
+        {
+            let offset = accessor.byte_offset + element * stride;
+            let index_data = data.get(offset..offset + index_size)
+                .ok_or_else(|| ImportError::invalid_data("gltf", format!("accessor {} is out of range", accessor_index)))?;
+            indices.push(read_index(index_data, accessor.component_type));
+        }
+        Ok(indices)
+    }
+
+    fn get_image_path(&self, texture_info: &Option<GltfTextureInfo>) -> Option<PathBuf>
+    {
+        let texture = self.root.textures.get(texture_info.as_ref()?.index)?;
+        let uri = self.root.images.get(texture.source?)?.uri.as_ref()?;
+        if uri.starts_with("data:")
+        {
+            return None;
+        }
+        Some(match &self.base_dir
+        {
+            Some(base_dir) => base_dir.join(uri),
+            None => PathBuf::from(uri)
+        })
+    }
+
+    fn convert_material(&self, material: &GltfMaterial) -> MeshMaterial
+    {
+        let mut mesh_material = MeshMaterial { name: material.name.clone().unwrap_or_default(), ..Default::default() };
+        if let Some(pbr) = &material.pbr_metallic_roughness
+        {
+            mesh_material.base_color = pbr.base_color_factor.unwrap_or(mesh_material.base_color);
+            mesh_material.base_color_texture = self.get_image_path(&pbr.base_color_texture);
+        }
+        mesh_material.normal_texture = self.get_image_path(&material.normal_texture);
+        mesh_material
+    }
+}
+
+fn get_attribute_channel(attribute: &str) -> Option<MeshDataChannel>
+{
+    match attribute
+    {
+        "POSITION" => Some(MeshDataChannel::Position),
+        "NORMAL" => Some(MeshDataChannel::Normal),
+        "TANGENT" => Some(MeshDataChannel::Tangent),
+        "TEXCOORD_0" => Some(MeshDataChannel::UV0),
+        "TEXCOORD_1" => Some(MeshDataChannel::UV1),
+        "COLOR_0" => Some(MeshDataChannel::Color),
+        "JOINTS_0" => Some(MeshDataChannel::BoneIndices),
+        "WEIGHTS_0" => Some(MeshDataChannel::BoneWeights),
+        _ => None
+    }
+}
+
+// Converts accessor data to the component count of the mesh channel, dropping extra components
+// and padding missing ones from the channel default (alpha of RGB colors).
+fn fit_channel_data(data: &[f32], component_count: usize, channel: usize) -> Vec<f32>
+{
+    let default_value = get_channel_default_value(channel);
+    let channel_size = default_value.len();
+    if component_count == channel_size
+    {
+        return data.to_vec();
+    }
+    let mut result = Vec::with_capacity(data.len() / component_count * channel_size);
+    for element in data.chunks_exact(component_count)
+    {
+        for component in 0..channel_size
+        {
+            result.push(if component < component_count { element[component] } else { default_value[component] });
+        }
+    }
+    result
+}
+
+fn triangulate_primitive(indices: Vec<u32>, mode: u32) -> Vec<u32>
+{
+    match mode
+    {
+        PRIMITIVE_MODE_TRIANGLE_STRIP =>
+        {
+            let mut triangles = vec![];
+            for i in 2..indices.len()
+            {
+                if i % 2 == 0
+                {
+                    triangles.extend_from_slice(&[indices[i - 2], indices[i - 1], indices[i]]);
+                }
+                else
+                {
+                    triangles.extend_from_slice(&[indices[i - 1], indices[i - 2], indices[i]]);
+                }
+            }
+            triangles
+        }
+        PRIMITIVE_MODE_TRIANGLE_FAN =>
+        {
+            let mut triangles = vec![];
+            for i in 2..indices.len()
+            {
+                triangles.extend_from_slice(&[indices[0], indices[i - 1], indices[i]]);
+            }
+            triangles
+        }
+        _ => indices
+    }
+}
+
+// glTF is right handed and the engine left handed, so everything is mirrored along z on import. A transform of glTF
+// space becomes one of engine space by mirroring on both sides.
+fn mirror_transform(matrix: Matrix4<f32>) -> Matrix4<f32>
+{
+    let mirror = Matrix4::from_nonuniform_scale(1.0, 1.0, -1.0);
+    mirror * matrix * mirror
+}
+
+fn get_matrix(m: &[f32]) -> Matrix4<f32>
+{
+    Matrix4::new(
+        m[0], m[1], m[2], m[3],
+        m[4], m[5], m[6], m[7],
+        m[8], m[9], m[10], m[11],
+        m[12], m[13], m[14], m[15])
+}
+
+// Matrices are split into translation, rotation and scale, glTF requires them to be decomposable. The result is in
+// engine space, mirroring negates z of the translation and the x and y of the rotation axis.
+fn get_node_transform(node: &GltfNode) -> Transform
+{
+    if node.matrix.is_some()
+    {
+        return Transform::from_matrix(&get_node_local_transform(node));
+    }
+    let translation = node.translation.unwrap_or([0.0, 0.0, 0.0]);
+    let rotation = node.rotation.unwrap_or([0.0, 0.0, 0.0, 1.0]);
+    let scale = node.scale.unwrap_or([1.0, 1.0, 1.0]);
+    Transform::new(
+        Vector3::new(translation[0], translation[1], -translation[2]),
+        Quaternion::new(rotation[3], -rotation[0], -rotation[1], rotation[2]),
+        Vector3::new(scale[0], scale[1], scale[2]))
+}
+
+fn get_node_local_transform(node: &GltfNode) -> Matrix4<f32>
+{
+    match node.matrix
+    {
+        Some(matrix) => mirror_transform(get_matrix(&matrix)),
+        None => get_node_transform(node).to_matrix()
+    }
+}
+
+// Merges a second set of four influences into the first, keeping the four with the largest weights.
+fn keep_strongest_influences(indices: &mut [f32], weights: &mut [f32], extra_indices: &[f32], extra_weights: &[f32])
+{
+    for vertex in 0..indices.len() / MAX_BONE_INFLUENCES
+    {
+        let range = vertex * MAX_BONE_INFLUENCES..(vertex + 1) * MAX_BONE_INFLUENCES;
+        let mut influences: Vec<(f32, f32)> = indices[range.clone()].iter().copied().zip(weights[range.clone()].iter().copied())
+            .chain(extra_indices[range.clone()].iter().copied().zip(extra_weights[range.clone()].iter().copied()))
+            .collect();
+        influences.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        for (influence, (index, weight)) in range.zip(influences)
+        {
+            indices[influence] = index;
+            weights[influence] = weight;
+        }
+    }
+}
+
+struct GltfPrimitiveData
+{
+    channels: MeshChannelData,
+    vertex_count: usize,
+    indices: Vec<u32>,
+    material: Option<usize>,
+    // Dense position, normal and tangent deltas of every morph target, empty when the target has no such attribute.
+    morph_targets: Vec<[Vec<f32>; 3]>
+}
+
+impl GltfDocument
+{
+    fn read_primitive(&self, primitive: &GltfPrimitive) -> Result<Option<GltfPrimitiveData>, ImportError>
+    {
+        let mode = primitive.mode.unwrap_or(PRIMITIVE_MODE_TRIANGLES);
+        if mode != PRIMITIVE_MODE_TRIANGLES && mode != PRIMITIVE_MODE_TRIANGLE_STRIP && mode != PRIMITIVE_MODE_TRIANGLE_FAN
+        {
+            warn!("skipping gltf primitive with non triangle mode {}", mode);
+            return Ok(None);
+        }
+
+        let mut channels = MeshChannelData::new();
+        let mut vertex_count = None;
+        for (attribute, accessor_index) in &primitive.attributes
+        {
+            let Some(channel) = get_attribute_channel(attribute) else { continue };
+            let (data, component_count) = self.read_accessor(*accessor_index)?;
+            let count = data.len() / component_count;
+            if *vertex_count.get_or_insert(count) != count
+            {
+                return Err(ImportError::invalid_data("gltf", format!("attribute {} has {} elements, expected {}", attribute, count, vertex_count.unwrap())));
+            }
+            channels.insert(channel as usize, fit_channel_data(&data, component_count, channel as usize));
+        }
+
+        if !channels.contains_key(&(MeshDataChannel::Position as usize))
+        {
+            return Err(ImportError::invalid_data("gltf", "primitive has no POSITION attribute"));
+        }
+        let vertex_count = vertex_count.unwrap_or(0);
+
+        // Only the four strongest of up to eight influences are kept.
+        let (index_channel, weight_channel) = (MeshDataChannel::BoneIndices as usize, MeshDataChannel::BoneWeights as usize);
+        let has_influences = channels.contains_key(&index_channel) && channels.contains_key(&weight_channel);
+        if let (Some(joints_accessor), Some(weights_accessor), true) = (primitive.attributes.get("JOINTS_1"), primitive.attributes.get("WEIGHTS_1"), has_influences)
+        {
+            let (extra_indices, index_component_count) = self.read_accessor(*joints_accessor)?;
+            let (extra_weights, weight_component_count) = self.read_accessor(*weights_accessor)?;
+            let extra_indices = fit_channel_data(&extra_indices, index_component_count, index_channel);
+            let extra_weights = fit_channel_data(&extra_weights, weight_component_count, weight_channel);
+            if extra_indices.len() != vertex_count * MAX_BONE_INFLUENCES || extra_weights.len() != vertex_count * MAX_BONE_INFLUENCES
+            {
+                return Err(ImportError::invalid_data("gltf", "JOINTS_1 or WEIGHTS_1 does not match the vertex count"));
+            }
+            let mut indices = channels.remove(&index_channel).unwrap();
+            let mut weights = channels.remove(&weight_channel).unwrap();
+            keep_strongest_influences(&mut indices, &mut weights, &extra_indices, &extra_weights);
+            channels.insert(index_channel, indices);
+            channels.insert(weight_channel, weights);
+        }
+
+        let indices = match primitive.indices
+        {
+            Some(accessor_index) => self.read_indices(accessor_index)?,
+            None => (0..vertex_count as u32).collect()
+        };
+        if let Some(index) = indices.iter().find(|index| **index as usize >= vertex_count)
+        {
+            return Err(ImportError::invalid_data("gltf", format!("index {} exceeds vertex count {}", index, vertex_count)));
+        }
+
+        let mut morph_targets = vec![];
+        for target in &primitive.targets
+        {
+            let mut deltas: [Vec<f32>; 3] = Default::default();
+            for (attribute, target_deltas) in ["POSITION", "NORMAL", "TANGENT"].iter().zip(deltas.iter_mut())
+            {
+                let Some(accessor_index) = target.get(*attribute) else { continue };
+                let (data, component_count) = self.read_accessor(*accessor_index)?;
+                if component_count != 3 || data.len() != vertex_count * 3
+                {
+                    return Err(ImportError::invalid_data("gltf", format!("morph target {} does not match the vertex count", attribute)));
+                }
+                *target_deltas = data;
+            }
+            morph_targets.push(deltas);
+        }
+
+        let mut primitive_data = GltfPrimitiveData { channels, vertex_count, indices: triangulate_primitive(indices, mode), material: primitive.material, morph_targets };
+        self.generate_missing_frames(&mut primitive_data)?;
+        Ok(Some(primitive_data))
+    }
+
+    // The glTF spec asks for flat normals when a primitive does not provide them, and missing tangents of normal
+    // mapped primitives are expected to be generated with MikkTSpace. Both happen before the primitives of a mesh are
+    // merged, where a missing channel would be filled with defaults. Split vertices keep their morph deltas.
+    fn generate_missing_frames(&self, primitive: &mut GltfPrimitiveData) -> Result<(), ImportError>
+    {
+        let has_channel = |channel: MeshDataChannel| primitive.channels.contains_key(&(channel as usize));
+        let is_normal_mapped = primitive.material.and_then(|material| self.root.materials.get(material))
+            .is_some_and(|material| self.get_image_path(&material.normal_texture).is_some());
+        let needs_normals = !has_channel(MeshDataChannel::Normal);
+        let needs_tangents = is_normal_mapped && has_channel(MeshDataChannel::UV0) && !has_channel(MeshDataChannel::Tangent);
+        if !needs_normals && !needs_tangents
+        {
+            return Ok(());
+        }
+
+        let mut mesh = Mesh
+        {
+            mesh_channel_data: std::mem::take(&mut primitive.channels),
+            mesh_index_data: std::mem::take(&mut primitive.indices),
+            ..Default::default()
+        };
+        for deltas in &primitive.morph_targets
+        {
+            // Zero position deltas keep the vertices of targets that only change normals or tangents.
+            let position_deltas = if deltas[0].is_empty() { vec![0.0; primitive.vertex_count * 3] } else { deltas[0].clone() };
+            mesh.add_morph_target(MorphTarget::from_dense("", &position_deltas, &deltas[1], &deltas[2], 0.0))?;
+        }
+        if needs_normals
+        {
+            mesh.generate_normals(NormalGenerationMode::Flat);
+        }
+        if needs_tangents
+        {
+            mesh.generate_tangents();
+        }
+
+        primitive.vertex_count = mesh.get_vertex_count();
+        for (deltas, target) in primitive.morph_targets.iter_mut().zip(&mesh.mesh_morph_targets)
+        {
+            for (kind_deltas, target_deltas) in deltas.iter_mut().zip([&target.position_deltas, &target.normal_deltas, &target.tangent_deltas])
+            {
+                if kind_deltas.is_empty()
+                {
+                    continue;
+                }
+                *kind_deltas = vec![0.0; primitive.vertex_count * 3];
+                for (entry, vertex) in target.vertices.iter().enumerate()
+                {
+                    let offset = *vertex as usize * 3;
+                    kind_deltas[offset..offset + 3].copy_from_slice(&target_deltas[entry * 3..entry * 3 + 3]);
+                }
+            }
+        }
+        primitive.channels = mesh.mesh_channel_data;
+        primitive.indices = mesh.mesh_index_data;
+        Ok(())
+    }
+
+    // Returns None when the mesh has no triangle primitives.
+    fn create_static_mesh(&self, name: &str, mesh: &GltfMesh) -> Result<Option<StaticMesh>, ImportError>
+    {
+        let mut primitives = vec![];
+        for primitive in &mesh.primitives
+        {
+            if let Some(primitive_data) = self.read_primitive(primitive)?
+            {
+                primitives.push(primitive_data);
+            }
+        }
+        if primitives.is_empty()
+        {
+            warn!("skipping gltf mesh {} without triangle primitives", name);
+            return Ok(None);
+        }
+
+        let mut static_mesh = StaticMesh::new(name);
+        let mut used_channels: Vec<usize> = primitives.iter().flat_map(|primitive| primitive.channels.keys().copied()).collect();
+        used_channels.sort_unstable();
+        used_channels.dedup();
+
+        let mut channel_data = MeshChannelData::new();
+        let mut indices = vec![];
+        let mut material_slots: BTreeMap<usize, usize> = BTreeMap::new();
+        let mut base_vertex = 0;
+        for primitive in &primitives
+        {
+            for channel in &used_channels
+            {
+                let target = channel_data.entry(*channel).or_default();
+                match primitive.channels.get(channel)
+                {
+                    Some(data) => target.extend_from_slice(data),
+                    None =>
+                    {
+                        for _ in 0..primitive.vertex_count
+                        {
+                            target.extend_from_slice(get_channel_default_value(*channel));
+                        }
+                    }
+                }
+            }
+
+            let material_index = primitive.material.and_then(|material| self.root.materials.get(material).map(|data| (material, data)))
+                .map(|(material, data)| *material_slots.entry(material).or_insert_with(|| static_mesh.add_material(self.convert_material(data))));
+
+            let first_index = indices.len() as u32;
+            indices.extend(primitive.indices.iter().map(|index| index + base_vertex));
+            static_mesh.add_section(MeshSection { first_index, index_count: primitive.indices.len() as u32, base_vertex: 0, material_slot: material_index });
+            base_vertex += primitive.vertex_count as u32;
+        }
+
+        // Every primitive has the same number of targets, the deltas are merged like the channels.
+        let target_count = primitives.iter().map(|primitive| primitive.morph_targets.len()).max().unwrap_or(0);
+        let target_names = mesh.extras.as_ref().map(|extras| extras.target_names.clone()).unwrap_or_default();
+        let mut morph_targets = vec![];
+        for target in 0..target_count
+        {
+            let mut deltas: [Vec<f32>; 3] = Default::default();
+            for (kind, kind_deltas) in deltas.iter_mut().enumerate()
+            {
+                let is_present = primitives.iter().any(|primitive| primitive.morph_targets.get(target).is_some_and(|target_deltas| !target_deltas[kind].is_empty()));
+                if !is_present
+                {
+                    continue;
+                }
+                for primitive in &primitives
+                {
+                    match primitive.morph_targets.get(target).filter(|target_deltas| !target_deltas[kind].is_empty())
+                    {
+                        Some(target_deltas) => kind_deltas.extend_from_slice(&target_deltas[kind]),
+                        None => kind_deltas.resize(kind_deltas.len() + primitive.vertex_count * 3, 0.0)
+                    }
+                }
+            }
+            let name = target_names.get(target).cloned().unwrap_or_else(|| format!("target_{}", target));
+            morph_targets.push(MorphTarget::from_dense(&name, &deltas[0], &deltas[1], &deltas[2], 0.0));
+        }
+
+        static_mesh.get_mesh_mut().mesh_channel_data = channel_data;
+        static_mesh.set_index_buffer(indices);
+        for morph_target in morph_targets
+        {
+            static_mesh.get_mesh_mut().add_morph_target(morph_target)?;
+        }
+        static_mesh.set_morph_weights(mesh.weights.clone().unwrap_or_default());
+        static_mesh.get_mesh().validate_structure()?;
+        static_mesh.get_mesh_mut().flip_handedness();
+        static_mesh.get_mesh_mut().update_bounds(false);
+        Ok(Some(static_mesh))
+    }
+
+    fn get_node_parents(&self) -> Vec<Option<usize>>
+    {
+        let mut parents = vec![None; self.root.nodes.len()];
+        for (node_index, node) in self.root.nodes.iter().enumerate()
+        {
+            for child in &node.children
+            {
+                if let Some(parent) = parents.get_mut(*child)
+                {
+                    *parent = Some(node_index);
+                }
+            }
+        }
+        parents
+    }
+
+    // Ancestors of a node from its parent up to the root.
+    fn get_node_ancestors(&self, node_index: usize, parents: &[Option<usize>]) -> Result<Vec<usize>, ImportError>
+    {
+        let mut ancestors = vec![];
+        let mut current = parents[node_index];
+        while let Some(ancestor) = current
+        {
+            if ancestors.len() > self.root.nodes.len()
+            {
+                return Err(ImportError::invalid_data("gltf", "node hierarchy contains a cycle"));
+            }
+            ancestors.push(ancestor);
+            current = parents[ancestor];
+        }
+        Ok(ancestors)
+    }
+
+    // Bones are sorted by depth so that parents come first. Returns the skeleton and the bone of every skin joint.
+    fn create_skeleton(&self, skin: &GltfSkin) -> Result<(Skeleton, Vec<usize>), ImportError>
+    {
+        if let Some(joint) = skin.joints.iter().find(|joint| **joint >= self.root.nodes.len())
+        {
+            return Err(ImportError::invalid_data("gltf", format!("joint node {} does not exist", joint)));
+        }
+        let inverse_bind_matrices: Vec<Matrix4<f32>> = match skin.inverse_bind_matrices
+        {
+            Some(accessor_index) =>
+            {
+                let (data, component_count) = self.read_accessor(accessor_index)?;
+                if component_count != 16 || data.len() / 16 != skin.joints.len()
+                {
+                    return Err(ImportError::invalid_data("gltf", "inverse bind matrices do not match the joints"));
+                }
+                data.chunks_exact(16).map(|matrix| mirror_transform(get_matrix(matrix))).collect()
+            }
+            None => vec![Matrix4::identity(); skin.joints.len()]
+        };
+
+        let parents = self.get_node_parents();
+        let mut joint_ancestors = vec![];
+        for joint in &skin.joints
+        {
+            joint_ancestors.push(self.get_node_ancestors(*joint, &parents)?);
+        }
+        let mut joint_order: Vec<usize> = (0..skin.joints.len()).collect();
+        joint_order.sort_by_key(|joint| joint_ancestors[*joint].len());
+        let mut joint_bones = vec![0; skin.joints.len()];
+        for (bone, joint) in joint_order.iter().enumerate()
+        {
+            joint_bones[*joint] = bone;
+        }
+
+        let mut skeleton = Skeleton::new();
+        for joint in joint_order
+        {
+            let node = &self.root.nodes[skin.joints[joint]];
+            // The closest ancestor that is a joint is the parent bone, the transforms of other ancestors are baked
+            // into the rest transform of root bones.
+            let ancestors = &joint_ancestors[joint];
+            let parent_position = ancestors.iter().position(|ancestor| skin.joints.contains(ancestor));
+            let parent_bone = parent_position.map(|position| joint_bones[skin.joints.iter().position(|other| *other == ancestors[position]).unwrap()]);
+            let mut rest_transform = get_node_local_transform(node);
+            for ancestor in &ancestors[..parent_position.unwrap_or(ancestors.len())]
+            {
+                rest_transform = get_node_local_transform(&self.root.nodes[*ancestor]) * rest_transform;
+            }
+            skeleton.add_bone(Bone
+            {
+                name: node.name.clone().unwrap_or_else(|| format!("joint_{}", skin.joints[joint])),
+                parent: parent_bone,
+                rest_transform,
+                inverse_bind_matrix: inverse_bind_matrices[joint]
+            })?;
+        }
+        Ok((skeleton, joint_bones))
+    }
+
+    // Skinned geometry stays in bind space, the glTF spec ignores the transform of the node and places it with
+    // the joints instead.
+    fn create_skeletal_mesh(&self, name: &str, mesh: &GltfMesh, skin_index: usize) -> Result<Option<SkeletalMesh>, ImportError>
+    {
+        let skin = self.root.skins.get(skin_index)
+            .ok_or_else(|| ImportError::invalid_data("gltf", format!("skin {} does not exist", skin_index)))?;
+        let Some(mut static_mesh) = self.create_static_mesh(name, mesh)? else { return Ok(None) };
+        let (skeleton, joint_bones) = self.create_skeleton(skin)?;
+        let mesh_data = static_mesh.get_mesh_mut();
+        mesh_data.normalize_bone_weights();
+        let channels = &mut mesh_data.mesh_channel_data;
+        if let (Some(mut bone_indices), Some(bone_weights)) = (channels.remove(&(MeshDataChannel::BoneIndices as usize)), channels.get(&(MeshDataChannel::BoneWeights as usize)))
+        {
+            // Unused influences point at bone 0.
+            for (bone_index, weight) in bone_indices.iter_mut().zip(bone_weights)
+            {
+                *bone_index = match joint_bones.get(*bone_index as usize)
+                {
+                    Some(bone) if *weight != 0.0 => *bone as f32,
+                    Some(_) => 0.0,
+                    None => *bone_index
+                };
+            }
+            channels.insert(MeshDataChannel::BoneIndices as usize, bone_indices);
+        }
+        Ok(Some(SkeletalMesh::from_static_mesh(static_mesh, skeleton)?))
+    }
+
+    fn add_node_to_scene(&self, scene: &mut Scene, node_index: usize, parent: Option<SceneNodeHandle>, depth: usize) -> Result<(), ImportError>
+    {
+        let node = self.root.nodes.get(node_index)
+            .ok_or_else(|| ImportError::invalid_data("gltf", format!("node {} does not exist", node_index)))?;
+        if depth > self.root.nodes.len()
+        {
+            return Err(ImportError::invalid_data("gltf", "node hierarchy contains a cycle"));
+        }
+
+        let scene_node = scene.create_node(&node.name.clone().unwrap_or_else(|| format!("node_{}", node_index)), parent)?;
+        scene.set_local_transform(scene_node, get_node_transform(node))?;
+        if let Some(mesh_index) = node.mesh
+        {
+            let mesh = self.root.meshes.get(mesh_index)
+                .ok_or_else(|| ImportError::invalid_data("gltf", format!("mesh {} does not exist", mesh_index)))?;
+            let name = node.name.clone().or_else(|| mesh.name.clone()).unwrap_or_else(|| format!("node_{}", node_index));
+            if let Some(skin_index) = node.skin
+            {
+                if let Some(mut skeletal_mesh) = self.create_skeletal_mesh(&name, mesh, skin_index)?
+                {
+                    if let Some(weights) = &node.weights
+                    {
+                        skeletal_mesh.get_render_mesh_mut().set_morph_weights(weights.clone());
+                    }
+                    // The joints place a skinned mesh, the transform of its node is ignored. Root bones already
+                    // contain the transforms above the skeleton, so the mesh goes to a root node of its own.
+                    let skin_node = scene.create_node(&name, None)?;
+                    scene.add_scene_proxy(skin_node, Box::new(skeletal_mesh))?;
+                }
+            }
+            else if let Some(mut static_mesh) = self.create_static_mesh(&name, mesh)?
+            {
+                // Node weights override the default weights of the mesh.
+                if let Some(weights) = &node.weights
+                {
+                    static_mesh.set_morph_weights(weights.clone());
+                }
+                scene.add_scene_proxy(scene_node, Box::new(static_mesh))?;
+            }
+        }
+
+        for child in &node.children
+        {
+            self.add_node_to_scene(scene, *child, Some(scene_node), depth + 1)?;
+        }
+        Ok(())
+    }
+
+    fn get_root_nodes(&self) -> Vec<usize>
+    {
+        let scene_index = self.root.scene.unwrap_or(0);
+        if let Some(scene) = self.root.scenes.get(scene_index)
+        {
+            return scene.nodes.clone();
+        }
+
+        // Without a scene every node that is nobody's child is a root.
+        let mut is_child = vec![false; self.root.nodes.len()];
+        for node in &self.root.nodes
+        {
+            for child in &node.children
+            {
+                if let Some(flag) = is_child.get_mut(*child)
+                {
+                    *flag = true;
+                }
+            }
+        }
+        (0..self.root.nodes.len()).filter(|node| !is_child[*node]).collect()
+    }
+}
+
+/// Builds a Scene from glTF 2.0 data, either JSON or binary GLB. External buffers are resolved relative to base_dir.
+/// The right handed glTF data is mirrored along z into the left handed engine space, see Mesh::flip_handedness.
+pub fn load_gltf_scene_from_slice(data: &[u8], base_dir: Option<&Path>) -> Result<Scene, ImportError>
+{
+    let document = GltfDocument::parse(data, base_dir)?;
+    let mut scene = Scene::new();
+    for node_index in document.get_root_nodes()
+    {
+        document.add_node_to_scene(&mut scene, node_index, None, 0)?;
+    }
+    Ok(scene)
+}
+
+/// Builds a Scene from a .gltf or .glb file with one scene node per glTF node. Every node with a mesh gets a
+/// StaticMesh proxy in node space, or a SkeletalMesh when it has a skin.
+pub fn load_gltf_scene(path: impl AsRef<Path>) -> Result<Scene, ImportError>
+{
+    let path = path.as_ref();
+    let data = std::fs::read(path)?;
+    load_gltf_scene_from_slice(&data, path.parent())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use cgmath::InnerSpace;
+
+    use super::*;
+
+    // Two roots, a mesh with an indexed and a sparse primitive, and a skin whose joints are listed child first. The
+    // .glb holds the same document with the buffer in its binary chunk.
+    const EMBEDDED_GLTF: &[u8] = include_bytes!("../../../assets/tests/hierarchy_skin_sparse.gltf");
+    const BINARY_GLB: &[u8] = include_bytes!("../../../assets/tests/hierarchy_skin_sparse.glb");
+    // One mesh with a primitive that has normals, a bent quad without normals whose morph target moves a shared
+    // vertex, and a normal mapped primitive without tangents.
+    const MIXED_PRIMITIVES_GLTF: &[u8] = include_bytes!("../../../assets/tests/mixed_primitives.gltf");
+
+    fn get_node_names(scene: &Scene, nodes: &[SceneNodeHandle]) -> Vec<String>
+    {
+        nodes.iter().map(|node| scene.get_node(*node).unwrap().get_name().to_string()).collect()
+    }
+
+    fn get_node_mesh(scene: &Scene, name: &str) -> (Mesh, Vec<MeshSection>, Vec<Option<MeshMaterial>>)
+    {
+        let proxy = scene.get_node(scene.find_node(name).unwrap()).unwrap().get_scene_proxies()[0];
+        let batches = scene.generate_scene_proxy_mesh_batches(proxy);
+        (batches[0].mesh.clone(), batches.iter().map(|batch| batch.section).collect(), batches.iter().map(|batch| batch.material.cloned()).collect())
+    }
+
+    #[test]
+    fn gltf_and_glb_build_the_hierarchy()
+    {
+        for data in [EMBEDDED_GLTF, BINARY_GLB]
+        {
+            let scene = load_gltf_scene_from_slice(data, None).unwrap();
+            // The skinned mesh gets a root of its own, placed by its joints.
+            assert_eq!(get_node_names(&scene, scene.get_root_nodes()), vec!["root", "skinned", "joint0"]);
+            let root = scene.find_node("root").unwrap();
+            assert_eq!(get_node_names(&scene, scene.get_node(root).unwrap().get_children()), vec!["quad", "skinned"]);
+            let joint1 = scene.get_node(scene.find_node("joint1").unwrap()).unwrap();
+            assert_eq!(joint1.get_parent(), scene.find_node("joint0"));
+            assert_eq!(scene.get_scene_proxy_count(), 2);
+        }
+    }
+
+    #[test]
+    fn transforms_are_mirrored_into_left_handed_space()
+    {
+        let scene = load_gltf_scene_from_slice(EMBEDDED_GLTF, None).unwrap();
+        let get_transform = |name: &str| *scene.get_node(scene.find_node(name).unwrap()).unwrap().get_local_transform();
+        assert_eq!(get_transform("root").translation, Vector3::new(1.0, 2.0, -3.0));
+        // A quarter turn around y turns the other way in mirrored space.
+        let rotation = get_transform("quad").rotation;
+        assert!((rotation * Vector3::new(1.0, 0.0, 0.0) - Vector3::new(0.0, 0.0, 1.0)).magnitude() < 1e-5);
+        assert!((get_transform("joint1").translation - Vector3::new(0.0, 1.0, -0.5)).magnitude() < 1e-6);
+    }
+
+    #[test]
+    fn primitives_become_sections()
+    {
+        let scene = load_gltf_scene_from_slice(EMBEDDED_GLTF, None).unwrap();
+        let (mesh, sections, materials) = get_node_mesh(&scene, "quad");
+        assert_eq!(mesh.get_vertex_count(), 6);
+        assert_eq!(sections.iter().map(|section| (section.first_index, section.index_count, section.material_slot)).collect::<Vec<_>>(), vec![(0, 3, Some(0)), (3, 3, Some(1))]);
+        let base_colors: Vec<[f32; 4]> = materials.iter().map(|material| material.as_ref().unwrap().base_color).collect();
+        assert_eq!(base_colors, vec![[1.0, 0.0, 0.0, 1.0], [0.0, 1.0, 0.0, 1.0]]);
+    }
+
+    #[test]
+    fn sparse_accessor_replaces_base_values()
+    {
+        let scene = load_gltf_scene_from_slice(BINARY_GLB, None).unwrap();
+        let (mesh, _, _) = get_node_mesh(&scene, "quad");
+        let positions: Vec<Vector3<f32>> = (3..6).map(|vertex| mesh.get_position(vertex)).collect();
+        assert_eq!(positions, vec![Vector3::new(1.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 0.0), Vector3::new(0.0, 1.0, 0.0)]);
+    }
+
+    #[test]
+    fn winding_agrees_with_mirrored_normals()
+    {
+        let scene = load_gltf_scene_from_slice(EMBEDDED_GLTF, None).unwrap();
+        let (mesh, _, _) = get_node_mesh(&scene, "quad");
+        let normals = &mesh.mesh_channel_data[&(MeshDataChannel::Normal as usize)];
+        for triangle in mesh.get_triangle_indices().chunks_exact(3)
+        {
+            let [p0, p1, p2] = [0, 1, 2].map(|corner| mesh.get_position(triangle[corner] as usize));
+            let normal = Vector3::new(normals[triangle[0] as usize * 3], normals[triangle[0] as usize * 3 + 1], normals[triangle[0] as usize * 3 + 2]);
+            assert_eq!(normal, Vector3::new(0.0, 0.0, -1.0));
+            assert!((p1 - p0).cross(p2 - p0).dot(normal) > 0.0);
+        }
+    }
+
+    #[test]
+    fn missing_normals_and_tangents_are_generated_per_primitive()
+    {
+        let scene = load_gltf_scene_from_slice(MIXED_PRIMITIVES_GLTF, None).unwrap();
+        let (mesh, sections, _) = get_node_mesh(&scene, "mixed");
+        let indices = mesh.get_triangle_indices();
+        let get_vector = |channel: MeshDataChannel, vertex: u32| {
+            let data = &mesh.mesh_channel_data[&(channel as usize)];
+            let size = data.len() / mesh.get_vertex_count();
+            Vector3::new(data[vertex as usize * size], data[vertex as usize * size + 1], data[vertex as usize * size + 2])
+        };
+        let get_section_vertices = |section: usize| {
+            let range = sections[section].first_index as usize..(sections[section].first_index + sections[section].index_count) as usize;
+            indices[range].to_vec()
+        };
+
+        // The provided normals are kept, the bent quad gets the flat normal of each of its triangles.
+        assert!(get_section_vertices(0).iter().all(|vertex| get_vector(MeshDataChannel::Normal, *vertex) == Vector3::new(0.0, 0.0, -1.0)));
+        let quad = get_section_vertices(1);
+        for (triangle, expected) in quad.chunks_exact(3).zip([Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -1.0)])
+        {
+            assert!(triangle.iter().all(|vertex| (get_vector(MeshDataChannel::Normal, *vertex) - expected).magnitude() < 1e-6));
+        }
+
+        // Every copy of the split vertex keeps its morph delta.
+        let target = &mesh.mesh_morph_targets[0];
+        let moved: Vec<u32> = quad.iter().copied().filter(|vertex| mesh.get_position(*vertex as usize) == Vector3::new(2.0, 0.0, 0.0)).collect();
+        assert_eq!(moved.len(), 2);
+        for vertex in moved
+        {
+            let entry = target.vertices.iter().position(|target_vertex| *target_vertex == vertex).unwrap();
+            assert_eq!(&target.position_deltas[entry * 3..entry * 3 + 3], &[0.0, 0.0, -0.5]);
+        }
+        assert_eq!(target.vertices.len(), 2);
+
+        // The tangents of the normal mapped primitive follow u.
+        for vertex in get_section_vertices(2)
+        {
+            assert!((get_vector(MeshDataChannel::Tangent, vertex) - Vector3::new(0.0, 1.0, 0.0)).magnitude() < 1e-5);
+        }
+        assert!(mesh.validate_structure().is_ok());
+    }
+
+    #[test]
+    fn skin_sorts_bones_parent_first()
+    {
+        let document = GltfDocument::parse(BINARY_GLB, None).unwrap();
+        let skeletal_mesh = document.create_skeletal_mesh("skinned", &document.root.meshes[1], 0).unwrap().unwrap();
+        let skeleton = skeletal_mesh.get_skeleton();
+        let bones = skeleton.get_bones();
+        assert_eq!(bones.iter().map(|bone| bone.name.as_str()).collect::<Vec<_>>(), vec!["joint0", "joint1"]);
+        assert_eq!(bones.iter().map(|bone| bone.parent).collect::<Vec<_>>(), vec![None, Some(0)]);
+        assert_eq!(bones[1].inverse_bind_matrix, Matrix4::from_translation(Vector3::new(-2.0, -1.0, 0.5)));
+
+        // The inverse bind matrices undo the rest pose.
+        for matrix in skeleton.get_skinning_matrices(&skeleton.get_rest_pose())
+        {
+            let difference: f32 = (0..4).map(|column| (matrix[column] - Matrix4::<f32>::identity()[column]).magnitude()).sum();
+            assert!(difference < 1e-5);
+        }
+
+        // Joint 0 of the skin is the child bone, unused influences point at bone 0. Flat normal generation reorders
+        // the vertices, so they are found by position.
+        let mesh = skeletal_mesh.get_mesh();
+        let bone_indices = &mesh.mesh_channel_data[&(MeshDataChannel::BoneIndices as usize)];
+        let get_bone_indices = |position: Vector3<f32>| {
+            let vertex = (0..mesh.get_vertex_count()).find(|vertex| mesh.get_position(*vertex) == position).unwrap();
+            bone_indices[vertex * 4..vertex * 4 + 4].to_vec()
+        };
+        assert_eq!(get_bone_indices(Vector3::new(0.0, 0.0, 0.0)), vec![1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(get_bone_indices(Vector3::new(1.0, 0.0, 0.0)), vec![0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(get_bone_indices(Vector3::new(0.0, 1.0, 0.0)), vec![1.0, 0.0, 0.0, 0.0]);
+    }
+}
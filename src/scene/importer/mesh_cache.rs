@@ -0,0 +1,948 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use cgmath::Vector3;
+use serde::{Deserialize, Serialize};
+
+use crate::scene::importer::ImportError;
+use crate::scene::mesh::*;
+use crate::scene::mesh::bounds::*;
+use crate::scene::mesh::morph::MorphTarget;
+use crate::scene::mesh::simplify::MeshLod;
+use crate::scene::mesh::validation::MeshError;
+use crate::scene::mesh::vertex_format::*;
+use crate::scene::mesh_material::MeshMaterial;
+use crate::scene::static_mesh::*;
+
+// Layout of a cooked mesh file, little endian, every offset counted from the start of the file:
+//   header           80 bytes, see write_mesh_cache_data
+//   lod table        one 176 byte entry per level of detail, level 0 is the base mesh
+//   per level        channel table (80 bytes per channel), sections (16 bytes each), u32 indices, f32 channel data,
+//                    morph target table (64 bytes per target) followed by the names, vertices and deltas of the targets
+//   metadata         json with the name, materials and morph weights of a static mesh
+//   gpu block        optional, the buffers of StaticMesh::get_gpu_data ready to be copied into upload buffers
+// Every table and data block starts at a multiple of 16 bytes, so a mapped file can be read in place.
+pub const MESH_CACHE_MAGIC: [u8; 8] = *b"RDXMESH\0";
+// Extension the scene loader recognizes cache files by.
+pub const MESH_CACHE_EXTENSION: &str = "rdxmesh";
+// Bump whenever the layout changes, files of other versions have to be cooked again.
+pub const MESH_CACHE_VERSION: u32 = 3;
+
+const MESH_CACHE_ALIGNMENT: usize = 16;
+const HEADER_SIZE: usize = 80;
+const LOD_ENTRY_SIZE: usize = 176;
+const BOUNDS_SIZE: usize = 112;
+const CHANNEL_ENTRY_SIZE: usize = 80;
+const SEMANTIC_NAME_SIZE: usize = 32;
+const MORPH_ENTRY_SIZE: usize = 64;
+const GPU_HEADER_SIZE: usize = 48;
+const STREAM_ENTRY_SIZE: usize = 8;
+
+const BOUNDS_FLAG_PRESENT: u32 = 1;
+const BOUNDS_FLAG_ORIENTED_BOX: u32 = 2;
+// Shares the flags of the level of detail entry with the bounds.
+const LOD_FLAG_POINT_LIST: u32 = 4;
+const NO_MATERIAL_SLOT: u32 = u32::MAX;
+const MORPH_FLAG_NORMALS: u32 = 1;
+const MORPH_FLAG_TANGENTS: u32 = 2;
+
+#[derive(Default, Serialize, Deserialize)]
+struct MeshCacheMetadata
+{
+    name: String,
+    materials: Vec<MeshMaterial>,
+    morph_weights: Vec<f32>
+}
+
+fn invalid_cache(message: impl Into<String>) -> ImportError
+{
+    ImportError::invalid_data("mesh cache", message)
+}
+
+/// 64 bit FNV-1a, used for the content hash of cache files. Also a convenient source hash of the asset bytes.
+pub fn get_mesh_cache_hash(data: &[u8]) -> u64
+{
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3))
+}
+
+// 0 stands for a channel without an explicit format.
+fn get_vertex_format_id(format: Option<VertexFormat>) -> u32
+{
+    match format
+    {
+        None => 0,
+        Some(VertexFormat::Float32) => 1,
+        Some(VertexFormat::Float16) => 2,
+        Some(VertexFormat::Unorm8x4) => 3,
+        Some(VertexFormat::Snorm8x4) => 4,
+        Some(VertexFormat::Unorm16) => 5,
+        Some(VertexFormat::Snorm16) => 6,
+        Some(VertexFormat::OctahedralSnorm16x2) => 7
+    }
+}
+
+fn get_vertex_format_from_id(id: u32) -> Result<Option<VertexFormat>, ImportError>
+{
+    match id
+    {
+        0 => Ok(None),
+        1 => Ok(Some(VertexFormat::Float32)),
+        2 => Ok(Some(VertexFormat::Float16)),
+        3 => Ok(Some(VertexFormat::Unorm8x4)),
+        4 => Ok(Some(VertexFormat::Snorm8x4)),
+        5 => Ok(Some(VertexFormat::Unorm16)),
+        6 => Ok(Some(VertexFormat::Snorm16)),
+        7 => Ok(Some(VertexFormat::OctahedralSnorm16x2)),
+        _ => Err(invalid_cache(format!("unknown vertex format {}", id)))
+    }
+}
+
+#[derive(Default)]
+struct CacheWriter
+{
+    data: Vec<u8>
+}
+
+impl CacheWriter
+{
+    fn align(&mut self)
+    {
+        let size = self.data.len().div_ceil(MESH_CACHE_ALIGNMENT) * MESH_CACHE_ALIGNMENT;
+        self.data.resize(size, 0);
+    }
+
+    // Leaves size zero bytes to be filled by write_at, returns their offset.
+    fn reserve(&mut self, size: usize) -> usize
+    {
+        self.align();
+        let offset = self.data.len();
+        self.data.resize(offset + size, 0);
+        offset
+    }
+
+    fn write_at(&mut self, offset: usize, bytes: &[u8])
+    {
+        self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8])
+    {
+        self.data.extend_from_slice(bytes);
+    }
+
+    fn write_u32(&mut self, value: u32)
+    {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    fn write_i32(&mut self, value: i32)
+    {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, value: u64)
+    {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    fn write_f32s(&mut self, values: &[f32])
+    {
+        for value in values
+        {
+            self.write_bytes(&value.to_le_bytes());
+        }
+    }
+
+    fn write_vector(&mut self, vector: Vector3<f32>)
+    {
+        self.write_f32s(&[vector.x, vector.y, vector.z]);
+    }
+}
+
+struct CacheReader<'a>
+{
+    data: &'a [u8],
+    offset: usize
+}
+
+impl<'a> CacheReader<'a>
+{
+    fn new(data: &'a [u8], offset: usize) -> Self
+    {
+        CacheReader { data, offset }
+    }
+
+    fn read_bytes(&mut self, size: usize) -> Result<&'a [u8], ImportError>
+    {
+        let bytes = get_cache_slice(self.data, self.offset as u64, size as u64)?;
+        self.offset += size;
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, ImportError>
+    {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    fn read_i32(&mut self) -> Result<i32, ImportError>
+    {
+        Ok(i32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, ImportError>
+    {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+
+    fn read_f32(&mut self) -> Result<f32, ImportError>
+    {
+        Ok(f32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    fn read_vector(&mut self) -> Result<Vector3<f32>, ImportError>
+    {
+        Ok(Vector3::new(self.read_f32()?, self.read_f32()?, self.read_f32()?))
+    }
+}
+
+fn get_cache_slice(data: &[u8], offset: u64, size: u64) -> Result<&[u8], ImportError>
+{
+    match offset.checked_add(size)
+    {
+        Some(end) if end <= data.len() as u64 => Ok(&data[offset as usize..end as usize]),
+        _ => Err(invalid_cache(format!("block at {} with {} bytes is outside of the file", offset, size)))
+    }
+}
+
+fn write_bounds(writer: &mut CacheWriter, bounds: Option<&MeshBounds>)
+{
+    let start = writer.data.len();
+    if let Some(bounds) = bounds
+    {
+        writer.write_vector(bounds.bounding_box.min);
+        writer.write_vector(bounds.bounding_box.max);
+        writer.write_vector(bounds.bounding_sphere.center);
+        writer.write_f32s(&[bounds.bounding_sphere.radius]);
+        if let Some(oriented_box) = &bounds.oriented_bounding_box
+        {
+            writer.write_vector(oriented_box.center);
+            for axis in &oriented_box.axes
+            {
+                writer.write_vector(*axis);
+            }
+            writer.write_vector(oriented_box.half_extents);
+        }
+    }
+    writer.data.resize(start + BOUNDS_SIZE, 0);
+}
+
+fn read_bounds(reader: &mut CacheReader, flags: u32) -> Result<Option<MeshBounds>, ImportError>
+{
+    let start = reader.offset;
+    let mut bounds = None;
+    if flags & BOUNDS_FLAG_PRESENT != 0
+    {
+        let bounding_box = BoundingBox { min: reader.read_vector()?, max: reader.read_vector()? };
+        let bounding_sphere = BoundingSphere { center: reader.read_vector()?, radius: reader.read_f32()? };
+        let oriented_bounding_box = if flags & BOUNDS_FLAG_ORIENTED_BOX != 0
+        {
+            Some(OrientedBoundingBox
+            {
+                center: reader.read_vector()?,
+                axes: [reader.read_vector()?, reader.read_vector()?, reader.read_vector()?],
+                half_extents: reader.read_vector()?
+            })
+        }
+        else
+        {
+            None
+        };
+        bounds = Some(MeshBounds { bounding_box, bounding_sphere, oriented_bounding_box });
+    }
+    reader.offset = start + BOUNDS_SIZE;
+    Ok(bounds)
+}
+
+fn write_morph_targets(writer: &mut CacheWriter, targets: &[MorphTarget]) -> usize
+{
+    let table_offset = writer.reserve(targets.len() * MORPH_ENTRY_SIZE);
+    for (target_index, target) in targets.iter().enumerate()
+    {
+        writer.align();
+        let name_offset = writer.data.len();
+        writer.write_bytes(target.name.as_bytes());
+        writer.align();
+        let vertices_offset = writer.data.len();
+        for vertex in &target.vertices
+        {
+            writer.write_u32(*vertex);
+        }
+        let mut delta_offsets = [0; 3];
+        for (deltas, offset) in [&target.position_deltas, &target.normal_deltas, &target.tangent_deltas].iter().zip(delta_offsets.iter_mut())
+        {
+            if !deltas.is_empty()
+            {
+                writer.align();
+                *offset = writer.data.len() as u64;
+                writer.write_f32s(deltas);
+            }
+        }
+
+        let mut flags = 0;
+        if !target.normal_deltas.is_empty()
+        {
+            flags |= MORPH_FLAG_NORMALS;
+        }
+        if !target.tangent_deltas.is_empty()
+        {
+            flags |= MORPH_FLAG_TANGENTS;
+        }
+        let mut entry = CacheWriter::default();
+        entry.write_u64(name_offset as u64);
+        entry.write_u32(target.name.len() as u32);
+        entry.write_u32(target.vertices.len() as u32);
+        entry.write_u32(flags);
+        entry.write_u32(0);
+        entry.write_u64(vertices_offset as u64);
+        for offset in delta_offsets
+        {
+            entry.write_u64(offset);
+        }
+        writer.write_at(table_offset + target_index * MORPH_ENTRY_SIZE, &entry.data);
+    }
+    table_offset
+}
+
+fn read_morph_target(data: &[u8], entry_offset: usize) -> Result<MorphTarget, ImportError>
+{
+    let mut reader = CacheReader::new(data, entry_offset);
+    let name_offset = reader.read_u64()?;
+    let name_size = reader.read_u32()? as u64;
+    let delta_count = reader.read_u32()? as u64;
+    let flags = reader.read_u32()?;
+    reader.read_u32()?;
+    let vertices_offset = reader.read_u64()?;
+    let name = std::str::from_utf8(get_cache_slice(data, name_offset, name_size)?)
+        .map_err(|_| invalid_cache("morph target name is not utf-8"))?;
+    let vertices = get_cache_slice(data, vertices_offset, delta_count * 4)?
+        .chunks_exact(4)
+        .map(|vertex| u32::from_le_bytes(vertex.try_into().unwrap()))
+        .collect();
+    let mut deltas: [Vec<f32>; 3] = Default::default();
+    for (kind, kind_deltas) in deltas.iter_mut().enumerate()
+    {
+        let offset = reader.read_u64()?;
+        let is_present = match kind
+        {
+            0 => true,
+            1 => flags & MORPH_FLAG_NORMALS != 0,
+            _ => flags & MORPH_FLAG_TANGENTS != 0
+        };
+        if is_present
+        {
+            *kind_deltas = get_cache_slice(data, offset, delta_count * 12)?
+                .chunks_exact(4)
+                .map(|value| f32::from_le_bytes(value.try_into().unwrap()))
+                .collect();
+        }
+    }
+    let [position_deltas, normal_deltas, tangent_deltas] = deltas;
+    Ok(MorphTarget { name: name.to_string(), vertices, position_deltas, normal_deltas, tangent_deltas })
+}
+
+fn write_lod(writer: &mut CacheWriter, lod_entry_offset: usize, mesh: &Mesh, error: f32) -> Result<(), ImportError>
+{
+    mesh.validate_structure()?;
+    let channels: Vec<usize> = mesh.mesh_channel_data.keys().copied().collect();
+    let channel_table_offset = writer.reserve(channels.len() * CHANNEL_ENTRY_SIZE);
+
+    writer.align();
+    let section_offset = writer.data.len();
+    for section in &mesh.mesh_sections
+    {
+        writer.write_u32(section.first_index);
+        writer.write_u32(section.index_count);
+        writer.write_i32(section.base_vertex);
+        writer.write_u32(section.material_slot.map(|slot| slot as u32).unwrap_or(NO_MATERIAL_SLOT));
+    }
+
+    writer.align();
+    let index_offset = writer.data.len();
+    for index in &mesh.mesh_index_data
+    {
+        writer.write_u32(*index);
+    }
+
+    for (channel_index, channel) in channels.iter().enumerate()
+    {
+        let desc = get_mesh_channel_desc(*channel).ok_or(MeshError::UnknownChannel(*channel))?;
+        if desc.semantic_name.len() >= SEMANTIC_NAME_SIZE
+        {
+            return Err(invalid_cache(format!("semantic name {} is too long", desc.semantic_name)));
+        }
+        let values = &mesh.mesh_channel_data[channel];
+        writer.align();
+        let data_offset = writer.data.len();
+        writer.write_f32s(values);
+
+        let mut entry = CacheWriter::default();
+        entry.write_bytes(desc.semantic_name.as_bytes());
+        entry.data.resize(SEMANTIC_NAME_SIZE, 0);
+        entry.write_u32(desc.semantic_index);
+        entry.write_u32(desc.get_component_count() as u32);
+        entry.write_u32(get_vertex_format_id(mesh.mesh_channel_formats.get(channel).copied()));
+        entry.write_u32(0);
+        let mut default_value = [0.0; 4];
+        default_value[..desc.default_value.len()].copy_from_slice(desc.default_value);
+        entry.write_f32s(&default_value);
+        entry.write_u64(data_offset as u64);
+        entry.write_u64(values.len() as u64);
+        writer.write_at(channel_table_offset + channel_index * CHANNEL_ENTRY_SIZE, &entry.data);
+    }
+    let morph_table_offset = write_morph_targets(writer, &mesh.mesh_morph_targets);
+
+    let bounds = mesh.get_bounds();
+    let mut bounds_flags = if mesh.mesh_topology == MeshPrimitiveTopology::PointList { LOD_FLAG_POINT_LIST } else { 0 };
+    if let Some(bounds) = bounds
+    {
+        bounds_flags |= BOUNDS_FLAG_PRESENT;
+        if bounds.oriented_bounding_box.is_some()
+        {
+            bounds_flags |= BOUNDS_FLAG_ORIENTED_BOX;
+        }
+    }
+    let mut entry = CacheWriter::default();
+    entry.write_f32s(&[error]);
+    entry.write_u32(mesh.get_vertex_count() as u32);
+    entry.write_u32(mesh.mesh_index_data.len() as u32);
+    entry.write_u32(channels.len() as u32);
+    entry.write_u32(mesh.mesh_sections.len() as u32);
+    entry.write_u32(match mesh.mesh_stream_layout
+    {
+        VertexStreamLayout::Interleaved => 0,
+        VertexStreamLayout::SeparatePosition => 1
+    });
+    entry.write_u32(bounds_flags);
+    entry.write_u32(mesh.mesh_morph_targets.len() as u32);
+    entry.write_u64(channel_table_offset as u64);
+    entry.write_u64(section_offset as u64);
+    entry.write_u64(index_offset as u64);
+    entry.write_u64(morph_table_offset as u64);
+    write_bounds(&mut entry, bounds);
+    writer.write_at(lod_entry_offset, &entry.data);
+    Ok(())
+}
+
+fn write_gpu_data(writer: &mut CacheWriter, gpu_data: &StaticMeshGpuData) -> usize
+{
+    let gpu_offset = writer.reserve(GPU_HEADER_SIZE);
+    for (stride, size) in gpu_data.strides.iter().zip(&gpu_data.stream_sizes)
+    {
+        writer.write_u32(*stride);
+        writer.write_u32(*size);
+    }
+    writer.align();
+    for lod_range in &gpu_data.lod_ranges
+    {
+        writer.write_u32(lod_range.first_index);
+        writer.write_u32(lod_range.index_count);
+        writer.write_i32(lod_range.base_vertex);
+        writer.write_u32(lod_range.vertex_count);
+    }
+    writer.align();
+    let vertex_data_offset = writer.data.len();
+    writer.write_bytes(&gpu_data.vertex_data);
+    writer.align();
+    let index_data_offset = writer.data.len();
+    writer.write_bytes(&gpu_data.index_data);
+
+    let mut header = CacheWriter::default();
+    header.write_u32(gpu_data.strides.len() as u32);
+    header.write_u32(gpu_data.index_stride);
+    header.write_u32(gpu_data.lod_ranges.len() as u32);
+    header.write_u32(0);
+    header.write_u64(vertex_data_offset as u64);
+    header.write_u64(gpu_data.vertex_data.len() as u64);
+    header.write_u64(index_data_offset as u64);
+    header.write_u64(gpu_data.index_data.len() as u64);
+    writer.write_at(gpu_offset, &header.data);
+    gpu_offset
+}
+
+fn write_mesh_cache_data(lods: &[(&Mesh, f32)], metadata: &MeshCacheMetadata, gpu_data: Option<&StaticMeshGpuData>, source_hash: u64) -> Result<Vec<u8>, ImportError>
+{
+    let mut writer = CacheWriter::default();
+    writer.reserve(HEADER_SIZE);
+    let lod_table_offset = writer.reserve(lods.len() * LOD_ENTRY_SIZE);
+    for (lod, (mesh, error)) in lods.iter().enumerate()
+    {
+        write_lod(&mut writer, lod_table_offset + lod * LOD_ENTRY_SIZE, mesh, *error)?;
+    }
+
+    writer.align();
+    let metadata_offset = writer.data.len();
+    writer.write_bytes(&serde_json::to_vec(metadata)?);
+    let metadata_size = writer.data.len() - metadata_offset;
+    let gpu_offset = gpu_data.map(|gpu_data| write_gpu_data(&mut writer, gpu_data)).unwrap_or(0);
+    writer.align();
+
+    let mut header = CacheWriter::default();
+    header.write_bytes(&MESH_CACHE_MAGIC);
+    header.write_u32(MESH_CACHE_VERSION);
+    header.write_u32(lods.len() as u32);
+    header.write_u64(source_hash);
+    header.write_u64(get_mesh_cache_hash(&writer.data[HEADER_SIZE..]));
+    header.write_u64(writer.data.len() as u64);
+    header.write_u64(metadata_offset as u64);
+    header.write_u64(metadata_size as u64);
+    header.write_u64(gpu_offset as u64);
+    writer.write_at(0, &header.data);
+    Ok(writer.data)
+}
+
+/// Writes a mesh as a cache with a single level of detail. source_hash identifies the asset and settings the mesh was
+/// cooked from, compare it with read_mesh_cache_source_hash to decide whether the cache is stale.
+pub fn write_mesh_cache<W: Write>(mesh: &Mesh, source_hash: u64, mut writer: W) -> Result<(), ImportError>
+{
+    writer.write_all(&write_mesh_cache_data(&[(mesh, 0.0)], &MeshCacheMetadata::default(), None, source_hash)?)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn save_mesh_cache(mesh: &Mesh, source_hash: u64, path: impl AsRef<Path>) -> Result<(), ImportError>
+{
+    write_mesh_cache(mesh, source_hash, BufWriter::new(File::create(path)?))
+}
+
+/// Writes every level of detail, the materials and the packed GPU buffers of a static mesh.
+pub fn write_static_mesh_cache<W: Write>(static_mesh: &StaticMesh, source_hash: u64, mut writer: W) -> Result<(), ImportError>
+{
+    let lods: Vec<(&Mesh, f32)> = (0..static_mesh.get_lod_count())
+        .map(|lod| (static_mesh.get_lod_mesh(lod), static_mesh.get_lod_error(lod)))
+        .collect();
+    let metadata = MeshCacheMetadata
+    {
+        name: static_mesh.get_name().to_string(),
+        materials: static_mesh.get_materials().clone(),
+        morph_weights: static_mesh.get_morph_weights().clone()
+    };
+    let gpu_data = static_mesh.get_gpu_data()?;
+    writer.write_all(&write_mesh_cache_data(&lods, &metadata, Some(&gpu_data), source_hash)?)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn save_static_mesh_cache(static_mesh: &StaticMesh, source_hash: u64, path: impl AsRef<Path>) -> Result<(), ImportError>
+{
+    write_static_mesh_cache(static_mesh, source_hash, BufWriter::new(File::create(path)?))
+}
+
+fn check_header(header: &[u8]) -> Result<(), ImportError>
+{
+    if header.len() < HEADER_SIZE || header[..8] != MESH_CACHE_MAGIC
+    {
+        return Err(invalid_cache("not a mesh cache file"));
+    }
+    let version = CacheReader::new(header, 8).read_u32()?;
+    if version != MESH_CACHE_VERSION
+    {
+        return Err(ImportError::CacheVersion { found: version, expected: MESH_CACHE_VERSION });
+    }
+    Ok(())
+}
+
+/// Reads only the header, for deciding whether a cache has to be cooked again without loading it.
+pub fn read_mesh_cache_source_hash(path: impl AsRef<Path>) -> Result<u64, ImportError>
+{
+    let mut header = [0; HEADER_SIZE];
+    File::open(path)?.read_exact(&mut header)?;
+    check_header(&header)?;
+    CacheReader::new(&header, 16).read_u64()
+}
+
+/// A cache file read or mapped into memory. Only get_gpu_data borrows its buffers from the file without copying,
+/// get_lod decodes the indices and channel values of a level one by one into a new Mesh.
+pub struct MeshCacheView<'a>
+{
+    data: &'a [u8],
+    lod_count: usize,
+    source_hash: u64,
+    metadata_offset: u64,
+    metadata_size: u64,
+    gpu_offset: u64
+}
+
+impl<'a> MeshCacheView<'a>
+{
+    /// Checks the magic, the version and the content hash.
+    pub fn new(data: &'a [u8]) -> Result<Self, ImportError>
+    {
+        check_header(data)?;
+        let mut reader = CacheReader::new(data, 12);
+        let lod_count = reader.read_u32()? as usize;
+        let source_hash = reader.read_u64()?;
+        let content_hash = reader.read_u64()?;
+        let file_size = reader.read_u64()?;
+        if file_size != data.len() as u64 || content_hash != get_mesh_cache_hash(&data[HEADER_SIZE..])
+        {
+            return Err(invalid_cache("content hash does not match, the file is truncated or corrupt"));
+        }
+        Ok(MeshCacheView
+        {
+            data,
+            lod_count,
+            source_hash,
+            metadata_offset: reader.read_u64()?,
+            metadata_size: reader.read_u64()?,
+            gpu_offset: reader.read_u64()?
+        })
+    }
+
+    pub fn get_source_hash(&self) -> u64
+    {
+        self.source_hash
+    }
+
+    pub fn get_lod_count(&self) -> usize
+    {
+        self.lod_count
+    }
+
+    pub fn get_lod(&self, lod: usize) -> Result<MeshLod, ImportError>
+    {
+        if lod >= self.lod_count
+        {
+            return Err(invalid_cache(format!("level of detail {} does not exist", lod)));
+        }
+        let mut reader = CacheReader::new(self.data, HEADER_SIZE + lod * LOD_ENTRY_SIZE);
+        let error = reader.read_f32()?;
+        let vertex_count = reader.read_u32()? as u64;
+        let index_count = reader.read_u32()? as u64;
+        let channel_count = reader.read_u32()? as usize;
+        let section_count = reader.read_u32()? as usize;
+        let stream_layout = match reader.read_u32()?
+        {
+            0 => VertexStreamLayout::Interleaved,
+            1 => VertexStreamLayout::SeparatePosition,
+            value => return Err(invalid_cache(format!("unknown vertex stream layout {}", value)))
+        };
+        let bounds_flags = reader.read_u32()?;
+        let morph_target_count = reader.read_u32()? as usize;
+        let channel_table_offset = reader.read_u64()? as usize;
+        let section_offset = reader.read_u64()? as usize;
+        let index_offset = reader.read_u64()?;
+        let morph_table_offset = reader.read_u64()? as usize;
+
+        let mut mesh = Mesh
+        {
+            mesh_index_data: get_cache_slice(self.data, index_offset, index_count * 4)?
+                .chunks_exact(4)
+                .map(|index| u32::from_le_bytes(index.try_into().unwrap()))
+                .collect(),
+            mesh_stream_layout: stream_layout,
+            mesh_bounds: read_bounds(&mut reader, bounds_flags)?,
+            mesh_topology: if bounds_flags & LOD_FLAG_POINT_LIST != 0 { MeshPrimitiveTopology::PointList } else { MeshPrimitiveTopology::TriangleList },
+            ..Default::default()
+        };
+
+        let mut reader = CacheReader::new(self.data, section_offset);
+        for _ in 0..section_count
+        {
+            let first_index = reader.read_u32()?;
+            let index_count = reader.read_u32()?;
+            let base_vertex = reader.read_i32()?;
+            let material_slot = reader.read_u32()?;
+            let material_slot = if material_slot == NO_MATERIAL_SLOT { None } else { Some(material_slot as usize) };
+            mesh.add_section(MeshSection { first_index, index_count, base_vertex, material_slot });
+        }
+
+        for channel_index in 0..channel_count
+        {
+            let mut reader = CacheReader::new(self.data, channel_table_offset + channel_index * CHANNEL_ENTRY_SIZE);
+            let name = reader.read_bytes(SEMANTIC_NAME_SIZE)?;
+            let name = std::str::from_utf8(&name[..name.iter().position(|byte| *byte == 0).unwrap_or(SEMANTIC_NAME_SIZE)])
+                .map_err(|_| invalid_cache("semantic name is not utf-8"))?;
+            let semantic_index = reader.read_u32()?;
+            let component_count = reader.read_u32()? as usize;
+            let format = get_vertex_format_from_id(reader.read_u32()?)?;
+            reader.read_u32()?;
+            let mut default_value = [0.0; 4];
+            for value in default_value.iter_mut()
+            {
+                *value = reader.read_f32()?;
+            }
+            let data_offset = reader.read_u64()?;
+            let value_count = reader.read_u64()?;
+            if component_count == 0 || component_count > 4 || value_count != vertex_count * component_count as u64
+            {
+                return Err(invalid_cache(format!("channel {}{} does not match the vertex count", name, semantic_index)));
+            }
+
+            // Channels registered at runtime can have other ids than when the cache was written.
+            let channel = match find_mesh_channel(name, semantic_index)
+            {
+                Some(channel) => channel,
+                None => register_mesh_channel(name, semantic_index, &default_value[..component_count])?
+            };
+            if get_channel_default_value(channel).len() != component_count
+            {
+                return Err(invalid_cache(format!("channel {}{} has {} components in the cache", name, semantic_index, component_count)));
+            }
+            let values = get_cache_slice(self.data, data_offset, value_count * 4)?
+                .chunks_exact(4)
+                .map(|value| f32::from_le_bytes(value.try_into().unwrap()))
+                .collect();
+            mesh.mesh_channel_data.insert(channel, values);
+            if let Some(format) = format
+            {
+                mesh.set_channel_format(channel, format)?;
+            }
+        }
+
+        for target_index in 0..morph_target_count
+        {
+            mesh.add_morph_target(read_morph_target(self.data, morph_table_offset + target_index * MORPH_ENTRY_SIZE)?)?;
+        }
+        // The content hash only catches damaged files, not files written from invalid meshes.
+        mesh.validate_structure()?;
+        Ok(MeshLod { mesh, error })
+    }
+
+    pub fn get_static_mesh(&self) -> Result<StaticMesh, ImportError>
+    {
+        let metadata: MeshCacheMetadata = serde_json::from_slice(get_cache_slice(self.data, self.metadata_offset, self.metadata_size)?)?;
+        let mut static_mesh = StaticMesh::new(&metadata.name);
+        *static_mesh.get_mesh_mut() = self.get_lod(0)?.mesh;
+        for lod in 1..self.lod_count
+        {
+            static_mesh.add_lod(self.get_lod(lod)?);
+        }
+        for material in metadata.materials
+        {
+            static_mesh.add_material(material);
+        }
+        static_mesh.set_morph_weights(metadata.morph_weights);
+        Ok(static_mesh)
+    }
+
+    /// Packed buffers borrowed from the file for StaticMesh::upload_gpu_data, None for caches of a plain Mesh.
+    pub fn get_gpu_data(&self) -> Result<Option<StaticMeshGpuData<'a>>, ImportError>
+    {
+        if self.gpu_offset == 0
+        {
+            return Ok(None);
+        }
+        let mut reader = CacheReader::new(self.data, self.gpu_offset as usize);
+        let stream_count = reader.read_u32()? as usize;
+        let index_stride = reader.read_u32()?;
+        let lod_range_count = reader.read_u32()? as usize;
+        reader.read_u32()?;
+        let vertex_data = get_cache_slice(self.data, reader.read_u64()?, reader.read_u64()?)?;
+        let index_data = get_cache_slice(self.data, reader.read_u64()?, reader.read_u64()?)?;
+        if lod_range_count != self.lod_count || (index_stride != 2 && index_stride != 4)
+        {
+            return Err(invalid_cache("gpu buffers do not match the levels of detail"));
+        }
+
+        let mut gpu_data = StaticMeshGpuData { vertex_data: Cow::Borrowed(vertex_data), index_data: Cow::Borrowed(index_data), index_stride, ..Default::default() };
+        let mut reader = CacheReader::new(self.data, self.gpu_offset as usize + GPU_HEADER_SIZE);
+        for _ in 0..stream_count
+        {
+            gpu_data.strides.push(reader.read_u32()?);
+            gpu_data.stream_sizes.push(reader.read_u32()?);
+        }
+        let lod_range_offset = self.gpu_offset as usize + GPU_HEADER_SIZE + stream_count * STREAM_ENTRY_SIZE;
+        let mut reader = CacheReader::new(self.data, lod_range_offset.div_ceil(MESH_CACHE_ALIGNMENT) * MESH_CACHE_ALIGNMENT);
+        for _ in 0..lod_range_count
+        {
+            gpu_data.lod_ranges.push(StaticMeshLodRange
+            {
+                first_index: reader.read_u32()?,
+                index_count: reader.read_u32()?,
+                base_vertex: reader.read_i32()?,
+                vertex_count: reader.read_u32()?
+            });
+        }
+        if gpu_data.stream_sizes.iter().map(|size| *size as usize).sum::<usize>() != vertex_data.len()
+        {
+            return Err(invalid_cache("vertex stream sizes do not match the vertex data"));
+        }
+        Ok(Some(gpu_data))
+    }
+}
+
+/// Loads the base mesh of a cache file.
+pub fn load_mesh_cache(path: impl AsRef<Path>) -> Result<Mesh, ImportError>
+{
+    let data = std::fs::read(path)?;
+    Ok(MeshCacheView::new(&data)?.get_lod(0)?.mesh)
+}
+
+/// Loads the CPU side of a static mesh. To upload without packing the vertices again, keep the file data and pass
+/// MeshCacheView::get_gpu_data to StaticMesh::upload_gpu_data.
+pub fn load_static_mesh_cache(path: impl AsRef<Path>) -> Result<StaticMesh, ImportError>
+{
+    let data = std::fs::read(path)?;
+    MeshCacheView::new(&data)?.get_static_mesh()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // Two sections with their own materials, a morph target moving the upper half and two reduced levels.
+    fn create_static_mesh() -> StaticMesh
+    {
+        let mut static_mesh = StaticMesh::new("sphere");
+        let mut mesh = Mesh::create_uv_sphere(1.0, 16, 8);
+        let index_count = mesh.mesh_index_data.len() as u32;
+        let half = index_count / 6 * 3;
+        mesh.mesh_sections = vec![
+            MeshSection { first_index: 0, index_count: half, base_vertex: 0, material_slot: Some(0) },
+            MeshSection { first_index: half, index_count: index_count - half, base_vertex: 0, material_slot: Some(1) }
+        ];
+        let deltas: Vec<f32> = (0..mesh.get_vertex_count()).flat_map(|vertex| [0.0, mesh.get_position(vertex).y.max(0.0), 0.0]).collect();
+        mesh.add_morph_target(MorphTarget::from_dense("stretch", &deltas, &[], &[], 0.0)).unwrap();
+        *static_mesh.get_mesh_mut() = mesh;
+        for name in ["top", "bottom"]
+        {
+            static_mesh.add_material(MeshMaterial { name: name.to_string(), ..Default::default() });
+        }
+        static_mesh.set_morph_weights(vec![0.25]);
+        static_mesh.generate_lods(3, 0.5);
+        assert_eq!(static_mesh.get_lod_count(), 3);
+        static_mesh
+    }
+
+    fn write_static_mesh(static_mesh: &StaticMesh) -> Vec<u8>
+    {
+        let mut data = vec![];
+        write_static_mesh_cache(static_mesh, 42, &mut data).unwrap();
+        data
+    }
+
+    fn round_trip(mesh: &Mesh) -> Mesh
+    {
+        let mut data = vec![];
+        write_mesh_cache(mesh, 7, &mut data).unwrap();
+        let view = MeshCacheView::new(&data).unwrap();
+        assert_eq!(view.get_source_hash(), 7);
+        view.get_lod(0).unwrap().mesh
+    }
+
+    #[test]
+    fn lod_round_trips_with_its_layout_bounds_and_topology()
+    {
+        let mut mesh = Mesh::create_box(Vector3::new(1.0, 2.0, 3.0), 1);
+        mesh.update_bounds(true);
+        mesh.set_vertex_stream_layout(VertexStreamLayout::SeparatePosition);
+        let loaded = round_trip(&mesh);
+        assert_eq!(loaded.mesh_channel_data, mesh.mesh_channel_data);
+        assert_eq!(loaded.mesh_index_data, mesh.mesh_index_data);
+        assert_eq!(loaded.get_sections(), mesh.get_sections());
+        assert_eq!(loaded.mesh_stream_layout, VertexStreamLayout::SeparatePosition);
+        assert_eq!(loaded.mesh_bounds, mesh.mesh_bounds);
+        assert_eq!(loaded.mesh_topology, MeshPrimitiveTopology::TriangleList);
+
+        let mut points = Mesh { mesh_topology: MeshPrimitiveTopology::PointList, ..Default::default() };
+        points.mesh_channel_data.insert(MeshDataChannel::Position as usize, vec![0.0, 0.0, 0.0, 1.0, 2.0, 3.0]);
+        let loaded = round_trip(&points);
+        assert_eq!(loaded.mesh_topology, MeshPrimitiveTopology::PointList);
+        assert_eq!(loaded.mesh_bounds, None);
+    }
+    #[test]
+    fn static_mesh_round_trips_with_lods_materials_and_morph_weights()
+    {
+        let static_mesh = create_static_mesh();
+        let data = write_static_mesh(&static_mesh);
+        let view = MeshCacheView::new(&data).unwrap();
+        assert_eq!((view.get_source_hash(), view.get_lod_count()), (42, 3));
+        let loaded = view.get_static_mesh().unwrap();
+        assert_eq!(loaded.get_name(), "sphere");
+        assert_eq!(loaded.get_materials(), static_mesh.get_materials());
+        assert_eq!(loaded.get_morph_weights(), &vec![0.25]);
+        assert_eq!(loaded.get_lod_count(), 3);
+        for lod in 0..3
+        {
+            let (mesh, loaded_mesh) = (static_mesh.get_lod_mesh(lod), loaded.get_lod_mesh(lod));
+            assert_eq!(loaded.get_lod_error(lod), static_mesh.get_lod_error(lod));
+            assert_eq!(loaded_mesh.mesh_channel_data, mesh.mesh_channel_data);
+            assert_eq!(loaded_mesh.mesh_index_data, mesh.mesh_index_data);
+            assert_eq!(loaded_mesh.get_sections(), mesh.get_sections());
+            assert_eq!(loaded_mesh.mesh_morph_targets, mesh.mesh_morph_targets);
+        }
+        assert_eq!(loaded.get_morphed_mesh().mesh_channel_data, static_mesh.get_morphed_mesh().mesh_channel_data);
+    }
+
+    #[test]
+    fn gpu_data_is_borrowed_from_the_file()
+    {
+        let static_mesh = create_static_mesh();
+        let data = write_static_mesh(&static_mesh);
+        let gpu_data = MeshCacheView::new(&data).unwrap().get_gpu_data().unwrap().unwrap();
+        let expected = static_mesh.get_gpu_data().unwrap();
+        for buffer in [&gpu_data.vertex_data, &gpu_data.index_data]
+        {
+            assert!(matches!(buffer, Cow::Borrowed(_)));
+            assert!(data.as_ptr_range().contains(&buffer.as_ptr()));
+            assert_eq!(buffer.as_ptr() as usize % MESH_CACHE_ALIGNMENT, data.as_ptr() as usize % MESH_CACHE_ALIGNMENT);
+        }
+        assert_eq!(gpu_data.vertex_data, expected.vertex_data);
+        assert_eq!(gpu_data.index_data, expected.index_data);
+        assert_eq!((&gpu_data.stream_sizes, &gpu_data.strides, gpu_data.index_stride), (&expected.stream_sizes, &expected.strides, expected.index_stride));
+        assert_eq!(gpu_data.lod_ranges, expected.lod_ranges);
+
+        // Caches of a plain mesh have no gpu block.
+        let mut plain = vec![];
+        write_mesh_cache(static_mesh.get_mesh(), 42, &mut plain).unwrap();
+        assert!(MeshCacheView::new(&plain).unwrap().get_gpu_data().unwrap().is_none());
+    }
+
+    #[test]
+    fn saved_files_report_their_source_hash()
+    {
+        let path = std::env::temp_dir().join(format!("rustdx_mesh_cache_{}.{}", std::process::id(), MESH_CACHE_EXTENSION));
+        save_static_mesh_cache(&create_static_mesh(), 42, &path).unwrap();
+        assert_eq!(read_mesh_cache_source_hash(&path).unwrap(), 42);
+        assert_eq!(load_static_mesh_cache(&path).unwrap().get_lod_count(), 3);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn damaged_files_are_rejected()
+    {
+        let data = write_static_mesh(&create_static_mesh());
+        let is_invalid = |data: &[u8]| matches!(MeshCacheView::new(data), Err(ImportError::InvalidData { .. }));
+        assert!(is_invalid(&data[..data.len() - MESH_CACHE_ALIGNMENT]));
+        assert!(is_invalid(&data[..HEADER_SIZE - 1]));
+
+        let mut wrong_magic = data.clone();
+        wrong_magic[0] = b'X';
+        assert!(is_invalid(&wrong_magic));
+
+        let mut wrong_version = data.clone();
+        wrong_version[8..12].copy_from_slice(&(MESH_CACHE_VERSION + 1).to_le_bytes());
+        assert!(matches!(MeshCacheView::new(&wrong_version), Err(ImportError::CacheVersion { found, expected: MESH_CACHE_VERSION }) if found == MESH_CACHE_VERSION + 1));
+
+        let mut corrupt = data.clone();
+        corrupt[data.len() / 2] ^= 1;
+        assert!(is_invalid(&corrupt));
+    }
+
+    #[test]
+    fn invalid_meshes_with_a_matching_hash_are_rejected()
+    {
+        let mut data = vec![];
+        write_mesh_cache(&Mesh::create_box(Vector3::new(1.0, 1.0, 1.0), 1), 0, &mut data).unwrap();
+        let index_offset = CacheReader::new(&data, HEADER_SIZE + 48).read_u64().unwrap() as usize;
+        data[index_offset..index_offset + 4].copy_from_slice(&10_000u32.to_le_bytes());
+        // The content hash is stored again, so only the validation of the level can catch the index.
+        let content_hash = get_mesh_cache_hash(&data[HEADER_SIZE..]);
+        data[24..32].copy_from_slice(&content_hash.to_le_bytes());
+        let view = MeshCacheView::new(&data).unwrap();
+        assert!(matches!(view.get_lod(0), Err(ImportError::Mesh(MeshError::IndexOutOfRange { .. }))));
+    }
+}
@@ -0,0 +1,39 @@
+pub mod obj_importer;
+pub mod gltf_importer;
+pub mod ply;
+pub mod mesh_cache;
+pub mod scene_file;
+
+use thiserror::Error;
+
+use crate::scene::mesh::validation::MeshError;
+use crate::scene::scene::SceneError;
+
+#[derive(Debug, Error)]
+pub enum ImportError
+{
+    #[error("cannot read asset: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("cannot load obj: {0}")]
+    Obj(#[from] tobj::LoadError),
+    #[error("cannot parse json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid {format} data: {message}")]
+    InvalidData { format: &'static str, message: String },
+    #[error("mesh cache version {found} is not the supported version {expected}")]
+    CacheVersion { found: u32, expected: u32 },
+    #[error("scene file version {found} is newer than the supported version {supported}")]
+    SceneVersion { found: u32, supported: u32 },
+    #[error("imported mesh is invalid: {0}")]
+    Mesh(#[from] MeshError),
+    #[error("cannot build scene: {0}")]
+    Scene(#[from] SceneError)
+}
+
+impl ImportError
+{
+    pub fn invalid_data(format: &'static str, message: impl Into<String>) -> Self
+    {
+        ImportError::InvalidData { format, message: message.into() }
+    }
+}
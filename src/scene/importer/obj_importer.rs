@@ -0,0 +1,294 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use log::warn;
+
+use crate::scene::importer::ImportError;
+use crate::scene::mesh::*;
+use crate::scene::mesh::normals::*;
+use crate::scene::mesh_material::*;
+use crate::scene::static_mesh::*;
+
+fn load_obj_data(path: &Path) -> Result<(Vec<tobj::Model>, Vec<tobj::Material>), ImportError>
+{
+    let load_options = tobj::LoadOptions
+    {
+        // OBJ indexes positions, normals and texcoords separately, a single index duplicates
+        // vertices where the attribute tuples differ so that one index buffer addresses all channels.
+        single_index: true,
+        triangulate: true,
+        ignore_points: true,
+        ignore_lines: true
+    };
+    let (models, materials) = tobj::load_obj(path, &load_options)?;
+    let materials = materials.unwrap_or_else(|err| {
+        warn!("cannot load materials of {:?}: {}", path, err);
+        vec![]
+    });
+    Ok((models, materials))
+}
+
+fn convert_obj_material(material: &tobj::Material, base_dir: &Path) -> MeshMaterial
+{
+    let texture_path = |name: &String| {
+        if name.is_empty() { None } else { Some(base_dir.join(name)) }
+    };
+    MeshMaterial
+    {
+        name: material.name.clone(),
+        base_color: [material.diffuse[0], material.diffuse[1], material.diffuse[2], material.dissolve],
+        base_color_texture: texture_path(&material.diffuse_texture),
+        normal_texture: texture_path(&material.normal_texture)
+    }
+}
+
+// Smooth normals of one object, for objects without normals in files where other objects have them.
+fn generate_object_normals(mesh: &tobj::Mesh) -> Vec<f32>
+{
+    let mut object = Mesh::default();
+    object.mesh_channel_data.insert(MeshDataChannel::Position as usize, mesh.positions.clone());
+    object.mesh_index_data = mesh.indices.clone();
+    object.generate_normals(NormalGenerationMode::Smooth(NormalWeighting::Angle));
+
+    // Smooth normals never split vertices, so every corner of a vertex carries the same normal.
+    let generated = &object.mesh_channel_data[&(MeshDataChannel::Normal as usize)];
+    let mut normals = get_channel_default_value(MeshDataChannel::Normal as usize).repeat(mesh.positions.len() / 3);
+    for (vertex, generated_vertex) in mesh.indices.iter().zip(&object.mesh_index_data)
+    {
+        let (vertex, generated_vertex) = (*vertex as usize, *generated_vertex as usize);
+        normals[vertex * 3..vertex * 3 + 3].copy_from_slice(&generated[generated_vertex * 3..generated_vertex * 3 + 3]);
+    }
+    normals
+}
+
+fn convert_obj_models(name: &str, models: &[&tobj::Model], materials: &[tobj::Material], base_dir: &Path) -> Result<StaticMesh, ImportError>
+{
+    let has_normals = models.iter().any(|model| !model.mesh.normals.is_empty());
+    let has_uvs = models.iter().any(|model| !model.mesh.texcoords.is_empty());
+    // Only vertex colors fill the Color channel, material colors stay in MeshMaterial::base_color.
+    let has_colors = models.iter().any(|model| !model.mesh.vertex_color.is_empty());
+
+    // Models sharing a material are concatenated so that every material ends up as one contiguous index range.
+    let mut models_by_material: BTreeMap<Option<usize>, Vec<&tobj::Model>> = BTreeMap::new();
+    for model in models
+    {
+        let material_id = model.mesh.material_id.filter(|id| *id < materials.len());
+        models_by_material.entry(material_id).or_default().push(model);
+    }
+
+    let mut static_mesh = StaticMesh::new(name);
+    let mut positions = vec![];
+    let mut normals = vec![];
+    let mut uvs = vec![];
+    let mut colors = vec![];
+    let mut indices = vec![];
+
+    for (material_id, material_models) in &models_by_material
+    {
+        let material_index = material_id.map(|id| static_mesh.add_material(convert_obj_material(&materials[id], base_dir)));
+
+        let first_index = indices.len() as u32;
+        for model in material_models
+        {
+            let mesh = &model.mesh;
+            let base_vertex = (positions.len() / 3) as u32;
+            let vertex_count = mesh.positions.len() / 3;
+
+            positions.extend_from_slice(&mesh.positions);
+            if has_normals
+            {
+                if mesh.normals.len() == vertex_count * 3
+                {
+                    normals.extend_from_slice(&mesh.normals);
+                }
+                else
+                {
+                    normals.extend(generate_object_normals(mesh));
+                }
+            }
+            if has_uvs
+            {
+                if mesh.texcoords.len() == vertex_count * 2
+                {
+                    // OBJ texture space has its origin at the bottom left, D3D samples from the top left.
+                    for uv in mesh.texcoords.chunks_exact(2)
+                    {
+                        uvs.push(uv[0]);
+                        uvs.push(1.0 - uv[1]);
+                    }
+                }
+                else
+                {
+                    for _ in 0..vertex_count
+                    {
+                        uvs.extend_from_slice(get_channel_default_value(MeshDataChannel::UV0 as usize));
+                    }
+                }
+            }
+            if has_colors
+            {
+                if mesh.vertex_color.len() == vertex_count * 3
+                {
+                    for color in mesh.vertex_color.chunks_exact(3)
+                    {
+                        colors.extend_from_slice(&[color[0], color[1], color[2], 1.0]);
+                    }
+                }
+                else
+                {
+                    // White leaves the material color unchanged where objects without vertex colors are tinted.
+                    colors.extend([1.0; 4].repeat(vertex_count));
+                }
+            }
+
+            indices.extend(mesh.indices.iter().map(|index| index + base_vertex));
+        }
+
+        static_mesh.add_section(MeshSection
+        {
+            first_index,
+            index_count: indices.len() as u32 - first_index,
+            base_vertex: 0,
+            material_slot: material_index
+        });
+    }
+
+    if positions.is_empty()
+    {
+        return Err(ImportError::invalid_data("obj", format!("{} contains no geometry", name)));
+    }
+
+    static_mesh.add_channel_data(MeshDataChannel::Position, positions);
+    if has_normals
+    {
+        static_mesh.add_channel_data(MeshDataChannel::Normal, normals);
+    }
+    if has_uvs
+    {
+        static_mesh.add_channel_data(MeshDataChannel::UV0, uvs);
+    }
+    if has_colors
+    {
+        static_mesh.add_channel_data(MeshDataChannel::Color, colors);
+    }
+    static_mesh.set_index_buffer(indices);
+    static_mesh.get_mesh().validate_structure()?;
+    if !has_normals
+    {
+        static_mesh.get_mesh_mut().generate_normals(NormalGenerationMode::Smooth(NormalWeighting::Angle));
+    }
+    // OBJ files are right handed by convention.
+    static_mesh.get_mesh_mut().flip_handedness();
+    static_mesh.get_mesh_mut().update_bounds(false);
+
+    Ok(static_mesh)
+}
+
+fn get_base_dir(path: &Path) -> &Path
+{
+    path.parent().unwrap_or_else(|| Path::new(""))
+}
+
+/// Loads every object of an OBJ file and its MTL materials into a single StaticMesh with one section per material.
+/// The right handed OBJ data is mirrored along z into the left handed engine space, see Mesh::flip_handedness.
+pub fn load_obj_static_mesh(path: impl AsRef<Path>) -> Result<StaticMesh, ImportError>
+{
+    let path = path.as_ref();
+    let (models, materials) = load_obj_data(path)?;
+    let name = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("obj");
+    let model_refs: Vec<&tobj::Model> = models.iter().collect();
+    convert_obj_models(name, &model_refs, &materials, get_base_dir(path))
+}
+
+/// Loads an OBJ file into one StaticMesh per object or group, each with one section per material.
+pub fn load_obj_static_meshes(path: impl AsRef<Path>) -> Result<Vec<StaticMesh>, ImportError>
+{
+    let path = path.as_ref();
+    let (models, materials) = load_obj_data(path)?;
+
+    // tobj splits an object into several models when its material changes, merge them back by name.
+    let mut models_by_name: Vec<(&str, Vec<&tobj::Model>)> = vec![];
+    for model in &models
+    {
+        match models_by_name.iter_mut().find(|(name, _)| *name == model.name)
+        {
+            Some((_, object_models)) => object_models.push(model),
+            None => models_by_name.push((&model.name, vec![model]))
+        }
+    }
+
+    let mut static_meshes = vec![];
+    for (name, object_models) in &models_by_name
+    {
+        static_meshes.push(convert_obj_models(name, object_models, &materials, get_base_dir(path))?);
+    }
+    Ok(static_meshes)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // The first object has normals along x, the second none, its triangle faces +y.
+    const PARTIAL_NORMALS_OBJ: &str = "o lit\nv 0 5 0\nv 0 6 0\nv 0 5 1\nvn 1 0 0\nf 1//1 2//1 3//1\n\
+        o unlit\nv 0 0 0\nv 0 0 1\nv 1 0 0\nf 4 5 6\n";
+
+    fn load_obj_text(name: &str, text: &str, mtl: Option<&str>) -> StaticMesh
+    {
+        let dir = std::env::temp_dir().join(format!("rustdx_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        if let Some(mtl) = mtl
+        {
+            std::fs::write(dir.join("materials.mtl"), mtl).unwrap();
+        }
+        std::fs::write(dir.join("mesh.obj"), text).unwrap();
+        let static_mesh = load_obj_static_mesh(dir.join("mesh.obj"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        static_mesh.unwrap()
+    }
+
+    fn get_normals(static_mesh: &StaticMesh) -> Vec<[f32; 3]>
+    {
+        static_mesh.get_mesh().mesh_channel_data[&(MeshDataChannel::Normal as usize)].chunks_exact(3).map(|normal| [normal[0], normal[1], normal[2]]).collect()
+    }
+
+    #[test]
+    fn objects_without_normals_get_generated_normals()
+    {
+        let path = std::env::temp_dir().join(format!("rustdx_partial_normals_{}.obj", std::process::id()));
+        std::fs::write(&path, PARTIAL_NORMALS_OBJ).unwrap();
+        let static_mesh = load_obj_static_mesh(&path);
+        let static_meshes = load_obj_static_meshes(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let static_mesh = static_mesh.unwrap();
+        for (vertex, normal) in get_normals(&static_mesh).iter().enumerate()
+        {
+            let expected = if static_mesh.get_mesh().get_position(vertex).y > 1.0 { [1.0, 0.0, 0.0] } else { [0.0, 1.0, 0.0] };
+            assert_eq!(*normal, expected, "vertex {}", vertex);
+        }
+        let static_meshes = static_meshes.unwrap();
+        assert_eq!(static_meshes[1].get_name(), "unlit");
+        assert!(get_normals(&static_meshes[1]).iter().all(|normal| *normal == [0.0, 1.0, 0.0]));
+    }
+
+    #[test]
+    fn material_colors_stay_in_the_material()
+    {
+        let mtl = "newmtl red\nKd 1 0 0\nd 0.5\n";
+        let static_mesh = load_obj_text("material_color", "mtllib materials.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\n", Some(mtl));
+        assert_eq!(static_mesh.get_materials()[0].base_color, [1.0, 0.0, 0.0, 0.5]);
+        assert!(!static_mesh.get_mesh().mesh_channel_data.contains_key(&(MeshDataChannel::Color as usize)));
+    }
+
+    #[test]
+    fn vertex_colors_fill_the_color_channel()
+    {
+        let static_mesh = load_obj_text("vertex_color", "v 0 0 0 1 0 0\nv 1 0 0 0 1 0\nv 0 1 0 0 0 1\nf 1 2 3\n", None);
+        let mut colors: Vec<&[f32]> = static_mesh.get_mesh().mesh_channel_data[&(MeshDataChannel::Color as usize)].chunks_exact(4).collect();
+        colors.sort_by(|a, b| b.partial_cmp(a).unwrap());
+        assert_eq!(colors, vec![[1.0, 0.0, 0.0, 1.0], [0.0, 1.0, 0.0, 1.0], [0.0, 0.0, 1.0, 1.0]]);
+    }
+}
+
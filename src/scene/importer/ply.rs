@@ -0,0 +1,615 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::scene::importer::ImportError;
+use crate::scene::mesh::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlyFormat
+{
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PlyScalarType
+{
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64
+}
+
+impl PlyScalarType
+{
+    fn parse(name: &str) -> Result<Self, ImportError>
+    {
+        match name
+        {
+            "char" | "int8" => Ok(PlyScalarType::Int8),
+            "uchar" | "uint8" => Ok(PlyScalarType::UInt8),
+            "short" | "int16" => Ok(PlyScalarType::Int16),
+            "ushort" | "uint16" => Ok(PlyScalarType::UInt16),
+            "int" | "int32" => Ok(PlyScalarType::Int32),
+            "uint" | "uint32" => Ok(PlyScalarType::UInt32),
+            "float" | "float32" => Ok(PlyScalarType::Float32),
+            "double" | "float64" => Ok(PlyScalarType::Float64),
+            _ => Err(ImportError::invalid_data("ply", format!("unknown property type {}", name)))
+        }
+    }
+
+    fn get_size(&self) -> usize
+    {
+        match self
+        {
+            PlyScalarType::Int8 | PlyScalarType::UInt8 => 1,
+            PlyScalarType::Int16 | PlyScalarType::UInt16 => 2,
+            PlyScalarType::Int32 | PlyScalarType::UInt32 | PlyScalarType::Float32 => 4,
+            PlyScalarType::Float64 => 8
+        }
+    }
+
+    // Integer colors are stored as 0..255 (or the full range of the type) and map to 0..1 in the mesh.
+    fn get_color_scale(&self) -> f64
+    {
+        match self
+        {
+            PlyScalarType::Int8 => 127.0,
+            PlyScalarType::UInt8 => 255.0,
+            PlyScalarType::Int16 => 32767.0,
+            PlyScalarType::UInt16 => 65535.0,
+            PlyScalarType::Int32 => 2147483647.0,
+            PlyScalarType::UInt32 => 4294967295.0,
+            PlyScalarType::Float32 | PlyScalarType::Float64 => 1.0
+        }
+    }
+}
+
+#[derive(Debug)]
+enum PlyProperty
+{
+    Scalar { name: String, value_type: PlyScalarType },
+    List { name: String, count_type: PlyScalarType, item_type: PlyScalarType }
+}
+
+impl PlyProperty
+{
+    fn get_name(&self) -> &str
+    {
+        match self
+        {
+            PlyProperty::Scalar { name, .. } => name,
+            PlyProperty::List { name, .. } => name
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PlyElement
+{
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>
+}
+
+struct PlyHeader
+{
+    format: PlyFormat,
+    elements: Vec<PlyElement>
+}
+
+fn read_header_line<R: BufRead>(reader: &mut R) -> Result<String, ImportError>
+{
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0
+    {
+        return Err(ImportError::invalid_data("ply", "unexpected end of header"));
+    }
+    Ok(line.trim().to_string())
+}
+
+fn parse_header<R: BufRead>(reader: &mut R) -> Result<PlyHeader, ImportError>
+{
+    if read_header_line(reader)? != "ply"
+    {
+        return Err(ImportError::invalid_data("ply", "missing ply magic"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = vec![];
+    loop
+    {
+        let line = read_header_line(reader)?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice()
+        {
+            ["end_header"] => break,
+            ["format", name, _version] =>
+            {
+                format = Some(match *name
+                {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                    "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                    _ => return Err(ImportError::invalid_data("ply", format!("unknown format {}", name)))
+                });
+            }
+            ["element", name, count] =>
+            {
+                let count = count.parse().map_err(|_| ImportError::invalid_data("ply", format!("invalid element count {}", count)))?;
+                elements.push(PlyElement { name: name.to_string(), count, properties: vec![] });
+            }
+            ["property", "list", count_type, item_type, name] =>
+            {
+                let element = elements.last_mut().ok_or_else(|| ImportError::invalid_data("ply", "property outside of an element"))?;
+                element.properties.push(PlyProperty::List
+                {
+                    name: name.to_string(),
+                    count_type: PlyScalarType::parse(count_type)?,
+                    item_type: PlyScalarType::parse(item_type)?
+                });
+            }
+            ["property", value_type, name] =>
+            {
+                let element = elements.last_mut().ok_or_else(|| ImportError::invalid_data("ply", "property outside of an element"))?;
+                element.properties.push(PlyProperty::Scalar { name: name.to_string(), value_type: PlyScalarType::parse(value_type)? });
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(ImportError::invalid_data("ply", format!("unexpected header line {}", line)))
+        }
+    }
+
+    let format = format.ok_or_else(|| ImportError::invalid_data("ply", "missing format line"))?;
+    Ok(PlyHeader { format, elements })
+}
+
+enum PlyValueReader<R: BufRead>
+{
+    Ascii { reader: R, tokens: Vec<String> },
+    Binary { reader: R, big_endian: bool }
+}
+
+impl<R: BufRead> PlyValueReader<R>
+{
+    fn new(reader: R, format: PlyFormat) -> Self
+    {
+        match format
+        {
+            PlyFormat::Ascii => PlyValueReader::Ascii { reader, tokens: vec![] },
+            PlyFormat::BinaryLittleEndian => PlyValueReader::Binary { reader, big_endian: false },
+            PlyFormat::BinaryBigEndian => PlyValueReader::Binary { reader, big_endian: true }
+        }
+    }
+
+    fn read(&mut self, value_type: PlyScalarType) -> Result<f64, ImportError>
+    {
+        match self
+        {
+            PlyValueReader::Ascii { reader, tokens } =>
+            {
+                while tokens.is_empty()
+                {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line)? == 0
+                    {
+                        return Err(ImportError::invalid_data("ply", "unexpected end of data"));
+                    }
+                    tokens.extend(line.split_whitespace().rev().map(str::to_string));
+                }
+                let token = tokens.pop().unwrap();
+                token.parse().map_err(|_| ImportError::invalid_data("ply", format!("invalid value {}", token)))
+            }
+            PlyValueReader::Binary { reader, big_endian } =>
+            {
+                let mut bytes = [0u8; 8];
+                let size = value_type.get_size();
+                reader.read_exact(&mut bytes[..size])?;
+                if *big_endian
+                {
+                    bytes[..size].reverse();
+                }
+                Ok(match value_type
+                {
+                    PlyScalarType::Int8 => bytes[0] as i8 as f64,
+                    PlyScalarType::UInt8 => bytes[0] as f64,
+                    PlyScalarType::Int16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    PlyScalarType::UInt16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    PlyScalarType::Int32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                    PlyScalarType::UInt32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                    PlyScalarType::Float32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+                    PlyScalarType::Float64 => f64::from_le_bytes(bytes)
+                })
+            }
+        }
+    }
+}
+
+// Maps a standard vertex property onto a mesh channel and the component it fills.
+fn get_vertex_property_target(name: &str) -> Option<(MeshDataChannel, usize)>
+{
+    match name
+    {
+        "x" => Some((MeshDataChannel::Position, 0)),
+        "y" => Some((MeshDataChannel::Position, 1)),
+        "z" => Some((MeshDataChannel::Position, 2)),
+        "nx" => Some((MeshDataChannel::Normal, 0)),
+        "ny" => Some((MeshDataChannel::Normal, 1)),
+        "nz" => Some((MeshDataChannel::Normal, 2)),
+        "u" | "s" | "texture_u" | "texture_s" => Some((MeshDataChannel::UV0, 0)),
+        "v" | "t" | "texture_v" | "texture_t" => Some((MeshDataChannel::UV0, 1)),
+        "red" | "diffuse_red" => Some((MeshDataChannel::Color, 0)),
+        "green" | "diffuse_green" => Some((MeshDataChannel::Color, 1)),
+        "blue" | "diffuse_blue" => Some((MeshDataChannel::Color, 2)),
+        "alpha" => Some((MeshDataChannel::Color, 3)),
+        _ => None
+    }
+}
+
+fn read_vertex_element<R: BufRead>(element: &PlyElement, values: &mut PlyValueReader<R>) -> Result<MeshChannelData, ImportError>
+{
+    let targets: Vec<Option<(MeshDataChannel, usize)>> = element.properties.iter()
+        .map(|property| get_vertex_property_target(property.get_name()))
+        .collect();
+
+    let mut channel_data = MeshChannelData::new();
+    for (channel, _) in targets.iter().flatten()
+    {
+        let channel = *channel as usize;
+        channel_data.entry(channel).or_insert_with(|| {
+            let default_value = get_channel_default_value(channel);
+            let mut data = Vec::with_capacity(element.count * default_value.len());
+            for _ in 0..element.count
+            {
+                data.extend_from_slice(default_value);
+            }
+            data
+        });
+    }
+    // Colors without an alpha property are opaque.
+    if let Some(colors) = channel_data.get_mut(&(MeshDataChannel::Color as usize))
+    {
+        for color in colors.chunks_exact_mut(4)
+        {
+            color[3] = 1.0;
+        }
+    }
+
+    for vertex in 0..element.count
+    {
+        for (property, target) in element.properties.iter().zip(&targets)
+        {
+            match property
+            {
+                PlyProperty::Scalar { value_type, .. } =>
+                {
+                    let mut value = values.read(*value_type)?;
+                    if let Some((channel, component)) = target
+                    {
+                        if *channel == MeshDataChannel::Color
+                        {
+                            value /= value_type.get_color_scale();
+                        }
+                        else if *channel == MeshDataChannel::UV0 && *component == 1
+                        {
+                            // PLY texture space has its origin at the bottom left, D3D samples from the top left.
+                            value = 1.0 - value;
+                        }
+                        let channel_size = get_channel_default_value(*channel as usize).len();
+                        channel_data.get_mut(&(*channel as usize)).unwrap()[vertex * channel_size + component] = value as f32;
+                    }
+                }
+                PlyProperty::List { count_type, item_type, .. } =>
+                {
+                    let count = values.read(*count_type)? as usize;
+                    for _ in 0..count
+                    {
+                        values.read(*item_type)?;
+                    }
+                }
+            }
+        }
+    }
+
+    if !channel_data.contains_key(&(MeshDataChannel::Position as usize))
+    {
+        return Err(ImportError::invalid_data("ply", "vertex element has no x/y/z properties"));
+    }
+    Ok(channel_data)
+}
+
+fn read_face_element<R: BufRead>(element: &PlyElement, values: &mut PlyValueReader<R>) -> Result<Vec<u32>, ImportError>
+{
+    let mut indices = vec![];
+    let mut polygon = vec![];
+    for _ in 0..element.count
+    {
+        for property in &element.properties
+        {
+            match property
+            {
+                PlyProperty::List { name, count_type, item_type } =>
+                {
+                    let count = values.read(*count_type)? as usize;
+                    let is_vertex_indices = name == "vertex_indices" || name == "vertex_index";
+                    polygon.clear();
+                    for _ in 0..count
+                    {
+                        let index = values.read(*item_type)?;
+                        if is_vertex_indices
+                        {
+                            if index < 0.0
+                            {
+                                return Err(ImportError::invalid_data("ply", format!("negative vertex index {}", index)));
+                            }
+                            polygon.push(index as u32);
+                        }
+                    }
+                    // Polygons are triangulated as fans around their first vertex.
+                    for i in 2..polygon.len()
+                    {
+                        indices.extend_from_slice(&[polygon[0], polygon[i - 1], polygon[i]]);
+                    }
+                }
+                PlyProperty::Scalar { value_type, .. } =>
+                {
+                    values.read(*value_type)?;
+                }
+            }
+        }
+    }
+    Ok(indices)
+}
+
+fn skip_element<R: BufRead>(element: &PlyElement, values: &mut PlyValueReader<R>) -> Result<(), ImportError>
+{
+    for _ in 0..element.count
+    {
+        for property in &element.properties
+        {
+            match property
+            {
+                PlyProperty::Scalar { value_type, .. } =>
+                {
+                    values.read(*value_type)?;
+                }
+                PlyProperty::List { count_type, item_type, .. } =>
+                {
+                    let count = values.read(*count_type)? as usize;
+                    for _ in 0..count
+                    {
+                        values.read(*item_type)?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads an ascii or binary PLY stream. Files without faces are point clouds and produce a point list, also when they
+/// declare an empty face element.
+pub fn load_ply_from_reader<R: BufRead>(mut reader: R) -> Result<Mesh, ImportError>
+{
+    let header = parse_header(&mut reader)?;
+    let mut values = PlyValueReader::new(reader, header.format);
+
+    let mut mesh = Mesh { mesh_topology: MeshPrimitiveTopology::PointList, ..Default::default() };
+    let mut vertex_count = None;
+    for element in &header.elements
+    {
+        match element.name.as_str()
+        {
+            "vertex" =>
+            {
+                mesh.mesh_channel_data = read_vertex_element(element, &mut values)?;
+                vertex_count = Some(element.count);
+            }
+            "face" =>
+            {
+                mesh.mesh_index_data = read_face_element(element, &mut values)?;
+                if !mesh.mesh_index_data.is_empty()
+                {
+                    mesh.mesh_topology = MeshPrimitiveTopology::TriangleList;
+                }
+            }
+            _ => skip_element(element, &mut values)?
+        }
+    }
+
+    let vertex_count = vertex_count.ok_or_else(|| ImportError::invalid_data("ply", "missing vertex element"))?;
+    if let Some(index) = mesh.mesh_index_data.iter().find(|index| **index as usize >= vertex_count)
+    {
+        return Err(ImportError::invalid_data("ply", format!("vertex index {} exceeds vertex count {}", index, vertex_count)));
+    }
+    mesh.validate_structure()?;
+    mesh.update_bounds(false);
+    Ok(mesh)
+}
+
+pub fn load_ply(path: impl AsRef<Path>) -> Result<Mesh, ImportError>
+{
+    load_ply_from_reader(BufReader::new(File::open(path)?))
+}
+
+fn write_value<W: Write>(writer: &mut W, format: PlyFormat, value: f64, value_type: PlyScalarType, is_last: bool) -> Result<(), ImportError>
+{
+    match (format, value_type)
+    {
+        (PlyFormat::Ascii, PlyScalarType::Float32) => write!(writer, "{}", value as f32)?,
+        (PlyFormat::Ascii, _) => write!(writer, "{}", value as u32)?,
+        (PlyFormat::BinaryLittleEndian, PlyScalarType::Float32) => writer.write_all(&(value as f32).to_le_bytes())?,
+        (PlyFormat::BinaryBigEndian, PlyScalarType::Float32) => writer.write_all(&(value as f32).to_be_bytes())?,
+        (PlyFormat::BinaryLittleEndian, PlyScalarType::UInt32) => writer.write_all(&(value as u32).to_le_bytes())?,
+        (PlyFormat::BinaryBigEndian, PlyScalarType::UInt32) => writer.write_all(&(value as u32).to_be_bytes())?,
+        (_, _) => writer.write_all(&[value as u8])?
+    }
+    if format == PlyFormat::Ascii
+    {
+        writer.write_all(if is_last { b"\n" } else { b" " })?;
+    }
+    Ok(())
+}
+
+/// Writes the position, normal, UV0 and color channels and the triangles of a mesh as PLY, triangle soups with one
+/// face per three vertices. Point lists are written without a face element.
+pub fn write_ply<W: Write>(mesh: &Mesh, mut writer: W, format: PlyFormat) -> Result<(), ImportError>
+{
+    let positions = mesh.mesh_channel_data.get(&(MeshDataChannel::Position as usize))
+        .ok_or_else(|| ImportError::invalid_data("ply", "mesh has no positions to write"))?;
+    let vertex_count = positions.len() / 3;
+    let indices = mesh.get_triangle_indices();
+
+    let mut properties: Vec<(MeshDataChannel, &[&str], PlyScalarType)> = vec![(MeshDataChannel::Position, &["x", "y", "z"], PlyScalarType::Float32)];
+    if mesh.mesh_channel_data.contains_key(&(MeshDataChannel::Normal as usize))
+    {
+        properties.push((MeshDataChannel::Normal, &["nx", "ny", "nz"], PlyScalarType::Float32));
+    }
+    if mesh.mesh_channel_data.contains_key(&(MeshDataChannel::UV0 as usize))
+    {
+        properties.push((MeshDataChannel::UV0, &["u", "v"], PlyScalarType::Float32));
+    }
+    if mesh.mesh_channel_data.contains_key(&(MeshDataChannel::Color as usize))
+    {
+        properties.push((MeshDataChannel::Color, &["red", "green", "blue", "alpha"], PlyScalarType::UInt8));
+    }
+    for (channel, _, _) in &properties
+    {
+        let channel_size = get_channel_default_value(*channel as usize).len();
+        if mesh.mesh_channel_data[&(*channel as usize)].len() != vertex_count * channel_size
+        {
+            return Err(ImportError::invalid_data("ply", format!("{:?} channel does not match the vertex count", channel)));
+        }
+    }
+
+    let format_name = match format
+    {
+        PlyFormat::Ascii => "ascii",
+        PlyFormat::BinaryLittleEndian => "binary_little_endian",
+        PlyFormat::BinaryBigEndian => "binary_big_endian"
+    };
+    writeln!(writer, "ply")?;
+    writeln!(writer, "format {} 1.0", format_name)?;
+    writeln!(writer, "comment written by RustDX")?;
+    writeln!(writer, "element vertex {}", vertex_count)?;
+    for (_, names, value_type) in &properties
+    {
+        let type_name = if *value_type == PlyScalarType::Float32 { "float" } else { "uchar" };
+        for name in names.iter()
+        {
+            writeln!(writer, "property {} {}", type_name, name)?;
+        }
+    }
+    if mesh.mesh_topology == MeshPrimitiveTopology::TriangleList
+    {
+        writeln!(writer, "element face {}", indices.len() / 3)?;
+        writeln!(writer, "property list uchar uint vertex_indices")?;
+    }
+    writeln!(writer, "end_header")?;
+
+    for vertex in 0..vertex_count
+    {
+        for (property_index, (channel, names, value_type)) in properties.iter().enumerate()
+        {
+            let data = &mesh.mesh_channel_data[&(*channel as usize)];
+            for component in 0..names.len()
+            {
+                let mut value = data[vertex * names.len() + component] as f64;
+                if *channel == MeshDataChannel::Color
+                {
+                    value = (value.clamp(0.0, 1.0) * 255.0).round();
+                }
+                else if *channel == MeshDataChannel::UV0 && component == 1
+                {
+                    value = 1.0 - value;
+                }
+                let is_last = property_index == properties.len() - 1 && component == names.len() - 1;
+                write_value(&mut writer, format, value, *value_type, is_last)?;
+            }
+        }
+    }
+
+    for triangle in indices.chunks_exact(3)
+    {
+        write_value(&mut writer, format, 3.0, PlyScalarType::UInt8, false)?;
+        for (corner, index) in triangle.iter().enumerate()
+        {
+            write_value(&mut writer, format, *index as f64, PlyScalarType::UInt32, corner == 2)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn save_ply(mesh: &Mesh, path: impl AsRef<Path>, format: PlyFormat) -> Result<(), ImportError>
+{
+    write_ply(mesh, BufWriter::new(File::create(path)?), format)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::scene::mesh::validation::MeshError;
+
+    const POINT_CLOUD: &str = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nend_header\n0 0 0\n1 0 0\n0 1 0\n";
+
+    fn round_trip(mesh: &Mesh, format: PlyFormat) -> Mesh
+    {
+        let mut data = vec![];
+        write_ply(mesh, &mut data, format).unwrap();
+        load_ply_from_reader(data.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn triangle_soup_is_written_with_faces()
+    {
+        let mut soup = Mesh::create_icosphere(1.0, 1);
+        soup.remap_vertices(&soup.get_triangle_indices());
+        soup.mesh_index_data.clear();
+
+        for format in [PlyFormat::Ascii, PlyFormat::BinaryLittleEndian, PlyFormat::BinaryBigEndian]
+        {
+            let loaded = round_trip(&soup, format);
+            assert_eq!(loaded.mesh_topology, MeshPrimitiveTopology::TriangleList);
+            assert_eq!(loaded.get_triangle_indices(), soup.get_triangle_indices());
+            assert_eq!(loaded.get_sections(), soup.get_sections());
+        }
+    }
+
+    #[test]
+    fn point_cloud_is_a_point_list()
+    {
+        let points = load_ply_from_reader(POINT_CLOUD.as_bytes()).unwrap();
+        assert_eq!(points.mesh_topology, MeshPrimitiveTopology::PointList);
+        assert_eq!(points.get_vertex_count(), 3);
+        assert!(points.get_triangle_indices().is_empty());
+        assert!(points.get_sections().is_empty());
+        assert_eq!(points.validate(), Ok(()));
+
+        let loaded = round_trip(&points, PlyFormat::Ascii);
+        assert_eq!(loaded.mesh_topology, MeshPrimitiveTopology::PointList);
+        assert_eq!(loaded.mesh_channel_data, points.mesh_channel_data);
+
+        // MeshLab and CloudCompare write an empty face element for point clouds.
+        let empty_faces = "ply\nformat ascii 1.0\nelement vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+            element face 0\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n1 0 0\n0 1 0\n0 0 1\n";
+        let points = load_ply_from_reader(empty_faces.as_bytes()).unwrap();
+        assert_eq!(points.mesh_topology, MeshPrimitiveTopology::PointList);
+        assert_eq!(points.get_vertex_count(), 4);
+        assert_eq!(points.validate(), Ok(()));
+    }
+
+    #[test]
+    fn point_list_with_indices_is_invalid()
+    {
+        let mut points = load_ply_from_reader(POINT_CLOUD.as_bytes()).unwrap();
+        points.mesh_index_data = vec![0, 1, 2];
+        assert_eq!(points.validate_structure(), Err(MeshError::InvalidPointList));
+    }
+}
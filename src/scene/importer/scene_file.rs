@@ -0,0 +1,497 @@
+use std::path::Path;
+
+use cgmath::{InnerSpace, Quaternion, Rad, Vector3};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::scene::camera::*;
+use crate::scene::importer::ImportError;
+use crate::scene::importer::gltf_importer::load_gltf_scene;
+use crate::scene::importer::mesh_cache::{load_static_mesh_cache, MESH_CACHE_EXTENSION};
+use crate::scene::importer::obj_importer::*;
+use crate::scene::importer::ply::load_ply;
+use crate::scene::light::Light;
+use crate::scene::scene::*;
+use crate::scene::scene_proxy::SceneProxy;
+use crate::scene::static_mesh::StaticMesh;
+use crate::scene::transform::Transform;
+
+// Bump whenever the format changes and append the migration from the previous version.
+pub const SCENE_FILE_VERSION: u32 = 1;
+
+// Upgrades the json of a scene file by one version, it must not touch the version field.
+type SceneFileMigration = fn(&mut Value) -> Result<(), ImportError>;
+
+// Entry i upgrades version i + 1 to version i + 2, so files of every older version still load.
+const SCENE_FILE_MIGRATIONS: [SceneFileMigration; SCENE_FILE_VERSION as usize - 1] = [];
+
+// Angles are in radians like everywhere else in the scene.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SceneFileProjection
+{
+    Perspective { vertical_fov: f32, near: f32, far: f32 },
+    InfinitePerspective { vertical_fov: f32, near: f32 },
+    Orthographic { height: f32, near: f32, far: f32 }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SceneFileCamera
+{
+    #[serde(flatten)]
+    pub projection: SceneFileProjection,
+    pub aspect_ratio: f32,
+    #[serde(default)]
+    pub is_reverse_z: bool,
+    // Relative to the node.
+    #[serde(default = "get_default_translation")]
+    pub translation: [f32; 3],
+    #[serde(default = "get_default_rotation")]
+    pub rotation: [f32; 4]
+}
+
+/// A node with its children nested inside it, so that nodes can be moved around in the text without renumbering
+/// anything. Rotations are quaternions as x, y, z, w.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneFileNode
+{
+    pub name: String,
+    #[serde(default = "get_default_translation")]
+    pub translation: [f32; 3],
+    #[serde(default = "get_default_rotation")]
+    pub rotation: [f32; 4],
+    #[serde(default = "get_default_scale")]
+    pub scale: [f32; 3],
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub meshes: Vec<SceneAssetReference>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub light: Option<Light>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera: Option<SceneFileCamera>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<SceneFileNode>
+}
+
+/// The text form of a scene, written as pretty printed json to keep diffs of hand edited scenes small.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneFile
+{
+    pub version: u32,
+    #[serde(default)]
+    pub nodes: Vec<SceneFileNode>
+}
+
+fn get_default_translation() -> [f32; 3]
+{
+    [0.0, 0.0, 0.0]
+}
+
+fn get_default_rotation() -> [f32; 4]
+{
+    [0.0, 0.0, 0.0, 1.0]
+}
+
+fn get_default_scale() -> [f32; 3]
+{
+    [1.0, 1.0, 1.0]
+}
+
+fn invalid_scene(message: impl Into<String>) -> ImportError
+{
+    ImportError::invalid_data("scene", message)
+}
+
+fn get_file_rotation(rotation: Quaternion<f32>) -> [f32; 4]
+{
+    [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s]
+}
+
+// Normalized, since hand edited files rarely hold unit quaternions.
+fn get_scene_rotation(rotation: [f32; 4]) -> Result<Quaternion<f32>, ImportError>
+{
+    let rotation = Quaternion::new(rotation[3], rotation[0], rotation[1], rotation[2]);
+    let length = rotation.magnitude();
+    if !(length > 0.0 && length.is_finite())
+    {
+        return Err(invalid_scene("rotations must be non zero quaternions"));
+    }
+    Ok(rotation / length)
+}
+
+// Serde can not deny unknown fields of structs with a flattened field, so the keys of cameras and lights are
+// checked against the keys their parsed values write back.
+fn check_known_fields<T: Serialize>(value: &Value, parsed: Option<&T>, kind: &str) -> Result<(), ImportError>
+{
+    let (Some(fields), Some(parsed)) = (value.as_object(), parsed) else { return Ok(()) };
+    let known_fields = serde_json::to_value(parsed)?;
+    match fields.keys().find(|field| known_fields.get(field.as_str()).is_none())
+    {
+        Some(field) => Err(invalid_scene(format!("unknown {} field {}", kind, field))),
+        None => Ok(())
+    }
+}
+
+fn check_flattened_fields(nodes: &Value, file_nodes: &[SceneFileNode]) -> Result<(), ImportError>
+{
+    let Some(nodes) = nodes.as_array() else { return Ok(()) };
+    for (node, file_node) in nodes.iter().zip(file_nodes)
+    {
+        check_known_fields(&node["camera"], file_node.camera.as_ref(), "camera")?;
+        check_known_fields(&node["light"], file_node.light.as_ref(), "light")?;
+        check_flattened_fields(&node["children"], &file_node.children)?;
+    }
+    Ok(())
+}
+
+impl From<Projection> for SceneFileProjection
+{
+    fn from(projection: Projection) -> Self
+    {
+        match projection
+        {
+            Projection::Perspective { vertical_fov, near, far } => SceneFileProjection::Perspective { vertical_fov: vertical_fov.0, near, far },
+            Projection::InfinitePerspective { vertical_fov, near } => SceneFileProjection::InfinitePerspective { vertical_fov: vertical_fov.0, near },
+            Projection::Orthographic { height, near, far } => SceneFileProjection::Orthographic { height, near, far }
+        }
+    }
+}
+
+impl From<SceneFileProjection> for Projection
+{
+    fn from(projection: SceneFileProjection) -> Self
+    {
+        match projection
+        {
+            SceneFileProjection::Perspective { vertical_fov, near, far } => Projection::Perspective { vertical_fov: Rad(vertical_fov), near, far },
+            SceneFileProjection::InfinitePerspective { vertical_fov, near } => Projection::InfinitePerspective { vertical_fov: Rad(vertical_fov), near },
+            SceneFileProjection::Orthographic { height, near, far } => Projection::Orthographic { height, near, far }
+        }
+    }
+}
+
+impl From<&Camera> for SceneFileCamera
+{
+    fn from(camera: &Camera) -> Self
+    {
+        SceneFileCamera
+        {
+            projection: camera.projection.into(),
+            aspect_ratio: camera.aspect_ratio,
+            is_reverse_z: camera.is_reverse_z,
+            translation: camera.position.into(),
+            rotation: get_file_rotation(camera.rotation)
+        }
+    }
+}
+
+impl TryFrom<&SceneFileCamera> for Camera
+{
+    type Error = ImportError;
+
+    fn try_from(camera: &SceneFileCamera) -> Result<Self, ImportError>
+    {
+        Ok(Camera
+        {
+            position: camera.translation.into(),
+            rotation: get_scene_rotation(camera.rotation)?,
+            projection: camera.projection.into(),
+            aspect_ratio: camera.aspect_ratio,
+            is_reverse_z: camera.is_reverse_z
+        })
+    }
+}
+
+impl SceneFile
+{
+    /// Records the hierarchy of the scene in the order of its roots and children. Scene proxies without an asset
+    /// reference are left out.
+    pub fn from_scene(scene: &Scene) -> Self
+    {
+        let nodes = scene.get_root_nodes().iter().map(|node| SceneFile::get_file_node(scene, *node)).collect();
+        SceneFile { version: SCENE_FILE_VERSION, nodes }
+    }
+
+    fn get_file_node(scene: &Scene, node: SceneNodeHandle) -> SceneFileNode
+    {
+        let scene_node = scene.get_node(node).unwrap();
+        let transform = scene_node.get_local_transform();
+        SceneFileNode
+        {
+            name: scene_node.get_name().to_string(),
+            translation: transform.translation.into(),
+            rotation: get_file_rotation(transform.rotation),
+            scale: transform.scale.into(),
+            meshes: scene_node.get_scene_proxies().iter().filter_map(|proxy| scene.get_scene_proxy_asset(*proxy).cloned()).collect(),
+            light: scene_node.get_light().copied(),
+            camera: scene_node.get_camera().map(SceneFileCamera::from),
+            children: scene_node.get_children().iter().map(|child| SceneFile::get_file_node(scene, *child)).collect()
+        }
+    }
+
+    /// Parses the json of a scene file and upgrades files of older versions first.
+    pub fn parse(text: &str) -> Result<Self, ImportError>
+    {
+        let mut value: Value = serde_json::from_str(text)?;
+        let version = value.get("version").and_then(Value::as_u64)
+            .ok_or_else(|| invalid_scene("the version is missing"))?;
+        if version == 0
+        {
+            return Err(invalid_scene("version 0 does not exist"));
+        }
+        if version > SCENE_FILE_VERSION as u64
+        {
+            return Err(ImportError::SceneVersion { found: version.min(u32::MAX as u64) as u32, supported: SCENE_FILE_VERSION });
+        }
+        for version in version as u32..SCENE_FILE_VERSION
+        {
+            SCENE_FILE_MIGRATIONS[version as usize - 1](&mut value)?;
+            value["version"] = Value::from(version + 1);
+        }
+        let file = SceneFile::deserialize(&value)?;
+        check_flattened_fields(&value["nodes"], &file.nodes)?;
+        Ok(file)
+    }
+
+    pub fn to_json(&self) -> Result<String, ImportError>
+    {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Builds the scene, load_asset creates the scene proxy of every asset reference.
+    pub fn create_scene(&self, mut load_asset: impl FnMut(&SceneAssetReference) -> Result<Box<dyn SceneProxy>, ImportError>) -> Result<Scene, ImportError>
+    {
+        let mut scene = Scene::new();
+        let mut pending: Vec<(&SceneFileNode, Option<SceneNodeHandle>)> = self.nodes.iter().rev().map(|node| (node, None)).collect();
+        while let Some((file_node, parent)) = pending.pop()
+        {
+            let node = scene.create_node(&file_node.name, parent)?;
+            let transform = Transform::new(Vector3::from(file_node.translation), get_scene_rotation(file_node.rotation)?, Vector3::from(file_node.scale));
+            scene.set_local_transform(node, transform)?;
+            scene.set_node_light(node, file_node.light)?;
+            scene.set_node_camera(node, file_node.camera.as_ref().map(Camera::try_from).transpose()?)?;
+            for asset in &file_node.meshes
+            {
+                let proxy = scene.add_scene_proxy(node, load_asset(asset)?)?;
+                scene.set_scene_proxy_asset(proxy, Some(asset.clone()))?;
+            }
+            // Reversed so that the children are created in file order.
+            pending.extend(file_node.children.iter().rev().map(|child| (child, Some(node))));
+        }
+        Ok(scene)
+    }
+}
+
+// Takes the scene proxy attached to the glTF node named by the reference, or the only one when it names none.
+fn take_gltf_scene_proxy(scene: &mut Scene, asset: &SceneAssetReference) -> Result<Box<dyn SceneProxy>, ImportError>
+{
+    let proxies: Vec<SceneProxyHandle> = scene.get_scene_proxies()
+        .map(|(proxy, _)| proxy)
+        .filter(|proxy| asset.mesh.as_ref().is_none_or(|name| {
+            let node = scene.get_scene_proxy_node(*proxy).unwrap();
+            scene.get_node(node).unwrap().get_name() == name
+        }))
+        .collect();
+    match (proxies.as_slice(), &asset.mesh)
+    {
+        ([proxy], _) => Ok(scene.remove_scene_proxy(*proxy).unwrap()),
+        ([], Some(name)) => Err(invalid_scene(format!("{} has no mesh node {}", asset.path, name))),
+        ([], None) => Err(invalid_scene(format!("{} has no meshes", asset.path))),
+        (_, Some(name)) => Err(invalid_scene(format!("{} has several mesh nodes named {}", asset.path, name))),
+        (_, None) => Err(invalid_scene(format!("{} has several meshes, name one with mesh", asset.path)))
+    }
+}
+
+/// Loads the asset of a reference relative to base_dir: OBJ files, as a whole or the object named by mesh, PLY files,
+/// glTF files, the mesh of the node named by mesh, and mesh cache files. Other extensions are rejected.
+pub fn load_scene_asset(base_dir: &Path, asset: &SceneAssetReference) -> Result<Box<dyn SceneProxy>, ImportError>
+{
+    let path = base_dir.join(&asset.path);
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("").to_lowercase();
+    let static_mesh = match (extension.as_str(), &asset.mesh)
+    {
+        ("obj", None) => load_obj_static_mesh(&path)?,
+        ("obj", Some(name)) => load_obj_static_meshes(&path)?.into_iter()
+            .find(|static_mesh| static_mesh.get_name() == name)
+            .ok_or_else(|| invalid_scene(format!("{} has no object {}", asset.path, name)))?,
+        ("ply", _) =>
+        {
+            let mut static_mesh = StaticMesh::new(path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("ply"));
+            *static_mesh.get_mesh_mut() = load_ply(&path)?;
+            static_mesh
+        }
+        ("gltf" | "glb", _) => return take_gltf_scene_proxy(&mut load_gltf_scene(&path)?, asset),
+        (MESH_CACHE_EXTENSION, _) => load_static_mesh_cache(&path)?,
+        _ => return Err(invalid_scene(format!("{} has an unsupported extension", asset.path)))
+    };
+    Ok(Box::new(static_mesh))
+}
+
+pub fn save_scene(scene: &Scene, path: impl AsRef<Path>) -> Result<(), ImportError>
+{
+    std::fs::write(path, SceneFile::from_scene(scene).to_json()?)?;
+    Ok(())
+}
+
+/// Loads a scene file with a custom asset loader, for assets load_scene_asset does not handle.
+pub fn load_scene_with(path: impl AsRef<Path>, load_asset: impl FnMut(&SceneAssetReference) -> Result<Box<dyn SceneProxy>, ImportError>) -> Result<Scene, ImportError>
+{
+    SceneFile::parse(&std::fs::read_to_string(path)?)?.create_scene(load_asset)
+}
+
+/// Loads a scene file with its assets resolved relative to the file.
+pub fn load_scene(path: impl AsRef<Path>) -> Result<Scene, ImportError>
+{
+    let path = path.as_ref();
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    load_scene_with(path, |asset| load_scene_asset(base_dir, asset))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::path::PathBuf;
+
+    use cgmath::Rotation3;
+
+    use super::*;
+    use crate::scene::light::LightType;
+
+    fn get_test_asset_dir() -> PathBuf
+    {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/tests")
+    }
+
+    fn get_gltf_reference(mesh: Option<&str>) -> SceneAssetReference
+    {
+        SceneAssetReference { path: "hierarchy_skin_sparse.gltf".to_string(), mesh: mesh.map(str::to_string) }
+    }
+
+    // Rotations are normalized on load, so they only match up to rounding.
+    fn assert_same_nodes(expected_scene: &Scene, expected_nodes: &[SceneNodeHandle], scene: &Scene, nodes: &[SceneNodeHandle])
+    {
+        assert_eq!(expected_nodes.len(), nodes.len());
+        for (expected_node, node) in expected_nodes.iter().zip(nodes)
+        {
+            let (expected_node, node) = (expected_scene.get_node(*expected_node).unwrap(), scene.get_node(*node).unwrap());
+            assert_eq!(node.get_name(), expected_node.get_name());
+            let (expected_transform, transform) = (expected_node.get_local_transform(), node.get_local_transform());
+            assert_eq!((transform.translation, transform.scale), (expected_transform.translation, expected_transform.scale));
+            assert!((transform.rotation - expected_transform.rotation).magnitude() < 1e-6);
+            assert_eq!(node.get_light(), expected_node.get_light());
+            match (expected_node.get_camera(), node.get_camera())
+            {
+                (Some(expected_camera), Some(camera)) =>
+                {
+                    assert_eq!(Camera { rotation: expected_camera.rotation, ..*camera }, *expected_camera);
+                    assert!((camera.rotation - expected_camera.rotation).magnitude() < 1e-6);
+                }
+                (expected_camera, camera) => assert_eq!(expected_camera.is_none(), camera.is_none())
+            }
+            let get_assets = |scene: &Scene, node: &SceneNode| -> Vec<SceneAssetReference> {
+                node.get_scene_proxies().iter().map(|proxy| scene.get_scene_proxy_asset(*proxy).unwrap().clone()).collect()
+            };
+            assert_eq!(get_assets(scene, node), get_assets(expected_scene, expected_node));
+            assert_same_nodes(expected_scene, expected_node.get_children(), scene, node.get_children());
+        }
+    }
+
+    #[test]
+    fn scenes_round_trip_through_json()
+    {
+        let mut scene = Scene::new();
+        let root = scene.create_node("root", None).unwrap();
+        let rotation = Quaternion::from_axis_angle(Vector3::new(1.0, 2.0, 3.0).normalize(), Rad(0.7));
+        scene.set_local_transform(root, Transform::new(Vector3::new(1.0, -2.0, 3.0), rotation, Vector3::new(2.0, 0.5, 1.5))).unwrap();
+        let lamp = scene.create_node("lamp", Some(root)).unwrap();
+        scene.set_local_transform(lamp, Transform::new(Vector3::new(0.0, 4.0, 0.0), Quaternion::from_angle_x(Rad(-1.2)), Vector3::new(1.0, 1.0, 1.0))).unwrap();
+        let spot = Light { color: [1.0, 0.5, 0.25], intensity: 20.0, ..Light::new(LightType::Spot { range: Some(15.0), inner_cone_angle: 0.3, outer_cone_angle: 0.5 }) };
+        scene.set_node_light(lamp, Some(spot)).unwrap();
+        let eye = scene.create_node("eye", Some(lamp)).unwrap();
+        let camera = Camera
+        {
+            position: Vector3::new(0.5, 0.0, -1.0),
+            rotation: Quaternion::from_angle_y(Rad(0.4)),
+            projection: Projection::InfinitePerspective { vertical_fov: Rad(1.0), near: 0.1 },
+            aspect_ratio: 16.0 / 9.0,
+            is_reverse_z: true
+        };
+        scene.set_node_camera(eye, Some(camera)).unwrap();
+        let statue = scene.create_node("statue", None).unwrap();
+        let asset = SceneAssetReference { path: "meshes/statue.obj".to_string(), mesh: Some("head".to_string()) };
+        let proxy = scene.add_scene_proxy(statue, Box::new(StaticMesh::new("head"))).unwrap();
+        scene.set_scene_proxy_asset(proxy, Some(asset.clone())).unwrap();
+
+        let json = SceneFile::from_scene(&scene).to_json().unwrap();
+        let mut loaded_assets = vec![];
+        let loaded = SceneFile::parse(&json).unwrap().create_scene(|asset| {
+            loaded_assets.push(asset.clone());
+            Ok(Box::new(StaticMesh::new("head")))
+        }).unwrap();
+        assert_eq!(loaded_assets, vec![asset]);
+        assert_eq!(loaded.get_node_count(), 4);
+        assert_same_nodes(&scene, scene.get_root_nodes(), &loaded, loaded.get_root_nodes());
+    }
+
+    #[test]
+    fn unknown_camera_and_light_fields_are_rejected()
+    {
+        let parse_node = |node: &str| SceneFile::parse(&format!(r#"{{ "version": 1, "nodes": [{}] }}"#, node));
+        let camera = r#""camera": { "type": "perspective", "vertical_fov": 1, "near": 0.1, "far": 10, "aspect_ratio": 1"#;
+        let light = r#""light": { "type": "spot", "inner_cone_angle": 0.2, "outer_cone_angle": 0.4"#;
+        assert!(parse_node(&format!(r#"{{ "name": "a", {}, "is_reverse_z": true }}, {} }} }}"#, camera, light)).is_ok());
+        assert!(parse_node(&format!(r#"{{ "name": "a", {}, "is_reverse_zz": true }} }}"#, camera)).is_err());
+        assert!(parse_node(&format!(r#"{{ "name": "a", {}, "intensty": 2 }} }}"#, light)).is_err());
+        // Fields of another projection or light type are unknown too, also in nested nodes.
+        assert!(parse_node(&format!(r#"{{ "name": "a", {}, "height": 2 }} }}"#, camera)).is_err());
+        assert!(parse_node(r#"{ "name": "a", "children": [{ "name": "b", "light": { "type": "directional", "range": 5 } }] }"#).is_err());
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected()
+    {
+        assert!(SceneFile::parse(r#"{ "version": 1, "nodes": [{ "name": "a" }] }"#).is_ok());
+        assert!(SceneFile::parse(r#"{ "version": 1, "nodes": [{ "name": "a", "rotaton": [0, 0, 0, 1] }] }"#).is_err());
+        assert!(SceneFile::parse(r#"{ "version": 1, "node": [] }"#).is_err());
+    }
+
+    #[test]
+    fn rotations_are_normalized()
+    {
+        let file = SceneFile::parse(r#"{ "version": 1, "nodes": [{ "name": "a", "rotation": [0, 0, 2, 2] }] }"#).unwrap();
+        let scene = file.create_scene(|_| unreachable!()).unwrap();
+        let rotation = scene.get_node(scene.find_node("a").unwrap()).unwrap().get_local_transform().rotation;
+        assert!((rotation.magnitude() - 1.0).abs() < 1e-6);
+        assert!((rotation.v.z - 0.5f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn zero_rotations_are_rejected()
+    {
+        let node = SceneFile::parse(r#"{ "version": 1, "nodes": [{ "name": "a", "rotation": [0, 0, 0, 0] }] }"#).unwrap();
+        assert!(node.create_scene(|_| unreachable!()).is_err());
+        let camera = r#"{ "version": 1, "nodes": [{ "name": "a", "camera":
+            { "type": "orthographic", "height": 1, "near": 0.1, "far": 10, "aspect_ratio": 1, "rotation": [0, 0, 0, 0] } }] }"#;
+        assert!(SceneFile::parse(camera).unwrap().create_scene(|_| unreachable!()).is_err());
+    }
+
+    #[test]
+    fn gltf_assets_pick_the_named_mesh_node()
+    {
+        let base_dir = get_test_asset_dir();
+        assert!(load_scene_asset(&base_dir, &get_gltf_reference(Some("quad"))).is_ok());
+        assert!(load_scene_asset(&base_dir, &get_gltf_reference(Some("skinned"))).is_ok());
+        assert!(load_scene_asset(&base_dir, &get_gltf_reference(Some("joint0"))).is_err());
+        // The file holds two meshes, so one has to be named.
+        assert!(load_scene_asset(&base_dir, &get_gltf_reference(None)).is_err());
+    }
+
+    #[test]
+    fn unknown_extensions_are_rejected()
+    {
+        let asset = SceneAssetReference { path: "mesh.fbx".to_string(), mesh: None };
+        assert!(matches!(load_scene_asset(&get_test_asset_dir(), &asset), Err(ImportError::InvalidData { .. })));
+    }
+}
+
@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+
+// Ranges are in world units, None for lights that never fall off to zero. Cone angles are half angles in radians.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LightType
+{
+    Directional,
+    Point
+    {
+        #[serde(default)]
+        range: Option<f32>
+    },
+    Spot
+    {
+        #[serde(default)]
+        range: Option<f32>,
+        inner_cone_angle: f32,
+        outer_cone_angle: f32
+    }
+}
+
+/// A light attached to a scene node. Directional and spot lights shine along the z axis of their node, the way the
+/// camera looks.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Light
+{
+    #[serde(flatten)]
+    pub light_type: LightType,
+    // Linear RGB.
+    #[serde(default = "get_default_light_color")]
+    pub color: [f32; 3],
+    #[serde(default = "get_default_light_intensity")]
+    pub intensity: f32
+}
+
+fn get_default_light_color() -> [f32; 3]
+{
+    [1.0, 1.0, 1.0]
+}
+
+fn get_default_light_intensity() -> f32
+{
+    1.0
+}
+
+impl Light
+{
+    pub fn new(light_type: LightType) -> Self
+    {
+        Light { light_type, color: get_default_light_color(), intensity: get_default_light_intensity() }
+    }
+}
@@ -0,0 +1,515 @@
+use std::cmp::Ordering;
+
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, MetricSpace, SquareMatrix, Vector3, Zero};
+
+use crate::scene::mesh::*;
+
+// Sweeps of the Jacobi eigenvalue iteration, a 3x3 matrix converges long before.
+const JACOBI_MAX_SWEEPS: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingBox
+{
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingSphere
+{
+    pub center: Vector3<f32>,
+    pub radius: f32
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OrientedBoundingBox
+{
+    pub center: Vector3<f32>,
+    // Orthonormal and right handed.
+    pub axes: [Vector3<f32>; 3],
+    pub half_extents: Vector3<f32>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeshBounds
+{
+    pub bounding_box: BoundingBox,
+    pub bounding_sphere: BoundingSphere,
+    pub oriented_bounding_box: Option<OrientedBoundingBox>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray
+{
+    pub origin: Vector3<f32>,
+    // Unit length.
+    pub direction: Vector3<f32>
+}
+
+impl Default for BoundingBox
+{
+    // An empty box, adding any point makes it valid.
+    fn default() -> Self
+    {
+        BoundingBox { min: Vector3::new(f32::MAX, f32::MAX, f32::MAX), max: Vector3::new(f32::MIN, f32::MIN, f32::MIN) }
+    }
+}
+
+impl BoundingBox
+{
+    pub fn from_points(points: &[Vector3<f32>]) -> Self
+    {
+        let mut bounding_box = BoundingBox::default();
+        for point in points
+        {
+            bounding_box.add_point(*point);
+        }
+        bounding_box
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn add_point(&mut self, point: Vector3<f32>)
+    {
+        self.min = Vector3::new(self.min.x.min(point.x), self.min.y.min(point.y), self.min.z.min(point.z));
+        self.max = Vector3::new(self.max.x.max(point.x), self.max.y.max(point.y), self.max.z.max(point.z));
+    }
+
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox
+    {
+        if other.is_empty()
+        {
+            return *self;
+        }
+        let mut result = *self;
+        result.add_point(other.min);
+        result.add_point(other.max);
+        result
+    }
+
+    pub fn get_center(&self) -> Vector3<f32>
+    {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn get_half_extents(&self) -> Vector3<f32>
+    {
+        (self.max - self.min) * 0.5
+    }
+
+    // Zero for an empty box.
+    pub fn get_surface_area(&self) -> f32
+    {
+        if self.is_empty()
+        {
+            return 0.0;
+        }
+        let size = self.max - self.min;
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    pub fn intersects(&self, other: &BoundingBox) -> bool
+    {
+        !self.is_empty() && !other.is_empty()
+            && self.min.x <= other.max.x && self.max.x >= other.min.x
+            && self.min.y <= other.max.y && self.max.y >= other.min.y
+            && self.min.z <= other.max.z && self.max.z >= other.min.z
+    }
+
+    pub fn intersects_sphere(&self, center: Vector3<f32>, radius: f32) -> bool
+    {
+        if self.is_empty()
+        {
+            return false;
+        }
+        let closest = Vector3::new(center.x.clamp(self.min.x, self.max.x), center.y.clamp(self.min.y, self.max.y), center.z.clamp(self.min.z, self.max.z));
+        closest.distance2(center) <= radius * radius
+    }
+
+    /// Distance along the ray where it enters the box, zero when it starts inside. None when the box is missed or
+    /// entered only after max_distance.
+    pub fn intersect_ray(&self, ray: &Ray, max_distance: f32) -> Option<f32>
+    {
+        if self.is_empty()
+        {
+            return None;
+        }
+        let (mut near, mut far) = (0.0f32, max_distance);
+        for axis in 0..3
+        {
+            // A ray parallel to the slab hits it only when it starts between the faces, including on one.
+            if ray.direction[axis] == 0.0
+            {
+                if ray.origin[axis] < self.min[axis] || ray.origin[axis] > self.max[axis]
+                {
+                    return None;
+                }
+                continue;
+            }
+            let inverse_direction = 1.0 / ray.direction[axis];
+            let first = (self.min[axis] - ray.origin[axis]) * inverse_direction;
+            let second = (self.max[axis] - ray.origin[axis]) * inverse_direction;
+            near = near.max(first.min(second));
+            far = far.min(first.max(second));
+            if near > far
+            {
+                return None;
+            }
+        }
+        Some(near)
+    }
+
+    pub fn get_corners(&self) -> [Vector3<f32>; 8]
+    {
+        let (min, max) = (self.min, self.max);
+        [
+            Vector3::new(min.x, min.y, min.z), Vector3::new(max.x, min.y, min.z),
+            Vector3::new(min.x, max.y, min.z), Vector3::new(max.x, max.y, min.z),
+            Vector3::new(min.x, min.y, max.z), Vector3::new(max.x, min.y, max.z),
+            Vector3::new(min.x, max.y, max.z), Vector3::new(max.x, max.y, max.z)
+        ]
+    }
+
+    /// Axis aligned box around the transformed box (Arvo's method).
+    pub fn transform(&self, transform: &Matrix4<f32>) -> BoundingBox
+    {
+        if self.is_empty()
+        {
+            return *self;
+        }
+        let center = (transform * self.get_center().extend(1.0)).truncate();
+        let half_extents = self.get_half_extents();
+        let mut transformed_half_extents = Vector3::zero();
+        for row in 0..3
+        {
+            transformed_half_extents[row] = (0..3).map(|column| transform[column][row].abs() * half_extents[column]).sum();
+        }
+        BoundingBox { min: center - transformed_half_extents, max: center + transformed_half_extents }
+    }
+}
+
+impl Ray
+{
+    pub fn new(origin: Vector3<f32>, direction: Vector3<f32>) -> Self
+    {
+        Ray { origin, direction: direction.normalize() }
+    }
+
+    pub fn get_point(&self, distance: f32) -> Vector3<f32>
+    {
+        self.origin + self.direction * distance
+    }
+}
+
+impl BoundingSphere
+{
+    /// Ritter's sphere, or the sphere around the box center when that one is smaller.
+    pub fn from_points(points: &[Vector3<f32>]) -> Self
+    {
+        if points.is_empty()
+        {
+            return BoundingSphere { center: Vector3::zero(), radius: 0.0 };
+        }
+
+        // Start from the most distant pair among the points with minimal and maximal coordinates.
+        let mut extremes = [(points[0], points[0]); 3];
+        for point in points
+        {
+            for axis in 0..3
+            {
+                if point[axis] < extremes[axis].0[axis] { extremes[axis].0 = *point; }
+                if point[axis] > extremes[axis].1[axis] { extremes[axis].1 = *point; }
+            }
+        }
+        let (min, max) = extremes.iter()
+            .max_by(|a, b| a.0.distance2(a.1).partial_cmp(&b.0.distance2(b.1)).unwrap_or(Ordering::Equal))
+            .copied()
+            .unwrap();
+
+        let mut center = (min + max) * 0.5;
+        let mut radius = min.distance(max) * 0.5;
+        for point in points
+        {
+            let distance = point.distance(center);
+            if distance > radius
+            {
+                let new_radius = (radius + distance) * 0.5;
+                center += (point - center) * ((new_radius - radius) / distance);
+                radius = new_radius;
+            }
+        }
+
+        let box_center = BoundingBox::from_points(points).get_center();
+        let box_radius = points.iter().map(|point| point.distance(box_center)).fold(0.0, f32::max);
+        if box_radius < radius
+        {
+            return BoundingSphere { center: box_center, radius: box_radius };
+        }
+        BoundingSphere { center, radius }
+    }
+
+    /// Sphere around the transformed sphere, scaled by the largest axis scale of the transform.
+    pub fn transform(&self, transform: &Matrix4<f32>) -> BoundingSphere
+    {
+        let center = (transform * self.center.extend(1.0)).truncate();
+        let scale = transform.x.truncate().magnitude().max(transform.y.truncate().magnitude()).max(transform.z.truncate().magnitude());
+        BoundingSphere { center, radius: self.radius * scale }
+    }
+}
+
+// Eigenvectors of a symmetric matrix as columns, by cyclic Jacobi rotations.
+fn get_symmetric_eigenvectors(matrix: Matrix3<f32>) -> Matrix3<f32>
+{
+    let mut a = matrix;
+    let mut vectors = Matrix3::identity();
+    for _ in 0..JACOBI_MAX_SWEEPS
+    {
+        let off_diagonal = a[1][0].abs() + a[2][0].abs() + a[2][1].abs();
+        if off_diagonal < 1e-9
+        {
+            break;
+        }
+        for (p, q) in [(0, 1), (0, 2), (1, 2)]
+        {
+            if a[q][p].abs() < 1e-12
+            {
+                continue;
+            }
+            let theta = (a[q][q] - a[p][p]) / (2.0 * a[q][p]);
+            let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+            let c = 1.0 / (t * t + 1.0).sqrt();
+            let s = t * c;
+            let mut rotation = Matrix3::identity();
+            rotation[p][p] = c;
+            rotation[q][q] = c;
+            rotation[q][p] = s;
+            rotation[p][q] = -s;
+            a = rotation.transpose() * a * rotation;
+            vectors = vectors * rotation;
+        }
+    }
+    vectors
+}
+
+impl OrientedBoundingBox
+{
+    /// Box aligned to the principal axes of the points.
+    pub fn from_points(points: &[Vector3<f32>]) -> Self
+    {
+        if points.is_empty()
+        {
+            return OrientedBoundingBox { center: Vector3::zero(), axes: [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()], half_extents: Vector3::zero() };
+        }
+
+        let mean = points.iter().fold(Vector3::zero(), |sum, point| sum + point) / points.len() as f32;
+        let mut covariance = Matrix3::from_value(0.0);
+        for point in points
+        {
+            let offset = point - mean;
+            for column in 0..3
+            {
+                for row in 0..3
+                {
+                    covariance[column][row] += offset[column] * offset[row];
+                }
+            }
+        }
+        covariance /= points.len() as f32;
+
+        let eigenvectors = get_symmetric_eigenvectors(covariance);
+        let x = eigenvectors.x.normalize();
+        let y = (eigenvectors.y - x * x.dot(eigenvectors.y)).normalize();
+        let axes = [x, y, x.cross(y)];
+
+        let mut min = Vector3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Vector3::new(f32::MIN, f32::MIN, f32::MIN);
+        for point in points
+        {
+            let offset = point - mean;
+            for axis in 0..3
+            {
+                let distance = offset.dot(axes[axis]);
+                min[axis] = min[axis].min(distance);
+                max[axis] = max[axis].max(distance);
+            }
+        }
+        let local_center = (min + max) * 0.5;
+        OrientedBoundingBox
+        {
+            center: mean + axes[0] * local_center.x + axes[1] * local_center.y + axes[2] * local_center.z,
+            axes,
+            half_extents: (max - min) * 0.5
+        }
+    }
+
+    pub fn get_corners(&self) -> [Vector3<f32>; 8]
+    {
+        let mut corners = [self.center; 8];
+        for (corner, point) in corners.iter_mut().enumerate()
+        {
+            for axis in 0..3
+            {
+                let sign = if corner & (1 << axis) != 0 { 1.0 } else { -1.0 };
+                *point += self.axes[axis] * (self.half_extents[axis] * sign);
+            }
+        }
+        corners
+    }
+}
+
+impl Mesh
+{
+    pub fn get_positions(&self) -> Vec<Vector3<f32>>
+    {
+        (0..self.get_vertex_count()).map(|vertex| self.get_position(vertex)).collect()
+    }
+
+    /// Computes the local space bounds of the position channel, the oriented box is optional as it costs an extra pass.
+    pub fn compute_bounds(&self, compute_oriented_box: bool) -> MeshBounds
+    {
+        let positions = self.get_positions();
+        MeshBounds
+        {
+            bounding_box: BoundingBox::from_points(&positions),
+            bounding_sphere: BoundingSphere::from_points(&positions),
+            oriented_bounding_box: if compute_oriented_box { Some(OrientedBoundingBox::from_points(&positions)) } else { None }
+        }
+    }
+
+    /// Computes and caches the bounds. The cache is refreshed by vertex remapping, code that edits
+    /// mesh_channel_data directly has to call this again.
+    pub fn update_bounds(&mut self, compute_oriented_box: bool) -> &MeshBounds
+    {
+        self.mesh_bounds = Some(self.compute_bounds(compute_oriented_box));
+        self.mesh_bounds.as_ref().unwrap()
+    }
+
+    pub fn get_bounds(&self) -> Option<&MeshBounds>
+    {
+        self.mesh_bounds.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use cgmath::{Deg, Quaternion, Rotation3};
+
+    use super::*;
+
+    // Points of a stretched and rotated box, spread by a small linear congruential generator.
+    fn create_point_cloud() -> Vec<Vector3<f32>>
+    {
+        let rotation = Quaternion::from_axis_angle(Vector3::new(1.0, 2.0, 3.0).normalize(), Deg(35.0));
+        let mut state = 12345u32;
+        let mut next = ||
+        {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 8) as f32 / (1u32 << 24) as f32 * 2.0 - 1.0
+        };
+        (0..200).map(|_|
+        {
+            let local = Vector3::new(next() * 4.0, next() * 1.5, next() * 0.5);
+            rotation * local + Vector3::new(3.0, -2.0, 1.0)
+        }).collect()
+    }
+
+    #[test]
+    fn ritter_sphere_contains_every_point()
+    {
+        let points = create_point_cloud();
+        let sphere = BoundingSphere::from_points(&points);
+        for point in &points
+        {
+            assert!(point.distance(sphere.center) <= sphere.radius * 1.0001 + 1e-5);
+        }
+        let box_radius = BoundingBox::from_points(&points).get_half_extents().magnitude();
+        assert!(sphere.radius <= box_radius + 1e-5);
+    }
+
+    #[test]
+    fn oriented_box_contains_every_point_with_orthonormal_axes()
+    {
+        let points = create_point_cloud();
+        let oriented_box = OrientedBoundingBox::from_points(&points);
+        for (index, axis) in oriented_box.axes.iter().enumerate()
+        {
+            assert!((axis.magnitude() - 1.0).abs() < 1e-4);
+            for other in &oriented_box.axes[index + 1..]
+            {
+                assert!(axis.dot(*other).abs() < 1e-4);
+            }
+        }
+        assert!(oriented_box.axes[0].cross(oriented_box.axes[1]).dot(oriented_box.axes[2]) > 0.0);
+        for point in &points
+        {
+            let offset = point - oriented_box.center;
+            for axis in 0..3
+            {
+                assert!(offset.dot(oriented_box.axes[axis]).abs() <= oriented_box.half_extents[axis] + 1e-4);
+            }
+        }
+
+        // The principal axes of the stretched box are tighter than the axis aligned box.
+        let half_extents = oriented_box.half_extents;
+        let aligned_half_extents = BoundingBox::from_points(&points).get_half_extents();
+        assert!(half_extents.x * half_extents.y * half_extents.z < aligned_half_extents.x * aligned_half_extents.y * aligned_half_extents.z);
+    }
+
+    #[test]
+    fn transformed_boxes_contain_the_transformed_corners()
+    {
+        let bounding_box = BoundingBox { min: Vector3::new(-1.0, -2.0, -0.5), max: Vector3::new(2.0, 1.0, 0.5) };
+        let transform = Matrix4::from_translation(Vector3::new(5.0, 0.0, -3.0))
+            * Matrix4::from(Quaternion::from_axis_angle(Vector3::unit_z(), Deg(90.0)))
+            * Matrix4::from_nonuniform_scale(2.0, 1.0, 3.0);
+        let transformed = bounding_box.transform(&transform);
+        let expected = BoundingBox::from_points(&bounding_box.get_corners().map(|corner| (transform * corner.extend(1.0)).truncate()));
+        assert!((transformed.min - expected.min).magnitude() < 1e-4);
+        assert!((transformed.max - expected.max).magnitude() < 1e-4);
+        assert!((transformed.min - Vector3::new(4.0, -2.0, -4.5)).magnitude() < 1e-4);
+        assert!((transformed.max - Vector3::new(7.0, 4.0, -1.5)).magnitude() < 1e-4);
+
+        assert!(BoundingBox::default().transform(&transform).is_empty());
+    }
+
+    #[test]
+    fn rays_enter_boxes_at_their_nearest_face()
+    {
+        let bounding_box = BoundingBox { min: Vector3::new(-1.0, -1.0, -1.0), max: Vector3::new(1.0, 1.0, 1.0) };
+        let ray = Ray::new(Vector3::new(-5.0, 0.5, 0.0), Vector3::unit_x());
+        assert_eq!(bounding_box.intersect_ray(&ray, 100.0), Some(4.0));
+        assert_eq!(bounding_box.intersect_ray(&ray, 3.0), None);
+
+        let inside = Ray::new(Vector3::zero(), Vector3::new(1.0, 1.0, 0.0));
+        assert_eq!(bounding_box.intersect_ray(&inside, 100.0), Some(0.0));
+
+        let diagonal = Ray::new(Vector3::new(-3.0, -3.0, 0.0), Vector3::new(1.0, 1.0, 0.0));
+        let distance = bounding_box.intersect_ray(&diagonal, 100.0).unwrap();
+        assert!((diagonal.get_point(distance) - Vector3::new(-1.0, -1.0, 0.0)).magnitude() < 1e-5);
+
+        // Missing to the side and pointing away both miss, running along a face grazes it.
+        assert_eq!(bounding_box.intersect_ray(&Ray::new(Vector3::new(-5.0, 2.0, 0.0), Vector3::unit_x()), 100.0), None);
+        assert_eq!(bounding_box.intersect_ray(&Ray::new(Vector3::new(-5.0, 0.0, 0.0), -Vector3::unit_x()), 100.0), None);
+        assert_eq!(bounding_box.intersect_ray(&Ray::new(Vector3::new(-5.0, 1.0, 0.0), Vector3::unit_x()), 100.0), Some(4.0));
+        assert_eq!(bounding_box.intersect_ray(&Ray::new(Vector3::new(-5.0, -1.0, 0.0), Vector3::unit_x()), 100.0), Some(4.0));
+        assert_eq!(BoundingBox::default().intersect_ray(&ray, 100.0), None);
+    }
+
+    #[test]
+    fn spheres_intersect_boxes_by_their_closest_point()
+    {
+        let bounding_box = BoundingBox { min: Vector3::new(-1.0, -1.0, -1.0), max: Vector3::new(1.0, 1.0, 1.0) };
+        assert!(bounding_box.intersects_sphere(Vector3::zero(), 0.1));
+        assert!(bounding_box.intersects_sphere(Vector3::new(2.0, 0.0, 0.0), 1.0));
+        assert!(!bounding_box.intersects_sphere(Vector3::new(2.0, 0.0, 0.0), 0.9));
+        // Near a corner the distance is diagonal, so the radius that reaches a face is not enough.
+        assert!(!bounding_box.intersects_sphere(Vector3::new(2.0, 2.0, 2.0), 1.5));
+        assert!(bounding_box.intersects_sphere(Vector3::new(2.0, 2.0, 2.0), 1.75));
+        assert!(!BoundingBox::default().intersects_sphere(Vector3::zero(), 10.0));
+    }
+}
+
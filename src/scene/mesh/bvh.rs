@@ -0,0 +1,180 @@
+use cgmath::Vector3;
+
+use crate::scene::mesh::bounds::*;
+
+const MAX_LEAF_SIZE: usize = 4;
+// Larger leaves are only made when the heuristic finds them cheaper than any split.
+const MAX_SAH_LEAF_SIZE: usize = 16;
+const SAH_BIN_COUNT: usize = 16;
+// Cost of visiting a node relative to testing one item.
+const SAH_TRAVERSAL_COST: f32 = 1.0;
+pub(crate) const NO_PARENT: u32 = u32::MAX;
+
+// Node of the hierarchies over scene proxies and over triangles. Children always come after their parent, node 0 is
+// the root.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BvhNode
+{
+    pub bounding_box: BoundingBox,
+    pub parent: u32,
+    // First child for inner nodes, the second child follows it. First item for leaves.
+    pub first: u32,
+    // Zero for inner nodes.
+    pub count: u32
+}
+
+#[derive(Clone, Copy)]
+struct SahBin
+{
+    bounding_box: BoundingBox,
+    count: usize
+}
+
+// Partitions the items and returns the size of the first half. None when a leaf is cheaper than any split, or when
+// all items have the same center and cannot be split.
+fn find_sah_split(items: &mut [usize], boxes: &[BoundingBox], centers: &[Vector3<f32>], bounding_box: &BoundingBox) -> Option<usize>
+{
+    let center_box = items.iter().fold(BoundingBox::default(), |bounds, item| bounds.union(&BoundingBox { min: centers[*item], max: centers[*item] }));
+    let center_extent = center_box.max - center_box.min;
+    let get_bin = |item: usize, axis: usize| -> usize {
+        let relative = (centers[item][axis] - center_box.min[axis]) / center_extent[axis];
+        ((relative * SAH_BIN_COUNT as f32) as usize).min(SAH_BIN_COUNT - 1)
+    };
+
+    let mut best: Option<(f32, usize, usize)> = None;
+    for axis in 0..3
+    {
+        if center_extent[axis] <= 0.0
+        {
+            continue;
+        }
+        let mut bins = [SahBin { bounding_box: BoundingBox::default(), count: 0 }; SAH_BIN_COUNT];
+        for item in items.iter()
+        {
+            let bin = &mut bins[get_bin(*item, axis)];
+            bin.bounding_box = bin.bounding_box.union(&boxes[*item]);
+            bin.count += 1;
+        }
+        // Cost of the items right of each split plane, swept from the right.
+        let mut right_costs = [0.0; SAH_BIN_COUNT];
+        let (mut right_box, mut right_count) = (BoundingBox::default(), 0);
+        for bin in (1..SAH_BIN_COUNT).rev()
+        {
+            right_box = right_box.union(&bins[bin].bounding_box);
+            right_count += bins[bin].count;
+            right_costs[bin] = right_box.get_surface_area() * right_count as f32;
+        }
+        let (mut left_box, mut left_count) = (BoundingBox::default(), 0);
+        for bin in 1..SAH_BIN_COUNT
+        {
+            left_box = left_box.union(&bins[bin - 1].bounding_box);
+            left_count += bins[bin - 1].count;
+            let cost = left_box.get_surface_area() * left_count as f32 + right_costs[bin];
+            if left_count > 0 && left_count < items.len() && best.is_none_or(|(best_cost, _, _)| cost < best_cost)
+            {
+                best = Some((cost, axis, bin));
+            }
+        }
+    }
+
+    let (cost, axis, split_bin) = best?;
+    let area = bounding_box.get_surface_area();
+    let split_cost = if area > 0.0 { SAH_TRAVERSAL_COST + cost / area } else { SAH_TRAVERSAL_COST };
+    if split_cost >= items.len() as f32 && items.len() <= MAX_SAH_LEAF_SIZE
+    {
+        return None;
+    }
+    let mut split = 0;
+    for index in 0..items.len()
+    {
+        if get_bin(items[index], axis) < split_bin
+        {
+            items.swap(index, split);
+            split += 1;
+        }
+    }
+    Some(split)
+}
+
+// Builds a hierarchy with binned surface area heuristic splits. Returns the nodes and the items in leaf order, leaves
+// refer to positions in that order. Empty boxes are allowed and never hit.
+pub(crate) fn build_bvh(boxes: &[BoundingBox]) -> (Vec<BvhNode>, Vec<usize>)
+{
+    let mut order: Vec<usize> = (0..boxes.len()).collect();
+    if boxes.is_empty()
+    {
+        return (vec![], order);
+    }
+    let centers: Vec<Vector3<f32>> = boxes.iter()
+        .map(|bounding_box| if bounding_box.is_empty() { Vector3::new(0.0, 0.0, 0.0) } else { bounding_box.get_center() })
+        .collect();
+    let mut nodes = vec![BvhNode { bounding_box: BoundingBox::default(), parent: NO_PARENT, first: 0, count: 0 }];
+    let mut pending = vec![(0, 0, order.len())];
+    while let Some((node, start, end)) = pending.pop()
+    {
+        let bounding_box = order[start..end].iter().fold(BoundingBox::default(), |bounds, item| bounds.union(&boxes[*item]));
+        nodes[node].bounding_box = bounding_box;
+        let split = if end - start > MAX_LEAF_SIZE { find_sah_split(&mut order[start..end], boxes, &centers, &bounding_box) } else { None };
+        match split
+        {
+            Some(split) =>
+            {
+                let first_child = nodes.len();
+                for _ in 0..2
+                {
+                    nodes.push(BvhNode { bounding_box: BoundingBox::default(), parent: node as u32, first: 0, count: 0 });
+                }
+                nodes[node].first = first_child as u32;
+                pending.push((first_child, start, start + split));
+                pending.push((first_child + 1, start + split, end));
+            }
+            None =>
+            {
+                nodes[node].first = start as u32;
+                nodes[node].count = (end - start) as u32;
+            }
+        }
+    }
+    (nodes, order)
+}
+
+// Closest hit along the ray, visiting nodes front to back and skipping them once they start behind the closest hit.
+// intersect gets the position of an item in leaf order and the closest distance so far, and returns its own hit.
+pub(crate) fn cast_ray_bvh(nodes: &[BvhNode], ray: &Ray, max_distance: f32, mut intersect: impl FnMut(usize, f32) -> Option<f32>) -> Option<(usize, f32)>
+{
+    let mut closest: Option<(usize, f32)> = None;
+    let mut closest_distance = max_distance;
+    let mut pending = match nodes.first().and_then(|root| root.bounding_box.intersect_ray(ray, max_distance))
+    {
+        Some(distance) => vec![(0, distance)],
+        None => vec![]
+    };
+    while let Some((node, entry_distance)) = pending.pop()
+    {
+        if entry_distance > closest_distance
+        {
+            continue;
+        }
+        let node = &nodes[node];
+        if node.count == 0
+        {
+            let children = [node.first as usize, node.first as usize + 1];
+            let mut hits: Vec<(usize, f32)> = children.iter()
+                .filter_map(|child| nodes[*child].bounding_box.intersect_ray(ray, closest_distance).map(|distance| (*child, distance)))
+                .collect();
+            // The nearer child is popped first.
+            hits.sort_by(|a, b| b.1.total_cmp(&a.1));
+            pending.extend(hits);
+            continue;
+        }
+        for item in node.first as usize..(node.first + node.count) as usize
+        {
+            if let Some(distance) = intersect(item, closest_distance).filter(|distance| *distance <= closest_distance)
+            {
+                closest_distance = distance;
+                closest = Some((item, distance));
+            }
+        }
+    }
+    closest
+}
@@ -0,0 +1,368 @@
+use cgmath::{InnerSpace, MetricSpace, Vector3, Vector4, Zero};
+
+use crate::scene::mesh::*;
+use crate::scene::mesh::bounds::*;
+use crate::scene::mesh::validation::MeshError;
+
+// Limits that fit the mesh shader output of most hardware well, one thread per vertex in a group of 64 or 128.
+pub const MESHLET_DEFAULT_MAX_VERTICES: usize = 64;
+pub const MESHLET_DEFAULT_MAX_PRIMITIVES: usize = 124;
+// D3D12 mesh shaders output at most 256 vertices and primitives, a packed index has 10 bits.
+pub const MESHLET_MAX_VERTICES_LIMIT: usize = 256;
+pub const MESHLET_MAX_PRIMITIVES_LIMIT: usize = 256;
+
+// How much a triangle facing away from the meshlet is penalized compared to one further away, keeps cones tight.
+const MESHLET_CONE_WEIGHT: f32 = 0.25;
+
+/// Ranges of one meshlet in MeshletData::vertex_indices and MeshletData::primitive_indices.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Meshlet
+{
+    pub vertex_offset: u32,
+    pub vertex_count: u32,
+    pub primitive_offset: u32,
+    pub primitive_count: u32
+}
+
+/// Culling data of a meshlet, laid out as float4 triples for a structured buffer.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MeshletBounds
+{
+    pub center: [f32; 3],
+    pub radius: f32,
+    // All triangles face away from any viewer inside the cone at cone_apex around -cone_axis with sine cone_cutoff.
+    pub cone_apex: [f32; 3],
+    pub cone_cutoff: f32,
+    pub cone_axis: [f32; 3],
+    pub padding: f32
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MeshletData
+{
+    pub meshlets: Vec<Meshlet>,
+    pub bounds: Vec<MeshletBounds>,
+    // Mesh vertex indices, vertex_count of them per meshlet.
+    pub vertex_indices: Vec<u32>,
+    // One triangle per entry, three 10 bit indices into the meshlet's vertices.
+    pub primitive_indices: Vec<u32>,
+    // Index into Mesh::get_sections of every meshlet, u32::MAX for triangles outside of all sections.
+    pub sections: Vec<u32>
+}
+
+pub fn pack_meshlet_triangle(i0: u32, i1: u32, i2: u32) -> u32
+{
+    (i0 & 0x3ff) | ((i1 & 0x3ff) << 10) | ((i2 & 0x3ff) << 20)
+}
+
+pub fn unpack_meshlet_triangle(packed: u32) -> [u32; 3]
+{
+    [packed & 0x3ff, (packed >> 10) & 0x3ff, (packed >> 20) & 0x3ff]
+}
+
+impl MeshletBounds
+{
+    /// True when every triangle faces away from a perspective camera at camera_position.
+    pub fn is_cone_culled(&self, camera_position: Vector3<f32>) -> bool
+    {
+        let apex = Vector3::from(self.cone_apex);
+        let direction = apex - camera_position;
+        if direction.magnitude2() == 0.0
+        {
+            return false;
+        }
+        direction.normalize().dot(Vector3::from(self.cone_axis)) >= self.cone_cutoff
+    }
+
+    /// True when every triangle faces away from an orthographic camera looking along view_direction.
+    pub fn is_cone_culled_orthographic(&self, view_direction: Vector3<f32>) -> bool
+    {
+        view_direction.normalize().dot(Vector3::from(self.cone_axis)) >= self.cone_cutoff
+    }
+
+    /// True when the bounding sphere is completely outside one of the planes, which are (normal, distance) with
+    /// normals pointing inside.
+    pub fn is_sphere_culled(&self, planes: &[Vector4<f32>]) -> bool
+    {
+        let center = Vector3::from(self.center);
+        planes.iter().any(|plane| plane.truncate().dot(center) + plane.w < -self.radius)
+    }
+}
+
+fn compute_meshlet_bounds(triangles: &[[Vector3<f32>; 3]]) -> MeshletBounds
+{
+    let points: Vec<Vector3<f32>> = triangles.iter().flat_map(|triangle| triangle.iter().copied()).collect();
+    let BoundingSphere { center, radius } = BoundingSphere::from_points(&points);
+    let mut bounds = MeshletBounds { center: center.into(), radius, cone_cutoff: 1.0, ..Default::default() };
+
+    let normals: Vec<Vector3<f32>> = triangles.iter()
+        .map(|triangle| (triangle[1] - triangle[0]).cross(triangle[2] - triangle[0]))
+        .filter(|normal| normal.magnitude2() > 0.0)
+        .map(|normal| normal.normalize())
+        .collect();
+    let axis = normals.iter().fold(Vector3::zero(), |sum, normal| sum + normal);
+    if normals.is_empty() || axis.magnitude2() == 0.0
+    {
+        return bounds;
+    }
+    let axis = axis.normalize();
+    let min_dot = normals.iter().map(|normal| normal.dot(axis)).fold(1.0f32, f32::min);
+    // Normals spread over more than a hemisphere, no viewer sees only back faces.
+    if min_dot <= 0.1
+    {
+        return bounds;
+    }
+
+    // The apex is the point on the axis behind the center that lies behind the plane of every triangle.
+    let mut max_t: f32 = 0.0;
+    for triangle in triangles
+    {
+        let normal = (triangle[1] - triangle[0]).cross(triangle[2] - triangle[0]);
+        if normal.magnitude2() == 0.0
+        {
+            continue;
+        }
+        let normal = normal.normalize();
+        max_t = max_t.max((center - triangle[0]).dot(normal) / axis.dot(normal));
+    }
+
+    bounds.cone_apex = (center - axis * max_t).into();
+    bounds.cone_axis = axis.into();
+    bounds.cone_cutoff = (1.0 - min_dot * min_dot).sqrt();
+    bounds
+}
+
+impl Mesh
+{
+    /// Splits the triangles into meshlets of at most max_vertices vertices and max_primitives triangles. Meshlets grow
+    /// over adjacent triangles of the same section that add the fewest vertices, so run optimize_vertex_cache first for
+    /// a good seed order.
+    pub fn build_meshlets(&self, max_vertices: usize, max_primitives: usize) -> Result<MeshletData, MeshError>
+    {
+        if !(3..=MESHLET_MAX_VERTICES_LIMIT).contains(&max_vertices) || !(1..=MESHLET_MAX_PRIMITIVES_LIMIT).contains(&max_primitives)
+        {
+            return Err(MeshError::InvalidMeshletLimits { max_vertices, max_primitives });
+        }
+        self.validate_structure()?;
+
+        let vertex_count = self.get_vertex_count();
+        let indices = self.get_triangle_indices();
+        let triangle_count = indices.len() / 3;
+        let triangle_sections = self.get_triangle_sections();
+
+        let mut vertex_triangles: Vec<Vec<usize>> = vec![vec![]; vertex_count];
+        let mut triangle_centroids = Vec::with_capacity(triangle_count);
+        let mut triangle_normals = Vec::with_capacity(triangle_count);
+        for (triangle, vertices) in indices.chunks_exact(3).enumerate()
+        {
+            for vertex in vertices
+            {
+                vertex_triangles[*vertex as usize].push(triangle);
+            }
+            let points = [0, 1, 2].map(|corner| self.get_position(vertices[corner] as usize));
+            let normal = (points[1] - points[0]).cross(points[2] - points[0]);
+            triangle_centroids.push((points[0] + points[1] + points[2]) / 3.0);
+            triangle_normals.push(if normal.magnitude2() > 0.0 { normal.normalize() } else { Vector3::zero() });
+        }
+
+        let mut min = Vector3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Vector3::new(f32::MIN, f32::MIN, f32::MIN);
+        for centroid in &triangle_centroids
+        {
+            min = Vector3::new(min.x.min(centroid.x), min.y.min(centroid.y), min.z.min(centroid.z));
+            max = Vector3::new(max.x.max(centroid.x), max.y.max(centroid.y), max.z.max(centroid.z));
+        }
+        let extent = if triangle_count > 0 { (max - min).magnitude().max(f32::EPSILON) } else { 1.0 };
+
+        let mut data = MeshletData::default();
+        let mut is_used = vec![false; triangle_count];
+        let mut local_vertices: Vec<Option<u32>> = vec![None; vertex_count];
+        let mut next_seed = 0;
+        loop
+        {
+            while next_seed < triangle_count && is_used[next_seed]
+            {
+                next_seed += 1;
+            }
+            if next_seed >= triangle_count
+            {
+                break;
+            }
+
+            let mut meshlet = Meshlet { vertex_offset: data.vertex_indices.len() as u32, primitive_offset: data.primitive_indices.len() as u32, ..Default::default() };
+            let mut centroid_sum = Vector3::zero();
+            let mut normal_sum = Vector3::zero();
+            let mut next_triangle = Some(next_seed);
+            while let Some(triangle) = next_triangle
+            {
+                is_used[triangle] = true;
+                let mut local = [0; 3];
+                for (corner, vertex) in indices[triangle * 3..triangle * 3 + 3].iter().enumerate()
+                {
+                    local[corner] = *local_vertices[*vertex as usize].get_or_insert_with(|| {
+                        data.vertex_indices.push(*vertex);
+                        meshlet.vertex_count += 1;
+                        meshlet.vertex_count - 1
+                    });
+                }
+                data.primitive_indices.push(pack_meshlet_triangle(local[0], local[1], local[2]));
+                meshlet.primitive_count += 1;
+                centroid_sum += triangle_centroids[triangle];
+                normal_sum += triangle_normals[triangle];
+
+                next_triangle = None;
+                if meshlet.primitive_count as usize >= max_primitives
+                {
+                    break;
+                }
+                let centroid = centroid_sum / meshlet.primitive_count as f32;
+                let normal = if normal_sum.magnitude2() > 0.0 { normal_sum.normalize() } else { Vector3::zero() };
+                let mut best_score = (usize::MAX, f32::MAX);
+                let meshlet_vertices = &data.vertex_indices[meshlet.vertex_offset as usize..];
+                for vertex in meshlet_vertices
+                {
+                    for candidate in &vertex_triangles[*vertex as usize]
+                    {
+                        if is_used[*candidate] || triangle_sections[*candidate] != triangle_sections[next_seed]
+                        {
+                            continue;
+                        }
+                        let new_vertex_count = indices[candidate * 3..candidate * 3 + 3].iter()
+                            .filter(|vertex| local_vertices[**vertex as usize].is_none())
+                            .count();
+                        if meshlet.vertex_count as usize + new_vertex_count > max_vertices
+                        {
+                            continue;
+                        }
+                        let spread = triangle_centroids[*candidate].distance(centroid) / extent
+                            + MESHLET_CONE_WEIGHT * (1.0 - triangle_normals[*candidate].dot(normal));
+                        let score = (new_vertex_count, spread);
+                        if score.0 < best_score.0 || (score.0 == best_score.0 && score.1 < best_score.1)
+                        {
+                            best_score = score;
+                            next_triangle = Some(*candidate);
+                        }
+                    }
+                }
+            }
+
+            let meshlet_vertices = &data.vertex_indices[meshlet.vertex_offset as usize..];
+            for vertex in meshlet_vertices
+            {
+                local_vertices[*vertex as usize] = None;
+            }
+            let triangles: Vec<[Vector3<f32>; 3]> = data.primitive_indices[meshlet.primitive_offset as usize..].iter()
+                .map(|packed| unpack_meshlet_triangle(*packed).map(|local| self.get_position(meshlet_vertices[local as usize] as usize)))
+                .collect();
+            data.bounds.push(compute_meshlet_bounds(&triangles));
+            data.sections.push(triangle_sections[next_seed]);
+            data.meshlets.push(meshlet);
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn get_meshlet_triangles(data: &MeshletData) -> Vec<[u32; 3]>
+    {
+        let mut triangles = vec![];
+        for meshlet in &data.meshlets
+        {
+            let vertices = &data.vertex_indices[meshlet.vertex_offset as usize..(meshlet.vertex_offset + meshlet.vertex_count) as usize];
+            for packed in &data.primitive_indices[meshlet.primitive_offset as usize..(meshlet.primitive_offset + meshlet.primitive_count) as usize]
+            {
+                triangles.push(unpack_meshlet_triangle(*packed).map(|local| vertices[local as usize]));
+            }
+        }
+        triangles
+    }
+
+    #[test]
+    fn pack_unpack_round_trip()
+    {
+        for triangle in [[0, 0, 0], [1, 2, 3], [255, 0, 128], [1023, 512, 7]]
+        {
+            assert_eq!(unpack_meshlet_triangle(pack_meshlet_triangle(triangle[0], triangle[1], triangle[2])), triangle);
+        }
+    }
+
+    #[test]
+    fn limits_are_respected()
+    {
+        let mesh = Mesh::create_icosphere(1.0, 3);
+        for (max_vertices, max_primitives) in [(3, 1), (16, 8), (MESHLET_DEFAULT_MAX_VERTICES, MESHLET_DEFAULT_MAX_PRIMITIVES), (256, 256)]
+        {
+            let data = mesh.build_meshlets(max_vertices, max_primitives).unwrap();
+            assert_eq!(data.meshlets.len(), data.bounds.len());
+            for meshlet in &data.meshlets
+            {
+                assert!(meshlet.vertex_count as usize <= max_vertices);
+                assert!(meshlet.primitive_count >= 1 && meshlet.primitive_count as usize <= max_primitives);
+                let primitives = &data.primitive_indices[meshlet.primitive_offset as usize..(meshlet.primitive_offset + meshlet.primitive_count) as usize];
+                assert!(primitives.iter().all(|packed| unpack_meshlet_triangle(*packed).iter().all(|local| *local < meshlet.vertex_count)));
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_limits_are_rejected()
+    {
+        let mesh = Mesh::create_icosphere(1.0, 0);
+        for (max_vertices, max_primitives) in [(2, 64), (257, 64), (64, 0), (64, 257)]
+        {
+            assert_eq!(mesh.build_meshlets(max_vertices, max_primitives).unwrap_err(), MeshError::InvalidMeshletLimits { max_vertices, max_primitives });
+        }
+    }
+
+    #[test]
+    fn every_triangle_is_in_one_meshlet()
+    {
+        let mesh = Mesh::create_torus(1.0, 0.25, 24, 12);
+        let data = mesh.build_meshlets(MESHLET_DEFAULT_MAX_VERTICES, MESHLET_DEFAULT_MAX_PRIMITIVES).unwrap();
+        let mut expected: Vec<[u32; 3]> = mesh.get_triangle_indices().chunks_exact(3).map(|triangle| [triangle[0], triangle[1], triangle[2]]).collect();
+        let mut triangles = get_meshlet_triangles(&data);
+        expected.sort();
+        triangles.sort();
+        assert_eq!(triangles, expected);
+    }
+
+    #[test]
+    fn cone_culls_viewers_behind_flat_meshlet()
+    {
+        let mesh = Mesh::create_plane(2.0, 2.0, 2, 2);
+        let data = mesh.build_meshlets(MESHLET_DEFAULT_MAX_VERTICES, MESHLET_DEFAULT_MAX_PRIMITIVES).unwrap();
+        assert_eq!(data.meshlets.len(), 1);
+        let indices = mesh.get_triangle_indices();
+        let points = [0, 1, 2].map(|corner| mesh.get_position(indices[corner] as usize));
+        let face_normal = (points[1] - points[0]).cross(points[2] - points[0]).normalize();
+        let bounds = data.bounds[0];
+        assert!(bounds.is_cone_culled(face_normal * -5.0));
+        assert!(!bounds.is_cone_culled(face_normal * 5.0));
+        assert!(bounds.is_cone_culled_orthographic(face_normal));
+        assert!(!bounds.is_cone_culled_orthographic(-face_normal));
+    }
+
+    #[test]
+    fn cone_is_disabled_for_closed_meshlet()
+    {
+        let data = Mesh::create_icosphere(1.0, 1).build_meshlets(MESHLET_MAX_VERTICES_LIMIT, MESHLET_MAX_PRIMITIVES_LIMIT).unwrap();
+        assert_eq!(data.meshlets.len(), 1);
+        assert!(!data.bounds[0].is_cone_culled(Vector3::new(0.0, 0.0, -5.0)));
+    }
+
+    #[test]
+    fn sphere_is_culled_only_outside_a_plane()
+    {
+        let bounds = MeshletBounds { center: [0.0, 0.0, 0.0], radius: 1.0, ..Default::default() };
+        assert!(bounds.is_sphere_culled(&[Vector4::new(1.0, 0.0, 0.0, -2.0)]));
+        assert!(!bounds.is_sphere_culled(&[Vector4::new(1.0, 0.0, 0.0, -0.5)]));
+        assert!(!bounds.is_sphere_culled(&[Vector4::new(0.0, 1.0, 0.0, 3.0), Vector4::new(0.0, 0.0, -1.0, 1.0)]));
+        assert!(bounds.is_sphere_culled(&[Vector4::new(0.0, 1.0, 0.0, 3.0), Vector4::new(0.0, 0.0, -1.0, -1.5)]));
+    }
+}
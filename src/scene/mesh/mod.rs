@@ -0,0 +1,380 @@
+pub mod normals;
+pub mod tangents;
+pub mod weld;
+pub mod optimize;
+pub mod simplify;
+pub mod meshlet;
+pub mod bounds;
+pub mod validation;
+pub mod vertex_format;
+pub mod primitives;
+pub mod skinning;
+pub mod morph;
+pub mod bvh;
+pub mod picking;
+
+use std::{collections::BTreeMap, sync::{mpsc::channel, RwLock}};
+use cgmath::Vector3;
+use lazy_static::lazy_static;
+use bounds::MeshBounds;
+use morph::MorphTarget;
+use validation::MeshError;
+use vertex_format::{get_vertex_layout, pack_vertex_element, VertexFormat, VertexLayout, VertexStreamLayout};
+
+pub type MeshChannelData = BTreeMap<usize, Vec<f32>>;
+
+// Ids of the built in channels, register_mesh_channel hands out the ids after them.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum MeshDataChannel
+{
+    Position = 0,
+    Normal = 1,
+    Tangent = 2,
+    UV0 = 3,
+    Color = 4,
+    UV1 = 5,
+    BoneIndices = 6,
+    BoneWeights = 7
+}
+
+impl From<MeshDataChannel> for usize
+{
+    fn from(channel: MeshDataChannel) -> usize
+    {
+        channel as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeshChannelDesc
+{
+    pub semantic_name: &'static str,
+    pub semantic_index: u32,
+    // Fills missing channels and components, its length is the component count of the channel.
+    pub default_value: &'static [f32]
+}
+
+impl MeshChannelDesc
+{
+    pub fn get_component_count(&self) -> usize
+    {
+        self.default_value.len()
+    }
+
+    // Define a vertex factory has to list to read the channel, VERTEX_FACTORY_USE_TEXCOORD1 for the second UV set.
+    pub fn get_vertex_factory_macro(&self) -> String
+    {
+        let index = if self.semantic_index > 0 { self.semantic_index.to_string() } else { String::new() };
+        format!("VERTEX_FACTORY_USE_{}{}", self.semantic_name.to_uppercase(), index)
+    }
+}
+
+lazy_static! {
+    // Indexed by channel id, the built in channels come first in the order of MeshDataChannel.
+    static ref GLOBAL_MESH_CHANNEL_REGISTRY: RwLock<Vec<MeshChannelDesc>> = RwLock::new(vec![
+        MeshChannelDesc { semantic_name: "Position", semantic_index: 0, default_value: &[0.0, 0.0, 0.0] },
+        MeshChannelDesc { semantic_name: "Normal", semantic_index: 0, default_value: &[0.0, 0.0, 1.0] },
+        // The w component holds the bitangent sign.
+        MeshChannelDesc { semantic_name: "Tangent", semantic_index: 0, default_value: &[0.0, 1.0, 0.0, 1.0] },
+        MeshChannelDesc { semantic_name: "TexCoord", semantic_index: 0, default_value: &[0.0, 0.0] },
+        MeshChannelDesc { semantic_name: "Color", semantic_index: 0, default_value: &[0.0, 0.0, 0.0, 1.0] },
+        MeshChannelDesc { semantic_name: "TexCoord", semantic_index: 1, default_value: &[0.0, 0.0] },
+        MeshChannelDesc { semantic_name: "BlendIndices", semantic_index: 0, default_value: &[0.0, 0.0, 0.0, 0.0] },
+        // Vertices without weights follow the first bone instead of collapsing to the origin.
+        MeshChannelDesc { semantic_name: "BlendWeight", semantic_index: 0, default_value: &[1.0, 0.0, 0.0, 0.0] },
+    ]);
+}
+
+/// Registers a custom channel with one to four f32 components and returns its id. Registering a semantic again
+/// returns the existing id, as long as the component count matches. Semantic names compare case insensitively like HLSL.
+pub fn register_mesh_channel(semantic_name: &str, semantic_index: u32, default_value: &[f32]) -> Result<usize, MeshError>
+{
+    let mut registry = GLOBAL_MESH_CHANNEL_REGISTRY.write().unwrap();
+    let existing = registry.iter().position(|desc| desc.semantic_name.eq_ignore_ascii_case(semantic_name) && desc.semantic_index == semantic_index);
+    let is_valid = match existing
+    {
+        Some(channel) => registry[channel].get_component_count() == default_value.len(),
+        None => !default_value.is_empty() && default_value.len() <= 4
+    };
+    if !is_valid
+    {
+        return Err(MeshError::InvalidChannelComponentCount { semantic_name: semantic_name.to_string(), semantic_index, component_count: default_value.len() });
+    }
+    if let Some(channel) = existing
+    {
+        return Ok(channel);
+    }
+
+    // Registrations live as long as the program, leaking them keeps MeshChannelDesc copyable.
+    registry.push(MeshChannelDesc
+    {
+        semantic_name: Box::leak(semantic_name.to_string().into_boxed_str()),
+        semantic_index,
+        default_value: Box::leak(default_value.to_vec().into_boxed_slice())
+    });
+    Ok(registry.len() - 1)
+}
+
+pub fn find_mesh_channel(semantic_name: &str, semantic_index: u32) -> Option<usize>
+{
+    GLOBAL_MESH_CHANNEL_REGISTRY.read().unwrap().iter()
+        .position(|desc| desc.semantic_name.eq_ignore_ascii_case(semantic_name) && desc.semantic_index == semantic_index)
+}
+
+pub fn get_mesh_channel_desc(channel: usize) -> Option<MeshChannelDesc>
+{
+    GLOBAL_MESH_CHANNEL_REGISTRY.read().unwrap().get(channel).copied()
+}
+
+pub fn get_mesh_channel_count() -> usize
+{
+    GLOBAL_MESH_CHANNEL_REGISTRY.read().unwrap().len()
+}
+
+// Panics for channels that are not registered.
+pub fn get_channel_default_value(channel: usize) -> &'static [f32]
+{
+    GLOBAL_MESH_CHANNEL_REGISTRY.read().unwrap()[channel].default_value
+}
+
+/// A range of whole triangles in the index buffer drawn with one material slot. Its indices are relative to
+/// base_vertex. Mesh processing expects base_vertex 0, which importers and processing produce. StaticMesh adds the
+/// base vertex of the level of detail when drawing.
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct MeshSection
+{
+    pub first_index: u32,
+    pub index_count: u32,
+    pub base_vertex: i32,
+    pub material_slot: Option<usize>
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeshPrimitiveTopology
+{
+    // Triangles of the index buffer, or of consecutive vertices for a mesh without indices.
+    #[default]
+    TriangleList,
+    // Vertices without triangles, such as point clouds. The mesh has no indices and no sections.
+    PointList
+}
+
+#[derive(Default, Clone, Debug)]
+pub struct Mesh
+{
+    pub mesh_channel_data : MeshChannelData,
+    pub mesh_index_data : Vec<u32>,
+    // Empty for a mesh drawn as a single range, see get_sections.
+    pub mesh_sections : Vec<MeshSection>,
+    // Vertex buffer format per channel, channels without an entry are stored as Float32.
+    pub mesh_channel_formats : BTreeMap<usize, VertexFormat>,
+    pub mesh_stream_layout : VertexStreamLayout,
+    pub mesh_morph_targets : Vec<MorphTarget>,
+    // Local space bounds of the position channel, see update_bounds.
+    pub mesh_bounds : Option<MeshBounds>,
+    pub mesh_topology : MeshPrimitiveTopology
+}
+
+impl Mesh
+{
+    /// Channels stored in the vertex buffer: position to color are always there, filled with defaults when
+    /// missing, the other channels only when the mesh has data for them.
+    pub fn get_vertex_channels(&self) -> Vec<usize>
+    {
+        let mut channels: Vec<usize> = (0..=MeshDataChannel::Color as usize).collect();
+        channels.extend(self.mesh_channel_data.keys().filter(|channel| **channel > MeshDataChannel::Color as usize));
+        channels
+    }
+
+    pub fn get_vertex_count(&self) -> usize
+    {
+        self.mesh_channel_data.get(&(MeshDataChannel::Position as usize)).map_or(0, |positions| positions.len() / 3)
+    }
+
+    pub fn get_position(&self, vertex: usize) -> Vector3<f32>
+    {
+        let positions = &self.mesh_channel_data[&(MeshDataChannel::Position as usize)];
+        Vector3::new(positions[vertex * 3], positions[vertex * 3 + 1], positions[vertex * 3 + 2])
+    }
+
+    // A mesh without index data is treated as a triangle soup, point lists have no triangles.
+    pub fn get_triangle_indices(&self) -> Vec<u32>
+    {
+        if self.mesh_topology == MeshPrimitiveTopology::PointList
+        {
+            return vec![];
+        }
+        if self.mesh_index_data.is_empty()
+        {
+            return (0..self.get_vertex_count() as u32).collect();
+        }
+        self.mesh_index_data.clone()
+    }
+
+    pub fn add_section(&mut self, section: MeshSection)
+    {
+        self.mesh_sections.push(section);
+    }
+
+    // A mesh without explicit sections is drawn as a single section over the whole index buffer. Point lists have
+    // nothing to draw with triangles.
+    pub fn get_sections(&self) -> Vec<MeshSection>
+    {
+        if self.mesh_topology == MeshPrimitiveTopology::PointList
+        {
+            return vec![];
+        }
+        if self.mesh_sections.is_empty()
+        {
+            return vec![MeshSection { first_index: 0, index_count: self.get_triangle_indices().len() as u32, base_vertex: 0, material_slot: None }];
+        }
+        self.mesh_sections.clone()
+    }
+
+    // Index into get_sections of every triangle, u32::MAX for triangles outside of all sections.
+    pub fn get_triangle_sections(&self) -> Vec<u32>
+    {
+        let mut triangle_sections = vec![u32::MAX; self.get_triangle_indices().len() / 3];
+        for (section_index, section) in self.get_sections().iter().enumerate()
+        {
+            let first_triangle = section.first_index as usize / 3;
+            for triangle_section in triangle_sections.iter_mut().skip(first_triangle).take(section.index_count as usize / 3)
+            {
+                *triangle_section = section_index as u32;
+            }
+        }
+        triangle_sections
+    }
+
+    // Rebuilds every channel so that the new vertex i is a copy of the old vertex source_vertices[i].
+    pub fn remap_vertices(&mut self, source_vertices: &[u32])
+    {
+        for (channel, data) in self.mesh_channel_data.iter_mut()
+        {
+            let channel_size = get_channel_default_value(*channel).len();
+            let mut remapped = Vec::with_capacity(source_vertices.len() * channel_size);
+            for source in source_vertices
+            {
+                let offset = *source as usize * channel_size;
+                remapped.extend_from_slice(&data[offset..offset + channel_size]);
+            }
+            *data = remapped;
+        }
+        self.remap_morph_targets(source_vertices);
+        if let Some(bounds) = self.mesh_bounds
+        {
+            self.update_bounds(bounds.oriented_bounding_box.is_some());
+        }
+    }
+
+    /// Mirrors the mesh along z, which converts between right handed data such as glTF or OBJ and the left handed
+    /// coordinates of the engine. Positions, normals, tangents and morph deltas get their z negated, the bitangent sign
+    /// flips and the winding of every triangle is reversed so that front faces stay front faces.
+    pub fn flip_handedness(&mut self)
+    {
+        for (channel, component_stride) in [(MeshDataChannel::Position, 3), (MeshDataChannel::Normal, 3), (MeshDataChannel::Tangent, 4)]
+        {
+            if let Some(data) = self.mesh_channel_data.get_mut(&(channel as usize))
+            {
+                for value in data.chunks_exact_mut(component_stride)
+                {
+                    value[2] = -value[2];
+                    if component_stride == 4
+                    {
+                        value[3] = -value[3];
+                    }
+                }
+            }
+        }
+        for target in self.mesh_morph_targets.iter_mut()
+        {
+            for deltas in [&mut target.position_deltas, &mut target.normal_deltas, &mut target.tangent_deltas]
+            {
+                for delta in deltas.chunks_exact_mut(3)
+                {
+                    delta[2] = -delta[2];
+                }
+            }
+        }
+
+        if self.mesh_topology == MeshPrimitiveTopology::TriangleList
+        {
+            if self.mesh_index_data.is_empty()
+            {
+                let vertex_order: Vec<u32> = (0..self.get_vertex_count() as u32 / 3).flat_map(|triangle| [triangle * 3, triangle * 3 + 2, triangle * 3 + 1]).collect();
+                self.remap_vertices(&vertex_order);
+            }
+            for triangle in self.mesh_index_data.chunks_exact_mut(3)
+            {
+                triangle.swap(1, 2);
+            }
+        }
+        if let Some(bounds) = self.mesh_bounds
+        {
+            self.update_bounds(bounds.oriented_bounding_box.is_some());
+        }
+    }
+
+    // Packs all channels into the streams and formats of get_vertex_layout, missing ones are filled with their default
+    // value. Returns the bytes of every stream and the vertex count.
+    pub fn get_vertex_buffer_data(&self) -> Result<(Vec<Vec<u8>>, u32), MeshError>
+    {
+        self.get_vertex_buffer_data_with_layout(&self.get_vertex_layout())
+    }
+
+    // Same as get_vertex_buffer_data with the layout of another mesh, so several meshes can share a vertex buffer.
+    pub fn get_vertex_buffer_data_with_layout(&self, layout: &VertexLayout) -> Result<(Vec<Vec<u8>>, u32), MeshError>
+    {
+        self.validate_structure()?;
+        let vertex_count = self.get_vertex_count();
+        let mut streams: Vec<Vec<u8>> = layout.strides.iter().map(|stride| Vec::with_capacity(vertex_count * *stride as usize)).collect();
+        for vertex_ind in 0..vertex_count
+        {
+            let vertex_starts: Vec<usize> = streams.iter().map(|stream| stream.len()).collect();
+            for element in &layout.elements
+            {
+                let data = &mut streams[element.slot as usize];
+                data.resize(vertex_starts[element.slot as usize] + element.offset as usize, 0);
+                let default_value = get_channel_default_value(element.channel);
+                let channel_size = default_value.len();
+                let value = match self.mesh_channel_data.get(&element.channel)
+                {
+                    Some(channel_data) => &channel_data[vertex_ind * channel_size..(vertex_ind + 1) * channel_size],
+                    None => default_value
+                };
+                pack_vertex_element(element.format, value, element.packed_component_count, data);
+            }
+            for (slot, data) in streams.iter_mut().enumerate()
+            {
+                data.resize(vertex_starts[slot] + layout.strides[slot] as usize, 0);
+            }
+        }
+
+        Ok((streams, vertex_count as u32))
+    }
+
+    pub fn get_index_buffer_data_u16(&self) -> Result<Vec<u16>, MeshError>
+    {
+        let mut data = vec![];
+
+        for i in 0..self.mesh_index_data.len()
+        {
+            let index = self.mesh_index_data[i];
+            if index > u16::MAX as u32
+            {
+                return Err(MeshError::IndexTooLarge(index));
+            }
+            data.push(index as u16);
+        }
+
+        Ok(data)
+    }
+}
+
+// Offset of a channel in a vertex that holds every registered channel up to it as Float32, see
+// Mesh::get_vertex_layout for the layout of an actual mesh.
+pub fn get_vertex_attribute_offset(channel: u32) -> u32
+{
+    let channels: Vec<usize> = (0..=channel as usize).collect();
+    get_vertex_layout(&channels, &BTreeMap::new(), VertexStreamLayout::Interleaved).elements[channel as usize].offset
+}
\ No newline at end of file
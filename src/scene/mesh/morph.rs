@@ -0,0 +1,332 @@
+use cgmath::{InnerSpace, Vector3};
+
+use crate::scene::mesh::*;
+use crate::scene::mesh::validation::MeshError;
+use crate::scene::mesh::vertex_format::f32_to_f16;
+
+/// Sparse blend shape, deltas are only stored for the vertices it moves.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MorphTarget
+{
+    pub name: String,
+    // Strictly increasing.
+    pub vertices: Vec<u32>,
+    // Three values per entry of vertices. Normal and tangent deltas are empty when the target does not change them.
+    pub position_deltas: Vec<f32>,
+    pub normal_deltas: Vec<f32>,
+    pub tangent_deltas: Vec<f32>
+}
+
+/// One delta of the packed morph buffer, a structured buffer element of 32 bytes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PackedMorphDelta
+{
+    pub position: [f32; 3],
+    pub vertex: u32,
+    // Half floats, normal xyz then tangent xyz, the last two are padding.
+    pub normal_tangent: [u16; 8]
+}
+
+static_assertions::assert_eq_size!(PackedMorphDelta, [u8; 32]);
+
+/// Range of a target in MorphBufferData::deltas.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MorphTargetRange
+{
+    pub first_delta: u32,
+    pub delta_count: u32
+}
+
+/// Deltas of all morph targets for evaluation on the GPU: one thread per delta of every target with a non zero
+/// weight adds the weighted delta to its vertex.
+#[derive(Clone, Debug, Default)]
+pub struct MorphBufferData
+{
+    pub targets: Vec<MorphTargetRange>,
+    pub deltas: Vec<PackedMorphDelta>
+}
+
+fn get_delta(deltas: &[f32], entry: usize) -> Vector3<f32>
+{
+    if deltas.is_empty() { Vector3::new(0.0, 0.0, 0.0) } else { Vector3::new(deltas[entry * 3], deltas[entry * 3 + 1], deltas[entry * 3 + 2]) }
+}
+
+impl MorphTarget
+{
+    /// Builds a target from one delta per vertex, keeping the vertices with any component larger than epsilon.
+    /// Empty normal or tangent deltas mean the target does not change them.
+    pub fn from_dense(name: &str, position_deltas: &[f32], normal_deltas: &[f32], tangent_deltas: &[f32], epsilon: f32) -> Self
+    {
+        let mut target = MorphTarget { name: name.to_string(), ..Default::default() };
+        for vertex in 0..position_deltas.len() / 3
+        {
+            let range = vertex * 3..vertex * 3 + 3;
+            let is_moved = [position_deltas, normal_deltas, tangent_deltas].iter()
+                .any(|deltas| deltas.get(range.clone()).is_some_and(|delta| delta.iter().any(|value| value.abs() > epsilon)));
+            if is_moved
+            {
+                target.vertices.push(vertex as u32);
+                target.position_deltas.extend_from_slice(&position_deltas[range.clone()]);
+                if !normal_deltas.is_empty()
+                {
+                    target.normal_deltas.extend_from_slice(&normal_deltas[range.clone()]);
+                }
+                if !tangent_deltas.is_empty()
+                {
+                    target.tangent_deltas.extend_from_slice(&tangent_deltas[range]);
+                }
+            }
+        }
+        target
+    }
+
+    pub fn get_delta_count(&self) -> usize
+    {
+        self.vertices.len()
+    }
+
+    pub fn get_max_position_delta(&self) -> f32
+    {
+        (0..self.vertices.len()).map(|entry| get_delta(&self.position_deltas, entry).magnitude()).fold(0.0, f32::max)
+    }
+
+    pub fn is_valid(&self, vertex_count: usize) -> bool
+    {
+        let delta_size = self.vertices.len() * 3;
+        self.vertices.windows(2).all(|pair| pair[0] < pair[1])
+            && self.vertices.last().is_none_or(|vertex| (*vertex as usize) < vertex_count)
+            && self.position_deltas.len() == delta_size
+            && (self.normal_deltas.is_empty() || self.normal_deltas.len() == delta_size)
+            && (self.tangent_deltas.is_empty() || self.tangent_deltas.len() == delta_size)
+    }
+}
+
+impl Mesh
+{
+    pub fn add_morph_target(&mut self, target: MorphTarget) -> Result<usize, MeshError>
+    {
+        if !target.is_valid(self.get_vertex_count())
+        {
+            return Err(MeshError::InvalidMorphTarget(self.mesh_morph_targets.len()));
+        }
+        self.mesh_morph_targets.push(target);
+        Ok(self.mesh_morph_targets.len() - 1)
+    }
+
+    pub fn find_morph_target(&self, name: &str) -> Option<usize>
+    {
+        self.mesh_morph_targets.iter().position(|target| target.name == name)
+    }
+
+    // Called by remap_vertices, a vertex copied several times gets the deltas on every copy.
+    pub(crate) fn remap_morph_targets(&mut self, source_vertices: &[u32])
+    {
+        if self.mesh_morph_targets.is_empty()
+        {
+            return;
+        }
+        let old_vertex_count = source_vertices.iter().map(|source| *source as usize + 1).max().unwrap_or(0);
+        let mut copies: Vec<Vec<u32>> = vec![vec![]; old_vertex_count];
+        for (vertex, source) in source_vertices.iter().enumerate()
+        {
+            copies[*source as usize].push(vertex as u32);
+        }
+        for target in self.mesh_morph_targets.iter_mut()
+        {
+            let mut entries: Vec<(u32, usize)> = target.vertices.iter().enumerate()
+                .flat_map(|(entry, vertex)| copies.get(*vertex as usize).into_iter().flatten().map(move |copy| (*copy, entry)))
+                .collect();
+            entries.sort_unstable();
+            let remap_deltas = |deltas: &[f32]| -> Vec<f32> {
+                if deltas.is_empty() { vec![] } else { entries.iter().flat_map(|(_, entry)| deltas[entry * 3..entry * 3 + 3].iter().copied()).collect() }
+            };
+            target.position_deltas = remap_deltas(&target.position_deltas);
+            target.normal_deltas = remap_deltas(&target.normal_deltas);
+            target.tangent_deltas = remap_deltas(&target.tangent_deltas);
+            target.vertices = entries.iter().map(|(vertex, _)| *vertex).collect();
+        }
+    }
+
+    /// CPU evaluation: adds the weighted deltas of every target to positions, normals and tangents. Normals and
+    /// tangents are normalized again, the tangent sign is kept. Missing weights count as zero.
+    pub fn apply_morph_targets(&self, weights: &[f32]) -> Mesh
+    {
+        let mut morphed = self.clone();
+        for (target, weight) in self.mesh_morph_targets.iter().zip(weights)
+        {
+            if *weight == 0.0
+            {
+                continue;
+            }
+            let channels = [
+                (MeshDataChannel::Position, &target.position_deltas),
+                (MeshDataChannel::Normal, &target.normal_deltas),
+                (MeshDataChannel::Tangent, &target.tangent_deltas)
+            ];
+            for (channel, deltas) in channels
+            {
+                let channel_size = get_channel_default_value(channel as usize).len();
+                let Some(data) = morphed.mesh_channel_data.get_mut(&(channel as usize)) else { continue };
+                if deltas.is_empty()
+                {
+                    continue;
+                }
+                for (entry, vertex) in target.vertices.iter().enumerate()
+                {
+                    let offset = *vertex as usize * channel_size;
+                    for component in 0..3
+                    {
+                        data[offset + component] += deltas[entry * 3 + component] * weight;
+                    }
+                }
+            }
+        }
+        for channel in [MeshDataChannel::Normal, MeshDataChannel::Tangent]
+        {
+            let channel_size = get_channel_default_value(channel as usize).len();
+            if let Some(data) = morphed.mesh_channel_data.get_mut(&(channel as usize))
+            {
+                for value in data.chunks_exact_mut(channel_size)
+                {
+                    let vector = Vector3::new(value[0], value[1], value[2]);
+                    if vector.magnitude2() > 0.0
+                    {
+                        let vector = vector.normalize();
+                        value[..3].copy_from_slice(&[vector.x, vector.y, vector.z]);
+                    }
+                }
+            }
+        }
+        if morphed.mesh_bounds.is_some()
+        {
+            morphed.update_bounds(false);
+        }
+        morphed
+    }
+
+    pub fn get_morph_buffer_data(&self) -> MorphBufferData
+    {
+        let mut buffer_data = MorphBufferData::default();
+        for target in &self.mesh_morph_targets
+        {
+            buffer_data.targets.push(MorphTargetRange { first_delta: buffer_data.deltas.len() as u32, delta_count: target.get_delta_count() as u32 });
+            for (entry, vertex) in target.vertices.iter().enumerate()
+            {
+                let normal = get_delta(&target.normal_deltas, entry);
+                let tangent = get_delta(&target.tangent_deltas, entry);
+                let mut normal_tangent = [0; 8];
+                for component in 0..3
+                {
+                    normal_tangent[component] = f32_to_f16(normal[component]);
+                    normal_tangent[component + 3] = f32_to_f16(tangent[component]);
+                }
+                buffer_data.deltas.push(PackedMorphDelta { position: get_delta(&target.position_deltas, entry).into(), vertex: *vertex, normal_tangent });
+            }
+        }
+        buffer_data
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // The plane faces +y with its tangents along +x, vertex 0 gets a negative bitangent sign.
+    fn create_morphed_plane() -> Mesh
+    {
+        let mut mesh = Mesh::create_plane(2.0, 2.0, 1, 1);
+        mesh.mesh_channel_data.get_mut(&(MeshDataChannel::Tangent as usize)).unwrap()[3] = -1.0;
+        let targets = [
+            MorphTarget { name: "a".to_string(), vertices: vec![0, 2], position_deltas: vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0], normal_deltas: vec![2.0, 0.0, 0.0, 0.0, 0.0, 0.0], tangent_deltas: vec![0.0, 2.0, 0.0, 0.0, 0.0, 0.0] },
+            MorphTarget { name: "b".to_string(), vertices: vec![2], position_deltas: vec![0.0, 0.0, 2.0], ..Default::default() },
+            MorphTarget { name: "c".to_string(), vertices: vec![1], position_deltas: vec![5.0, 5.0, 5.0], ..Default::default() }
+        ];
+        for target in targets
+        {
+            mesh.add_morph_target(target).unwrap();
+        }
+        mesh
+    }
+
+    fn get_vector(mesh: &Mesh, channel: MeshDataChannel, vertex: usize) -> (Vector3<f32>, f32)
+    {
+        let channel_size = get_channel_default_value(channel as usize).len();
+        let value = &mesh.mesh_channel_data[&(channel as usize)][vertex * channel_size..(vertex + 1) * channel_size];
+        (Vector3::new(value[0], value[1], value[2]), value.get(3).copied().unwrap_or(0.0))
+    }
+
+    #[test]
+    fn from_dense_keeps_moved_vertices()
+    {
+        let target = MorphTarget::from_dense("smile", &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.05, 0.0], &[], &[0.0, 0.2, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0], 0.1);
+        assert_eq!(target.name, "smile");
+        assert_eq!(target.vertices, vec![0, 1]);
+        assert_eq!(target.position_deltas, vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+        assert!(target.normal_deltas.is_empty());
+        assert_eq!(target.tangent_deltas, vec![0.0, 0.2, 0.0, 0.0, 0.0, 0.0]);
+        assert!(target.is_valid(3) && !target.is_valid(1));
+
+        let mut mesh = Mesh::create_plane(2.0, 2.0, 1, 1);
+        let unsorted = MorphTarget { vertices: vec![1, 0], position_deltas: vec![0.0; 6], ..Default::default() };
+        assert_eq!(mesh.add_morph_target(unsorted), Err(MeshError::InvalidMorphTarget(0)));
+    }
+
+    #[test]
+    fn remapped_vertices_copy_their_deltas()
+    {
+        let mut mesh = create_morphed_plane();
+        mesh.remap_vertices(&[2, 0, 2, 3]);
+        let target = &mesh.mesh_morph_targets[0];
+        assert_eq!(target.vertices, vec![0, 1, 2]);
+        assert_eq!(target.position_deltas, vec![0.0, 1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        assert_eq!(target.normal_deltas, vec![0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(mesh.mesh_morph_targets[1].vertices, vec![0, 2]);
+        // The only vertex of the last target was dropped.
+        assert!(mesh.mesh_morph_targets[2].vertices.is_empty() && mesh.mesh_morph_targets[2].position_deltas.is_empty());
+        assert!(mesh.mesh_morph_targets.iter().all(|target| target.is_valid(mesh.get_vertex_count())));
+    }
+
+    #[test]
+    fn weighted_deltas_are_applied_and_renormalized()
+    {
+        let mesh = create_morphed_plane();
+        // The third target has no weight.
+        let morphed = mesh.apply_morph_targets(&[0.5, 0.25]);
+        assert_eq!(morphed.get_position(0), mesh.get_position(0) + Vector3::new(0.5, 0.0, 0.0));
+        assert_eq!(morphed.get_position(1), mesh.get_position(1));
+        assert_eq!(morphed.get_position(2), mesh.get_position(2) + Vector3::new(0.0, 0.5, 0.5));
+        assert_eq!(morphed.get_position(3), mesh.get_position(3));
+
+        let (normal, _) = get_vector(&morphed, MeshDataChannel::Normal, 0);
+        assert!((normal - Vector3::new(1.0, 1.0, 0.0).normalize()).magnitude() < 1e-6);
+        let (tangent, sign) = get_vector(&morphed, MeshDataChannel::Tangent, 0);
+        assert!((tangent - Vector3::new(1.0, 1.0, 0.0).normalize()).magnitude() < 1e-6);
+        assert_eq!(sign, -1.0);
+        assert_eq!(get_vector(&morphed, MeshDataChannel::Normal, 2), get_vector(&mesh, MeshDataChannel::Normal, 2));
+
+        let unchanged = mesh.apply_morph_targets(&[]);
+        assert_eq!(unchanged.mesh_channel_data, mesh.mesh_channel_data);
+    }
+
+    #[test]
+    fn morph_buffer_packs_targets_in_order()
+    {
+        let buffer_data = create_morphed_plane().get_morph_buffer_data();
+        assert_eq!(buffer_data.targets, vec![
+            MorphTargetRange { first_delta: 0, delta_count: 2 },
+            MorphTargetRange { first_delta: 2, delta_count: 1 },
+            MorphTargetRange { first_delta: 3, delta_count: 1 }
+        ]);
+        assert_eq!(buffer_data.deltas.len(), 4);
+        // Normal xyz, tangent xyz and padding as half floats: 2.0 is 0x4000.
+        assert_eq!(buffer_data.deltas[0], PackedMorphDelta { position: [1.0, 0.0, 0.0], vertex: 0, normal_tangent: [0x4000, 0, 0, 0, 0x4000, 0, 0, 0] });
+        assert_eq!(buffer_data.deltas[1], PackedMorphDelta { position: [0.0, 1.0, 0.0], vertex: 2, normal_tangent: [0; 8] });
+        // Targets without normal or tangent deltas pack zeros.
+        assert_eq!(buffer_data.deltas[2], PackedMorphDelta { position: [0.0, 0.0, 2.0], vertex: 2, normal_tangent: [0; 8] });
+        assert_eq!(buffer_data.deltas[3].vertex, 1);
+    }
+}
+
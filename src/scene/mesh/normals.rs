@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Vector3, Zero};
+
+use crate::scene::mesh::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NormalWeighting
+{
+    // Larger triangles contribute more, cheap and good for evenly tessellated meshes.
+    Area,
+    // Each triangle contributes by the corner angle at the vertex, independent of tessellation.
+    Angle
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NormalGenerationMode
+{
+    Flat,
+    Smooth(NormalWeighting),
+    // Faces whose normals differ by more than crease_angle (radians) are not averaged, their shared vertices are split.
+    Crease { weighting: NormalWeighting, crease_angle: f32 }
+}
+
+// Corner normals of a vertex closer than about 0.25 degrees are considered equal and do not split it.
+const NORMAL_MERGE_COS: f32 = 0.99999;
+
+fn get_position_key(position: Vector3<f32>) -> [u32; 3]
+{
+    // Folds -0.0 onto 0.0 so both hash to the same position.
+    [(position.x + 0.0).to_bits(), (position.y + 0.0).to_bits(), (position.z + 0.0).to_bits()]
+}
+
+fn get_corner_angle(a: Vector3<f32>, b: Vector3<f32>, c: Vector3<f32>) -> f32
+{
+    let edge0 = b - a;
+    let edge1 = c - a;
+    let length = edge0.magnitude() * edge1.magnitude();
+    if length == 0.0
+    {
+        return 0.0;
+    }
+    (edge0.dot(edge1) / length).clamp(-1.0, 1.0).acos()
+}
+
+impl Mesh
+{
+    pub fn generate_normals(&mut self, mode: NormalGenerationMode)
+    {
+        let vertex_count = self.get_vertex_count();
+        let indices = self.get_triangle_indices();
+        let triangle_count = indices.len() / 3;
+
+        // Unnormalized face normals have a length of twice the triangle area.
+        let mut face_normals = Vec::with_capacity(triangle_count);
+        let mut corner_weights = Vec::with_capacity(triangle_count * 3);
+        for triangle in indices.chunks_exact(3)
+        {
+            let p0 = self.get_position(triangle[0] as usize);
+            let p1 = self.get_position(triangle[1] as usize);
+            let p2 = self.get_position(triangle[2] as usize);
+            let face_normal = (p1 - p0).cross(p2 - p0);
+            face_normals.push(face_normal);
+            corner_weights.push(get_corner_angle(p0, p1, p2));
+            corner_weights.push(get_corner_angle(p1, p2, p0));
+            corner_weights.push(get_corner_angle(p2, p0, p1));
+        }
+        let unit_face_normals: Vec<Vector3<f32>> = face_normals.iter()
+            .map(|normal| if normal.magnitude2() > 0.0 { normal.normalize() } else { Vector3::zero() })
+            .collect();
+
+        // Corners are grouped by position rather than by vertex index so that UV seams stay smooth.
+        let mut position_groups: HashMap<[u32; 3], usize> = HashMap::new();
+        let mut vertex_groups = Vec::with_capacity(vertex_count);
+        for vertex in 0..vertex_count
+        {
+            let group_count = position_groups.len();
+            vertex_groups.push(*position_groups.entry(get_position_key(self.get_position(vertex))).or_insert(group_count));
+        }
+        let mut group_corners: Vec<Vec<usize>> = vec![vec![]; position_groups.len()];
+        for (corner, vertex) in indices.iter().enumerate().take(triangle_count * 3)
+        {
+            group_corners[vertex_groups[*vertex as usize]].push(corner);
+        }
+
+        let get_contribution = |corner: usize, weighting: NormalWeighting| {
+            let triangle = corner / 3;
+            match weighting
+            {
+                NormalWeighting::Area => face_normals[triangle],
+                NormalWeighting::Angle => unit_face_normals[triangle] * corner_weights[corner]
+            }
+        };
+
+        let default_normal = get_channel_default_value(MeshDataChannel::Normal as usize);
+        let default_normal = Vector3::new(default_normal[0], default_normal[1], default_normal[2]);
+        let mut corner_normals = Vec::with_capacity(triangle_count * 3);
+        for corner in 0..triangle_count * 3
+        {
+            let triangle = corner / 3;
+            let normal = match mode
+            {
+                NormalGenerationMode::Flat => unit_face_normals[triangle],
+                NormalGenerationMode::Smooth(weighting) =>
+                {
+                    group_corners[vertex_groups[indices[corner] as usize]].iter()
+                        .fold(Vector3::zero(), |sum, other| sum + get_contribution(*other, weighting))
+                }
+                NormalGenerationMode::Crease { weighting, crease_angle } =>
+                {
+                    let min_cos = crease_angle.cos();
+                    group_corners[vertex_groups[indices[corner] as usize]].iter()
+                        .filter(|other| unit_face_normals[triangle].dot(unit_face_normals[**other / 3]) >= min_cos)
+                        .fold(Vector3::zero(), |sum, other| sum + get_contribution(*other, weighting))
+                }
+            };
+            let normal = if normal.magnitude2() > 0.0
+            {
+                normal.normalize()
+            }
+            else if unit_face_normals[triangle].magnitude2() > 0.0
+            {
+                unit_face_normals[triangle]
+            }
+            else
+            {
+                default_normal
+            };
+            corner_normals.push(normal);
+        }
+
+        // A vertex keeps its index while all of its corners agree on the normal, otherwise it is split.
+        let mut split_vertices: Vec<Vec<(Vector3<f32>, u32)>> = vec![vec![]; vertex_count];
+        let mut source_vertices = vec![];
+        let mut normals = vec![];
+        let mut new_indices = Vec::with_capacity(triangle_count * 3);
+        for (corner, normal) in corner_normals.iter().enumerate()
+        {
+            let vertex = indices[corner];
+            let splits = &mut split_vertices[vertex as usize];
+            let new_vertex = match splits.iter().find(|(split_normal, _)| split_normal.dot(*normal) >= NORMAL_MERGE_COS)
+            {
+                Some((_, new_vertex)) => *new_vertex,
+                None =>
+                {
+                    let new_vertex = source_vertices.len() as u32;
+                    source_vertices.push(vertex);
+                    normals.extend_from_slice(&[normal.x, normal.y, normal.z]);
+                    splits.push((*normal, new_vertex));
+                    new_vertex
+                }
+            };
+            new_indices.push(new_vertex);
+        }
+
+        // Vertices not referenced by any triangle keep their data and the default normal.
+        let mut is_referenced = vec![false; vertex_count];
+        for vertex in &source_vertices
+        {
+            is_referenced[*vertex as usize] = true;
+        }
+        for (vertex, referenced) in is_referenced.iter().enumerate()
+        {
+            if !referenced
+            {
+                source_vertices.push(vertex as u32);
+                normals.extend_from_slice(&[default_normal.x, default_normal.y, default_normal.z]);
+            }
+        }
+
+        self.mesh_channel_data.remove(&(MeshDataChannel::Normal as usize));
+        self.remap_vertices(&source_vertices);
+        self.mesh_channel_data.insert(MeshDataChannel::Normal as usize, normals);
+        self.mesh_index_data = new_indices;
+    }
+
+    // Returns true when normals had to be generated.
+    pub fn generate_normals_if_missing(&mut self, mode: NormalGenerationMode) -> bool
+    {
+        if self.mesh_channel_data.contains_key(&(MeshDataChannel::Normal as usize))
+        {
+            return false;
+        }
+        self.generate_normals(mode);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // Eight shared corners, every face split along one diagonal.
+    fn create_shared_cube() -> Mesh
+    {
+        let mut mesh = Mesh::default();
+        let positions = (0..8).flat_map(|corner| (0..3).map(move |axis| if corner & (1 << axis) != 0 { 1.0 } else { -1.0 })).collect();
+        mesh.mesh_channel_data.insert(MeshDataChannel::Position as usize, positions);
+        for [a, b, c, d] in [[0, 2, 3, 1], [4, 5, 7, 6], [0, 1, 5, 4], [2, 6, 7, 3], [0, 4, 6, 2], [1, 3, 7, 5]]
+        {
+            mesh.mesh_index_data.extend_from_slice(&[a, b, c, a, c, d]);
+        }
+        mesh
+    }
+
+    // Two triangles hinged at a right angle along the edge from vertex 0 to vertex 1, the +y one has twice the area.
+    fn create_hinge() -> Mesh
+    {
+        let mut mesh = Mesh::default();
+        mesh.mesh_channel_data.insert(MeshDataChannel::Position as usize, vec![0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 2.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        mesh.mesh_index_data = vec![0, 1, 2, 0, 3, 1];
+        mesh
+    }
+
+    fn get_normal(mesh: &Mesh, vertex: usize) -> Vector3<f32>
+    {
+        let normals = &mesh.mesh_channel_data[&(MeshDataChannel::Normal as usize)];
+        Vector3::new(normals[vertex * 3], normals[vertex * 3 + 1], normals[vertex * 3 + 2])
+    }
+
+    fn assert_near(a: Vector3<f32>, b: Vector3<f32>)
+    {
+        assert!((a - b).magnitude() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    // Every corner keeps its position and gets the outward normal of its cube face.
+    fn assert_split_cube(mesh: &Mesh, original: &Mesh)
+    {
+        assert_eq!(mesh.get_vertex_count(), 24);
+        assert_eq!(mesh.mesh_index_data.len(), original.mesh_index_data.len());
+        assert_ne!(mesh.mesh_index_data, original.mesh_index_data);
+        for (corner, (vertex, original_vertex)) in mesh.mesh_index_data.iter().zip(&original.mesh_index_data).enumerate()
+        {
+            let position = mesh.get_position(*vertex as usize);
+            assert_eq!(position, original.get_position(*original_vertex as usize));
+            let normal = get_normal(mesh, *vertex as usize);
+            let triangle = &original.mesh_index_data[corner / 3 * 3..corner / 3 * 3 + 3];
+            let center = triangle.iter().fold(Vector3::zero(), |sum, vertex| sum + original.get_position(*vertex as usize));
+            assert!(normal.dot(center) > 0.0);
+            assert_eq!(normal.x.abs() + normal.y.abs() + normal.z.abs(), 1.0);
+        }
+    }
+
+    #[test]
+    fn flat_normals_split_every_face()
+    {
+        let original = create_shared_cube();
+        let mut mesh = original.clone();
+        mesh.generate_normals(NormalGenerationMode::Flat);
+        assert_split_cube(&mesh, &original);
+
+        let mut hinge = create_hinge();
+        hinge.generate_normals(NormalGenerationMode::Flat);
+        assert_eq!(hinge.get_vertex_count(), 6);
+    }
+
+    #[test]
+    fn area_weighting_favours_larger_triangles()
+    {
+        let mut mesh = create_hinge();
+        mesh.generate_normals(NormalGenerationMode::Smooth(NormalWeighting::Area));
+        assert_eq!(mesh.get_vertex_count(), 4);
+        assert_eq!(mesh.mesh_index_data, vec![0, 1, 2, 0, 3, 1]);
+        assert_near(get_normal(&mesh, 0), Vector3::new(1.0, 2.0, 0.0).normalize());
+        assert_near(get_normal(&mesh, 1), Vector3::new(1.0, 2.0, 0.0).normalize());
+        assert_near(get_normal(&mesh, 2), Vector3::unit_y());
+        assert_near(get_normal(&mesh, 3), Vector3::unit_x());
+    }
+
+    #[test]
+    fn angle_weighting_ignores_the_triangulation()
+    {
+        // Both triangles have a right angle at vertex 0.
+        let mut mesh = create_hinge();
+        mesh.generate_normals(NormalGenerationMode::Smooth(NormalWeighting::Angle));
+        assert_eq!(mesh.get_vertex_count(), 4);
+        assert_near(get_normal(&mesh, 0), Vector3::new(1.0, 1.0, 0.0).normalize());
+
+        // Each cube corner sees a right angle of every face however the face is split.
+        let mut cube = create_shared_cube();
+        cube.generate_normals(NormalGenerationMode::Smooth(NormalWeighting::Angle));
+        assert_eq!(cube.get_vertex_count(), 8);
+        for vertex in 0..8
+        {
+            assert_near(get_normal(&cube, vertex), cube.get_position(vertex).normalize());
+        }
+    }
+
+    #[test]
+    fn creases_split_sharp_edges_only()
+    {
+        let original = create_shared_cube();
+        let mut mesh = original.clone();
+        mesh.generate_normals(NormalGenerationMode::Crease { weighting: NormalWeighting::Angle, crease_angle: 30f32.to_radians() });
+        assert_split_cube(&mesh, &original);
+
+        let mut hinge = create_hinge();
+        hinge.generate_normals(NormalGenerationMode::Crease { weighting: NormalWeighting::Area, crease_angle: 30f32.to_radians() });
+        assert_eq!(hinge.get_vertex_count(), 6);
+
+        // A right angle is below a 100 degree crease, so the hinge stays smooth.
+        let mut hinge = create_hinge();
+        hinge.generate_normals(NormalGenerationMode::Crease { weighting: NormalWeighting::Area, crease_angle: 100f32.to_radians() });
+        assert_eq!(hinge.get_vertex_count(), 4);
+        assert_near(get_normal(&hinge, 0), Vector3::new(1.0, 2.0, 0.0).normalize());
+    }
+
+    #[test]
+    fn unreferenced_vertices_keep_the_default_normal()
+    {
+        let mut mesh = create_hinge();
+        mesh.mesh_channel_data.get_mut(&(MeshDataChannel::Position as usize)).unwrap().extend_from_slice(&[5.0, 5.0, 5.0]);
+        mesh.generate_normals(NormalGenerationMode::Flat);
+        assert_eq!(mesh.get_vertex_count(), 7);
+        assert_eq!(mesh.get_position(6), Vector3::new(5.0, 5.0, 5.0));
+        let normal: [f32; 3] = get_normal(&mesh, 6).into();
+        assert_eq!(normal.as_slice(), get_channel_default_value(MeshDataChannel::Normal as usize));
+    }
+}
+
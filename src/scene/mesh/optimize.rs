@@ -0,0 +1,514 @@
+use std::cmp::Ordering;
+
+use cgmath::{InnerSpace, Vector3, Zero};
+
+use crate::scene::mesh::*;
+
+// FIFO cache size used to measure ACMR/ATVR, close to the post-transform cache of current GPUs.
+pub const VERTEX_CACHE_ANALYSIS_SIZE: usize = 16;
+// Cache used to measure vertex fetch, in bytes, modelled as a FIFO of cache lines.
+pub const VERTEX_FETCH_CACHE_LINE_SIZE: usize = 64;
+pub const VERTEX_FETCH_CACHE_SIZE: usize = 16 * 1024;
+
+// Tom Forsyth, "Linear-Speed Vertex Cache Optimisation".
+const FORSYTH_CACHE_SIZE: usize = 32;
+const FORSYTH_CACHE_DECAY_POWER: f32 = 1.5;
+const FORSYTH_LAST_TRIANGLE_SCORE: f32 = 0.75;
+const FORSYTH_VALENCE_BOOST_SCALE: f32 = 2.0;
+const FORSYTH_VALENCE_BOOST_POWER: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct VertexCacheStatistics
+{
+    pub cache_misses: usize,
+    // Average cache miss ratio, transformed vertices per triangle. 0.5 is the optimum for large regular grids, 3 the worst case.
+    pub acmr: f32,
+    // Average transform to vertex ratio, transformed vertices per referenced vertex. 1 is optimal.
+    pub atvr: f32
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MeshOptimizationStatistics
+{
+    pub before: VertexCacheStatistics,
+    pub after: VertexCacheStatistics
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct VertexFetchStatistics
+{
+    // Bytes of the cache lines loaded from the vertex buffers.
+    pub bytes_fetched: usize,
+    // Fetched bytes per byte of referenced vertex data. 1 is optimal, vertices straddling cache lines push it above.
+    pub overfetch: f32
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct VertexFetchOptimizationStatistics
+{
+    pub before: VertexFetchStatistics,
+    pub after: VertexFetchStatistics
+}
+
+struct FifoVertexCache
+{
+    // Timestamp of the insertion of every vertex, a vertex is cached while it is less than cache_size insertions old.
+    timestamps: Vec<usize>,
+    time: usize,
+    cache_size: usize
+}
+
+impl FifoVertexCache
+{
+    fn new(vertex_count: usize, cache_size: usize) -> Self
+    {
+        FifoVertexCache { timestamps: vec![0; vertex_count], time: cache_size + 1, cache_size }
+    }
+
+    fn clear(&mut self)
+    {
+        self.time += self.cache_size + 1;
+    }
+
+    // Returns true for a miss.
+    fn access(&mut self, item: usize) -> bool
+    {
+        if self.time - self.timestamps[item] <= self.cache_size
+        {
+            return false;
+        }
+        self.timestamps[item] = self.time;
+        self.time += 1;
+        true
+    }
+
+    // Returns the number of misses.
+    fn access_triangle(&mut self, triangle: &[u32]) -> usize
+    {
+        triangle.iter().filter(|vertex| self.access(**vertex as usize)).count()
+    }
+}
+
+/// Simulates a FIFO post-transform cache of cache_size entries over a triangle list.
+pub fn analyze_vertex_cache(indices: &[u32], vertex_count: usize, cache_size: usize) -> VertexCacheStatistics
+{
+    let triangle_count = indices.len() / 3;
+    let mut cache = FifoVertexCache::new(vertex_count, cache_size);
+    let mut is_referenced = vec![false; vertex_count];
+    let mut cache_misses = 0;
+    for triangle in indices.chunks_exact(3)
+    {
+        cache_misses += cache.access_triangle(triangle);
+        for vertex in triangle
+        {
+            is_referenced[*vertex as usize] = true;
+        }
+    }
+    let referenced_count = is_referenced.iter().filter(|referenced| **referenced).count();
+    VertexCacheStatistics
+    {
+        cache_misses,
+        acmr: if triangle_count > 0 { cache_misses as f32 / triangle_count as f32 } else { 0.0 },
+        atvr: if referenced_count > 0 { cache_misses as f32 / referenced_count as f32 } else { 0.0 }
+    }
+}
+
+/// Simulates the cache lines that fetching the vertices of a triangle list loads from one vertex buffer with the
+/// given stride.
+pub fn analyze_vertex_fetch(indices: &[u32], vertex_count: usize, vertex_stride: usize) -> VertexFetchStatistics
+{
+    let line_count = (vertex_count * vertex_stride).div_ceil(VERTEX_FETCH_CACHE_LINE_SIZE);
+    let mut cache = FifoVertexCache::new(line_count, VERTEX_FETCH_CACHE_SIZE / VERTEX_FETCH_CACHE_LINE_SIZE);
+    let mut is_referenced = vec![false; vertex_count];
+    let mut lines_fetched = 0;
+    for vertex in indices.iter().map(|index| *index as usize)
+    {
+        is_referenced[vertex] = true;
+        let first_line = vertex * vertex_stride / VERTEX_FETCH_CACHE_LINE_SIZE;
+        let last_line = ((vertex + 1) * vertex_stride).div_ceil(VERTEX_FETCH_CACHE_LINE_SIZE);
+        lines_fetched += (first_line..last_line).filter(|line| cache.access(*line)).count();
+    }
+    let bytes_fetched = lines_fetched * VERTEX_FETCH_CACHE_LINE_SIZE;
+    let referenced_bytes = is_referenced.iter().filter(|referenced| **referenced).count() * vertex_stride;
+    VertexFetchStatistics
+    {
+        bytes_fetched,
+        overfetch: if referenced_bytes > 0 { bytes_fetched as f32 / referenced_bytes as f32 } else { 0.0 }
+    }
+}
+
+fn get_forsyth_vertex_score(cache_position: Option<usize>, remaining_valence: usize) -> f32
+{
+    if remaining_valence == 0
+    {
+        return -1.0;
+    }
+    let cache_score = match cache_position
+    {
+        // The vertices of the last triangle get a fixed score so that the next triangle does not simply reuse its edge.
+        Some(position) if position < 3 => FORSYTH_LAST_TRIANGLE_SCORE,
+        Some(position) => (1.0 - (position - 3) as f32 / (FORSYTH_CACHE_SIZE - 3) as f32).powf(FORSYTH_CACHE_DECAY_POWER),
+        None => 0.0
+    };
+    // Vertices with few remaining triangles are boosted to finish them off and avoid lone triangles later.
+    cache_score + FORSYTH_VALENCE_BOOST_SCALE * (remaining_valence as f32).powf(-FORSYTH_VALENCE_BOOST_POWER)
+}
+
+fn optimize_vertex_cache_forsyth(indices: &[u32], vertex_count: usize) -> Vec<u32>
+{
+    let triangle_count = indices.len() / 3;
+
+    let mut vertex_triangles: Vec<Vec<usize>> = vec![vec![]; vertex_count];
+    for (triangle, vertices) in indices.chunks_exact(3).enumerate()
+    {
+        for vertex in vertices
+        {
+            vertex_triangles[*vertex as usize].push(triangle);
+        }
+    }
+
+    let mut cache_positions: Vec<Option<usize>> = vec![None; vertex_count];
+    let mut vertex_scores: Vec<f32> = vertex_triangles.iter().map(|triangles| get_forsyth_vertex_score(None, triangles.len())).collect();
+    let mut triangle_scores: Vec<f32> = indices.chunks_exact(3)
+        .map(|vertices| vertices.iter().map(|vertex| vertex_scores[*vertex as usize]).sum())
+        .collect();
+    let mut is_emitted = vec![false; triangle_count];
+
+    let mut cache: Vec<u32> = Vec::with_capacity(FORSYTH_CACHE_SIZE + 3);
+    let mut result = Vec::with_capacity(triangle_count * 3);
+    let mut next_unemitted = 0;
+    let mut best_triangle = (0..triangle_count).max_by(|a, b| triangle_scores[*a].partial_cmp(&triangle_scores[*b]).unwrap_or(Ordering::Equal));
+
+    while let Some(triangle) = best_triangle
+    {
+        is_emitted[triangle] = true;
+        let vertices = &indices[triangle * 3..triangle * 3 + 3];
+        result.extend_from_slice(vertices);
+
+        // The emitted vertices move to the front of the LRU cache.
+        let mut new_cache: Vec<u32> = vertices.to_vec();
+        new_cache.extend(cache.iter().filter(|vertex| !vertices.contains(vertex)));
+        for vertex in vertices
+        {
+            vertex_triangles[*vertex as usize].retain(|other| *other != triangle);
+        }
+        for (position, vertex) in new_cache.iter().enumerate()
+        {
+            let vertex = *vertex as usize;
+            cache_positions[vertex] = if position < FORSYTH_CACHE_SIZE { Some(position) } else { None };
+            let score = get_forsyth_vertex_score(cache_positions[vertex], vertex_triangles[vertex].len());
+            let delta = score - vertex_scores[vertex];
+            vertex_scores[vertex] = score;
+            for other in &vertex_triangles[vertex]
+            {
+                triangle_scores[*other] += delta;
+            }
+        }
+        new_cache.truncate(FORSYTH_CACHE_SIZE);
+        cache = new_cache;
+
+        // Only triangles touching the cache change score, the best one of them is emitted next.
+        best_triangle = None;
+        let mut best_score = f32::MIN;
+        for vertex in &cache
+        {
+            for other in &vertex_triangles[*vertex as usize]
+            {
+                if triangle_scores[*other] > best_score
+                {
+                    best_score = triangle_scores[*other];
+                    best_triangle = Some(*other);
+                }
+            }
+        }
+        if best_triangle.is_none()
+        {
+            while next_unemitted < triangle_count && is_emitted[next_unemitted]
+            {
+                next_unemitted += 1;
+            }
+            if next_unemitted < triangle_count
+            {
+                best_triangle = Some(next_unemitted);
+            }
+        }
+    }
+    result
+}
+
+// Splits the triangle list into clusters that can be reordered without losing much vertex cache efficiency.
+fn get_overdraw_clusters(indices: &[u32], vertex_count: usize, threshold: f32) -> Vec<usize>
+{
+    let triangle_count = indices.len() / 3;
+    let mut cache = FifoVertexCache::new(vertex_count, VERTEX_CACHE_ANALYSIS_SIZE);
+
+    // A triangle missing all of its vertices starts from a cold cache anyway, reordering there costs nothing.
+    let mut hard_boundaries = vec![];
+    for (triangle, vertices) in indices.chunks_exact(3).enumerate()
+    {
+        if cache.access_triangle(vertices) == 3
+        {
+            hard_boundaries.push(triangle);
+        }
+    }
+    hard_boundaries.push(triangle_count);
+
+    // Hard clusters are split further wherever the local ACMR stays within threshold of the cluster ACMR.
+    let mut boundaries = vec![];
+    for cluster in hard_boundaries.windows(2)
+    {
+        let (start, end) = (cluster[0], cluster[1]);
+        cache.clear();
+        let cluster_misses: usize = (start..end).map(|triangle| cache.access_triangle(&indices[triangle * 3..triangle * 3 + 3])).sum();
+        let cluster_threshold = threshold * cluster_misses as f32 / (end - start) as f32;
+
+        cache.clear();
+        let mut soft_start = start;
+        let mut soft_misses = 0;
+        boundaries.push(start);
+        for triangle in start..end
+        {
+            soft_misses += cache.access_triangle(&indices[triangle * 3..triangle * 3 + 3]);
+            if triangle + 1 < end && soft_misses as f32 / (triangle + 1 - soft_start) as f32 <= cluster_threshold
+            {
+                boundaries.push(triangle + 1);
+                soft_start = triangle + 1;
+                soft_misses = 0;
+                cache.clear();
+            }
+        }
+    }
+    boundaries.push(triangle_count);
+    boundaries
+}
+
+impl Mesh
+{
+    pub fn get_vertex_cache_statistics(&self) -> VertexCacheStatistics
+    {
+        analyze_vertex_cache(&self.get_triangle_indices(), self.get_vertex_count(), VERTEX_CACHE_ANALYSIS_SIZE)
+    }
+
+    // Summed over the vertex streams of get_vertex_layout, the overfetch is weighted by the stream strides.
+    pub fn get_vertex_fetch_statistics(&self) -> VertexFetchStatistics
+    {
+        let indices = self.get_triangle_indices();
+        let strides = self.get_vertex_layout().strides;
+        let mut statistics = VertexFetchStatistics::default();
+        let mut referenced_bytes = 0.0;
+        for stride in strides.iter().map(|stride| *stride as usize)
+        {
+            let stream_statistics = analyze_vertex_fetch(&indices, self.get_vertex_count(), stride);
+            statistics.bytes_fetched += stream_statistics.bytes_fetched;
+            if stream_statistics.overfetch > 0.0
+            {
+                referenced_bytes += stream_statistics.bytes_fetched as f32 / stream_statistics.overfetch;
+            }
+        }
+        statistics.overfetch = if referenced_bytes > 0.0 { statistics.bytes_fetched as f32 / referenced_bytes } else { 0.0 };
+        statistics
+    }
+
+    /// Reorders the triangles of every section for post-transform vertex cache reuse with Tom Forsyth's algorithm.
+    pub fn optimize_vertex_cache(&mut self) -> MeshOptimizationStatistics
+    {
+        let before = self.get_vertex_cache_statistics();
+        let indices = self.get_triangle_indices();
+        let mut new_indices = indices.clone();
+        for section in self.get_sections()
+        {
+            let range = section.first_index as usize..(section.first_index + section.index_count) as usize;
+            new_indices[range.clone()].copy_from_slice(&optimize_vertex_cache_forsyth(&indices[range], self.get_vertex_count()));
+        }
+        self.mesh_index_data = new_indices;
+        MeshOptimizationStatistics { before, after: self.get_vertex_cache_statistics() }
+    }
+
+    /// Reorders clusters of a cache optimized triangle list so that outward facing clusters are drawn first, which
+    /// lets early depth testing reject more of the hidden ones. threshold bounds the ACMR loss, 1.05 allows 5% more misses.
+    /// Sections are reordered independently. Run it after optimize_vertex_cache.
+    pub fn optimize_overdraw(&mut self, threshold: f32) -> MeshOptimizationStatistics
+    {
+        let before = self.get_vertex_cache_statistics();
+        let indices = self.get_triangle_indices();
+        let mut new_indices = indices.clone();
+        for section in self.get_sections()
+        {
+            let range = section.first_index as usize..(section.first_index + section.index_count) as usize;
+            new_indices[range.clone()].copy_from_slice(&self.get_overdraw_order(&indices[range], threshold));
+        }
+        self.mesh_index_data = new_indices;
+        MeshOptimizationStatistics { before, after: self.get_vertex_cache_statistics() }
+    }
+
+    fn get_overdraw_order(&self, indices: &[u32], threshold: f32) -> Vec<u32>
+    {
+        let boundaries = get_overdraw_clusters(indices, self.get_vertex_count(), threshold);
+
+        let mut mesh_center = Vector3::zero();
+        let mut mesh_area = 0.0;
+        let mut clusters = vec![];
+        for cluster in boundaries.windows(2)
+        {
+            let mut centroid = Vector3::zero();
+            let mut normal = Vector3::zero();
+            let mut area = 0.0;
+            for triangle in indices[cluster[0] * 3..cluster[1] * 3].chunks_exact(3)
+            {
+                let p0 = self.get_position(triangle[0] as usize);
+                let p1 = self.get_position(triangle[1] as usize);
+                let p2 = self.get_position(triangle[2] as usize);
+                let face_normal = (p1 - p0).cross(p2 - p0);
+                let triangle_area = face_normal.magnitude();
+                centroid += (p0 + p1 + p2) * (triangle_area / 3.0);
+                normal += face_normal;
+                area += triangle_area;
+            }
+            mesh_center += centroid;
+            mesh_area += area;
+            clusters.push((cluster[0], cluster[1], if area > 0.0 { centroid / area } else { centroid }, normal));
+        }
+        if mesh_area > 0.0
+        {
+            mesh_center /= mesh_area;
+        }
+
+        let mut sort_keys: Vec<(f32, usize)> = clusters.iter().enumerate()
+            .map(|(cluster, (_, _, centroid, normal))| {
+                let normal = if normal.magnitude2() > 0.0 { normal.normalize() } else { *normal };
+                ((centroid - mesh_center).dot(normal), cluster)
+            })
+            .collect();
+        sort_keys.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+
+        sort_keys.iter()
+            .flat_map(|(_, cluster)| indices[clusters[*cluster].0 * 3..clusters[*cluster].1 * 3].iter().copied())
+            .collect()
+    }
+
+    /// Reorders the vertices of every channel in order of first use by the index buffer so that vertex fetch reads
+    /// memory linearly. Unreferenced vertices are moved to the end. Run it after the triangle order is final, the
+    /// vertex cache statistics do not change.
+    pub fn optimize_vertex_fetch(&mut self) -> VertexFetchOptimizationStatistics
+    {
+        let before = self.get_vertex_fetch_statistics();
+        let vertex_count = self.get_vertex_count();
+        let indices = self.get_triangle_indices();
+
+        let mut vertex_remap: Vec<Option<u32>> = vec![None; vertex_count];
+        let mut source_vertices = Vec::with_capacity(vertex_count);
+        for index in &indices
+        {
+            if vertex_remap[*index as usize].is_none()
+            {
+                vertex_remap[*index as usize] = Some(source_vertices.len() as u32);
+                source_vertices.push(*index);
+            }
+        }
+        for (vertex, remap) in vertex_remap.iter_mut().enumerate()
+        {
+            if remap.is_none()
+            {
+                *remap = Some(source_vertices.len() as u32);
+                source_vertices.push(vertex as u32);
+            }
+        }
+
+        self.remap_vertices(&source_vertices);
+        self.mesh_index_data = indices.iter().map(|index| vertex_remap[*index as usize].unwrap()).collect();
+        VertexFetchOptimizationStatistics { before, after: self.get_vertex_fetch_statistics() }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::scene::mesh::vertex_format::VertexStreamLayout;
+
+    // Deterministic Fisher-Yates shuffle with a linear congruential generator.
+    fn shuffle<T>(items: &mut [T], seed: u64)
+    {
+        let mut state = seed;
+        for index in (1..items.len()).rev()
+        {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            items.swap(index, (state >> 33) as usize % (index + 1));
+        }
+    }
+
+    fn get_sorted_triangles(indices: &[u32]) -> Vec<[u32; 3]>
+    {
+        let mut triangles: Vec<[u32; 3]> = indices.chunks_exact(3).map(|triangle| [triangle[0], triangle[1], triangle[2]]).collect();
+        triangles.sort();
+        triangles
+    }
+
+    fn create_shuffled_sphere() -> Mesh
+    {
+        let mut mesh = Mesh::create_uv_sphere(1.0, 48, 24);
+        let mut triangles: Vec<[u32; 3]> = mesh.get_triangle_indices().chunks_exact(3).map(|triangle| [triangle[0], triangle[1], triangle[2]]).collect();
+        shuffle(&mut triangles, 7);
+        mesh.mesh_index_data = triangles.concat();
+        mesh
+    }
+
+    #[test]
+    fn vertex_cache_optimization_lowers_acmr()
+    {
+        let mut mesh = create_shuffled_sphere();
+        let triangles = get_sorted_triangles(&mesh.get_triangle_indices());
+        let statistics = mesh.optimize_vertex_cache();
+        assert!(statistics.after.acmr < statistics.before.acmr, "{:?}", statistics);
+        assert!(statistics.after.atvr < statistics.before.atvr);
+        assert_eq!(statistics.after, mesh.get_vertex_cache_statistics());
+        assert_eq!(get_sorted_triangles(&mesh.get_triangle_indices()), triangles);
+    }
+
+    #[test]
+    fn overdraw_optimization_keeps_triangles()
+    {
+        let mut mesh = create_shuffled_sphere();
+        mesh.optimize_vertex_cache();
+        let triangles = get_sorted_triangles(&mesh.get_triangle_indices());
+        mesh.optimize_overdraw(1.05);
+        assert_eq!(get_sorted_triangles(&mesh.get_triangle_indices()), triangles);
+    }
+
+    #[test]
+    fn vertex_fetch_optimization_lowers_overfetch()
+    {
+        // Interleaved vertices of the primitives fill exactly one cache line, the position stream is where order matters.
+        let mut mesh = Mesh::create_uv_sphere(1.0, 48, 24);
+        mesh.set_vertex_stream_layout(VertexStreamLayout::SeparatePosition);
+        let mut source_vertices: Vec<u32> = (0..mesh.get_vertex_count() as u32).collect();
+        shuffle(&mut source_vertices, 11);
+        let mut vertex_remap = vec![0; source_vertices.len()];
+        for (vertex, source) in source_vertices.iter().enumerate()
+        {
+            vertex_remap[*source as usize] = vertex as u32;
+        }
+        mesh.remap_vertices(&source_vertices);
+        mesh.mesh_index_data = mesh.mesh_index_data.iter().map(|index| vertex_remap[*index as usize]).collect();
+        mesh.optimize_vertex_cache();
+
+        let get_triangle_positions = |mesh: &Mesh| -> Vec<[[u32; 3]; 3]> {
+            let mut triangles: Vec<[[u32; 3]; 3]> = mesh.get_triangle_indices().chunks_exact(3)
+                .map(|triangle| [0, 1, 2].map(|corner| {
+                    let position = mesh.get_position(triangle[corner] as usize);
+                    [position.x.to_bits(), position.y.to_bits(), position.z.to_bits()]
+                }))
+                .collect();
+            triangles.sort();
+            triangles
+        };
+        let triangles = get_triangle_positions(&mesh);
+        let cache_statistics = mesh.get_vertex_cache_statistics();
+        let statistics = mesh.optimize_vertex_fetch();
+        assert!(statistics.after.bytes_fetched < statistics.before.bytes_fetched, "{:?}", statistics);
+        assert!(statistics.after.overfetch < statistics.before.overfetch);
+        assert!(statistics.after.overfetch >= 1.0);
+        assert_eq!(mesh.get_vertex_cache_statistics(), cache_statistics);
+        assert_eq!(get_triangle_positions(&mesh), triangles);
+    }
+}
@@ -0,0 +1,208 @@
+use std::borrow::Cow;
+
+use cgmath::{InnerSpace, Vector2, Vector3};
+
+use crate::scene::mesh::*;
+use crate::scene::mesh::bounds::*;
+use crate::scene::mesh::bvh::*;
+
+// Rays closer to parallel with a triangle than this miss it.
+const RAY_TRIANGLE_EPSILON: f32 = 1e-8;
+
+/// Hierarchy over the triangles of one mesh, valid until its positions or indices change.
+#[derive(Clone, Debug, Default)]
+pub struct MeshTriangleBvh
+{
+    nodes: Vec<BvhNode>,
+    // Triangle indices in leaf order.
+    triangles: Vec<u32>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MeshRayHit
+{
+    pub distance: f32,
+    // Index of the triangle in get_triangle_indices, three indices per triangle.
+    pub triangle: u32,
+    // Weights of the three corners, they sum to one.
+    pub barycentrics: Vector3<f32>,
+    pub position: Vector3<f32>,
+    // Interpolated from the normal channel and normalized, None when the mesh has no normals.
+    pub normal: Option<Vector3<f32>>,
+    pub uv: Option<Vector2<f32>>
+}
+
+impl MeshTriangleBvh
+{
+    pub fn get_triangle_count(&self) -> usize
+    {
+        self.triangles.len()
+    }
+}
+
+// Möller–Trumbore, double sided. Returns the distance and the weights of the second and third corner.
+fn intersect_triangle(ray: &Ray, corners: [Vector3<f32>; 3], max_distance: f32) -> Option<(f32, f32, f32)>
+{
+    let edge0 = corners[1] - corners[0];
+    let edge1 = corners[2] - corners[0];
+    let p = ray.direction.cross(edge1);
+    let determinant = edge0.dot(p);
+    if determinant.abs() < RAY_TRIANGLE_EPSILON
+    {
+        return None;
+    }
+    let inverse_determinant = 1.0 / determinant;
+    let t = ray.origin - corners[0];
+    let u = t.dot(p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u)
+    {
+        return None;
+    }
+    let q = t.cross(edge0);
+    let v = ray.direction.dot(q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0
+    {
+        return None;
+    }
+    let distance = edge1.dot(q) * inverse_determinant;
+    if distance < 0.0 || distance > max_distance
+    {
+        return None;
+    }
+    Some((distance, u, v))
+}
+
+impl Mesh
+{
+    // get_triangle_indices without copying the index buffer.
+    fn get_picking_indices(&self) -> Cow<'_, [u32]>
+    {
+        if self.mesh_index_data.is_empty() { Cow::Owned(self.get_triangle_indices()) } else { Cow::Borrowed(&self.mesh_index_data) }
+    }
+
+    fn get_triangle_corners(&self, indices: &[u32], triangle: usize) -> [Vector3<f32>; 3]
+    {
+        [0, 1, 2].map(|corner| self.get_position(indices[triangle * 3 + corner] as usize))
+    }
+
+    pub fn build_triangle_bvh(&self) -> MeshTriangleBvh
+    {
+        let indices = self.get_picking_indices();
+        let boxes: Vec<BoundingBox> = (0..indices.len() / 3).map(|triangle| BoundingBox::from_points(&self.get_triangle_corners(&indices, triangle))).collect();
+        let (nodes, order) = build_bvh(&boxes);
+        MeshTriangleBvh { nodes, triangles: order.into_iter().map(|triangle| triangle as u32).collect() }
+    }
+
+    // Interpolates a channel of the triangle's corners, None when the mesh has no data for it.
+    fn interpolate_channel<const N: usize>(&self, channel: MeshDataChannel, corners: [usize; 3], barycentrics: Vector3<f32>) -> Option<[f32; N]>
+    {
+        let data = self.mesh_channel_data.get(&(channel as usize))?;
+        let channel_size = get_channel_default_value(channel as usize).len();
+        let mut value = [0.0; N];
+        for (corner, weight) in corners.iter().zip([barycentrics.x, barycentrics.y, barycentrics.z])
+        {
+            for (component, component_value) in value.iter_mut().enumerate()
+            {
+                *component_value += data[corner * channel_size + component] * weight;
+            }
+        }
+        Some(value)
+    }
+
+    fn get_ray_hit(&self, indices: &[u32], triangle: usize, ray: &Ray, distance: f32, u: f32, v: f32) -> MeshRayHit
+    {
+        let corners = [0, 1, 2].map(|corner| indices[triangle * 3 + corner] as usize);
+        let barycentrics = Vector3::new(1.0 - u - v, u, v);
+        MeshRayHit
+        {
+            distance,
+            triangle: triangle as u32,
+            barycentrics,
+            position: ray.get_point(distance),
+            normal: self.interpolate_channel::<3>(MeshDataChannel::Normal, corners, barycentrics)
+                .map(Vector3::from)
+                .filter(|normal| normal.magnitude2() > 0.0)
+                .map(|normal| normal.normalize()),
+            uv: self.interpolate_channel::<2>(MeshDataChannel::UV0, corners, barycentrics).map(Vector2::from)
+        }
+    }
+
+    /// Closest triangle hit within max_distance, testing every triangle. Use intersect_ray_with_bvh for repeated queries.
+    pub fn intersect_ray(&self, ray: &Ray, max_distance: f32) -> Option<MeshRayHit>
+    {
+        let indices = self.get_picking_indices();
+        let mut closest: Option<(usize, f32, f32, f32)> = None;
+        let mut closest_distance = max_distance;
+        for triangle in 0..indices.len() / 3
+        {
+            if let Some((distance, u, v)) = intersect_triangle(ray, self.get_triangle_corners(&indices, triangle), closest_distance)
+            {
+                closest_distance = distance;
+                closest = Some((triangle, distance, u, v));
+            }
+        }
+        closest.map(|(triangle, distance, u, v)| self.get_ray_hit(&indices, triangle, ray, distance, u, v))
+    }
+
+    /// Same as intersect_ray through a hierarchy built by build_triangle_bvh for this mesh.
+    pub fn intersect_ray_with_bvh(&self, bvh: &MeshTriangleBvh, ray: &Ray, max_distance: f32) -> Option<MeshRayHit>
+    {
+        let indices = self.get_picking_indices();
+        let mut barycentrics = (0.0, 0.0);
+        let (item, distance) = cast_ray_bvh(&bvh.nodes, ray, max_distance, |item, closest_distance| {
+            let triangle = bvh.triangles[item] as usize;
+            let (distance, u, v) = intersect_triangle(ray, self.get_triangle_corners(&indices, triangle), closest_distance)?;
+            // Only kept while it is the closest, every later hit is closer.
+            barycentrics = (u, v);
+            Some(distance)
+        })?;
+        Some(self.get_ray_hit(&indices, bvh.triangles[item] as usize, ray, distance, barycentrics.0, barycentrics.1))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // Rays from a ring around the sphere towards points near its center, some of them passing it.
+    fn get_test_rays() -> Vec<Ray>
+    {
+        (0..64).map(|index| {
+            let angle = index as f32 * 0.37;
+            let origin = Vector3::new(angle.cos() * 5.0, (index as f32 * 0.21).sin() * 3.0, angle.sin() * 5.0);
+            let target = Vector3::new((index % 5) as f32 * 0.31 - 0.6, (index % 7) as f32 * 0.23 - 0.7, (index % 3) as f32 * 0.4 - 0.4);
+            Ray::new(origin, target * 1.4 - origin)
+        }).collect()
+    }
+
+    #[test]
+    fn bvh_hits_match_the_brute_force_hits()
+    {
+        let mesh = Mesh::create_uv_sphere(1.0, 24, 12);
+        let bvh = mesh.build_triangle_bvh();
+        let mut hit_count = 0;
+        for ray in get_test_rays()
+        {
+            let (hit, bvh_hit) = (mesh.intersect_ray(&ray, f32::MAX), mesh.intersect_ray_with_bvh(&bvh, &ray, f32::MAX));
+            assert_eq!(hit.is_some(), bvh_hit.is_some());
+            let (Some(hit), Some(bvh_hit)) = (hit, bvh_hit) else { continue };
+            hit_count += 1;
+            assert_eq!(bvh_hit.triangle, hit.triangle);
+            assert_eq!(bvh_hit.distance, hit.distance);
+            assert_eq!(bvh_hit.barycentrics, hit.barycentrics);
+            assert_eq!(bvh_hit.normal, hit.normal);
+            assert_eq!(bvh_hit.uv, hit.uv);
+            assert!((hit.barycentrics.x + hit.barycentrics.y + hit.barycentrics.z - 1.0).abs() < 1e-5);
+            // The sphere is coarse, but its hit points and normals stay close to the exact ones.
+            assert!((hit.position - ray.get_point(hit.distance)).magnitude() < 1e-4);
+            assert!(hit.normal.unwrap().dot(hit.position.normalize()) > 0.95);
+            assert!(hit.uv.is_some());
+
+            // Hits beyond max_distance are ignored.
+            assert!(mesh.intersect_ray_with_bvh(&bvh, &ray, hit.distance * 0.9).is_none());
+        }
+        assert!(hit_count > 16 && hit_count < 64);
+    }
+}
+
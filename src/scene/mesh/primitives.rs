@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use cgmath::{InnerSpace, Vector2, Vector3, Zero};
+
+use crate::scene::mesh::*;
+
+// Collects the channels of a primitive. Triangles are wound so that their geometric normal, cross(p1 - p0, p2 - p0),
+// agrees with the vertex normals, which makes them clockwise when seen from outside as D3D12 expects by default.
+#[derive(Default)]
+struct PrimitiveBuilder
+{
+    positions: Vec<Vector3<f32>>,
+    normals: Vec<Vector3<f32>>,
+    tangents: Vec<[f32; 4]>,
+    uvs: Vec<Vector2<f32>>,
+    indices: Vec<u32>
+}
+
+impl PrimitiveBuilder
+{
+    // tangent and bitangent are the directions of increasing u and v, the bitangent only decides the sign in w.
+    fn add_vertex(&mut self, position: Vector3<f32>, normal: Vector3<f32>, tangent: Vector3<f32>, bitangent: Vector3<f32>, uv: Vector2<f32>) -> u32
+    {
+        let tangent = (tangent - normal * normal.dot(tangent)).normalize();
+        let sign = if normal.cross(tangent).dot(bitangent) < 0.0 { -1.0 } else { 1.0 };
+        self.positions.push(position);
+        self.normals.push(normal);
+        self.tangents.push([tangent.x, tangent.y, tangent.z, sign]);
+        self.uvs.push(uv);
+        self.positions.len() as u32 - 1
+    }
+
+    fn add_triangle(&mut self, v0: u32, v1: u32, v2: u32)
+    {
+        let [p0, p1, p2] = [v0, v1, v2].map(|vertex| self.positions[vertex as usize]);
+        let normal = self.normals[v0 as usize] + self.normals[v1 as usize] + self.normals[v2 as usize];
+        if (p1 - p0).cross(p2 - p0).dot(normal) < 0.0
+        {
+            self.indices.extend_from_slice(&[v0, v2, v1]);
+        }
+        else
+        {
+            self.indices.extend_from_slice(&[v0, v1, v2]);
+        }
+    }
+
+    // Grid of (u_segments + 1) * (v_segments + 1) vertices spanning u_axis and v_axis around center, u and v run 0..1.
+    fn add_grid(&mut self, center: Vector3<f32>, u_axis: Vector3<f32>, v_axis: Vector3<f32>, normal: Vector3<f32>, u_segments: u32, v_segments: u32)
+    {
+        let first_vertex = self.positions.len() as u32;
+        for v in 0..=v_segments
+        {
+            for u in 0..=u_segments
+            {
+                let uv = Vector2::new(u as f32 / u_segments as f32, v as f32 / v_segments as f32);
+                let position = center + u_axis * (uv.x - 0.5) + v_axis * (uv.y - 0.5);
+                self.add_vertex(position, normal, u_axis, v_axis, uv);
+            }
+        }
+        let row = u_segments + 1;
+        for v in 0..v_segments
+        {
+            for u in 0..u_segments
+            {
+                let vertex = first_vertex + v * row + u;
+                self.add_triangle(vertex, vertex + 1, vertex + row + 1);
+                self.add_triangle(vertex, vertex + row + 1, vertex + row);
+            }
+        }
+    }
+
+    // Revolves a profile of (radius, height) points with their (radial, vertical) normals around the y axis. u follows
+    // the angle, v the arc length of the profile. Points on the axis get one vertex per segment and no degenerate triangles.
+    fn add_lathe(&mut self, profile: &[(Vector2<f32>, Vector2<f32>)], segments: u32)
+    {
+        let mut arc_lengths = vec![0.0];
+        for points in profile.windows(2)
+        {
+            arc_lengths.push(arc_lengths.last().unwrap() + (points[1].0 - points[0].0).magnitude());
+        }
+        let total_length = arc_lengths.last().copied().unwrap_or(0.0).max(f32::EPSILON);
+
+        let first_vertex = self.positions.len() as u32;
+        for (point_index, (point, normal)) in profile.iter().enumerate()
+        {
+            let previous = profile[point_index.saturating_sub(1)].0;
+            let next = profile[(point_index + 1).min(profile.len() - 1)].0;
+            let profile_direction = next - previous;
+            for segment in 0..=segments
+            {
+                let angle = 2.0 * PI * segment as f32 / segments as f32;
+                let (sin, cos) = angle.sin_cos();
+                let position = Vector3::new(point.x * cos, point.y, point.x * sin);
+                let vertex_normal = Vector3::new(normal.x * cos, normal.y, normal.x * sin).normalize();
+                let tangent = Vector3::new(-sin, 0.0, cos);
+                let bitangent = Vector3::new(profile_direction.x * cos, profile_direction.y, profile_direction.x * sin);
+                let uv = Vector2::new(segment as f32 / segments as f32, arc_lengths[point_index] / total_length);
+                self.add_vertex(position, vertex_normal, tangent, bitangent, uv);
+            }
+        }
+        let row = segments + 1;
+        for point_index in 0..profile.len().saturating_sub(1)
+        {
+            for segment in 0..segments
+            {
+                let vertex = first_vertex + point_index as u32 * row + segment;
+                if profile[point_index].0.x > 0.0
+                {
+                    self.add_triangle(vertex, vertex + 1, vertex + row + 1);
+                }
+                if profile[point_index + 1].0.x > 0.0
+                {
+                    self.add_triangle(vertex, vertex + row + 1, vertex + row);
+                }
+            }
+        }
+    }
+
+    // Cap facing up or down, mapped like the box face with the same normal.
+    fn add_disk(&mut self, height: f32, radius: f32, normal_sign: f32, segments: u32)
+    {
+        let normal = Vector3::new(0.0, normal_sign, 0.0);
+        let tangent = Vector3::unit_x();
+        let bitangent = Vector3::new(0.0, 0.0, -normal_sign);
+        let get_uv = |x: f32, z: f32| Vector2::new(0.5 + 0.5 * x / radius, 0.5 - 0.5 * normal_sign * z / radius);
+        let center = self.add_vertex(Vector3::new(0.0, height, 0.0), normal, tangent, bitangent, get_uv(0.0, 0.0));
+        for segment in 0..=segments
+        {
+            let angle = 2.0 * PI * segment as f32 / segments as f32;
+            let (x, z) = (radius * angle.cos(), radius * angle.sin());
+            self.add_vertex(Vector3::new(x, height, z), normal, tangent, bitangent, get_uv(x, z));
+        }
+        for segment in 0..segments
+        {
+            self.add_triangle(center, center + 1 + segment, center + 2 + segment);
+        }
+    }
+
+    fn build(self) -> Mesh
+    {
+        let mut mesh = Mesh::default();
+        mesh.mesh_channel_data.insert(MeshDataChannel::Position as usize, self.positions.iter().flat_map(|position| [position.x, position.y, position.z]).collect());
+        mesh.mesh_channel_data.insert(MeshDataChannel::Normal as usize, self.normals.iter().flat_map(|normal| [normal.x, normal.y, normal.z]).collect());
+        mesh.mesh_channel_data.insert(MeshDataChannel::Tangent as usize, self.tangents.concat());
+        mesh.mesh_channel_data.insert(MeshDataChannel::UV0 as usize, self.uvs.iter().flat_map(|uv| [uv.x, uv.y]).collect());
+        mesh.mesh_index_data = self.indices;
+        mesh.update_bounds(false);
+        mesh
+    }
+}
+
+fn get_sphere_profile(radius: f32, first_angle: f32, last_angle: f32, rings: u32, height_offset: f32) -> Vec<(Vector2<f32>, Vector2<f32>)>
+{
+    (0..=rings)
+        .map(|ring| {
+            let angle = first_angle + (last_angle - first_angle) * ring as f32 / rings as f32;
+            // Exactly on the axis at the poles, so they get no degenerate triangles.
+            let sin = if angle == 0.0 || angle == PI { 0.0 } else { angle.sin() };
+            let normal = Vector2::new(sin, angle.cos());
+            (Vector2::new(radius * normal.x, radius * normal.y + height_offset), normal)
+        })
+        .collect()
+}
+
+/// Procedural primitives centered at the origin with y up. Every primitive has positions, normals, tangents, UVs and
+/// cached bounds. Tessellation arguments are clamped to the smallest sensible value.
+impl Mesh
+{
+    /// Box with the given edge lengths, every face a grid of subdivisions x subdivisions quads with its own UV square.
+    pub fn create_box(size: Vector3<f32>, subdivisions: u32) -> Mesh
+    {
+        let subdivisions = subdivisions.max(1);
+        let mut builder = PrimitiveBuilder::default();
+        // Normal and the viewer's up direction of every face, u runs to the right and v downwards seen from outside.
+        let faces = [
+            (Vector3::unit_x(), Vector3::unit_y()), (-Vector3::unit_x(), Vector3::unit_y()),
+            (Vector3::unit_z(), Vector3::unit_y()), (-Vector3::unit_z(), Vector3::unit_y()),
+            (Vector3::unit_y(), Vector3::unit_z()), (-Vector3::unit_y(), -Vector3::unit_z())
+        ];
+        let scale = |axis: Vector3<f32>| Vector3::new(axis.x * size.x, axis.y * size.y, axis.z * size.z);
+        for (normal, up) in faces
+        {
+            let right = up.cross(-normal);
+            builder.add_grid(scale(normal) * 0.5, scale(right), scale(-up), normal, subdivisions, subdivisions);
+        }
+        builder.build()
+    }
+
+    /// Plane in the xz plane facing +y, with u along x and v along -z.
+    pub fn create_plane(size_x: f32, size_z: f32, subdivisions_x: u32, subdivisions_z: u32) -> Mesh
+    {
+        let mut builder = PrimitiveBuilder::default();
+        builder.add_grid(Vector3::zero(), Vector3::new(size_x, 0.0, 0.0), Vector3::new(0.0, 0.0, -size_z), Vector3::unit_y(), subdivisions_x.max(1), subdivisions_z.max(1));
+        builder.build()
+    }
+
+    /// Latitude and longitude sphere, u around the y axis and v from the north pole down.
+    pub fn create_uv_sphere(radius: f32, segments: u32, rings: u32) -> Mesh
+    {
+        let mut builder = PrimitiveBuilder::default();
+        builder.add_lathe(&get_sphere_profile(radius, 0.0, PI, rings.max(2), 0.0), segments.max(3));
+        builder.build()
+    }
+
+    /// Subdivided icosahedron, every subdivision splits each triangle into four. UVs are the spherical mapping of
+    /// create_uv_sphere, with vertices duplicated along the seam.
+    pub fn create_icosphere(radius: f32, subdivisions: u32) -> Mesh
+    {
+        let golden = (1.0 + 5.0f32.sqrt()) * 0.5;
+        let mut directions: Vec<Vector3<f32>> = [
+            (-1.0, golden, 0.0), (1.0, golden, 0.0), (-1.0, -golden, 0.0), (1.0, -golden, 0.0),
+            (0.0, -1.0, golden), (0.0, 1.0, golden), (0.0, -1.0, -golden), (0.0, 1.0, -golden),
+            (golden, 0.0, -1.0), (golden, 0.0, 1.0), (-golden, 0.0, -1.0), (-golden, 0.0, 1.0)
+        ].iter().map(|(x, y, z)| Vector3::new(*x, *y, *z).normalize()).collect();
+        let mut triangles: Vec<[u32; 3]> = vec![
+            [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11], [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+            [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9], [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1]
+        ];
+        for _ in 0..subdivisions
+        {
+            let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+            let mut get_midpoint = |a: u32, b: u32, directions: &mut Vec<Vector3<f32>>| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    directions.push((directions[a as usize] + directions[b as usize]).normalize());
+                    directions.len() as u32 - 1
+                })
+            };
+            let mut subdivided = Vec::with_capacity(triangles.len() * 4);
+            for [a, b, c] in triangles
+            {
+                let ab = get_midpoint(a, b, &mut directions);
+                let bc = get_midpoint(b, c, &mut directions);
+                let ca = get_midpoint(c, a, &mut directions);
+                subdivided.extend_from_slice(&[[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]);
+            }
+            triangles = subdivided;
+        }
+
+        let get_u = |direction: Vector3<f32>| {
+            let angle = direction.z.atan2(direction.x);
+            (if angle < 0.0 { angle + 2.0 * PI } else { angle }) / (2.0 * PI)
+        };
+        let add_sphere_vertex = |builder: &mut PrimitiveBuilder, direction: Vector3<f32>, u: f32| {
+            let angle = u * 2.0 * PI;
+            let (sin, cos) = angle.sin_cos();
+            let tangent = Vector3::new(-sin, 0.0, cos);
+            // dP/dv, still defined at the poles where the tangent plane has no preferred direction.
+            let bitangent = Vector3::new(cos * direction.y, -(1.0 - direction.y * direction.y).max(0.0).sqrt(), sin * direction.y);
+            builder.add_vertex(direction * radius, direction, tangent, bitangent, Vector2::new(u, direction.y.clamp(-1.0, 1.0).acos() / PI))
+        };
+        let is_pole = |direction: Vector3<f32>| direction.x.abs() < 1e-6 && direction.z.abs() < 1e-6;
+
+        let mut builder = PrimitiveBuilder::default();
+        let mut vertices: Vec<Option<u32>> = vec![None; directions.len()];
+        let mut seam_copies: HashMap<u32, u32> = HashMap::new();
+        for triangle in triangles
+        {
+            // The u of a pole is undefined, pole corners get their own vertex with the mean u of the other corners.
+            let mut us = triangle.map(|vertex| get_u(directions[vertex as usize]));
+            let non_poles: Vec<usize> = (0..3).filter(|corner| !is_pole(directions[triangle[*corner] as usize])).collect();
+            let max_u = non_poles.iter().map(|corner| us[*corner]).fold(0.0, f32::max);
+            let mut triangle_vertices = [0; 3];
+            for corner in non_poles.iter().copied()
+            {
+                let vertex = triangle[corner];
+                let direction = directions[vertex as usize];
+                // Triangles crossing the seam at u = 0 get copies of their small u vertices with u + 1.
+                triangle_vertices[corner] = if max_u - us[corner] > 0.5
+                {
+                    us[corner] += 1.0;
+                    *seam_copies.entry(vertex).or_insert_with(|| add_sphere_vertex(&mut builder, direction, us[corner]))
+                }
+                else
+                {
+                    *vertices[vertex as usize].get_or_insert_with(|| add_sphere_vertex(&mut builder, direction, us[corner]))
+                };
+            }
+            let pole_u = non_poles.iter().map(|corner| us[*corner]).sum::<f32>() / non_poles.len() as f32;
+            for corner in (0..3).filter(|corner| !non_poles.contains(corner))
+            {
+                triangle_vertices[corner] = add_sphere_vertex(&mut builder, directions[triangle[corner] as usize], pole_u);
+            }
+            builder.add_triangle(triangle_vertices[0], triangle_vertices[1], triangle_vertices[2]);
+        }
+        builder.build()
+    }
+
+    /// Cylinder along y with caps, segments around and height_segments along the side.
+    pub fn create_cylinder(radius: f32, height: f32, segments: u32, height_segments: u32) -> Mesh
+    {
+        let (segments, height_segments) = (segments.max(3), height_segments.max(1));
+        let profile: Vec<(Vector2<f32>, Vector2<f32>)> = (0..=height_segments)
+            .map(|ring| (Vector2::new(radius, height * (0.5 - ring as f32 / height_segments as f32)), Vector2::new(1.0, 0.0)))
+            .collect();
+        let mut builder = PrimitiveBuilder::default();
+        builder.add_lathe(&profile, segments);
+        builder.add_disk(height * 0.5, radius, 1.0, segments);
+        builder.add_disk(-height * 0.5, radius, -1.0, segments);
+        builder.build()
+    }
+
+    /// Cone along y with its apex at the top and a cap at the bottom.
+    pub fn create_cone(radius: f32, height: f32, segments: u32, height_segments: u32) -> Mesh
+    {
+        let (segments, height_segments) = (segments.max(3), height_segments.max(1));
+        let normal = Vector2::new(height, radius).normalize();
+        let profile: Vec<(Vector2<f32>, Vector2<f32>)> = (0..=height_segments)
+            .map(|ring| {
+                let t = ring as f32 / height_segments as f32;
+                (Vector2::new(radius * t, height * (0.5 - t)), normal)
+            })
+            .collect();
+        let mut builder = PrimitiveBuilder::default();
+        builder.add_lathe(&profile, segments);
+        builder.add_disk(-height * 0.5, radius, -1.0, segments);
+        builder.build()
+    }
+
+    /// Capsule along y, height is the distance between the centers of the two hemispheres and rings the number of
+    /// rings per hemisphere.
+    pub fn create_capsule(radius: f32, height: f32, segments: u32, rings: u32) -> Mesh
+    {
+        let rings = rings.max(1);
+        let mut profile = get_sphere_profile(radius, 0.0, PI * 0.5, rings, height * 0.5);
+        // Without a cylinder part both hemispheres share the equator.
+        let skipped_rings = if height > 0.0 { 0 } else { 1 };
+        profile.extend(get_sphere_profile(radius, PI * 0.5, PI, rings, -height * 0.5).into_iter().skip(skipped_rings));
+        let mut builder = PrimitiveBuilder::default();
+        builder.add_lathe(&profile, segments.max(3));
+        builder.build()
+    }
+
+    /// Torus around the y axis, major_radius to the center of the tube and minor_radius of the tube.
+    pub fn create_torus(major_radius: f32, minor_radius: f32, major_segments: u32, minor_segments: u32) -> Mesh
+    {
+        let minor_segments = minor_segments.max(3);
+        let profile: Vec<(Vector2<f32>, Vector2<f32>)> = (0..=minor_segments)
+            .map(|ring| {
+                let angle = 2.0 * PI * ring as f32 / minor_segments as f32;
+                let normal = Vector2::new(angle.cos(), angle.sin());
+                (Vector2::new(major_radius, 0.0) + normal * minor_radius, normal)
+            })
+            .collect();
+        let mut builder = PrimitiveBuilder::default();
+        builder.add_lathe(&profile, major_segments.max(3));
+        builder.build()
+    }
+
+    /// One triangle covering the clip space square, positions are in clip space and UVs cover 0..1 inside of it.
+    pub fn create_fullscreen_triangle() -> Mesh
+    {
+        let mut builder = PrimitiveBuilder::default();
+        let normal = -Vector3::unit_z();
+        let (tangent, bitangent) = (Vector3::unit_x(), -Vector3::unit_y());
+        let v0 = builder.add_vertex(Vector3::new(-1.0, -1.0, 0.0), normal, tangent, bitangent, Vector2::new(0.0, 1.0));
+        let v1 = builder.add_vertex(Vector3::new(-1.0, 3.0, 0.0), normal, tangent, bitangent, Vector2::new(0.0, -1.0));
+        let v2 = builder.add_vertex(Vector3::new(3.0, -1.0, 0.0), normal, tangent, bitangent, Vector2::new(2.0, 1.0));
+        builder.add_triangle(v0, v1, v2);
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn get_primitives() -> Vec<(&'static str, Mesh)>
+    {
+        vec![
+            ("box", Mesh::create_box(Vector3::new(1.0, 2.0, 3.0), 2)),
+            ("plane", Mesh::create_plane(2.0, 1.0, 3, 2)),
+            ("uv sphere", Mesh::create_uv_sphere(1.0, 12, 6)),
+            ("icosphere", Mesh::create_icosphere(1.0, 2)),
+            ("cylinder", Mesh::create_cylinder(0.5, 2.0, 12, 2)),
+            ("cone", Mesh::create_cone(0.5, 1.0, 12, 2)),
+            ("capsule", Mesh::create_capsule(0.5, 1.0, 12, 3)),
+            ("flat capsule", Mesh::create_capsule(0.5, 0.0, 12, 3)),
+            ("torus", Mesh::create_torus(1.0, 0.25, 16, 8)),
+            ("fullscreen triangle", Mesh::create_fullscreen_triangle())
+        ]
+    }
+
+    fn get_vector(data: &[f32], vertex: usize, size: usize) -> Vector3<f32>
+    {
+        Vector3::new(data[vertex * size], data[vertex * size + 1], data[vertex * size + 2])
+    }
+
+    #[test]
+    fn primitives_are_valid()
+    {
+        for (name, mesh) in get_primitives()
+        {
+            assert_eq!(mesh.validate(), Ok(()), "{} is invalid", name);
+        }
+    }
+
+    #[test]
+    fn winding_agrees_with_normals()
+    {
+        for (name, mesh) in get_primitives()
+        {
+            let normals = &mesh.mesh_channel_data[&(MeshDataChannel::Normal as usize)];
+            for triangle in mesh.get_triangle_indices().chunks_exact(3)
+            {
+                let [p0, p1, p2] = [0, 1, 2].map(|corner| mesh.get_position(triangle[corner] as usize));
+                let normal = triangle.iter().fold(Vector3::zero(), |sum, vertex| sum + get_vector(normals, *vertex as usize, 3));
+                assert!((p1 - p0).cross(p2 - p0).dot(normal) > 0.0, "{} has triangle {:?} wound against its normals", name, triangle);
+            }
+        }
+    }
+
+    #[test]
+    fn tangent_frames_are_orthonormal()
+    {
+        for (name, mesh) in get_primitives()
+        {
+            let normals = &mesh.mesh_channel_data[&(MeshDataChannel::Normal as usize)];
+            let tangents = &mesh.mesh_channel_data[&(MeshDataChannel::Tangent as usize)];
+            for vertex in 0..mesh.get_vertex_count()
+            {
+                let normal = get_vector(normals, vertex, 3);
+                let tangent = get_vector(tangents, vertex, 4);
+                assert!((normal.magnitude() - 1.0).abs() < 1e-5, "{} has normal {:?} at vertex {}", name, normal, vertex);
+                assert!((tangent.magnitude() - 1.0).abs() < 1e-5, "{} has tangent {:?} at vertex {}", name, tangent, vertex);
+                assert!(normal.dot(tangent).abs() < 1e-5, "{} has a tangent off the normal plane at vertex {}", name, vertex);
+                assert_eq!(tangents[vertex * 4 + 3].abs(), 1.0, "{} has a bitangent sign that is not one at vertex {}", name, vertex);
+            }
+        }
+    }
+}
+
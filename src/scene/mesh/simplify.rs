@@ -0,0 +1,527 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::scene::mesh::*;
+
+// Border and seam edges are kept in place by planes perpendicular to their triangles, weighted up against surface planes.
+const BORDER_EDGE_WEIGHT: f64 = 10.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SimplificationTarget
+{
+    // Collapse edges until at most this many triangles are left.
+    TriangleCount(usize),
+    // Collapse every edge whose error, relative to the bounding box diagonal of the mesh, stays below this bound.
+    Error(f32)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MeshLod
+{
+    pub mesh: Mesh,
+    // Geometric deviation from the source mesh relative to its bounding box diagonal, 0 for the source itself.
+    pub error: f32
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum VertexKind
+{
+    Manifold,
+    // Lies on exactly one open edge loop, slides along it.
+    Border,
+    // Lies on exactly one attribute seam, slides along it with all of its wedges.
+    Seam,
+    Locked
+}
+
+#[derive(Clone, Copy, Default)]
+struct Quadric
+{
+    // Upper triangle of a symmetric 4x4 matrix: a00 a01 a02 a03 a11 a12 a13 a22 a23 a33.
+    a: [f64; 10],
+    weight: f64
+}
+
+impl Quadric
+{
+    fn from_plane(normal: Vector3<f64>, point: Vector3<f64>, weight: f64) -> Self
+    {
+        let (a, b, c) = (normal.x, normal.y, normal.z);
+        let d = -normal.dot(point);
+        Quadric
+        {
+            a: [a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d].map(|value| value * weight),
+            weight
+        }
+    }
+
+    fn add(&mut self, other: &Quadric)
+    {
+        for (value, other_value) in self.a.iter_mut().zip(other.a.iter())
+        {
+            *value += other_value;
+        }
+        self.weight += other.weight;
+    }
+
+    // Weighted RMS distance of the point to the accumulated planes.
+    fn get_error(&self, point: Vector3<f64>) -> f64
+    {
+        if self.weight <= 0.0
+        {
+            return 0.0;
+        }
+        let (x, y, z) = (point.x, point.y, point.z);
+        let a = &self.a;
+        let error = x * x * a[0] + 2.0 * x * y * a[1] + 2.0 * x * z * a[2] + 2.0 * x * a[3]
+            + y * y * a[4] + 2.0 * y * z * a[5] + 2.0 * y * a[6]
+            + z * z * a[7] + 2.0 * z * a[8]
+            + a[9];
+        (error.max(0.0) / self.weight).sqrt()
+    }
+}
+
+struct MeshTopology
+{
+    kinds: Vec<VertexKind>,
+    border_neighbours: Vec<Vec<u32>>,
+    seam_neighbours: Vec<Vec<u32>>,
+    // Number of triangles on each position edge, keyed by the ordered position pair.
+    edge_triangle_counts: HashMap<(u32, u32), usize>,
+    position_triangles: Vec<Vec<usize>>,
+    position_wedges: Vec<Vec<u32>>
+}
+
+fn push_unique(values: &mut Vec<u32>, value: u32)
+{
+    if !values.contains(&value)
+    {
+        values.push(value);
+    }
+}
+
+// Positions are identified by their first vertex, vertex_positions maps every vertex to it.
+fn analyze_topology(triangles: &[[u32; 3]], regions: &[u32], vertex_positions: &[u32]) -> MeshTopology
+{
+    let vertex_count = vertex_positions.len();
+    let mut position_half_edges: HashMap<(u32, u32, u32), usize> = HashMap::new();
+    let mut vertex_half_edges: HashSet<(u32, u32)> = HashSet::new();
+    let mut edge_triangle_counts: HashMap<(u32, u32), usize> = HashMap::new();
+    let mut position_triangles: Vec<Vec<usize>> = vec![vec![]; vertex_count];
+    let mut position_wedges: Vec<Vec<u32>> = vec![vec![]; vertex_count];
+    for (triangle, vertices) in triangles.iter().enumerate()
+    {
+        for corner in 0..3
+        {
+            let (v0, v1) = (vertices[corner], vertices[(corner + 1) % 3]);
+            let (p0, p1) = (vertex_positions[v0 as usize], vertex_positions[v1 as usize]);
+            *position_half_edges.entry((p0, p1, regions[triangle])).or_insert(0) += 1;
+            vertex_half_edges.insert((v0, v1));
+            *edge_triangle_counts.entry((p0.min(p1), p0.max(p1))).or_insert(0) += 1;
+            position_triangles[p0 as usize].push(triangle);
+            push_unique(&mut position_wedges[p0 as usize], v0);
+        }
+    }
+
+    let mut border_neighbours: Vec<Vec<u32>> = vec![vec![]; vertex_count];
+    let mut seam_neighbours: Vec<Vec<u32>> = vec![vec![]; vertex_count];
+    let mut is_non_manifold = vec![false; vertex_count];
+    for (triangle, vertices) in triangles.iter().enumerate()
+    {
+        for corner in 0..3
+        {
+            let (v0, v1) = (vertices[corner], vertices[(corner + 1) % 3]);
+            let (p0, p1) = (vertex_positions[v0 as usize], vertex_positions[v1 as usize]);
+            if position_half_edges[&(p0, p1, regions[triangle])] > 1
+            {
+                is_non_manifold[p0 as usize] = true;
+                is_non_manifold[p1 as usize] = true;
+            }
+            // Edges between regions are borders too, so both sides move together and stay watertight.
+            if !position_half_edges.contains_key(&(p1, p0, regions[triangle]))
+            {
+                push_unique(&mut border_neighbours[p0 as usize], p1);
+                push_unique(&mut border_neighbours[p1 as usize], p0);
+            }
+            else if !vertex_half_edges.contains(&(v1, v0))
+            {
+                push_unique(&mut seam_neighbours[p0 as usize], p1);
+                push_unique(&mut seam_neighbours[p1 as usize], p0);
+            }
+        }
+    }
+
+    let kinds = (0..vertex_count).map(|position| {
+        let wedge_count = position_wedges[position].len();
+        if is_non_manifold[position]
+        {
+            VertexKind::Locked
+        }
+        else if !border_neighbours[position].is_empty()
+        {
+            if wedge_count == 1 && border_neighbours[position].len() == 2 && seam_neighbours[position].is_empty() { VertexKind::Border } else { VertexKind::Locked }
+        }
+        else if !seam_neighbours[position].is_empty()
+        {
+            if wedge_count == 2 && seam_neighbours[position].len() == 2 { VertexKind::Seam } else { VertexKind::Locked }
+        }
+        else if wedge_count > 1
+        {
+            VertexKind::Locked
+        }
+        else
+        {
+            VertexKind::Manifold
+        }
+    }).collect();
+
+    MeshTopology { kinds, border_neighbours, seam_neighbours, edge_triangle_counts, position_triangles, position_wedges }
+}
+
+fn get_triangle_normal(p0: Vector3<f64>, p1: Vector3<f64>, p2: Vector3<f64>) -> Vector3<f64>
+{
+    (p1 - p0).cross(p2 - p0)
+}
+
+impl Mesh
+{
+    /// Simplifies the mesh with every section as its own region, see simplify_regions. The result keeps the sections,
+    /// triangles outside of all sections are moved behind them.
+    pub fn simplify(&self, target: SimplificationTarget) -> MeshLod
+    {
+        let sections = self.get_sections();
+        let (mut lod, triangle_sections) = self.simplify_regions(&self.get_triangle_sections(), target);
+        if !self.mesh_sections.is_empty()
+        {
+            for (section_index, section) in sections.iter().enumerate()
+            {
+                let first_triangle = triangle_sections.iter().position(|triangle_section| *triangle_section == section_index as u32).unwrap_or(triangle_sections.len());
+                let triangle_count = triangle_sections.iter().filter(|triangle_section| **triangle_section == section_index as u32).count();
+                lod.mesh.add_section(MeshSection { first_index: first_triangle as u32 * 3, index_count: triangle_count as u32 * 3, ..*section });
+            }
+        }
+        lod
+    }
+
+    /// Collapses edges in order of quadric error. Vertices only move onto neighbouring vertices, so channel data is kept
+    /// as is. Mesh borders, edges between triangles of different regions and UV or normal seams (vertices sharing a
+    /// position with different channel data) only collapse along themselves. Duplicate vertices with identical data
+    /// count as seams, weld them first, triangle soups are welded here. Returns the simplified mesh and the region of
+    /// every output triangle, the triangles are sorted by region. The returned mesh has no sections, simplify rebuilds
+    /// them. Point lists are returned unchanged.
+    pub fn simplify_regions(&self, triangle_regions: &[u32], target: SimplificationTarget) -> (MeshLod, Vec<u32>)
+    {
+        if self.mesh_topology == MeshPrimitiveTopology::PointList
+        {
+            return (MeshLod { mesh: self.clone(), error: 0.0 }, vec![]);
+        }
+        // A soup with a triangle always gets indices from welding.
+        if self.mesh_index_data.is_empty() && self.get_vertex_count() >= 3
+        {
+            // Every corner of a soup is its own wedge, which would lock all vertices. Welding keeps the triangle order.
+            let mut welded = self.clone();
+            welded.weld_identical_vertices();
+            return welded.simplify_regions(triangle_regions, target);
+        }
+        let vertex_count = self.get_vertex_count();
+        let indices = self.get_triangle_indices();
+        let mut triangles: Vec<[u32; 3]> = indices.chunks_exact(3).map(|triangle| [triangle[0], triangle[1], triangle[2]]).collect();
+        let mut regions: Vec<u32> = triangle_regions.to_vec();
+        regions.resize(triangles.len(), 0);
+
+        // Positions are normalized to the unit bounding box diagonal so that errors are relative.
+        let mut min = Vector3::new(f64::MAX, f64::MAX, f64::MAX);
+        let mut max = Vector3::new(f64::MIN, f64::MIN, f64::MIN);
+        let mut positions = Vec::with_capacity(vertex_count);
+        for vertex in 0..vertex_count
+        {
+            let position = self.get_position(vertex).cast::<f64>().unwrap();
+            min = Vector3::new(min.x.min(position.x), min.y.min(position.y), min.z.min(position.z));
+            max = Vector3::new(max.x.max(position.x), max.y.max(position.y), max.z.max(position.z));
+            positions.push(position);
+        }
+        let extent = if vertex_count > 0 { (max - min).magnitude() } else { 0.0 };
+        let scale = if extent > 0.0 { 1.0 / extent } else { 1.0 };
+        for position in positions.iter_mut()
+        {
+            *position = (*position - min) * scale;
+        }
+
+        let mut position_ids: HashMap<[u64; 3], u32> = HashMap::new();
+        let vertex_positions: Vec<u32> = positions.iter().enumerate()
+            .map(|(vertex, position)| *position_ids.entry([(position.x + 0.0).to_bits(), (position.y + 0.0).to_bits(), (position.z + 0.0).to_bits()]).or_insert(vertex as u32))
+            .collect();
+
+        let mut topology = analyze_topology(&triangles, &regions, &vertex_positions);
+        let mut quadrics = vec![Quadric::default(); vertex_count];
+        for triangle in &triangles
+        {
+            let corners = triangle.map(|vertex| vertex_positions[vertex as usize]);
+            let points = corners.map(|position| positions[position as usize]);
+            let normal = get_triangle_normal(points[0], points[1], points[2]);
+            let double_area = normal.magnitude();
+            if double_area == 0.0
+            {
+                continue;
+            }
+            let normal = normal / double_area;
+            let plane = Quadric::from_plane(normal, points[0], double_area * 0.5);
+            for (corner, position) in corners.iter().enumerate()
+            {
+                quadrics[*position as usize].add(&plane);
+
+                let next = corners[(corner + 1) % 3];
+                let is_border = topology.border_neighbours[*position as usize].contains(&next);
+                let is_seam = topology.seam_neighbours[*position as usize].contains(&next);
+                if is_border || is_seam
+                {
+                    let edge = points[(corner + 1) % 3] - points[corner];
+                    let edge_normal = edge.cross(normal);
+                    if edge_normal.magnitude2() > 0.0
+                    {
+                        let edge_plane = Quadric::from_plane(edge_normal.normalize(), points[corner], edge.magnitude2() * BORDER_EDGE_WEIGHT);
+                        quadrics[*position as usize].add(&edge_plane);
+                        quadrics[next as usize].add(&edge_plane);
+                    }
+                }
+            }
+        }
+
+        let (target_triangle_count, error_limit) = match target
+        {
+            SimplificationTarget::TriangleCount(count) => (count, f64::MAX),
+            SimplificationTarget::Error(error) => (0, error as f64)
+        };
+
+        let mut result_error: f64 = 0.0;
+        while triangles.len() > target_triangle_count
+        {
+            let mut candidates: Vec<(f64, u32, u32)> = vec![];
+            for triangle in &triangles
+            {
+                for corner in 0..3
+                {
+                    let a = vertex_positions[triangle[corner] as usize];
+                    let b = vertex_positions[triangle[(corner + 1) % 3] as usize];
+                    for (from, to) in [(a, b), (b, a)]
+                    {
+                        let allowed = match topology.kinds[from as usize]
+                        {
+                            VertexKind::Manifold => true,
+                            VertexKind::Border => topology.border_neighbours[from as usize].contains(&to),
+                            VertexKind::Seam => topology.seam_neighbours[from as usize].contains(&to),
+                            VertexKind::Locked => false
+                        };
+                        if allowed && from != to
+                        {
+                            candidates.push((quadrics[from as usize].get_error(positions[to as usize]), from, to));
+                        }
+                    }
+                }
+            }
+            candidates.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+            let mut is_collapse_locked = vec![false; vertex_count];
+            let mut wedge_remap: Vec<u32> = (0..vertex_count as u32).collect();
+            let mut triangles_left = triangles.len();
+            let mut collapse_count = 0;
+            for (error, from, to) in candidates
+            {
+                if error > error_limit || triangles_left <= target_triangle_count
+                {
+                    break;
+                }
+                if is_collapse_locked[from as usize] || is_collapse_locked[to as usize]
+                {
+                    continue;
+                }
+
+                // Every wedge of the collapsed position moves onto the wedge of the target it shares a triangle with.
+                let mut wedge_targets = vec![];
+                for wedge in &topology.position_wedges[from as usize]
+                {
+                    let target_wedge = topology.position_triangles[from as usize].iter()
+                        .filter(|triangle| triangles[**triangle].contains(wedge))
+                        .flat_map(|triangle| triangles[*triangle].iter())
+                        .find(|vertex| vertex_positions[**vertex as usize] == to);
+                    match target_wedge
+                    {
+                        Some(target_wedge) => wedge_targets.push((*wedge, *target_wedge)),
+                        None => break
+                    }
+                }
+                if wedge_targets.len() != topology.position_wedges[from as usize].len()
+                {
+                    continue;
+                }
+
+                // Triangles that stay must not flip over.
+                let has_flips = topology.position_triangles[from as usize].iter().any(|triangle| {
+                    let corners = triangles[*triangle].map(|vertex| vertex_positions[vertex as usize]);
+                    if corners.contains(&to)
+                    {
+                        return false;
+                    }
+                    let points = corners.map(|position| positions[position as usize]);
+                    let moved = corners.map(|position| if position == from { positions[to as usize] } else { positions[position as usize] });
+                    let old_normal = get_triangle_normal(points[0], points[1], points[2]);
+                    let new_normal = get_triangle_normal(moved[0], moved[1], moved[2]);
+                    old_normal.dot(new_normal) <= 0.0
+                });
+                if has_flips
+                {
+                    continue;
+                }
+
+                for (wedge, target_wedge) in wedge_targets
+                {
+                    wedge_remap[wedge as usize] = target_wedge;
+                }
+                let from_quadric = quadrics[from as usize];
+                quadrics[to as usize].add(&from_quadric);
+                // The triangles around both ends change, so their quadric errors and flip tests are stale until the
+                // next pass.
+                for triangle in topology.position_triangles[from as usize].iter().chain(topology.position_triangles[to as usize].iter())
+                {
+                    for vertex in triangles[*triangle]
+                    {
+                        is_collapse_locked[vertex_positions[vertex as usize] as usize] = true;
+                    }
+                }
+                triangles_left = triangles_left.saturating_sub(topology.edge_triangle_counts[&(from.min(to), from.max(to))]);
+                result_error = result_error.max(error);
+                collapse_count += 1;
+            }
+            if collapse_count == 0
+            {
+                break;
+            }
+
+            let mut remaining_triangles = Vec::with_capacity(triangles_left);
+            let mut remaining_regions = Vec::with_capacity(triangles_left);
+            for (triangle, region) in triangles.iter().zip(regions.iter())
+            {
+                let triangle = triangle.map(|vertex| wedge_remap[vertex as usize]);
+                let corners = triangle.map(|vertex| vertex_positions[vertex as usize]);
+                if corners[0] != corners[1] && corners[1] != corners[2] && corners[2] != corners[0]
+                {
+                    remaining_triangles.push(triangle);
+                    remaining_regions.push(*region);
+                }
+            }
+            triangles = remaining_triangles;
+            regions = remaining_regions;
+            topology = analyze_topology(&triangles, &regions, &vertex_positions);
+        }
+
+        let mut order: Vec<usize> = (0..triangles.len()).collect();
+        order.sort_by_key(|triangle| regions[*triangle]);
+
+        let mut vertex_remap: Vec<Option<u32>> = vec![None; vertex_count];
+        let mut source_vertices = vec![];
+        let mut new_indices = Vec::with_capacity(triangles.len() * 3);
+        for triangle in &order
+        {
+            for vertex in &triangles[*triangle]
+            {
+                let new_vertex = *vertex_remap[*vertex as usize].get_or_insert_with(|| {
+                    source_vertices.push(*vertex);
+                    source_vertices.len() as u32 - 1
+                });
+                new_indices.push(new_vertex);
+            }
+        }
+
+        let mut mesh = self.clone();
+        mesh.remap_vertices(&source_vertices);
+        mesh.mesh_index_data = new_indices;
+        mesh.mesh_sections.clear();
+        let lod = MeshLod { mesh, error: result_error as f32 };
+        (lod, order.iter().map(|triangle| regions[*triangle]).collect())
+    }
+
+    /// Builds lod_count levels, each with triangle_ratio times the triangles of the previous one, every level simplified
+    /// from the source so that its error is measured against it. Stops early when the mesh cannot be reduced further.
+    pub fn generate_lod_chain(&self, lod_count: usize, triangle_ratio: f32) -> Vec<MeshLod>
+    {
+        let mut lods = vec![MeshLod { mesh: self.clone(), error: 0.0 }];
+        let mut target_triangle_count = (self.get_triangle_indices().len() / 3) as f32;
+        while lods.len() < lod_count
+        {
+            target_triangle_count *= triangle_ratio;
+            let lod = self.simplify(SimplificationTarget::TriangleCount(target_triangle_count as usize));
+            // Soups have no index data, compare what both levels actually draw.
+            if lod.mesh.get_triangle_indices().len() >= lods.last().unwrap().mesh.get_triangle_indices().len()
+            {
+                break;
+            }
+            lods.push(lod);
+        }
+        lods
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn get_triangle_counts(lods: &[MeshLod]) -> Vec<usize>
+    {
+        lods.iter().map(|lod| lod.mesh.get_triangle_indices().len() / 3).collect()
+    }
+
+    fn unweld(mesh: &Mesh) -> Mesh
+    {
+        let mut soup = mesh.clone();
+        soup.remap_vertices(&mesh.get_triangle_indices());
+        soup.mesh_index_data.clear();
+        soup
+    }
+
+    #[test]
+    fn lod_chain_halves_triangles()
+    {
+        let lods = Mesh::create_icosphere(1.0, 3).generate_lod_chain(4, 0.5);
+        assert_eq!(get_triangle_counts(&lods), vec![1280, 640, 320, 160]);
+        assert!(lods.windows(2).all(|pair| pair[0].error <= pair[1].error));
+    }
+
+    #[test]
+    fn lod_chain_of_triangle_soup_matches_indexed_mesh()
+    {
+        let mesh = Mesh::create_icosphere(1.0, 3);
+        let soup = unweld(&mesh);
+        assert!(soup.mesh_index_data.is_empty());
+        let lods = soup.generate_lod_chain(4, 0.5);
+        assert_eq!(get_triangle_counts(&lods), get_triangle_counts(&mesh.generate_lod_chain(4, 0.5)));
+        assert!(lods[1..].iter().all(|lod| lod.mesh.validate().is_ok()));
+    }
+
+    #[test]
+    fn point_lists_and_soups_without_triangles_are_kept()
+    {
+        let mut points = Mesh { mesh_topology: MeshPrimitiveTopology::PointList, ..Default::default() };
+        points.mesh_channel_data.insert(MeshDataChannel::Position as usize, vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        let lods = points.generate_lod_chain(3, 0.5);
+        assert_eq!(lods.len(), 1);
+        let lod = points.simplify(SimplificationTarget::TriangleCount(0));
+        assert_eq!(lod.mesh.mesh_topology, MeshPrimitiveTopology::PointList);
+        assert_eq!(lod.mesh.mesh_channel_data, points.mesh_channel_data);
+
+        let mut pair = Mesh::default();
+        pair.mesh_channel_data.insert(MeshDataChannel::Position as usize, vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+        assert!(pair.simplify(SimplificationTarget::TriangleCount(0)).mesh.get_triangle_indices().len() < 3);
+    }
+
+    #[test]
+    fn error_target_keeps_flat_plane_flat()
+    {
+        let plane = Mesh::create_plane(1.0, 1.0, 8, 8);
+        let lod = plane.simplify(SimplificationTarget::Error(1e-4));
+        assert!(lod.mesh.get_triangle_indices().len() < plane.get_triangle_indices().len());
+        assert!((0..lod.mesh.get_vertex_count()).all(|vertex| lod.mesh.get_position(vertex).y == 0.0));
+    }
+}
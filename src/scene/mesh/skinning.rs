@@ -0,0 +1,317 @@
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, SquareMatrix, Vector3, Vector4, Zero};
+
+use crate::scene::mesh::*;
+use crate::scene::mesh::bounds::BoundingBox;
+use crate::scene::mesh::validation::MeshError;
+
+// Influences per vertex, the size of the BoneIndices and BoneWeights channels.
+pub const MAX_BONE_INFLUENCES: usize = 4;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bone
+{
+    pub name: String,
+    // Always a bone that comes before this one.
+    pub parent: Option<usize>,
+    // Transform relative to the parent in the rest pose, relative to mesh space for root bones.
+    pub rest_transform: Matrix4<f32>,
+    // Mesh space to bone space at bind time.
+    pub inverse_bind_matrix: Matrix4<f32>
+}
+
+/// Bone hierarchy, sorted so that parents come before their children.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Skeleton
+{
+    bones: Vec<Bone>
+}
+
+impl Skeleton
+{
+    pub fn new() -> Self
+    {
+        Skeleton::default()
+    }
+
+    pub fn add_bone(&mut self, bone: Bone) -> Result<usize, MeshError>
+    {
+        if bone.parent.is_some_and(|parent| parent >= self.bones.len())
+        {
+            return Err(MeshError::InvalidBoneParent(self.bones.len()));
+        }
+        self.bones.push(bone);
+        Ok(self.bones.len() - 1)
+    }
+
+    pub fn get_bones(&self) -> &Vec<Bone>
+    {
+        &self.bones
+    }
+
+    pub fn get_bone_count(&self) -> usize
+    {
+        self.bones.len()
+    }
+
+    pub fn find_bone(&self, name: &str) -> Option<usize>
+    {
+        self.bones.iter().position(|bone| bone.name == name)
+    }
+
+    /// Local transform of every bone in the rest pose.
+    pub fn get_rest_pose(&self) -> Vec<Matrix4<f32>>
+    {
+        self.bones.iter().map(|bone| bone.rest_transform).collect()
+    }
+
+    /// Mesh space transforms of a pose given as one local transform per bone.
+    pub fn get_model_transforms(&self, local_pose: &[Matrix4<f32>]) -> Vec<Matrix4<f32>>
+    {
+        assert_eq!(local_pose.len(), self.bones.len(), "pose needs one transform per bone");
+        let mut model_transforms: Vec<Matrix4<f32>> = Vec::with_capacity(self.bones.len());
+        for (bone, local_transform) in self.bones.iter().zip(local_pose)
+        {
+            let model_transform = match bone.parent
+            {
+                Some(parent) => model_transforms[parent] * local_transform,
+                None => *local_transform
+            };
+            model_transforms.push(model_transform);
+        }
+        model_transforms
+    }
+
+    /// Matrices that move bind pose vertices with their bones, as uploaded for GPU skinning.
+    pub fn get_skinning_matrices(&self, local_pose: &[Matrix4<f32>]) -> Vec<Matrix4<f32>>
+    {
+        self.get_model_transforms(local_pose).iter()
+            .zip(&self.bones)
+            .map(|(model_transform, bone)| model_transform * bone.inverse_bind_matrix)
+            .collect()
+    }
+}
+
+// Normal matrix of a blended transform, the upper 3x3 itself when it cannot be inverted.
+fn get_normal_matrix(matrix: &Matrix3<f32>) -> Matrix3<f32>
+{
+    matrix.invert().map(|inverse| inverse.transpose()).unwrap_or(*matrix)
+}
+
+impl Mesh
+{
+    /// Scales the bone weights of every vertex to sum to one. Vertices without any weight are bound fully to the
+    /// bone of their first influence.
+    pub fn normalize_bone_weights(&mut self)
+    {
+        let Some(weights) = self.mesh_channel_data.get_mut(&(MeshDataChannel::BoneWeights as usize)) else { return };
+        for vertex_weights in weights.chunks_exact_mut(MAX_BONE_INFLUENCES)
+        {
+            for weight in vertex_weights.iter_mut()
+            {
+                *weight = weight.max(0.0);
+            }
+            let sum: f32 = vertex_weights.iter().sum();
+            if sum > 0.0
+            {
+                vertex_weights.iter_mut().for_each(|weight| *weight /= sum);
+            }
+            else
+            {
+                vertex_weights.copy_from_slice(&[1.0, 0.0, 0.0, 0.0]);
+            }
+        }
+    }
+
+    fn check_bone_indices(&self, bone_count: usize) -> Result<(), MeshError>
+    {
+        let (Some(indices), Some(weights)) = (self.mesh_channel_data.get(&(MeshDataChannel::BoneIndices as usize)), self.mesh_channel_data.get(&(MeshDataChannel::BoneWeights as usize)))
+        else
+        {
+            return Err(MeshError::MissingSkinWeights);
+        };
+        for (vertex, (vertex_indices, vertex_weights)) in indices.chunks_exact(MAX_BONE_INFLUENCES).zip(weights.chunks_exact(MAX_BONE_INFLUENCES)).enumerate()
+        {
+            for (bone, weight) in vertex_indices.iter().zip(vertex_weights)
+            {
+                if *weight != 0.0 && (*bone < 0.0 || *bone as usize >= bone_count)
+                {
+                    return Err(MeshError::BoneIndexOutOfRange { vertex, bone: *bone as i64, bone_count });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Linear blend skinning of positions, normals and tangents, the reference for GPU skinning. Normals use the
+    /// inverse transpose of the blended matrix so that scaled bones keep them perpendicular. Influences with zero
+    /// weight are ignored.
+    pub fn skin(&self, skinning_matrices: &[Matrix4<f32>]) -> Result<Mesh, MeshError>
+    {
+        self.validate_structure()?;
+        self.check_bone_indices(skinning_matrices.len())?;
+        let bone_indices = &self.mesh_channel_data[&(MeshDataChannel::BoneIndices as usize)];
+        let bone_weights = &self.mesh_channel_data[&(MeshDataChannel::BoneWeights as usize)];
+        let vertex_count = self.get_vertex_count();
+        let blended_matrices: Vec<Matrix4<f32>> = (0..vertex_count)
+            .map(|vertex| {
+                let mut blended = Matrix4::zero();
+                for influence in vertex * MAX_BONE_INFLUENCES..(vertex + 1) * MAX_BONE_INFLUENCES
+                {
+                    if bone_weights[influence] != 0.0
+                    {
+                        blended += skinning_matrices[bone_indices[influence] as usize] * bone_weights[influence];
+                    }
+                }
+                blended
+            })
+            .collect();
+
+        let mut skinned = self.clone();
+        if let Some(positions) = skinned.mesh_channel_data.get_mut(&(MeshDataChannel::Position as usize))
+        {
+            for (position, matrix) in positions.chunks_exact_mut(3).zip(&blended_matrices)
+            {
+                let transformed = matrix * Vector4::new(position[0], position[1], position[2], 1.0);
+                position.copy_from_slice(&[transformed.x, transformed.y, transformed.z]);
+            }
+        }
+        if let Some(normals) = skinned.mesh_channel_data.get_mut(&(MeshDataChannel::Normal as usize))
+        {
+            for (normal, matrix) in normals.chunks_exact_mut(3).zip(&blended_matrices)
+            {
+                let normal_matrix = get_normal_matrix(&Matrix3::from_cols(matrix.x.truncate(), matrix.y.truncate(), matrix.z.truncate()));
+                let transformed = normal_matrix * Vector3::new(normal[0], normal[1], normal[2]);
+                let transformed = if transformed.magnitude2() > 0.0 { transformed.normalize() } else { transformed };
+                normal.copy_from_slice(&[transformed.x, transformed.y, transformed.z]);
+            }
+        }
+        if let Some(tangents) = skinned.mesh_channel_data.get_mut(&(MeshDataChannel::Tangent as usize))
+        {
+            for (tangent, matrix) in tangents.chunks_exact_mut(4).zip(&blended_matrices)
+            {
+                let transformed = (matrix * Vector4::new(tangent[0], tangent[1], tangent[2], 0.0)).truncate();
+                let transformed = if transformed.magnitude2() > 0.0 { transformed.normalize() } else { transformed };
+                tangent[..3].copy_from_slice(&[transformed.x, transformed.y, transformed.z]);
+            }
+        }
+        if skinned.mesh_bounds.is_some()
+        {
+            skinned.update_bounds(false);
+        }
+        Ok(skinned)
+    }
+
+    /// Bind pose box of the vertices influenced by each bone, empty for bones without vertices. Transformed by the
+    /// skinning matrices they bound any pose.
+    pub fn compute_bone_bounds(&self, bone_count: usize) -> Result<Vec<BoundingBox>, MeshError>
+    {
+        self.check_bone_indices(bone_count)?;
+        let bone_indices = &self.mesh_channel_data[&(MeshDataChannel::BoneIndices as usize)];
+        let bone_weights = &self.mesh_channel_data[&(MeshDataChannel::BoneWeights as usize)];
+        let mut bone_bounds = vec![BoundingBox::default(); bone_count];
+        for vertex in 0..self.get_vertex_count()
+        {
+            let position = self.get_position(vertex);
+            for influence in vertex * MAX_BONE_INFLUENCES..(vertex + 1) * MAX_BONE_INFLUENCES
+            {
+                if bone_weights[influence] != 0.0
+                {
+                    bone_bounds[bone_indices[influence] as usize].add_point(position);
+                }
+            }
+        }
+        Ok(bone_bounds)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // A plane of four vertices, vertex i bound to the bones and weights of influences[i].
+    fn create_skinned_plane(influences: [([f32; 4], [f32; 4]); 4]) -> Mesh
+    {
+        let mut mesh = Mesh::create_plane(2.0, 2.0, 1, 1);
+        assert_eq!(mesh.get_vertex_count(), 4);
+        mesh.mesh_channel_data.insert(MeshDataChannel::BoneIndices as usize, influences.iter().flat_map(|(bones, _)| *bones).collect());
+        mesh.mesh_channel_data.insert(MeshDataChannel::BoneWeights as usize, influences.iter().flat_map(|(_, weights)| *weights).collect());
+        mesh
+    }
+
+    fn create_two_bone_skeleton() -> Skeleton
+    {
+        let root_transform = Matrix4::from_translation(Vector3::new(0.0, 1.0, 0.0));
+        let child_transform = Matrix4::from_angle_z(cgmath::Deg(30.0)) * Matrix4::from_translation(Vector3::new(1.0, 0.0, 0.0));
+        let mut skeleton = Skeleton::new();
+        skeleton.add_bone(Bone { name: "root".to_string(), parent: None, rest_transform: root_transform, inverse_bind_matrix: root_transform.invert().unwrap() }).unwrap();
+        let child_model = root_transform * child_transform;
+        skeleton.add_bone(Bone { name: "child".to_string(), parent: Some(0), rest_transform: child_transform, inverse_bind_matrix: child_model.invert().unwrap() }).unwrap();
+        skeleton
+    }
+
+    fn assert_channels_near(a: &Mesh, b: &Mesh, channel: MeshDataChannel)
+    {
+        let (a, b) = (&a.mesh_channel_data[&(channel as usize)], &b.mesh_channel_data[&(channel as usize)]);
+        assert_eq!(a.len(), b.len());
+        for (x, y) in a.iter().zip(b)
+        {
+            assert!((x - y).abs() < 1e-5, "{:?} differs: {:?} and {:?}", channel, a, b);
+        }
+    }
+
+    #[test]
+    fn rest_pose_round_trips()
+    {
+        let skeleton = create_two_bone_skeleton();
+        let mesh = create_skinned_plane([
+            ([0.0, 0.0, 0.0, 0.0], [1.0, 0.0, 0.0, 0.0]),
+            ([1.0, 0.0, 0.0, 0.0], [1.0, 0.0, 0.0, 0.0]),
+            ([0.0, 1.0, 0.0, 0.0], [0.25, 0.75, 0.0, 0.0]),
+            ([1.0, 0.0, 0.0, 0.0], [0.5, 0.5, 0.0, 0.0])
+        ]);
+        let skinned = mesh.skin(&skeleton.get_skinning_matrices(&skeleton.get_rest_pose())).unwrap();
+        for channel in [MeshDataChannel::Position, MeshDataChannel::Normal, MeshDataChannel::Tangent]
+        {
+            assert_channels_near(&skinned, &mesh, channel);
+        }
+    }
+
+    #[test]
+    fn single_bone_translation_moves_positions_only()
+    {
+        let mesh = create_skinned_plane([([0.0; 4], [1.0, 0.0, 0.0, 0.0]); 4]);
+        let offset = Vector3::new(1.0, 2.0, 3.0);
+        let skinned = mesh.skin(&[Matrix4::from_translation(offset)]).unwrap();
+        for vertex in 0..mesh.get_vertex_count()
+        {
+            assert_eq!(skinned.get_position(vertex), mesh.get_position(vertex) + offset);
+        }
+        assert_channels_near(&skinned, &mesh, MeshDataChannel::Normal);
+        assert_channels_near(&skinned, &mesh, MeshDataChannel::Tangent);
+    }
+
+    #[test]
+    fn weights_are_normalized_before_blending()
+    {
+        let mut mesh = create_skinned_plane([
+            ([0.0, 1.0, 0.0, 0.0], [2.0, 2.0, 0.0, 0.0]),
+            ([0.0, 1.0, 0.0, 0.0], [3.0, -1.0, 0.0, 0.0]),
+            ([1.0, 0.0, 0.0, 0.0], [0.0, 0.0, 0.0, 0.0]),
+            ([0.0, 1.0, 0.0, 0.0], [0.1, 0.3, 0.0, 0.0])
+        ]);
+        mesh.normalize_bone_weights();
+        assert_eq!(mesh.mesh_channel_data[&(MeshDataChannel::BoneWeights as usize)], vec![
+            0.5, 0.5, 0.0, 0.0,
+            1.0, 0.0, 0.0, 0.0,
+            1.0, 0.0, 0.0, 0.0,
+            0.25, 0.75, 0.0, 0.0
+        ]);
+
+        let skinned = mesh.skin(&[Matrix4::from_translation(Vector3::new(4.0, 0.0, 0.0)), Matrix4::identity()]).unwrap();
+        let shifts: Vec<f32> = (0..mesh.get_vertex_count()).map(|vertex| skinned.get_position(vertex).x - mesh.get_position(vertex).x).collect();
+        // Vertex 2 is bound fully to its first influence, bone 1.
+        assert_eq!(shifts, vec![2.0, 4.0, 0.0, 1.0]);
+    }
+}
+
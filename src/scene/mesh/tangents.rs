@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Vector2, Vector3, Zero};
+use log::warn;
+
+use crate::scene::mesh::*;
+
+// Corner tangents of a vertex closer than about 0.25 degrees with the same handedness do not split it.
+const TANGENT_MERGE_COS: f32 = 0.99999;
+
+struct TriangleTangentInfo
+{
+    // Direction of increasing u, normalized and flipped for mirrored UVs as MikkTSpace does.
+    tangent: Vector3<f32>,
+    orient_preserving: bool,
+    degenerate: bool
+}
+
+fn get_vertex_key(position: Vector3<f32>, normal: Vector3<f32>, uv: Vector2<f32>) -> [u32; 8]
+{
+    [
+        (position.x + 0.0).to_bits(), (position.y + 0.0).to_bits(), (position.z + 0.0).to_bits(),
+        (normal.x + 0.0).to_bits(), (normal.y + 0.0).to_bits(), (normal.z + 0.0).to_bits(),
+        (uv.x + 0.0).to_bits(), (uv.y + 0.0).to_bits()
+    ]
+}
+
+fn project_onto_plane(vector: Vector3<f32>, normal: Vector3<f32>) -> Vector3<f32>
+{
+    let projected = vector - normal * normal.dot(vector);
+    if projected.magnitude2() > 0.0 { projected.normalize() } else { Vector3::zero() }
+}
+
+fn get_any_perpendicular(normal: Vector3<f32>) -> Vector3<f32>
+{
+    let axis = if normal.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+    project_onto_plane(axis, normal)
+}
+
+fn find_group_root(parents: &mut [usize], mut node: usize) -> usize
+{
+    while parents[node] != node
+    {
+        parents[node] = parents[parents[node]];
+        node = parents[node];
+    }
+    node
+}
+
+impl Mesh
+{
+    fn get_normal(&self, vertex: usize) -> Vector3<f32>
+    {
+        let normals = &self.mesh_channel_data[&(MeshDataChannel::Normal as usize)];
+        Vector3::new(normals[vertex * 3], normals[vertex * 3 + 1], normals[vertex * 3 + 2])
+    }
+
+    fn get_uv0(&self, vertex: usize) -> Vector2<f32>
+    {
+        let uvs = &self.mesh_channel_data[&(MeshDataChannel::UV0 as usize)];
+        Vector2::new(uvs[vertex * 2], uvs[vertex * 2 + 1])
+    }
+
+    /// Generates MikkTSpace tangents from the position, normal and UV0 channels. The Tangent channel stores
+    /// the tangent in xyz and the bitangent sign in w, bitangent = cross(normal, tangent) * w. Vertices whose
+    /// corners end up with different tangent frames are split and the index buffer is rewritten.
+    pub fn generate_tangents(&mut self) -> bool
+    {
+        let vertex_count = self.get_vertex_count();
+        let has_normals = self.mesh_channel_data.get(&(MeshDataChannel::Normal as usize)).is_some_and(|normals| normals.len() == vertex_count * 3);
+        let has_uvs = self.mesh_channel_data.get(&(MeshDataChannel::UV0 as usize)).is_some_and(|uvs| uvs.len() == vertex_count * 2);
+        if vertex_count == 0 || !has_normals || !has_uvs
+        {
+            warn!("tangent generation needs position, normal and uv0 channels of matching size");
+            return false;
+        }
+
+        let indices = self.get_triangle_indices();
+        let triangle_count = indices.len() / 3;
+
+        // MikkTSpace works on vertices with identical position, normal and uv, regardless of the index buffer.
+        let mut welded_ids: HashMap<[u32; 8], usize> = HashMap::new();
+        let mut welded_vertices = Vec::with_capacity(vertex_count);
+        for vertex in 0..vertex_count
+        {
+            let key = get_vertex_key(self.get_position(vertex), self.get_normal(vertex), self.get_uv0(vertex));
+            let welded_count = welded_ids.len();
+            welded_vertices.push(*welded_ids.entry(key).or_insert(welded_count));
+        }
+        let corner_welded: Vec<usize> = indices.iter().take(triangle_count * 3).map(|index| welded_vertices[*index as usize]).collect();
+
+        let mut triangle_infos = Vec::with_capacity(triangle_count);
+        for triangle in indices.chunks_exact(3)
+        {
+            let (v0, v1, v2) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+            let d2 = self.get_position(v1) - self.get_position(v0);
+            let d3 = self.get_position(v2) - self.get_position(v0);
+            let t21 = self.get_uv0(v1) - self.get_uv0(v0);
+            let t31 = self.get_uv0(v2) - self.get_uv0(v0);
+
+            let signed_area_st = t21.x * t31.y - t21.y * t31.x;
+            let orient_preserving = signed_area_st > 0.0;
+            let sign = if orient_preserving { 1.0 } else { -1.0 };
+            let tangent = d2 * t31.y - d3 * t21.y;
+            let degenerate = d2.cross(d3).magnitude2() == 0.0 || signed_area_st == 0.0 || tangent.magnitude2() == 0.0;
+            triangle_infos.push(TriangleTangentInfo
+            {
+                tangent: if degenerate { Vector3::zero() } else { tangent.normalize() * sign },
+                orient_preserving,
+                degenerate
+            });
+        }
+
+        // Triangles around a vertex form one tangent space group when they have the same UV orientation and
+        // are connected through shared edges. Different groups of one vertex get different tangents.
+        let mut welded_corners: Vec<Vec<usize>> = vec![vec![]; welded_ids.len()];
+        for (corner, welded) in corner_welded.iter().enumerate()
+        {
+            if !triangle_infos[corner / 3].degenerate
+            {
+                welded_corners[*welded].push(corner);
+            }
+        }
+
+        let mut corner_tangents: Vec<Option<(Vector3<f32>, f32)>> = vec![None; triangle_count * 3];
+        for corners in &welded_corners
+        {
+            let mut parents: Vec<usize> = (0..corners.len()).collect();
+            for a in 0..corners.len()
+            {
+                for b in a + 1..corners.len()
+                {
+                    let (triangle_a, triangle_b) = (corners[a] / 3, corners[b] / 3);
+                    if triangle_infos[triangle_a].orient_preserving != triangle_infos[triangle_b].orient_preserving
+                    {
+                        continue;
+                    }
+                    let shares_edge = (0..3).any(|i| {
+                        let other = corner_welded[triangle_a * 3 + i];
+                        other != corner_welded[corners[a]] && (0..3).any(|j| corner_welded[triangle_b * 3 + j] == other)
+                    });
+                    if shares_edge
+                    {
+                        let (root_a, root_b) = (find_group_root(&mut parents, a), find_group_root(&mut parents, b));
+                        parents[root_a] = root_b;
+                    }
+                }
+            }
+
+            let mut group_sums: HashMap<usize, Vector3<f32>> = HashMap::new();
+            for (i, corner) in corners.iter().enumerate()
+            {
+                let triangle = corner / 3;
+                let vertex = indices[*corner] as usize;
+                let previous = indices[triangle * 3 + (corner + 2) % 3] as usize;
+                let next = indices[triangle * 3 + (corner + 1) % 3] as usize;
+                let normal = self.get_normal(vertex);
+                let position = self.get_position(vertex);
+
+                // Tangents are weighted by the corner angle measured in the tangent plane of the vertex.
+                let edge0 = project_onto_plane(self.get_position(next) - position, normal);
+                let edge1 = project_onto_plane(self.get_position(previous) - position, normal);
+                let angle = edge0.dot(edge1).clamp(-1.0, 1.0).acos();
+                let tangent = project_onto_plane(triangle_infos[triangle].tangent, normal);
+
+                *group_sums.entry(find_group_root(&mut parents, i)).or_insert_with(Vector3::zero) += tangent * angle;
+            }
+
+            for (i, corner) in corners.iter().enumerate()
+            {
+                let normal = self.get_normal(indices[*corner] as usize);
+                let sum = group_sums[&find_group_root(&mut parents, i)];
+                let tangent = if sum.magnitude2() > 0.0 { sum.normalize() } else { get_any_perpendicular(normal) };
+                let sign = if triangle_infos[corner / 3].orient_preserving { 1.0 } else { -1.0 };
+                corner_tangents[*corner] = Some((tangent, sign));
+            }
+        }
+
+        // Corners of degenerate triangles borrow the tangent space of the same vertex in another triangle.
+        let mut vertex_tangents: HashMap<usize, (Vector3<f32>, f32)> = HashMap::new();
+        for (corner, tangent) in corner_tangents.iter().enumerate()
+        {
+            if let Some(tangent) = tangent
+            {
+                vertex_tangents.entry(corner_welded[corner]).or_insert(*tangent);
+            }
+        }
+        let corner_tangents: Vec<(Vector3<f32>, f32)> = corner_tangents.iter().enumerate()
+            .map(|(corner, tangent)| tangent.unwrap_or_else(|| {
+                vertex_tangents.get(&corner_welded[corner]).copied()
+                    .unwrap_or_else(|| (get_any_perpendicular(self.get_normal(indices[corner] as usize)), 1.0))
+            }))
+            .collect();
+
+        let mut split_vertices: Vec<Vec<(Vector3<f32>, f32, u32)>> = vec![vec![]; vertex_count];
+        let mut source_vertices = vec![];
+        let mut tangents = vec![];
+        let mut new_indices = Vec::with_capacity(triangle_count * 3);
+        for (corner, (tangent, sign)) in corner_tangents.iter().enumerate()
+        {
+            let vertex = indices[corner];
+            let splits = &mut split_vertices[vertex as usize];
+            let new_vertex = match splits.iter().find(|(split_tangent, split_sign, _)| split_sign == sign && split_tangent.dot(*tangent) >= TANGENT_MERGE_COS)
+            {
+                Some((_, _, new_vertex)) => *new_vertex,
+                None =>
+                {
+                    let new_vertex = source_vertices.len() as u32;
+                    source_vertices.push(vertex);
+                    tangents.extend_from_slice(&[tangent.x, tangent.y, tangent.z, *sign]);
+                    splits.push((*tangent, *sign, new_vertex));
+                    new_vertex
+                }
+            };
+            new_indices.push(new_vertex);
+        }
+
+        // Vertices not referenced by any triangle keep their data and the default tangent.
+        let default_tangent = get_channel_default_value(MeshDataChannel::Tangent as usize);
+        for (vertex, splits) in split_vertices.iter().enumerate()
+        {
+            if splits.is_empty()
+            {
+                source_vertices.push(vertex as u32);
+                tangents.extend_from_slice(default_tangent);
+            }
+        }
+
+        self.mesh_channel_data.remove(&(MeshDataChannel::Tangent as usize));
+        self.remap_vertices(&source_vertices);
+        self.mesh_channel_data.insert(MeshDataChannel::Tangent as usize, tangents);
+        self.mesh_index_data = new_indices;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use bevy_mikktspace::Geometry;
+
+    use super::*;
+
+    // Triangle corners in the layout the reference implementation expects.
+    struct ReferenceGeometry<'a>
+    {
+        mesh: &'a Mesh,
+        indices: Vec<u32>,
+        tangents: Vec<[f32; 4]>
+    }
+
+    impl Geometry for ReferenceGeometry<'_>
+    {
+        fn num_faces(&self) -> usize
+        {
+            self.indices.len() / 3
+        }
+
+        fn num_vertices_of_face(&self, _face: usize) -> usize
+        {
+            3
+        }
+
+        fn position(&self, face: usize, vert: usize) -> [f32; 3]
+        {
+            self.mesh.get_position(self.indices[face * 3 + vert] as usize).into()
+        }
+
+        fn normal(&self, face: usize, vert: usize) -> [f32; 3]
+        {
+            self.mesh.get_normal(self.indices[face * 3 + vert] as usize).into()
+        }
+
+        fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2]
+        {
+            self.mesh.get_uv0(self.indices[face * 3 + vert] as usize).into()
+        }
+
+        fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize)
+        {
+            self.tangents[face * 3 + vert] = tangent;
+        }
+    }
+
+    fn get_reference_tangents(mesh: &Mesh) -> Vec<[f32; 4]>
+    {
+        let indices = mesh.get_triangle_indices();
+        let mut geometry = ReferenceGeometry { mesh, tangents: vec![[0.0; 4]; indices.len()], indices };
+        assert!(bevy_mikktspace::generate_tangents(&mut geometry));
+        geometry.tangents
+    }
+
+    // A sphere whose western half has mirrored UVs, so it has a UV seam, a mirroring seam along x = 0 and poles, next
+    // to a box with a UV island and hard normals per face.
+    fn create_seam_mesh() -> Mesh
+    {
+        let mut mesh = Mesh::create_uv_sphere(1.0, 16, 8);
+        let vertex_count = mesh.get_vertex_count();
+        for vertex in 0..vertex_count
+        {
+            if mesh.get_position(vertex).x < 0.0
+            {
+                let uvs = mesh.mesh_channel_data.get_mut(&(MeshDataChannel::UV0 as usize)).unwrap();
+                uvs[vertex * 2] = 1.0 - uvs[vertex * 2];
+            }
+        }
+        let cube = Mesh::create_box(Vector3::new(1.0, 2.0, 3.0), 2);
+        for (channel, data) in mesh.mesh_channel_data.iter_mut()
+        {
+            data.extend_from_slice(&cube.mesh_channel_data[channel]);
+        }
+        mesh.mesh_index_data.extend(cube.mesh_index_data.iter().map(|index| index + vertex_count as u32));
+        mesh.mesh_sections.clear();
+        mesh.mesh_channel_data.remove(&(MeshDataChannel::Tangent as usize));
+        mesh
+    }
+
+    #[test]
+    fn tangents_match_reference_mikktspace()
+    {
+        let mut mesh = create_seam_mesh();
+        let reference = get_reference_tangents(&mesh);
+        assert!(mesh.generate_tangents());
+
+        // Corners keep their order, only the vertices they refer to are split.
+        let indices = mesh.get_triangle_indices();
+        let tangents = &mesh.mesh_channel_data[&(MeshDataChannel::Tangent as usize)];
+        assert_eq!(indices.len(), reference.len());
+        for (corner, expected) in reference.iter().enumerate()
+        {
+            let vertex = indices[corner] as usize;
+            let tangent = Vector3::new(tangents[vertex * 4], tangents[vertex * 4 + 1], tangents[vertex * 4 + 2]);
+            let expected_tangent = Vector3::new(expected[0], expected[1], expected[2]);
+            assert!(tangent.dot(expected_tangent) > 0.999, "corner {} has tangent {:?}, expected {:?}", corner, tangent, expected_tangent);
+            assert_eq!(tangents[vertex * 4 + 3], expected[3], "corner {} has the wrong bitangent sign", corner);
+        }
+    }
+}
+
@@ -0,0 +1,337 @@
+use cgmath::InnerSpace;
+use thiserror::Error;
+
+use crate::scene::mesh::*;
+use crate::scene::mesh::vertex_format::{is_channel_format_supported, VertexFormat};
+
+#[derive(Clone, Debug, Error, PartialEq)]
+pub enum MeshError
+{
+    #[error("mesh has no position channel")]
+    MissingPositions,
+    #[error("channel {0} is not a registered mesh data channel")]
+    UnknownChannel(usize),
+    #[error("channel {semantic_name}{semantic_index} cannot have {component_count} components")]
+    InvalidChannelComponentCount { semantic_name: String, semantic_index: u32, component_count: usize },
+    #[error("channel {channel} has {value_count} values, which is not a multiple of its {component_count} components")]
+    ChannelSizeNotDivisible { channel: usize, value_count: usize, component_count: usize },
+    #[error("channel {channel} has {vertex_count} vertices but the position channel has {expected}")]
+    ChannelLengthMismatch { channel: usize, vertex_count: usize, expected: usize },
+    #[error("index buffer has {0} indices, which is not a multiple of 3")]
+    IndexCountNotDivisible(usize),
+    #[error("index {index} at position {position} is out of range for {vertex_count} vertices")]
+    IndexOutOfRange { position: usize, index: u32, vertex_count: usize },
+    #[error("section {0} is outside of the index buffer or does not cover whole triangles")]
+    InvalidSection(usize),
+    #[error("index {0} does not fit into 16 bits")]
+    IndexTooLarge(u32),
+    #[error("channel {channel} of vertex {vertex} is NaN or infinite")]
+    NonFiniteValue { channel: usize, vertex: usize },
+    #[error("triangle {0} is degenerate")]
+    DegenerateTriangle(usize),
+    #[error("normal of vertex {0} has zero length")]
+    ZeroLengthNormal(usize),
+    #[error("vertex format {format:?} cannot store channel {channel}")]
+    UnsupportedVertexFormat { channel: usize, format: VertexFormat },
+    #[error("mesh has no bone indices or bone weights")]
+    MissingSkinWeights,
+    #[error("vertex {vertex} is bound to bone {bone} but the skeleton has {bone_count} bones")]
+    BoneIndexOutOfRange { vertex: usize, bone: i64, bone_count: usize },
+    #[error("parent of bone {0} does not come before it")]
+    InvalidBoneParent(usize),
+    #[error("morph target {0} has unsorted or out of range vertices or mismatched deltas")]
+    InvalidMorphTarget(usize),
+    #[error("point lists cannot have indices or sections")]
+    InvalidPointList,
+    #[error("meshlets cannot have {max_vertices} vertices and {max_primitives} primitives, the limits are 3..=256 and 1..=256")]
+    InvalidMeshletLimits { max_vertices: usize, max_primitives: usize }
+}
+
+impl Mesh
+{
+    fn check_channel_layout(&self, errors: &mut Vec<MeshError>)
+    {
+        let positions = match self.mesh_channel_data.get(&(MeshDataChannel::Position as usize))
+        {
+            Some(positions) => positions,
+            None =>
+            {
+                errors.push(MeshError::MissingPositions);
+                return;
+            }
+        };
+        let expected = positions.len() / 3;
+        for (channel, data) in &self.mesh_channel_data
+        {
+            if *channel >= get_mesh_channel_count()
+            {
+                errors.push(MeshError::UnknownChannel(*channel));
+                continue;
+            }
+            let component_count = get_channel_default_value(*channel).len();
+            if !data.len().is_multiple_of(component_count)
+            {
+                errors.push(MeshError::ChannelSizeNotDivisible { channel: *channel, value_count: data.len(), component_count });
+            }
+            else if data.len() / component_count != expected
+            {
+                errors.push(MeshError::ChannelLengthMismatch { channel: *channel, vertex_count: data.len() / component_count, expected });
+            }
+        }
+        for (channel, format) in &self.mesh_channel_formats
+        {
+            if !is_channel_format_supported(*channel, *format)
+            {
+                errors.push(MeshError::UnsupportedVertexFormat { channel: *channel, format: *format });
+            }
+        }
+    }
+
+    // Index checks need a consistent channel layout to know the vertex count.
+    fn check_indices_and_values(&self, errors: &mut Vec<MeshError>)
+    {
+        let vertex_count = self.get_vertex_count();
+        let triangle_index_count = self.get_triangle_indices().len();
+        if self.mesh_topology == MeshPrimitiveTopology::PointList
+        {
+            if !self.mesh_index_data.is_empty() || !self.mesh_sections.is_empty()
+            {
+                errors.push(MeshError::InvalidPointList);
+            }
+        }
+        // Triangle soups use one index per vertex.
+        else if !triangle_index_count.is_multiple_of(3)
+        {
+            errors.push(MeshError::IndexCountNotDivisible(triangle_index_count));
+        }
+        let mut base_vertices = vec![0; self.mesh_index_data.len()];
+        for (section_index, section) in self.mesh_sections.iter().enumerate()
+        {
+            let is_whole_triangles = section.first_index.is_multiple_of(3) && section.index_count.is_multiple_of(3);
+            if !is_whole_triangles || section.first_index as usize + section.index_count as usize > triangle_index_count
+            {
+                errors.push(MeshError::InvalidSection(section_index));
+            }
+            for base_vertex in base_vertices.iter_mut().skip(section.first_index as usize).take(section.index_count as usize)
+            {
+                *base_vertex = section.base_vertex as i64;
+            }
+        }
+        for (position, index) in self.mesh_index_data.iter().enumerate()
+        {
+            let vertex = *index as i64 + base_vertices[position];
+            if vertex < 0 || vertex >= vertex_count as i64
+            {
+                errors.push(MeshError::IndexOutOfRange { position, index: *index, vertex_count });
+            }
+        }
+        for (target_index, target) in self.mesh_morph_targets.iter().enumerate()
+        {
+            if !target.is_valid(vertex_count)
+            {
+                errors.push(MeshError::InvalidMorphTarget(target_index));
+            }
+        }
+        for (channel, data) in &self.mesh_channel_data
+        {
+            let component_count = get_channel_default_value(*channel).len();
+            for (vertex, value) in data.chunks_exact(component_count).enumerate()
+            {
+                if value.iter().any(|component| !component.is_finite())
+                {
+                    errors.push(MeshError::NonFiniteValue { channel: *channel, vertex });
+                }
+            }
+        }
+    }
+
+    /// Checks everything that would make the vertex or index buffer unusable: channel layout, index range and
+    /// non finite values. Returns the first problem found.
+    pub fn validate_structure(&self) -> Result<(), MeshError>
+    {
+        let mut errors = vec![];
+        self.check_channel_layout(&mut errors);
+        if errors.is_empty()
+        {
+            self.check_indices_and_values(&mut errors);
+        }
+        match errors.into_iter().next()
+        {
+            Some(error) => Err(error),
+            None => Ok(())
+        }
+    }
+
+    /// Lists every problem of the mesh, including degenerate triangles and zero length normals, which still
+    /// produce valid buffers but render incorrectly or break tangent and normal generation.
+    pub fn get_validation_report(&self) -> Vec<MeshError>
+    {
+        let mut errors = vec![];
+        self.check_channel_layout(&mut errors);
+        if !errors.is_empty()
+        {
+            return errors;
+        }
+        self.check_indices_and_values(&mut errors);
+        if !errors.is_empty()
+        {
+            return errors;
+        }
+
+        for (triangle, vertices) in self.get_triangle_indices().chunks_exact(3).enumerate()
+        {
+            let p0 = self.get_position(vertices[0] as usize);
+            let p1 = self.get_position(vertices[1] as usize);
+            let p2 = self.get_position(vertices[2] as usize);
+            let has_repeated_index = vertices[0] == vertices[1] || vertices[1] == vertices[2] || vertices[2] == vertices[0];
+            if has_repeated_index || (p1 - p0).cross(p2 - p0).magnitude2() == 0.0
+            {
+                errors.push(MeshError::DegenerateTriangle(triangle));
+            }
+        }
+        if let Some(normals) = self.mesh_channel_data.get(&(MeshDataChannel::Normal as usize))
+        {
+            for (vertex, normal) in normals.chunks_exact(3).enumerate()
+            {
+                if normal[0] == 0.0 && normal[1] == 0.0 && normal[2] == 0.0
+                {
+                    errors.push(MeshError::ZeroLengthNormal(vertex));
+                }
+            }
+        }
+        errors
+    }
+
+    /// Strict validation, fails on the first entry of get_validation_report.
+    pub fn validate(&self) -> Result<(), MeshError>
+    {
+        match self.get_validation_report().into_iter().next()
+        {
+            Some(error) => Err(error),
+            None => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // Two triangles sharing an edge, with normals.
+    fn create_quad() -> Mesh
+    {
+        let mut mesh = Mesh::default();
+        mesh.mesh_channel_data.insert(MeshDataChannel::Position as usize, vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0]);
+        mesh.mesh_channel_data.insert(MeshDataChannel::Normal as usize, [0.0, 0.0, -1.0].repeat(4));
+        mesh.mesh_index_data = vec![0, 2, 1, 0, 3, 2];
+        mesh
+    }
+
+    fn assert_invalid(mesh: &Mesh, error: MeshError)
+    {
+        assert_eq!(mesh.validate_structure(), Err(error.clone()));
+        assert_eq!(mesh.validate(), Err(error));
+    }
+
+    #[test]
+    fn valid_meshes_pass()
+    {
+        let mesh = create_quad();
+        assert_eq!(mesh.validate_structure(), Ok(()));
+        assert_eq!(mesh.validate(), Ok(()));
+        assert!(mesh.get_validation_report().is_empty());
+        let points = Mesh { mesh_topology: MeshPrimitiveTopology::PointList, mesh_index_data: vec![], ..create_quad() };
+        assert_eq!(points.validate(), Ok(()));
+    }
+
+    #[test]
+    fn channel_layout_errors()
+    {
+        let mut mesh = create_quad();
+        mesh.mesh_channel_data.remove(&(MeshDataChannel::Position as usize));
+        assert_invalid(&mesh, MeshError::MissingPositions);
+
+        let channel = get_mesh_channel_count() + 3;
+        let mut mesh = create_quad();
+        mesh.mesh_channel_data.insert(channel, vec![0.0; 4]);
+        assert_invalid(&mesh, MeshError::UnknownChannel(channel));
+
+        let normal = MeshDataChannel::Normal as usize;
+        let mut mesh = create_quad();
+        mesh.mesh_channel_data.get_mut(&normal).unwrap().push(0.0);
+        assert_invalid(&mesh, MeshError::ChannelSizeNotDivisible { channel: normal, value_count: 13, component_count: 3 });
+        mesh.mesh_channel_data.get_mut(&normal).unwrap().truncate(9);
+        assert_invalid(&mesh, MeshError::ChannelLengthMismatch { channel: normal, vertex_count: 3, expected: 4 });
+
+        let mut mesh = create_quad();
+        mesh.mesh_channel_formats.insert(MeshDataChannel::Position as usize, VertexFormat::OctahedralSnorm16x2);
+        assert_invalid(&mesh, MeshError::UnsupportedVertexFormat { channel: MeshDataChannel::Position as usize, format: VertexFormat::OctahedralSnorm16x2 });
+    }
+
+    #[test]
+    fn index_and_value_errors()
+    {
+        let mut mesh = create_quad();
+        mesh.mesh_index_data.pop();
+        assert_invalid(&mesh, MeshError::IndexCountNotDivisible(5));
+        // Soups count one index per vertex.
+        mesh.mesh_index_data.clear();
+        assert_invalid(&mesh, MeshError::IndexCountNotDivisible(4));
+
+        let mut mesh = create_quad();
+        mesh.mesh_index_data[4] = 4;
+        assert_invalid(&mesh, MeshError::IndexOutOfRange { position: 4, index: 4, vertex_count: 4 });
+        // Indices are checked relative to the base vertex of their section.
+        mesh.mesh_index_data[4] = 3;
+        mesh.add_section(MeshSection { first_index: 0, index_count: 3, base_vertex: 0, material_slot: None });
+        mesh.add_section(MeshSection { first_index: 3, index_count: 3, base_vertex: -1, material_slot: None });
+        assert_invalid(&mesh, MeshError::IndexOutOfRange { position: 3, index: 0, vertex_count: 4 });
+
+        for (first_index, index_count) in [(3, 6), (1, 3), (0, 4)]
+        {
+            let mut mesh = create_quad();
+            mesh.add_section(MeshSection { first_index, index_count, base_vertex: 0, material_slot: None });
+            assert_invalid(&mesh, MeshError::InvalidSection(0));
+        }
+
+        let mut mesh = create_quad();
+        mesh.mesh_morph_targets.push(MorphTarget { vertices: vec![4], position_deltas: vec![0.0; 3], ..Default::default() });
+        assert_invalid(&mesh, MeshError::InvalidMorphTarget(0));
+
+        let mut mesh = create_quad();
+        mesh.mesh_channel_data.get_mut(&(MeshDataChannel::Normal as usize)).unwrap()[7] = f32::NAN;
+        assert_invalid(&mesh, MeshError::NonFiniteValue { channel: MeshDataChannel::Normal as usize, vertex: 2 });
+
+        let points = Mesh { mesh_topology: MeshPrimitiveTopology::PointList, ..create_quad() };
+        assert_invalid(&points, MeshError::InvalidPointList);
+
+        let mut mesh = create_quad();
+        mesh.mesh_index_data[0] = 70_000;
+        assert_eq!(mesh.get_index_buffer_data_u16(), Err(MeshError::IndexTooLarge(70_000)));
+    }
+
+    #[test]
+    fn report_lists_rendering_problems()
+    {
+        let mut mesh = create_quad();
+        // Two triangles with a repeated index and one with a corner halfway along the opposite edge.
+        mesh.mesh_index_data.extend_from_slice(&[0, 0, 1, 0, 1, 1, 0, 4, 1]);
+        mesh.mesh_channel_data.get_mut(&(MeshDataChannel::Position as usize)).unwrap().extend_from_slice(&[0.5, 0.0, 0.0]);
+        mesh.mesh_channel_data.get_mut(&(MeshDataChannel::Normal as usize)).unwrap().extend_from_slice(&[0.0, 0.0, -1.0]);
+        mesh.mesh_channel_data.get_mut(&(MeshDataChannel::Normal as usize)).unwrap()[3..6].fill(0.0);
+        assert_eq!(mesh.validate_structure(), Ok(()));
+        assert_eq!(mesh.get_validation_report(), vec![
+            MeshError::DegenerateTriangle(2),
+            MeshError::DegenerateTriangle(3),
+            MeshError::DegenerateTriangle(4),
+            MeshError::ZeroLengthNormal(1)
+        ]);
+        assert_eq!(mesh.validate(), Err(MeshError::DegenerateTriangle(2)));
+
+        // Structural problems are reported alone, the triangle checks need valid indices.
+        mesh.mesh_index_data.push(9);
+        assert_eq!(mesh.get_validation_report(), vec![MeshError::IndexCountNotDivisible(16), MeshError::IndexOutOfRange { position: 15, index: 9, vertex_count: 5 }]);
+    }
+}
+
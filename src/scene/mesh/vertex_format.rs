@@ -0,0 +1,373 @@
+use std::collections::BTreeMap;
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::scene::mesh::*;
+use crate::scene::mesh::validation::MeshError;
+
+// D3D12 wants element offsets aligned to 4 bytes, the strides are padded the same way.
+const VERTEX_ELEMENT_ALIGNMENT: u32 = 4;
+
+/// Storage format of a channel in the vertex buffer. Channel data stays f32 on the CPU and is converted when
+/// interleaving. Normalized formats clamp, so keep UVs outside 0..1 in Float32 or Float16.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum VertexFormat
+{
+    Float32,
+    // Three components are padded to four, DXGI has no three component 16 bit formats.
+    Float16,
+    Unorm8x4,
+    Snorm8x4,
+    Unorm16,
+    Snorm16,
+    // Unit vectors folded onto an octahedron, two snorm16 values. Only for normals, vertex shaders decode them with
+    // GetVertexNormal.
+    OctahedralSnorm16x2
+}
+
+impl VertexFormat
+{
+    pub fn get_component_size(&self) -> u32
+    {
+        match self
+        {
+            VertexFormat::Float32 => 4,
+            VertexFormat::Float16 | VertexFormat::Unorm16 | VertexFormat::Snorm16 | VertexFormat::OctahedralSnorm16x2 => 2,
+            VertexFormat::Unorm8x4 | VertexFormat::Snorm8x4 => 1
+        }
+    }
+
+    /// Number of components stored for a channel with component_count values, None when the format cannot hold it.
+    pub fn get_packed_component_count(&self, component_count: usize) -> Option<usize>
+    {
+        match self
+        {
+            _ if component_count == 0 || component_count > 4 => None,
+            VertexFormat::Float32 => Some(component_count),
+            VertexFormat::Float16 | VertexFormat::Unorm16 | VertexFormat::Snorm16 => Some(if component_count == 3 { 4 } else { component_count }),
+            VertexFormat::Unorm8x4 | VertexFormat::Snorm8x4 => Some(4),
+            VertexFormat::OctahedralSnorm16x2 => if component_count == 3 { Some(2) } else { None }
+        }
+    }
+}
+
+/// How the channels are split into vertex buffer streams, stream i is bound to input slot i.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VertexStreamLayout
+{
+    // Every channel interleaved in slot 0.
+    #[default]
+    Interleaved,
+    // Positions alone in slot 0 so depth and shadow passes only fetch them, the other channels interleaved in slot 1.
+    SeparatePosition
+}
+
+impl VertexStreamLayout
+{
+    pub fn get_stream_count(&self) -> usize
+    {
+        match self
+        {
+            VertexStreamLayout::Interleaved => 1,
+            VertexStreamLayout::SeparatePosition => 2
+        }
+    }
+
+    pub fn get_channel_slot(&self, channel: usize) -> u32
+    {
+        match self
+        {
+            VertexStreamLayout::Interleaved => 0,
+            VertexStreamLayout::SeparatePosition => if channel == MeshDataChannel::Position as usize { 0 } else { 1 }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VertexElement
+{
+    pub channel: usize,
+    pub format: VertexFormat,
+    pub packed_component_count: usize,
+    // Input slot of the stream holding the element, the offset is relative to a vertex of that stream.
+    pub slot: u32,
+    pub offset: u32
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VertexLayout
+{
+    pub elements: Vec<VertexElement>,
+    // One per stream.
+    pub strides: Vec<u32>
+}
+
+impl VertexLayout
+{
+    pub fn get_stream_count(&self) -> usize
+    {
+        self.strides.len()
+    }
+
+    pub fn get_element(&self, channel: impl Into<usize>) -> Option<&VertexElement>
+    {
+        let channel = channel.into();
+        self.elements.iter().find(|element| element.channel == channel)
+    }
+}
+
+fn align_up(value: u32, alignment: u32) -> u32
+{
+    value.div_ceil(alignment) * alignment
+}
+
+/// Layout of the given registered channels in order, channels without a format are stored as Float32.
+/// Formats that cannot hold a channel fall back to Float32, Mesh::validate_structure reports them.
+pub fn get_vertex_layout(channels: &[usize], channel_formats: &BTreeMap<usize, VertexFormat>, stream_layout: VertexStreamLayout) -> VertexLayout
+{
+    let mut layout = VertexLayout { elements: vec![], strides: vec![0; stream_layout.get_stream_count()] };
+    for channel in channels.iter().copied()
+    {
+        let component_count = get_channel_default_value(channel).len();
+        let requested_format = channel_formats.get(&channel).copied().unwrap_or(VertexFormat::Float32);
+        let (format, packed_component_count) = match requested_format.get_packed_component_count(component_count)
+        {
+            Some(packed_component_count) => (requested_format, packed_component_count),
+            None => (VertexFormat::Float32, component_count)
+        };
+        let slot = stream_layout.get_channel_slot(channel);
+        let stride = &mut layout.strides[slot as usize];
+        let offset = align_up(*stride, VERTEX_ELEMENT_ALIGNMENT);
+        layout.elements.push(VertexElement { channel, format, packed_component_count, slot, offset });
+        *stride = offset + format.get_component_size() * packed_component_count as u32;
+    }
+    for stride in layout.strides.iter_mut()
+    {
+        *stride = align_up(*stride, VERTEX_ELEMENT_ALIGNMENT);
+    }
+    layout
+}
+
+/// Converts to IEEE half precision bits, rounding to nearest even.
+pub fn f32_to_f16(value: f32) -> u16
+{
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff
+    {
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f
+    {
+        return sign | 0x7c00;
+    }
+    if half_exponent <= 0
+    {
+        if half_exponent < -10
+        {
+            return sign;
+        }
+        // Subnormal half, shift the mantissa with its implicit leading one into place.
+        let full_mantissa = mantissa | 0x80_0000;
+        let shift = (14 - half_exponent) as u32;
+        let mut half_mantissa = full_mantissa >> shift;
+        let remainder = full_mantissa & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        if remainder > halfway || (remainder == halfway && half_mantissa & 1 == 1)
+        {
+            half_mantissa += 1;
+        }
+        return sign | half_mantissa as u16;
+    }
+
+    // A rounding carry into the exponent is still the correct encoding, up to infinity.
+    let mut half = ((half_exponent as u32) << 10) | (mantissa >> 13);
+    let remainder = mantissa & 0x1fff;
+    if remainder > 0x1000 || (remainder == 0x1000 && half & 1 == 1)
+    {
+        half += 1;
+    }
+    sign | half as u16
+}
+
+pub fn f16_to_f32(half: u16) -> f32
+{
+    let sign = ((half & 0x8000) as u32) << 16;
+    let exponent = ((half >> 10) & 0x1f) as u32;
+    let mantissa = (half & 0x3ff) as u32;
+    if exponent == 0
+    {
+        let value = mantissa as f32 / (1 << 24) as f32;
+        return if sign != 0 { -value } else { value };
+    }
+    if exponent == 0x1f
+    {
+        return f32::from_bits(sign | 0x7f80_0000 | (mantissa << 13));
+    }
+    f32::from_bits(sign | ((exponent + 112) << 23) | (mantissa << 13))
+}
+
+fn get_sign_not_zero(value: f32) -> f32
+{
+    if value >= 0.0 { 1.0 } else { -1.0 }
+}
+
+/// Maps a unit vector onto the [-1, 1] square, the lower hemisphere folded over the diagonals.
+pub fn encode_octahedral(vector: Vector3<f32>) -> [f32; 2]
+{
+    let length = vector.x.abs() + vector.y.abs() + vector.z.abs();
+    if length == 0.0
+    {
+        return [0.0, 0.0];
+    }
+    let (x, y) = (vector.x / length, vector.y / length);
+    if vector.z < 0.0
+    {
+        return [(1.0 - y.abs()) * get_sign_not_zero(x), (1.0 - x.abs()) * get_sign_not_zero(y)];
+    }
+    [x, y]
+}
+
+pub fn decode_octahedral(encoded: [f32; 2]) -> Vector3<f32>
+{
+    let (mut x, mut y) = (encoded[0], encoded[1]);
+    let z = 1.0 - x.abs() - y.abs();
+    if z < 0.0
+    {
+        let (folded_x, folded_y) = (x, y);
+        x = (1.0 - folded_y.abs()) * get_sign_not_zero(folded_x);
+        y = (1.0 - folded_x.abs()) * get_sign_not_zero(folded_y);
+    }
+    let vector = Vector3::new(x, y, z);
+    if vector.magnitude2() > 0.0 { vector.normalize() } else { vector }
+}
+
+/// Whether the vertex buffer can store the channel in the format. Vertex shaders only decode octahedral normals, see
+/// VERTEX_FACTORY_NORMAL_OCTAHEDRAL.
+pub fn is_channel_format_supported(channel: usize, format: VertexFormat) -> bool
+{
+    channel < get_mesh_channel_count()
+        && format.get_packed_component_count(get_channel_default_value(channel).len()).is_some()
+        && (format != VertexFormat::OctahedralSnorm16x2 || channel == MeshDataChannel::Normal as usize)
+}
+
+/// Appends one channel value of a vertex in the given format, padding missing components with zero.
+pub fn pack_vertex_element(format: VertexFormat, values: &[f32], packed_component_count: usize, data: &mut Vec<u8>)
+{
+    let get_component = |component: usize| values.get(component).copied().unwrap_or(0.0);
+    match format
+    {
+        VertexFormat::Float32 =>
+        {
+            for component in 0..packed_component_count
+            {
+                data.extend_from_slice(&get_component(component).to_le_bytes());
+            }
+        }
+        VertexFormat::Float16 =>
+        {
+            for component in 0..packed_component_count
+            {
+                data.extend_from_slice(&f32_to_f16(get_component(component)).to_le_bytes());
+            }
+        }
+        VertexFormat::Unorm8x4 =>
+        {
+            for component in 0..packed_component_count
+            {
+                data.push((get_component(component).clamp(0.0, 1.0) * 255.0).round() as u8);
+            }
+        }
+        VertexFormat::Snorm8x4 =>
+        {
+            for component in 0..packed_component_count
+            {
+                data.push(((get_component(component).clamp(-1.0, 1.0) * 127.0).round() as i8) as u8);
+            }
+        }
+        VertexFormat::Unorm16 =>
+        {
+            for component in 0..packed_component_count
+            {
+                data.extend_from_slice(&((get_component(component).clamp(0.0, 1.0) * 65535.0).round() as u16).to_le_bytes());
+            }
+        }
+        VertexFormat::Snorm16 =>
+        {
+            for component in 0..packed_component_count
+            {
+                data.extend_from_slice(&((get_component(component).clamp(-1.0, 1.0) * 32767.0).round() as i16).to_le_bytes());
+            }
+        }
+        VertexFormat::OctahedralSnorm16x2 =>
+        {
+            let encoded = encode_octahedral(Vector3::new(get_component(0), get_component(1), get_component(2)));
+            for component in encoded.iter()
+            {
+                data.extend_from_slice(&((component.clamp(-1.0, 1.0) * 32767.0).round() as i16).to_le_bytes());
+            }
+        }
+    }
+}
+
+impl Mesh
+{
+    pub fn set_channel_format(&mut self, channel: impl Into<usize>, format: VertexFormat) -> Result<(), MeshError>
+    {
+        let channel = channel.into();
+        get_mesh_channel_desc(channel).ok_or(MeshError::UnknownChannel(channel))?;
+        if !is_channel_format_supported(channel, format)
+        {
+            return Err(MeshError::UnsupportedVertexFormat { channel, format });
+        }
+        self.mesh_channel_formats.insert(channel, format);
+        Ok(())
+    }
+
+    pub fn get_channel_format(&self, channel: impl Into<usize>) -> VertexFormat
+    {
+        self.mesh_channel_formats.get(&channel.into()).copied().unwrap_or(VertexFormat::Float32)
+    }
+
+    pub fn set_vertex_stream_layout(&mut self, stream_layout: VertexStreamLayout)
+    {
+        self.mesh_stream_layout = stream_layout;
+    }
+
+    pub fn get_vertex_layout(&self) -> VertexLayout
+    {
+        get_vertex_layout(&self.get_vertex_channels(), &self.mesh_channel_formats, self.mesh_stream_layout)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn octahedral_encoding_round_trips()
+    {
+        for vector in [Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, 0.0, -1.0), Vector3::new(1.0, -2.0, 0.5), Vector3::new(-0.3, 0.4, -2.0), Vector3::new(0.0, -1.0, 0.0)]
+        {
+            let vector = vector.normalize();
+            assert!((decode_octahedral(encode_octahedral(vector)) - vector).magnitude() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn octahedral_format_is_only_for_normals()
+    {
+        let mut mesh = Mesh::create_icosphere(1.0, 0);
+        assert_eq!(mesh.set_channel_format(MeshDataChannel::Normal, VertexFormat::OctahedralSnorm16x2), Ok(()));
+        assert_eq!(mesh.validate_structure(), Ok(()));
+        let error = MeshError::UnsupportedVertexFormat { channel: MeshDataChannel::Position as usize, format: VertexFormat::OctahedralSnorm16x2 };
+        assert_eq!(mesh.set_channel_format(MeshDataChannel::Position, VertexFormat::OctahedralSnorm16x2), Err(error.clone()));
+        mesh.mesh_channel_formats.insert(MeshDataChannel::Position as usize, VertexFormat::OctahedralSnorm16x2);
+        assert_eq!(mesh.validate_structure(), Err(error));
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::scene::mesh::*;
+
+fn get_component_key(value: f32) -> u32
+{
+    // Folds -0.0 onto 0.0 so both hash to the same vertex.
+    (value + 0.0).to_bits()
+}
+
+impl Mesh
+{
+    // Bits of the morph deltas of every vertex, empty without morph targets.
+    fn get_morph_keys(&self) -> Vec<Vec<u32>>
+    {
+        let mut keys = vec![vec![]; if self.mesh_morph_targets.is_empty() { 0 } else { self.get_vertex_count() }];
+        for (target_index, target) in self.mesh_morph_targets.iter().enumerate()
+        {
+            for (entry, vertex) in target.vertices.iter().enumerate()
+            {
+                let key = &mut keys[*vertex as usize];
+                key.push(target_index as u32);
+                for deltas in [&target.position_deltas, &target.normal_deltas, &target.tangent_deltas]
+                {
+                    key.extend(deltas.get(entry * 3..entry * 3 + 3).unwrap_or(&[]).iter().map(|value| get_component_key(*value)));
+                }
+            }
+        }
+        keys
+    }
+
+    fn is_vertex_within_epsilon(&self, a: usize, b: usize, channel_epsilons: &BTreeMap<usize, f32>) -> bool
+    {
+        self.mesh_channel_data.iter().all(|(channel, data)| {
+            let channel_size = get_channel_default_value(*channel).len();
+            let epsilon = channel_epsilons.get(channel).copied().unwrap_or(0.0);
+            (0..channel_size).all(|component| {
+                let (value_a, value_b) = (data[a * channel_size + component], data[b * channel_size + component]);
+                value_a == value_b || (value_a - value_b).abs() <= epsilon
+            })
+        })
+    }
+
+    /// Merges vertices whose channels are all bitwise identical, see weld_vertices.
+    pub fn weld_identical_vertices(&mut self) -> usize
+    {
+        self.weld_vertices(&BTreeMap::new())
+    }
+
+    /// Merges vertices whose channels all match within the epsilon given per channel (exact match for channels
+    /// without an entry) and rebuilds mesh_index_data, turning a triangle soup into an indexed mesh. Every channel
+    /// takes part in the comparison, so vertices on UV seams or hard edges stay separate. The first vertex of each
+    /// merged set is kept, vertices moved differently by a morph target stay separate. Returns the number of removed
+    /// vertices.
+    pub fn weld_vertices(&mut self, channel_epsilons: &BTreeMap<usize, f32>) -> usize
+    {
+        let vertex_count = self.get_vertex_count();
+        let indices = self.get_triangle_indices();
+        let use_epsilon = channel_epsilons.iter().any(|(channel, epsilon)| *epsilon > 0.0 && self.mesh_channel_data.contains_key(channel));
+        let morph_keys = self.get_morph_keys();
+
+        let mut source_vertices: Vec<u32> = vec![];
+        let mut vertex_remap = Vec::with_capacity(vertex_count);
+        if !use_epsilon
+        {
+            let mut welded_ids: HashMap<Vec<u32>, u32> = HashMap::new();
+            for vertex in 0..vertex_count
+            {
+                let mut key = vec![];
+                for (channel, data) in &self.mesh_channel_data
+                {
+                    let channel_size = get_channel_default_value(*channel).len();
+                    key.extend(data[vertex * channel_size..(vertex + 1) * channel_size].iter().map(|value| get_component_key(*value)));
+                }
+                if let Some(morph_key) = morph_keys.get(vertex)
+                {
+                    key.extend_from_slice(morph_key);
+                }
+                let welded = *welded_ids.entry(key).or_insert_with(|| {
+                    source_vertices.push(vertex as u32);
+                    source_vertices.len() as u32 - 1
+                });
+                vertex_remap.push(welded);
+            }
+        }
+        else
+        {
+            // Candidates are found through a grid with cells as large as the position epsilon, a vertex can only
+            // merge with kept vertices in its own or a neighbouring cell.
+            let cell_size = channel_epsilons.get(&(MeshDataChannel::Position as usize)).copied().unwrap_or(0.0);
+            let get_cell = |value: f32| if cell_size > 0.0 { (value / cell_size).floor() as i64 } else { get_component_key(value) as i64 };
+            let neighbours: &[i64] = if cell_size > 0.0 { &[-1, 0, 1] } else { &[0] };
+
+            let mut cells: HashMap<[i64; 3], Vec<u32>> = HashMap::new();
+            for vertex in 0..vertex_count
+            {
+                let position = self.get_position(vertex);
+                let cell = [get_cell(position.x), get_cell(position.y), get_cell(position.z)];
+
+                let mut welded = None;
+                'search: for x in neighbours
+                {
+                    for y in neighbours
+                    {
+                        for z in neighbours
+                        {
+                            let candidates = match cells.get(&[cell[0] + x, cell[1] + y, cell[2] + z])
+                            {
+                                Some(candidates) => candidates,
+                                None => continue
+                            };
+                            for candidate in candidates
+                            {
+                                let source = source_vertices[*candidate as usize] as usize;
+                                if morph_keys.get(source) == morph_keys.get(vertex) && self.is_vertex_within_epsilon(source, vertex, channel_epsilons)
+                                {
+                                    welded = Some(*candidate);
+                                    break 'search;
+                                }
+                            }
+                        }
+                    }
+                }
+
+                let welded = welded.unwrap_or_else(|| {
+                    source_vertices.push(vertex as u32);
+                    let welded = source_vertices.len() as u32 - 1;
+                    cells.entry(cell).or_default().push(welded);
+                    welded
+                });
+                vertex_remap.push(welded);
+            }
+        }
+
+        self.remap_vertices(&source_vertices);
+        self.mesh_index_data = indices.iter().map(|index| vertex_remap[*index as usize]).collect();
+        vertex_count - source_vertices.len()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // Two triangles of a unit quad as a soup, the corners of the second triangle are moved by the given offsets.
+    fn create_quad_soup(position_offset: f32, uv_offset: f32) -> Mesh
+    {
+        let corners = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+        let get_offset = |vertex: usize, offset: f32| if vertex >= 3 { offset } else { 0.0 };
+        let mut mesh = Mesh::default();
+        let positions = corners.iter().enumerate()
+            .flat_map(|(vertex, [x, y])| [x + get_offset(vertex, position_offset), y + get_offset(vertex, position_offset), 0.0])
+            .collect();
+        let uvs = corners.iter().enumerate()
+            .flat_map(|(vertex, [u, v])| [u + get_offset(vertex, uv_offset), *v])
+            .collect();
+        mesh.mesh_channel_data.insert(MeshDataChannel::Position as usize, positions);
+        mesh.mesh_channel_data.insert(MeshDataChannel::UV0 as usize, uvs);
+        mesh.mesh_index_data = (0..6).collect();
+        mesh
+    }
+
+    fn create_epsilons(position_epsilon: f32, uv_epsilon: f32) -> BTreeMap<usize, f32>
+    {
+        BTreeMap::from([(MeshDataChannel::Position as usize, position_epsilon), (MeshDataChannel::UV0 as usize, uv_epsilon)])
+    }
+
+    #[test]
+    fn identical_vertices_are_welded()
+    {
+        let mut mesh = create_quad_soup(0.0, 0.0);
+        assert_eq!(mesh.weld_identical_vertices(), 2);
+        assert_eq!(mesh.get_vertex_count(), 4);
+        assert_eq!(mesh.mesh_index_data, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(mesh.mesh_channel_data[&(MeshDataChannel::UV0 as usize)], vec![0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 1.0]);
+
+        let mut jittered = create_quad_soup(-1e-4, 0.0);
+        assert_eq!(jittered.weld_identical_vertices(), 0);
+        assert_eq!(jittered.mesh_index_data, (0..6).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn vertices_within_their_channel_epsilons_are_welded()
+    {
+        // The negative offset puts the moved corners into the neighbouring grid cell.
+        let mut mesh = create_quad_soup(-1e-4, 1e-4);
+        assert_eq!(mesh.weld_vertices(&create_epsilons(1e-3, 1e-3)), 2);
+        assert_eq!(mesh.get_vertex_count(), 4);
+        assert_eq!(mesh.mesh_index_data, vec![0, 1, 2, 0, 2, 3]);
+        // The first vertex of each merged set is kept.
+        assert_eq!(mesh.get_position(0), Vector3::new(0.0, 0.0, 0.0));
+        assert_eq!(mesh.get_position(2), Vector3::new(1.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn uvs_beyond_their_epsilon_stay_separate()
+    {
+        let mut mesh = create_quad_soup(-1e-4, 0.01);
+        assert_eq!(mesh.weld_vertices(&create_epsilons(1e-3, 1e-3)), 0);
+        assert_eq!(mesh.get_vertex_count(), 6);
+        assert_eq!(mesh.mesh_index_data, (0..6).collect::<Vec<u32>>());
+
+        // A UV seam stays open while the position alone would merge, the vertex after it moves down.
+        let mut mesh = create_quad_soup(-1e-4, 0.0);
+        mesh.mesh_channel_data.get_mut(&(MeshDataChannel::UV0 as usize)).unwrap()[6] = 0.5;
+        assert_eq!(mesh.weld_vertices(&create_epsilons(1e-3, 1e-3)), 1);
+        assert_eq!(mesh.mesh_index_data, vec![0, 1, 2, 3, 2, 4]);
+        assert_eq!(mesh.mesh_channel_data[&(MeshDataChannel::UV0 as usize)][6], 0.5);
+
+        // Channels without an epsilon have to match exactly.
+        let mut mesh = create_quad_soup(-1e-4, 1e-4);
+        assert_eq!(mesh.weld_vertices(&BTreeMap::from([(MeshDataChannel::Position as usize, 1e-3)])), 0);
+
+        let mut mesh = create_quad_soup(-1e-4, 0.01);
+        assert_eq!(mesh.weld_vertices(&create_epsilons(1e-3, 0.1)), 2);
+        assert_eq!(mesh.mesh_index_data, vec![0, 1, 2, 0, 2, 3]);
+    }
+}
+
@@ -0,0 +1,26 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MeshMaterial
+{
+    pub name: String,
+    pub base_color: [f32; 4],
+    pub base_color_texture: Option<PathBuf>,
+    pub normal_texture: Option<PathBuf>
+}
+
+impl Default for MeshMaterial
+{
+    fn default() -> Self
+    {
+        MeshMaterial
+        {
+            name: String::new(),
+            base_color: [1.0, 1.0, 1.0, 1.0],
+            base_color_texture: None,
+            normal_texture: None
+        }
+    }
+}
@@ -1,4 +1,13 @@
 pub mod static_mesh;
+pub mod skeletal_mesh;
 pub mod scene_proxy;
 pub mod mesh;
+pub mod mesh_material;
 pub mod scene;
+pub mod camera;
+pub mod culling;
+pub mod scene_bvh;
+pub mod picking;
+pub mod transform;
+pub mod light;
+pub mod importer;
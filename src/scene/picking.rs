@@ -0,0 +1,138 @@
+use cgmath::{InnerSpace, Matrix, Matrix3, SquareMatrix, Vector3};
+
+use crate::scene::mesh::bounds::Ray;
+use crate::scene::mesh::picking::MeshRayHit;
+use crate::scene::scene::*;
+use crate::scene::scene_bvh::SceneBvh;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScenePickHit
+{
+    pub proxy: SceneProxyHandle,
+    // World space, along the unit direction of the picking ray.
+    pub distance: f32,
+    pub position: Vector3<f32>,
+    pub normal: Option<Vector3<f32>>,
+    // The hit in the local space of the proxy, with triangle, barycentrics and UV.
+    pub mesh_hit: MeshRayHit
+}
+
+impl Scene
+{
+    /// Intersects a world space ray with the triangles of one scene proxy.
+    pub fn pick_scene_proxy(&self, proxy: SceneProxyHandle, ray: &Ray, max_distance: f32) -> Option<ScenePickHit>
+    {
+        let world_transform = self.get_scene_proxy_world_transform(proxy)?;
+        let inverse_transform = world_transform.invert()?;
+        let local_direction = (inverse_transform * ray.direction.extend(0.0)).truncate();
+        // Local distances are this many times the world distances.
+        let scale = local_direction.magnitude();
+        if scale == 0.0
+        {
+            return None;
+        }
+        let local_ray = Ray::new((inverse_transform * ray.origin.extend(1.0)).truncate(), local_direction);
+        let mesh_hit = self.get_scene_proxy(proxy)?.intersect_ray(&local_ray, max_distance * scale)?;
+        let normal_matrix = Matrix3::from_cols(inverse_transform.x.truncate(), inverse_transform.y.truncate(), inverse_transform.z.truncate()).transpose();
+        Some(ScenePickHit
+        {
+            proxy,
+            distance: mesh_hit.distance / scale,
+            position: (world_transform * mesh_hit.position.extend(1.0)).truncate(),
+            normal: mesh_hit.normal.map(|normal| (normal_matrix * normal).normalize()),
+            mesh_hit
+        })
+    }
+
+    /// Closest scene proxy whose triangles the world space ray hits within max_distance. Tests the world box of every
+    /// proxy, SceneBvh::pick scales better for large scenes.
+    pub fn pick(&self, ray: &Ray, max_distance: f32) -> Option<ScenePickHit>
+    {
+        let mut closest: Option<ScenePickHit> = None;
+        for (proxy, _) in self.get_scene_proxies()
+        {
+            let closest_distance = closest.map_or(max_distance, |hit| hit.distance);
+            let bounding_box = self.get_scene_proxy_world_bounds(proxy).unwrap().bounding_box;
+            if bounding_box.intersect_ray(ray, closest_distance).is_none()
+            {
+                continue;
+            }
+            if let Some(hit) = self.pick_scene_proxy(proxy, ray, closest_distance).filter(|hit| hit.distance <= closest_distance)
+            {
+                closest = Some(hit);
+            }
+        }
+        closest
+    }
+}
+
+impl SceneBvh
+{
+    /// Same as Scene::pick, testing the triangles of proxies front to back and only while their boxes start before the
+    /// closest hit. The hierarchy has to be up to date with the scene.
+    pub fn pick(&self, scene: &Scene, ray: &Ray, max_distance: f32) -> Option<ScenePickHit>
+    {
+        let mut closest: Option<ScenePickHit> = None;
+        self.cast_ray_with(ray, max_distance, |proxy, _, closest_distance| {
+            // Rounding in the local space can put a hit just past closest_distance, which cast_ray_with drops.
+            let hit = scene.pick_scene_proxy(proxy, ray, closest_distance).filter(|hit| hit.distance <= closest_distance)?;
+            closest = Some(hit);
+            Some(hit.distance)
+        });
+        closest
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use cgmath::{Deg, Quaternion, Rotation3};
+
+    use super::*;
+    use crate::scene::mesh::Mesh;
+    use crate::scene::static_mesh::StaticMesh;
+    use crate::scene::transform::Transform;
+
+    fn add_unit_box(scene: &mut Scene, transform: Transform) -> SceneProxyHandle
+    {
+        let node = scene.create_node("box", None).unwrap();
+        scene.set_local_transform(node, transform).unwrap();
+        let mut static_mesh = StaticMesh::new("box");
+        *static_mesh.get_mesh_mut() = Mesh::create_box(Vector3::new(1.0, 1.0, 1.0), 1);
+        scene.add_scene_proxy(node, Box::new(static_mesh)).unwrap()
+    }
+
+    #[test]
+    fn picks_report_world_distances_of_scaled_nodes()
+    {
+        let mut scene = Scene::new();
+        // Scaled to 2 x 3 x 4 and turned a quarter around z, so its front face is at z = -2.
+        let rotation = Quaternion::from_angle_z(Deg(90.0));
+        let near_box = add_unit_box(&mut scene, Transform::new(Vector3::new(0.0, 0.0, 0.0), rotation, Vector3::new(2.0, 3.0, 4.0)));
+        let far_box = add_unit_box(&mut scene, Transform::new(Vector3::new(0.0, 0.0, 10.0), rotation, Vector3::new(5.0, 5.0, 5.0)));
+        let bvh = SceneBvh::build(&scene);
+
+        let ray = Ray::new(Vector3::new(0.5, 0.5, -10.0), Vector3::unit_z());
+        for hit in [scene.pick(&ray, f32::MAX), bvh.pick(&scene, &ray, f32::MAX)]
+        {
+            let hit = hit.unwrap();
+            assert_eq!(hit.proxy, near_box);
+            assert!((hit.distance - 8.0).abs() < 1e-5);
+            assert!((hit.position - Vector3::new(0.5, 0.5, -2.0)).magnitude() < 1e-5);
+            assert!((hit.normal.unwrap() - Vector3::new(0.0, 0.0, -1.0)).magnitude() < 1e-5);
+            // The local hit is on the face of the unit box.
+            assert!((hit.mesh_hit.position.z + 0.5).abs() < 1e-5 && (hit.mesh_hit.distance - 2.0).abs() < 1e-5);
+        }
+        assert!(scene.pick(&ray, 7.9).is_none() && bvh.pick(&scene, &ray, 7.9).is_none());
+
+        // From behind, the larger box is hit first.
+        let ray = Ray::new(Vector3::new(0.5, 0.5, 20.0), -Vector3::unit_z());
+        for hit in [scene.pick(&ray, f32::MAX), bvh.pick(&scene, &ray, f32::MAX)]
+        {
+            let hit = hit.unwrap();
+            assert_eq!(hit.proxy, far_box);
+            assert!((hit.distance - 7.5).abs() < 1e-5);
+        }
+    }
+}
+
@@ -1,20 +1,613 @@
-use crate::scene::mesh::*;
+use std::any::Any;
+use std::cell::Cell;
+
+use cgmath::{Matrix4, SquareMatrix};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::scene::camera::Camera;
+use crate::scene::light::Light;
+use crate::scene::mesh::bounds::*;
 use crate::scene::scene_proxy::*;
+use crate::scene::transform::Transform;
+
+/// Stays valid until its node is removed, a handle of a removed node never refers to a later node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SceneNodeHandle
+{
+    index: u32,
+    generation: u32
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SceneProxyHandle
+{
+    index: u32,
+    generation: u32
+}
+
+/// Where the asset of a scene proxy comes from, so that a saved scene can load it again. The path is relative to the
+/// scene file with forward slashes, mesh picks one mesh by name in files that hold several.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SceneAssetReference
+{
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mesh: Option<String>
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SceneError
+{
+    #[error("scene node {0:?} does not exist")]
+    InvalidNode(SceneNodeHandle),
+    #[error("scene proxy {0:?} does not exist")]
+    InvalidSceneProxy(SceneProxyHandle),
+    #[error("scene node {0:?} cannot become a child of its own subtree")]
+    CyclicHierarchy(SceneNodeHandle)
+}
+
+struct Slot<T>
+{
+    generation: u32,
+    value: Option<T>
+}
+
+// Storage behind the handles, freed slots are reused with the next generation.
+struct Slots<T>
+{
+    slots: Vec<Slot<T>>,
+    free_slots: Vec<u32>
+}
+
+impl<T> Default for Slots<T>
+{
+    fn default() -> Self
+    {
+        Slots { slots: vec![], free_slots: vec![] }
+    }
+}
+
+impl<T> Slots<T>
+{
+    fn insert(&mut self, value: T) -> (u32, u32)
+    {
+        if let Some(index) = self.free_slots.pop()
+        {
+            let slot = &mut self.slots[index as usize];
+            slot.value = Some(value);
+            return (index, slot.generation);
+        }
+        self.slots.push(Slot { generation: 0, value: Some(value) });
+        (self.slots.len() as u32 - 1, 0)
+    }
+
+    fn remove(&mut self, index: u32, generation: u32) -> Option<T>
+    {
+        let slot = self.slots.get_mut(index as usize).filter(|slot| slot.generation == generation)?;
+        let value = slot.value.take()?;
+        slot.generation += 1;
+        self.free_slots.push(index);
+        Some(value)
+    }
+
+    fn get(&self, index: u32, generation: u32) -> Option<&T>
+    {
+        self.slots.get(index as usize).filter(|slot| slot.generation == generation).and_then(|slot| slot.value.as_ref())
+    }
+
+    fn get_mut(&mut self, index: u32, generation: u32) -> Option<&mut T>
+    {
+        self.slots.get_mut(index as usize).filter(|slot| slot.generation == generation).and_then(|slot| slot.value.as_mut())
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (u32, u32, &T)>
+    {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| slot.value.as_ref().map(|value| (index as u32, slot.generation, value)))
+    }
+
+    fn len(&self) -> usize
+    {
+        self.slots.len() - self.free_slots.len()
+    }
+}
+
+pub struct SceneNode
+{
+    name: String,
+    local_transform: Transform,
+    parent: Option<SceneNodeHandle>,
+    children: Vec<SceneNodeHandle>,
+    scene_proxies: Vec<SceneProxyHandle>,
+    light: Option<Light>,
+    // Position and rotation relative to the node, see Scene::get_world_camera.
+    camera: Option<Camera>,
+    // Cached, a dirty node always has dirty descendants.
+    world_transform: Cell<Matrix4<f32>>,
+    is_world_transform_dirty: Cell<bool>
+}
+
+impl SceneNode
+{
+    pub fn get_name(&self) -> &str
+    {
+        &self.name
+    }
 
+    pub fn get_local_transform(&self) -> &Transform
+    {
+        &self.local_transform
+    }
+
+    pub fn get_parent(&self) -> Option<SceneNodeHandle>
+    {
+        self.parent
+    }
+
+    pub fn get_children(&self) -> &Vec<SceneNodeHandle>
+    {
+        &self.children
+    }
+
+    pub fn get_scene_proxies(&self) -> &Vec<SceneProxyHandle>
+    {
+        &self.scene_proxies
+    }
+
+    pub fn get_light(&self) -> Option<&Light>
+    {
+        self.light.as_ref()
+    }
+
+    pub fn get_camera(&self) -> Option<&Camera>
+    {
+        self.camera.as_ref()
+    }
+}
+
+struct SceneProxyEntry
+{
+    proxy: Box<dyn SceneProxy>,
+    node: SceneNodeHandle,
+    asset: Option<SceneAssetReference>
+}
+
+/// Node hierarchy with local transforms, scene proxies are attached to nodes and placed by their world transforms.
+#[derive(Default)]
 pub struct Scene
 {
-    scene_proxies: Vec<Box<dyn SceneProxy>>
+    nodes: Slots<SceneNode>,
+    root_nodes: Vec<SceneNodeHandle>,
+    scene_proxies: Slots<SceneProxyEntry>
 }
 
 impl Scene
 {
-    pub fn add_scene_proxy(&mut self, in_proxy: Box<dyn SceneProxy>)
+    pub fn new() -> Self
     {
-        self.scene_proxies.push(in_proxy);
+        Scene::default()
     }
 
-    pub fn get_scene_proxies(&self) -> &Vec<Box<dyn SceneProxy>>
+    pub fn create_node(&mut self, name: &str, parent: Option<SceneNodeHandle>) -> Result<SceneNodeHandle, SceneError>
     {
-        &self.scene_proxies
+        if let Some(parent) = parent
+        {
+            self.get_node_checked(parent)?;
+        }
+        let (index, generation) = self.nodes.insert(SceneNode
+        {
+            name: name.to_string(),
+            local_transform: Transform::default(),
+            parent,
+            children: vec![],
+            scene_proxies: vec![],
+            light: None,
+            camera: None,
+            world_transform: Cell::new(Matrix4::identity()),
+            is_world_transform_dirty: Cell::new(true)
+        });
+        let node = SceneNodeHandle { index, generation };
+        self.get_children_mut(parent).push(node);
+        Ok(node)
+    }
+
+    /// Removes the node with all of its descendants and their scene proxies.
+    pub fn remove_node(&mut self, node: SceneNodeHandle) -> Result<(), SceneError>
+    {
+        let parent = self.get_node_checked(node)?.parent;
+        self.get_children_mut(parent).retain(|child| *child != node);
+        let mut pending = vec![node];
+        while let Some(node) = pending.pop()
+        {
+            let removed = self.nodes.remove(node.index, node.generation).unwrap();
+            for proxy in removed.scene_proxies
+            {
+                self.scene_proxies.remove(proxy.index, proxy.generation);
+            }
+            pending.extend(removed.children);
+        }
+        Ok(())
+    }
+
+    pub fn get_node(&self, node: SceneNodeHandle) -> Option<&SceneNode>
+    {
+        self.nodes.get(node.index, node.generation)
+    }
+
+    pub fn get_nodes(&self) -> impl Iterator<Item = (SceneNodeHandle, &SceneNode)>
+    {
+        self.nodes.iter().map(|(index, generation, node)| (SceneNodeHandle { index, generation }, node))
+    }
+
+    pub fn get_node_count(&self) -> usize
+    {
+        self.nodes.len()
+    }
+
+    pub fn get_root_nodes(&self) -> &Vec<SceneNodeHandle>
+    {
+        &self.root_nodes
+    }
+
+    pub fn find_node(&self, name: &str) -> Option<SceneNodeHandle>
+    {
+        self.get_nodes().find(|(_, node)| node.name == name).map(|(handle, _)| handle)
+    }
+
+    fn get_node_checked(&self, node: SceneNodeHandle) -> Result<&SceneNode, SceneError>
+    {
+        self.get_node(node).ok_or(SceneError::InvalidNode(node))
+    }
+
+    // The parent has to exist.
+    fn get_children_mut(&mut self, parent: Option<SceneNodeHandle>) -> &mut Vec<SceneNodeHandle>
+    {
+        match parent
+        {
+            Some(parent) => &mut self.nodes.get_mut(parent.index, parent.generation).unwrap().children,
+            None => &mut self.root_nodes
+        }
+    }
+
+    fn mark_world_transform_dirty(&self, node: SceneNodeHandle)
+    {
+        let mut pending = vec![node];
+        while let Some(node) = pending.pop()
+        {
+            let node = self.get_node(node).unwrap();
+            if !node.is_world_transform_dirty.replace(true)
+            {
+                pending.extend_from_slice(&node.children);
+            }
+        }
     }
-}
\ No newline at end of file
+
+    /// Moves the node under another parent, or to the roots for None. With keep_world_transform the local transform
+    /// is changed so that the node stays where it is.
+    pub fn set_parent(&mut self, node: SceneNodeHandle, parent: Option<SceneNodeHandle>, keep_world_transform: bool) -> Result<(), SceneError>
+    {
+        let old_parent = self.get_node_checked(node)?.parent;
+        if let Some(parent) = parent
+        {
+            self.get_node_checked(parent)?;
+            let mut ancestor = Some(parent);
+            while let Some(current) = ancestor
+            {
+                if current == node
+                {
+                    return Err(SceneError::CyclicHierarchy(node));
+                }
+                ancestor = self.get_node(current).unwrap().parent;
+            }
+        }
+
+        if keep_world_transform
+        {
+            let world_transform = self.get_world_transform(node).unwrap();
+            let parent_transform = parent.map(|parent| self.get_world_transform(parent).unwrap()).unwrap_or(Matrix4::identity());
+            let local_transform = parent_transform.invert().unwrap_or(Matrix4::identity()) * world_transform;
+            self.nodes.get_mut(node.index, node.generation).unwrap().local_transform = Transform::from_matrix(&local_transform);
+        }
+        self.get_children_mut(old_parent).retain(|child| *child != node);
+        self.get_children_mut(parent).push(node);
+        self.nodes.get_mut(node.index, node.generation).unwrap().parent = parent;
+        self.mark_world_transform_dirty(node);
+        Ok(())
+    }
+
+    pub fn set_local_transform(&mut self, node: SceneNodeHandle, transform: Transform) -> Result<(), SceneError>
+    {
+        let scene_node = self.nodes.get_mut(node.index, node.generation).ok_or(SceneError::InvalidNode(node))?;
+        scene_node.local_transform = transform;
+        self.mark_world_transform_dirty(node);
+        Ok(())
+    }
+
+    /// Local to world matrix of the node, recomputed only when the node or an ancestor changed since the last call.
+    pub fn get_world_transform(&self, node: SceneNodeHandle) -> Option<Matrix4<f32>>
+    {
+        let scene_node = self.get_node(node)?;
+        if scene_node.is_world_transform_dirty.get()
+        {
+            let local_transform = scene_node.local_transform.to_matrix();
+            let world_transform = match scene_node.parent
+            {
+                Some(parent) => self.get_world_transform(parent)? * local_transform,
+                None => local_transform
+            };
+            scene_node.world_transform.set(world_transform);
+            scene_node.is_world_transform_dirty.set(false);
+        }
+        Some(scene_node.world_transform.get())
+    }
+
+    pub fn set_node_light(&mut self, node: SceneNodeHandle, light: Option<Light>) -> Result<(), SceneError>
+    {
+        self.nodes.get_mut(node.index, node.generation).ok_or(SceneError::InvalidNode(node))?.light = light;
+        Ok(())
+    }
+
+    /// Attaches a camera whose position and rotation are relative to the node.
+    pub fn set_node_camera(&mut self, node: SceneNodeHandle, camera: Option<Camera>) -> Result<(), SceneError>
+    {
+        self.nodes.get_mut(node.index, node.generation).ok_or(SceneError::InvalidNode(node))?.camera = camera;
+        Ok(())
+    }
+
+    /// The camera of the node placed in world space. Scale of the node does not affect the camera.
+    pub fn get_world_camera(&self, node: SceneNodeHandle) -> Option<Camera>
+    {
+        let camera = *self.get_node(node)?.camera.as_ref()?;
+        let world_transform = self.get_world_transform(node)?;
+        Some(Camera
+        {
+            position: (world_transform * camera.position.extend(1.0)).truncate(),
+            rotation: Transform::from_matrix(&world_transform).rotation * camera.rotation,
+            ..camera
+        })
+    }
+
+    pub fn add_scene_proxy(&mut self, node: SceneNodeHandle, proxy: Box<dyn SceneProxy>) -> Result<SceneProxyHandle, SceneError>
+    {
+        self.get_node_checked(node)?;
+        let (index, generation) = self.scene_proxies.insert(SceneProxyEntry { proxy, node, asset: None });
+        let handle = SceneProxyHandle { index, generation };
+        self.nodes.get_mut(node.index, node.generation).unwrap().scene_proxies.push(handle);
+        Ok(handle)
+    }
+
+    pub fn remove_scene_proxy(&mut self, proxy: SceneProxyHandle) -> Option<Box<dyn SceneProxy>>
+    {
+        let entry = self.scene_proxies.remove(proxy.index, proxy.generation)?;
+        self.nodes.get_mut(entry.node.index, entry.node.generation).unwrap().scene_proxies.retain(|handle| *handle != proxy);
+        Some(entry.proxy)
+    }
+
+    pub fn get_scene_proxy(&self, proxy: SceneProxyHandle) -> Option<&dyn SceneProxy>
+    {
+        self.scene_proxies.get(proxy.index, proxy.generation).map(|entry| entry.proxy.as_ref())
+    }
+
+    /// Changes to the geometry move the bounds of the proxy, call SceneBvh::refit_scene_proxy afterwards.
+    pub fn get_scene_proxy_mut(&mut self, proxy: SceneProxyHandle) -> Option<&mut dyn SceneProxy>
+    {
+        self.scene_proxies.get_mut(proxy.index, proxy.generation).map(|entry| entry.proxy.as_mut())
+    }
+
+    /// The proxy as its concrete type, None when it is of another type.
+    pub fn get_scene_proxy_as<T: SceneProxy>(&self, proxy: SceneProxyHandle) -> Option<&T>
+    {
+        let proxy: &dyn Any = self.get_scene_proxy(proxy)?;
+        proxy.downcast_ref()
+    }
+
+    /// Same as get_scene_proxy_mut for a proxy of a concrete type, None when it is of another type.
+    pub fn get_scene_proxy_mut_as<T: SceneProxy>(&mut self, proxy: SceneProxyHandle) -> Option<&mut T>
+    {
+        let proxy: &mut dyn Any = self.get_scene_proxy_mut(proxy)?;
+        proxy.downcast_mut()
+    }
+
+    pub fn get_scene_proxies(&self) -> impl Iterator<Item = (SceneProxyHandle, &dyn SceneProxy)>
+    {
+        self.scene_proxies.iter().map(|(index, generation, entry)| (SceneProxyHandle { index, generation }, entry.proxy.as_ref()))
+    }
+
+    pub fn get_scene_proxy_count(&self) -> usize
+    {
+        self.scene_proxies.len()
+    }
+
+    pub fn get_scene_proxy_node(&self, proxy: SceneProxyHandle) -> Option<SceneNodeHandle>
+    {
+        self.scene_proxies.get(proxy.index, proxy.generation).map(|entry| entry.node)
+    }
+
+    /// Records which asset the proxy was loaded from, proxies without a reference are not saved with the scene.
+    pub fn set_scene_proxy_asset(&mut self, proxy: SceneProxyHandle, asset: Option<SceneAssetReference>) -> Result<(), SceneError>
+    {
+        self.scene_proxies.get_mut(proxy.index, proxy.generation).ok_or(SceneError::InvalidSceneProxy(proxy))?.asset = asset;
+        Ok(())
+    }
+
+    pub fn get_scene_proxy_asset(&self, proxy: SceneProxyHandle) -> Option<&SceneAssetReference>
+    {
+        self.scene_proxies.get(proxy.index, proxy.generation).and_then(|entry| entry.asset.as_ref())
+    }
+
+    pub fn get_scene_proxy_world_transform(&self, proxy: SceneProxyHandle) -> Option<Matrix4<f32>>
+    {
+        self.get_world_transform(self.get_scene_proxy_node(proxy)?)
+    }
+
+    /// Bounds of the proxy in world space. The box is the box around the transformed local box.
+    pub fn get_scene_proxy_world_bounds(&self, proxy: SceneProxyHandle) -> Option<MeshBounds>
+    {
+        let world_transform = self.get_scene_proxy_world_transform(proxy)?;
+        let bounds = self.get_scene_proxy(proxy)?.get_bounds();
+        Some(MeshBounds
+        {
+            bounding_box: bounds.bounding_box.transform(&world_transform),
+            bounding_sphere: bounds.bounding_sphere.transform(&world_transform),
+            oriented_bounding_box: None
+        })
+    }
+
+    /// Batches of one scene proxy with the world transform of its node.
+    pub fn generate_scene_proxy_mesh_batches(&self, proxy: SceneProxyHandle) -> Vec<MeshBatch<'_>>
+    {
+        let (Some(scene_proxy), Some(world_transform)) = (self.get_scene_proxy(proxy), self.get_scene_proxy_world_transform(proxy)) else { return vec![] };
+        scene_proxy.generate_mesh_batches().into_iter().map(|mesh_batch| MeshBatch { world_transform, ..mesh_batch }).collect()
+    }
+
+    /// Batches of every scene proxy, see generate_visible_mesh_batches for a single view.
+    pub fn generate_mesh_batches(&self) -> Vec<MeshBatch<'_>>
+    {
+        self.get_scene_proxies().flat_map(|(proxy, _)| self.generate_scene_proxy_mesh_batches(proxy)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use cgmath::{InnerSpace, Quaternion, Rotation3, Vector3};
+
+    use super::*;
+    use crate::scene::mesh::Mesh;
+    use crate::scene::scene_bvh::SceneBvh;
+    use crate::scene::skeletal_mesh::SkeletalMesh;
+    use crate::scene::static_mesh::StaticMesh;
+
+    fn add_box(scene: &mut Scene, node: SceneNodeHandle) -> SceneProxyHandle
+    {
+        let mut static_mesh = StaticMesh::new("box");
+        *static_mesh.get_mesh_mut() = Mesh::create_box(Vector3::new(1.0, 1.0, 1.0), 1);
+        scene.add_scene_proxy(node, Box::new(static_mesh)).unwrap()
+    }
+
+    fn assert_matrix_near(a: Matrix4<f32>, b: Matrix4<f32>)
+    {
+        let difference: f32 = (0..4).map(|column| (a[column] - b[column]).magnitude()).sum();
+        assert!(difference < 1e-5, "{:?} differs from {:?}", a, b);
+    }
+
+    // root, child and grandchild, each moved one unit along x and the root also rotated and scaled.
+    fn create_chain() -> (Scene, [SceneNodeHandle; 3])
+    {
+        let mut scene = Scene::new();
+        let root = scene.create_node("root", None).unwrap();
+        let child = scene.create_node("child", Some(root)).unwrap();
+        let grandchild = scene.create_node("grandchild", Some(child)).unwrap();
+        let rotation = Quaternion::from_angle_y(cgmath::Deg(90.0));
+        scene.set_local_transform(root, Transform::new(Vector3::new(1.0, 0.0, 0.0), rotation, Vector3::new(2.0, 2.0, 2.0))).unwrap();
+        for node in [child, grandchild]
+        {
+            scene.set_local_transform(node, Transform::from_translation(Vector3::new(1.0, 0.0, 0.0))).unwrap();
+        }
+        (scene, [root, child, grandchild])
+    }
+
+    #[test]
+    fn world_transforms_follow_their_ancestors()
+    {
+        let (mut scene, [root, child, grandchild]) = create_chain();
+        // The root turns +x into -z and doubles the two unit steps below it.
+        assert_matrix_near(scene.get_world_transform(grandchild).unwrap(), Matrix4::from_translation(Vector3::new(1.0, 0.0, -4.0)) * Matrix4::from_angle_y(cgmath::Deg(90.0)) * Matrix4::from_scale(2.0));
+
+        // Cached world transforms are recomputed when an ancestor moves.
+        scene.set_local_transform(root, Transform::from_translation(Vector3::new(0.0, 5.0, 0.0))).unwrap();
+        assert_matrix_near(scene.get_world_transform(grandchild).unwrap(), Matrix4::from_translation(Vector3::new(2.0, 5.0, 0.0)));
+        scene.set_local_transform(child, Transform::default()).unwrap();
+        assert_matrix_near(scene.get_world_transform(grandchild).unwrap(), Matrix4::from_translation(Vector3::new(1.0, 5.0, 0.0)));
+        assert_matrix_near(scene.get_world_transform(root).unwrap(), Matrix4::from_translation(Vector3::new(0.0, 5.0, 0.0)));
+    }
+
+    #[test]
+    fn reparenting_can_keep_the_world_transform()
+    {
+        let (mut scene, [root, child, grandchild]) = create_chain();
+        let world_transform = scene.get_world_transform(grandchild).unwrap();
+        scene.set_parent(grandchild, None, true).unwrap();
+        assert_matrix_near(scene.get_world_transform(grandchild).unwrap(), world_transform);
+        assert_eq!(scene.get_root_nodes(), &vec![root, grandchild]);
+        assert!(scene.get_node(child).unwrap().get_children().is_empty());
+
+        scene.set_parent(grandchild, Some(root), true).unwrap();
+        assert_matrix_near(scene.get_world_transform(grandchild).unwrap(), world_transform);
+        assert_eq!(scene.get_node(grandchild).unwrap().get_parent(), Some(root));
+
+        // Without keep_world_transform the local transform stays and the node moves with its new parent.
+        let local_transform = *scene.get_node(grandchild).unwrap().get_local_transform();
+        scene.set_parent(grandchild, Some(child), false).unwrap();
+        assert_eq!(*scene.get_node(grandchild).unwrap().get_local_transform(), local_transform);
+        assert_matrix_near(scene.get_world_transform(grandchild).unwrap(), scene.get_world_transform(child).unwrap() * local_transform.to_matrix());
+    }
+
+    #[test]
+    fn cycles_are_rejected()
+    {
+        let (mut scene, [root, child, grandchild]) = create_chain();
+        assert_eq!(scene.set_parent(root, Some(grandchild), false), Err(SceneError::CyclicHierarchy(root)));
+        assert_eq!(scene.set_parent(child, Some(child), true), Err(SceneError::CyclicHierarchy(child)));
+        assert_eq!(scene.get_root_nodes(), &vec![root]);
+        assert_eq!(scene.get_node(child).unwrap().get_parent(), Some(root));
+        assert_eq!(scene.get_node(grandchild).unwrap().get_parent(), Some(child));
+    }
+
+    #[test]
+    fn removing_a_node_removes_its_subtree_and_proxies()
+    {
+        let (mut scene, [root, child, grandchild]) = create_chain();
+        let root_proxy = add_box(&mut scene, root);
+        let child_proxy = add_box(&mut scene, child);
+        let grandchild_proxy = add_box(&mut scene, grandchild);
+        scene.remove_node(child).unwrap();
+        assert_eq!(scene.get_node_count(), 1);
+        assert!(scene.get_node(root).unwrap().get_children().is_empty());
+        assert!(scene.get_node(child).is_none() && scene.get_node(grandchild).is_none());
+        assert!(scene.get_scene_proxy(child_proxy).is_none() && scene.get_scene_proxy(grandchild_proxy).is_none());
+        assert_eq!(scene.get_scene_proxies().map(|(proxy, _)| proxy).collect::<Vec<_>>(), vec![root_proxy]);
+        assert_eq!(scene.remove_node(child), Err(SceneError::InvalidNode(child)));
+    }
+
+    #[test]
+    fn stale_handles_do_not_reach_reused_slots()
+    {
+        let mut scene = Scene::new();
+        let node = scene.create_node("old", None).unwrap();
+        let proxy = add_box(&mut scene, node);
+        scene.remove_node(node).unwrap();
+        let new_node = scene.create_node("new", None).unwrap();
+        let new_proxy = add_box(&mut scene, new_node);
+        assert_eq!((new_node.index, new_proxy.index), (node.index, proxy.index));
+
+        assert!(scene.get_node(node).is_none());
+        assert!(scene.get_world_transform(node).is_none());
+        assert_eq!(scene.set_local_transform(node, Transform::default()), Err(SceneError::InvalidNode(node)));
+        assert_eq!(scene.create_node("child", Some(node)), Err(SceneError::InvalidNode(node)));
+        assert!(scene.get_scene_proxy(proxy).is_none() && scene.get_scene_proxy_node(proxy).is_none());
+        assert!(scene.remove_scene_proxy(proxy).is_none());
+        assert_eq!(scene.get_node(new_node).unwrap().get_name(), "new");
+    }
+
+    #[test]
+    fn mutated_proxies_are_found_after_a_refit()
+    {
+        let mut scene = Scene::new();
+        let node = scene.create_node("box", None).unwrap();
+        let mut static_mesh = StaticMesh::new("box");
+        *static_mesh.get_mesh_mut() = Mesh::create_box(Vector3::new(1.0, 1.0, 1.0), 1);
+        let proxy = scene.add_scene_proxy(node, Box::new(static_mesh)).unwrap();
+        let mut bvh = SceneBvh::build(&scene);
+        let ray = Ray::new(Vector3::new(5.0, 0.0, -20.0), Vector3::unit_z());
+        assert!(bvh.cast_ray(&ray, f32::MAX).is_none());
+
+        assert!(scene.get_scene_proxy_mut_as::<SkeletalMesh>(proxy).is_none());
+        let static_mesh = scene.get_scene_proxy_mut_as::<StaticMesh>(proxy).unwrap();
+        *static_mesh.get_mesh_mut() = Mesh::create_box(Vector3::new(20.0, 20.0, 20.0), 1);
+        assert_eq!(scene.get_scene_proxy_as::<StaticMesh>(proxy).unwrap().get_name(), "box");
+        assert!(bvh.refit_scene_proxy(&scene, proxy));
+        assert_eq!(bvh.cast_ray(&ray, f32::MAX).map(|hit| hit.proxy), Some(proxy));
+    }
+}
+
@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+
+use cgmath::Vector3;
+
+use crate::scene::culling::*;
+use crate::scene::mesh::bounds::*;
+use crate::scene::mesh::bvh::*;
+use crate::scene::scene::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SceneRayHit
+{
+    pub proxy: SceneProxyHandle,
+    pub distance: f32
+}
+
+/// Bounding volume hierarchy over the world space boxes of the scene proxies. Moved proxies are handled by refitting,
+/// added and removed ones need a new build.
+#[derive(Clone, Debug, Default)]
+pub struct SceneBvh
+{
+    nodes: Vec<BvhNode>,
+    // Items in leaf order.
+    proxies: Vec<SceneProxyHandle>,
+    proxy_boxes: Vec<BoundingBox>,
+    proxy_leaves: Vec<u32>,
+    proxy_items: HashMap<SceneProxyHandle, u32>
+}
+
+fn get_scene_proxy_box(scene: &Scene, proxy: SceneProxyHandle) -> BoundingBox
+{
+    scene.get_scene_proxy_world_bounds(proxy).map(|bounds| bounds.bounding_box).unwrap_or_default()
+}
+
+impl SceneBvh
+{
+    /// Builds the hierarchy with binned surface area heuristic splits.
+    pub fn build(scene: &Scene) -> Self
+    {
+        let mut bvh = SceneBvh::default();
+        for (proxy, _) in scene.get_scene_proxies()
+        {
+            bvh.proxies.push(proxy);
+            bvh.proxy_boxes.push(get_scene_proxy_box(scene, proxy));
+        }
+        let (nodes, order) = build_bvh(&bvh.proxy_boxes);
+        bvh.nodes = nodes;
+        bvh.proxies = order.iter().map(|item| bvh.proxies[*item]).collect();
+        bvh.proxy_boxes = order.iter().map(|item| bvh.proxy_boxes[*item]).collect();
+        bvh.proxy_leaves = vec![0; order.len()];
+        for (node_index, node) in bvh.nodes.iter().enumerate().filter(|(_, node)| node.count > 0)
+        {
+            for item in node.first..node.first + node.count
+            {
+                bvh.proxy_leaves[item as usize] = node_index as u32;
+            }
+        }
+        bvh.proxy_items = bvh.proxies.iter().enumerate().map(|(item, proxy)| (*proxy, item as u32)).collect();
+        bvh
+    }
+
+    pub fn get_proxy_count(&self) -> usize
+    {
+        self.proxies.len()
+    }
+
+    pub fn get_bounds(&self) -> BoundingBox
+    {
+        self.nodes.first().map(|root| root.bounding_box).unwrap_or_default()
+    }
+
+    fn update_node_box(&mut self, node: usize)
+    {
+        let BvhNode { first, count, .. } = self.nodes[node];
+        self.nodes[node].bounding_box = if count > 0
+        {
+            self.proxy_boxes[first as usize..(first + count) as usize].iter().fold(BoundingBox::default(), |bounds, proxy_box| bounds.union(proxy_box))
+        }
+        else
+        {
+            self.nodes[first as usize].bounding_box.union(&self.nodes[first as usize + 1].bounding_box)
+        };
+    }
+
+    /// Reads the world bounds of every proxy again and refits the nodes above the ones that moved. Returns the number
+    /// of moved proxies. Removed proxies get empty boxes and are never found.
+    pub fn refit(&mut self, scene: &Scene) -> usize
+    {
+        let mut is_dirty = vec![false; self.nodes.len()];
+        let mut moved_count = 0;
+        for item in 0..self.proxies.len()
+        {
+            let bounding_box = get_scene_proxy_box(scene, self.proxies[item]);
+            if bounding_box != self.proxy_boxes[item]
+            {
+                self.proxy_boxes[item] = bounding_box;
+                is_dirty[self.proxy_leaves[item] as usize] = true;
+                moved_count += 1;
+            }
+        }
+        // Children come after their parents, walking backwards finishes every child before its parent.
+        for node in (0..self.nodes.len()).rev()
+        {
+            if is_dirty[node]
+            {
+                self.update_node_box(node);
+                if self.nodes[node].parent != NO_PARENT
+                {
+                    is_dirty[self.nodes[node].parent as usize] = true;
+                }
+            }
+        }
+        moved_count
+    }
+
+    /// Refits the path of a single proxy, cheaper than refit when only a few proxies moved or one was changed through
+    /// Scene::get_scene_proxy_mut. False for proxies that were not in the scene at build time.
+    pub fn refit_scene_proxy(&mut self, scene: &Scene, proxy: SceneProxyHandle) -> bool
+    {
+        let Some(item) = self.proxy_items.get(&proxy).map(|item| *item as usize) else { return false };
+        self.proxy_boxes[item] = get_scene_proxy_box(scene, proxy);
+        let mut node = self.proxy_leaves[item];
+        while node != NO_PARENT
+        {
+            self.update_node_box(node as usize);
+            node = self.nodes[node as usize].parent;
+        }
+        true
+    }
+
+    // Calls visit for every proxy whose box passes the test, node boxes are tested with the same function.
+    fn query(&self, is_overlapping: impl Fn(&BoundingBox) -> bool, mut visit: impl FnMut(SceneProxyHandle))
+    {
+        if self.nodes.is_empty()
+        {
+            return;
+        }
+        let mut pending = vec![0];
+        while let Some(node) = pending.pop()
+        {
+            let node = &self.nodes[node];
+            if !is_overlapping(&node.bounding_box)
+            {
+                continue;
+            }
+            if node.count == 0
+            {
+                pending.extend_from_slice(&[node.first as usize, node.first as usize + 1]);
+                continue;
+            }
+            for item in node.first as usize..(node.first + node.count) as usize
+            {
+                if is_overlapping(&self.proxy_boxes[item])
+                {
+                    visit(self.proxies[item]);
+                }
+            }
+        }
+    }
+
+    pub fn query_box(&self, bounding_box: &BoundingBox) -> Vec<SceneProxyHandle>
+    {
+        let mut proxies = vec![];
+        self.query(|node_box| node_box.intersects(bounding_box), |proxy| proxies.push(proxy));
+        proxies
+    }
+
+    pub fn query_sphere(&self, center: Vector3<f32>, radius: f32) -> Vec<SceneProxyHandle>
+    {
+        let mut proxies = vec![];
+        self.query(|node_box| node_box.intersects_sphere(center, radius), |proxy| proxies.push(proxy));
+        proxies
+    }
+
+    /// Frustum culling through the hierarchy, finds the same proxies as Scene::cull_scene_proxies. Nodes completely
+    /// inside the frustum accept all of their proxies without testing them. Every proxy of the build counts as tested.
+    pub fn cull(&self, frustum: &ViewFrustum) -> ViewVisibility
+    {
+        let mut visibility = ViewVisibility::default();
+        let mut pending = if self.nodes.is_empty() { vec![] } else { vec![(0, false)] };
+        while let Some((node, is_inside)) = pending.pop()
+        {
+            let node = &self.nodes[node];
+            let is_inside = is_inside || frustum.contains_box(&node.bounding_box);
+            if !is_inside && !frustum.is_box_visible(&node.bounding_box)
+            {
+                continue;
+            }
+            if node.count == 0
+            {
+                pending.extend_from_slice(&[(node.first as usize, is_inside), (node.first as usize + 1, is_inside)]);
+                continue;
+            }
+            for item in node.first as usize..(node.first + node.count) as usize
+            {
+                // An empty box inside a node that is inside still must not count as visible.
+                let proxy_box = &self.proxy_boxes[item];
+                if (is_inside && !proxy_box.is_empty()) || frustum.is_box_visible(proxy_box)
+                {
+                    visibility.visible_proxies.push(self.proxies[item]);
+                }
+            }
+        }
+        let tested_count = self.proxies.len() as u32;
+        let visible_count = visibility.visible_proxies.len() as u32;
+        visibility.statistics = CullingStatistics { tested_count, visible_count, culled_count: tested_count - visible_count };
+        visibility
+    }
+
+    /// Closest proxy box hit by the ray within max_distance.
+    pub fn cast_ray(&self, ray: &Ray, max_distance: f32) -> Option<SceneRayHit>
+    {
+        self.cast_ray_with(ray, max_distance, |_, box_distance, _| Some(box_distance))
+    }
+
+    /// Closest hit with a custom test for the proxies whose boxes the ray enters. intersect gets the proxy, the
+    /// distance where the ray enters its box and the distance of the closest hit so far, and returns the distance of
+    /// its own hit. Nodes are visited front to back and skipped once they start behind the closest hit.
+    pub fn cast_ray_with(&self, ray: &Ray, max_distance: f32, mut intersect: impl FnMut(SceneProxyHandle, f32, f32) -> Option<f32>) -> Option<SceneRayHit>
+    {
+        let (item, distance) = cast_ray_bvh(&self.nodes, ray, max_distance, |item, closest_distance| {
+            let box_distance = self.proxy_boxes[item].intersect_ray(ray, closest_distance)?;
+            intersect(self.proxies[item], box_distance, closest_distance)
+        })?;
+        Some(SceneRayHit { proxy: self.proxies[item], distance })
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::collections::HashSet;
+
+    use cgmath::{Deg, InnerSpace};
+
+    use super::*;
+    use crate::scene::camera::*;
+    use crate::scene::mesh::Mesh;
+    use crate::scene::static_mesh::StaticMesh;
+    use crate::scene::transform::Transform;
+
+    // Boxes of different sizes scattered over a few units, and one proxy without bounds.
+    fn create_scene() -> (Scene, Vec<SceneNodeHandle>)
+    {
+        let mut scene = Scene::new();
+        let mut nodes = vec![];
+        for index in 0..48
+        {
+            let node = scene.create_node(&format!("box_{}", index), None).unwrap();
+            let position = Vector3::new((index * 7 % 13) as f32 - 6.0, (index * 5 % 11) as f32 - 5.0, (index * 3 % 17) as f32);
+            let scale = 0.5 + (index % 4) as f32 * 0.5;
+            scene.set_local_transform(node, Transform { translation: position, scale: Vector3::new(scale, scale, scale), ..Default::default() }).unwrap();
+            let mut static_mesh = StaticMesh::new("box");
+            *static_mesh.get_mesh_mut() = Mesh::create_box(Vector3::new(1.0, 1.0, 1.0), 1);
+            scene.add_scene_proxy(node, Box::new(static_mesh)).unwrap();
+            nodes.push(node);
+        }
+        let empty_node = scene.create_node("empty", None).unwrap();
+        scene.add_scene_proxy(empty_node, Box::new(StaticMesh::new("empty"))).unwrap();
+        (scene, nodes)
+    }
+
+    fn find_proxies(scene: &Scene, is_overlapping: impl Fn(&BoundingBox) -> bool) -> HashSet<SceneProxyHandle>
+    {
+        scene.get_scene_proxies().map(|(proxy, _)| proxy).filter(|proxy| is_overlapping(&get_scene_proxy_box(scene, *proxy))).collect()
+    }
+
+    fn get_box(center: Vector3<f32>, half_extent: f32) -> BoundingBox
+    {
+        let half_extents = Vector3::new(half_extent, half_extent, half_extent);
+        BoundingBox { min: center - half_extents, max: center + half_extents }
+    }
+
+    #[test]
+    fn queries_match_a_brute_force_scan()
+    {
+        let (scene, _) = create_scene();
+        let bvh = SceneBvh::build(&scene);
+        assert_eq!(bvh.get_proxy_count(), 49);
+        for (center, size) in [(Vector3::new(0.0, 0.0, 8.0), 2.0), (Vector3::new(-5.0, 4.0, 1.0), 1.0), (Vector3::new(30.0, 0.0, 0.0), 5.0), (Vector3::new(0.0, 0.0, 8.0), 50.0)]
+        {
+            let query_box = get_box(center, size);
+            let found: HashSet<SceneProxyHandle> = bvh.query_box(&query_box).into_iter().collect();
+            assert_eq!(found, find_proxies(&scene, |proxy_box| proxy_box.intersects(&query_box)));
+            let found: HashSet<SceneProxyHandle> = bvh.query_sphere(center, size).into_iter().collect();
+            assert_eq!(found, find_proxies(&scene, |proxy_box| proxy_box.intersects_sphere(center, size)));
+        }
+    }
+
+    #[test]
+    fn culling_finds_the_proxies_of_the_scene_test()
+    {
+        let (scene, _) = create_scene();
+        let bvh = SceneBvh::build(&scene);
+        for (eye, target) in [(Vector3::new(0.0, 0.0, -10.0), Vector3::new(0.0, 0.0, 8.0)), (Vector3::new(-3.0, 1.0, 4.0), Vector3::new(5.0, -2.0, 12.0))]
+        {
+            let mut camera = Camera::new(Projection::Perspective { vertical_fov: Deg(40.0).into(), near: 0.1, far: 20.0 }, 1.5);
+            camera.look_at(eye, target, Vector3::unit_y());
+            let frustum = ViewFrustum::from_camera(&camera);
+            let (bvh_visibility, scene_visibility) = (bvh.cull(&frustum), scene.cull_scene_proxies(&frustum));
+            let visible: HashSet<SceneProxyHandle> = bvh_visibility.visible_proxies.iter().copied().collect();
+            assert_eq!(visible.len(), bvh_visibility.visible_proxies.len());
+            assert_eq!(visible, scene_visibility.visible_proxies.iter().copied().collect());
+            assert_eq!(bvh_visibility.statistics, scene_visibility.statistics);
+            assert!(bvh_visibility.statistics.visible_count > 0 && bvh_visibility.statistics.culled_count > 0);
+        }
+    }
+
+    #[test]
+    fn refit_follows_moved_nodes()
+    {
+        let (mut scene, nodes) = create_scene();
+        let mut bvh = SceneBvh::build(&scene);
+        let proxy = scene.get_node(nodes[5]).unwrap().get_scene_proxies()[0];
+        let far_away = Vector3::new(100.0, 0.0, 0.0);
+        scene.set_local_transform(nodes[5], Transform::from_translation(far_away)).unwrap();
+        assert!(!bvh.query_sphere(far_away, 1.0).contains(&proxy));
+
+        assert_eq!(bvh.refit(&scene), 1);
+        assert_eq!(bvh.query_sphere(far_away, 1.0), vec![proxy]);
+        assert!(bvh.get_bounds().max.x >= 100.5);
+        let query_box = get_box(Vector3::new(0.0, 0.0, 8.0), 50.0);
+        let found: HashSet<SceneProxyHandle> = bvh.query_box(&query_box).into_iter().collect();
+        assert_eq!(found, find_proxies(&scene, |proxy_box| proxy_box.intersects(&query_box)));
+        assert_eq!(bvh.refit(&scene), 0);
+    }
+
+    #[test]
+    fn rays_hit_the_nearest_proxy()
+    {
+        let (scene, _) = create_scene();
+        let bvh = SceneBvh::build(&scene);
+        for ray in [
+            Ray::new(Vector3::new(0.0, 0.0, -20.0), Vector3::unit_z()),
+            Ray::new(Vector3::new(-20.0, 1.0, 8.0), Vector3::new(1.0, 0.1, 0.05).normalize()),
+            Ray::new(Vector3::new(3.0, 3.0, 30.0), Vector3::new(-0.2, -0.3, -1.0).normalize())
+        ]
+        {
+            let expected = scene.get_scene_proxies()
+                .filter_map(|(proxy, _)| get_scene_proxy_box(&scene, proxy).intersect_ray(&ray, f32::MAX).map(|distance| (proxy, distance)))
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            let hit = bvh.cast_ray(&ray, f32::MAX);
+            assert_eq!(hit.map(|hit| (hit.proxy, hit.distance)), expected);
+            assert!(expected.is_some());
+            // Hits beyond max_distance are ignored.
+            let (_, distance) = expected.unwrap();
+            assert!(bvh.cast_ray(&ray, distance * 0.5).is_none());
+        }
+    }
+}
+
@@ -1,12 +1,34 @@
+use std::any::Any;
+
+use cgmath::Matrix4;
+
 use crate::scene::mesh::*;
+use crate::scene::mesh::bounds::*;
+use crate::scene::mesh::picking::MeshRayHit;
+use crate::scene::mesh_material::*;
 
+// One section of a proxy, drawn with a single material.
 pub struct MeshBatch<'a>
 {
     pub mesh: &'a Mesh,
-    pub mesh_index_in_gpu_scene: u32
+    // Index range and base vertex in the proxy's uploaded buffers.
+    pub section: MeshSection,
+    pub material: Option<&'a MeshMaterial>,
+    pub mesh_index_in_gpu_scene: u32,
+    // Local to world, for the per object constants. Proxies return identity, Scene::generate_mesh_batches fills in
+    // the transform of the node. A negative determinant mirrors the geometry, the front face winding flips.
+    pub world_transform: Matrix4<f32>
 }
 
-pub trait SceneProxy
+// Any lets the scene hand out proxies as their concrete type, see Scene::get_scene_proxy_mut_as.
+pub trait SceneProxy: Any
 {
-    fn generate_mesh_batch<'a>(&'a self) -> MeshBatch<'a>;
+    fn generate_mesh_batches<'a>(&'a self) -> Vec<MeshBatch<'a>>;
+    // Local space bounds of the proxy's geometry.
+    fn get_bounds(&self) -> MeshBounds;
+    // Closest hit of a local space ray with the proxy's triangles, for picking. Proxies without triangles are never hit.
+    fn intersect_ray(&self, _ray: &Ray, _max_distance: f32) -> Option<MeshRayHit>
+    {
+        None
+    }
 }
\ No newline at end of file
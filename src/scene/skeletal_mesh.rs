@@ -0,0 +1,169 @@
+use cgmath::{InnerSpace, Matrix4, Vector3};
+
+use crate::scene::scene_proxy::*;
+use crate::scene::static_mesh::*;
+use crate::scene::mesh::*;
+use crate::scene::mesh::bounds::*;
+use crate::scene::mesh::picking::MeshRayHit;
+use crate::scene::mesh::skinning::*;
+use crate::scene::mesh::validation::*;
+use crate::scene::mesh_material::*;
+use crate::d3d12_wrapper::d3d12_device::*;
+
+/// A mesh deformed by a skeleton. The render data is a static mesh in bind pose with bone indices and weights, meant
+/// to be skinned on the GPU with get_skinning_matrices. Mesh::skin is the CPU reference. No vertex factory skins yet,
+/// so until one does the proxy draws the bind pose, while picking sees the current pose.
+#[derive(Default)]
+pub struct SkeletalMesh
+{
+    render_mesh: StaticMesh,
+    skeleton: Skeleton,
+    // Local transform of every bone.
+    pose: Vec<Matrix4<f32>>,
+    // Bind pose bounds of the vertices of every bone, see update_bounds.
+    bone_bounds: Vec<BoundingBox>
+}
+
+impl SkeletalMesh
+{
+    pub fn new(name: &str) -> Self
+    {
+        SkeletalMesh { render_mesh: StaticMesh::new(name), ..Default::default() }
+    }
+
+    /// Takes the mesh, materials and levels of detail of render_mesh, which needs a bone index and weight per vertex.
+    pub fn from_static_mesh(render_mesh: StaticMesh, skeleton: Skeleton) -> Result<Self, MeshError>
+    {
+        let mut skeletal_mesh = SkeletalMesh { render_mesh, ..Default::default() };
+        skeletal_mesh.set_skeleton(skeleton);
+        skeletal_mesh.update_bounds()?;
+        Ok(skeletal_mesh)
+    }
+
+    pub fn get_name(&self) -> &str
+    {
+        self.render_mesh.get_name()
+    }
+
+    pub fn get_mesh(&self) -> &Mesh
+    {
+        self.render_mesh.get_mesh()
+    }
+
+    pub fn get_render_mesh(&self) -> &StaticMesh
+    {
+        &self.render_mesh
+    }
+
+    // Call update_bounds after changing the vertices.
+    pub fn get_render_mesh_mut(&mut self) -> &mut StaticMesh
+    {
+        &mut self.render_mesh
+    }
+
+    pub fn add_material(&mut self, material: MeshMaterial) -> usize
+    {
+        self.render_mesh.add_material(material)
+    }
+
+    pub fn get_materials(&self) -> &Vec<MeshMaterial>
+    {
+        self.render_mesh.get_materials()
+    }
+
+    /// Replaces the skeleton and resets the pose to its rest pose.
+    pub fn set_skeleton(&mut self, skeleton: Skeleton)
+    {
+        self.pose = skeleton.get_rest_pose();
+        self.skeleton = skeleton;
+    }
+
+    pub fn get_skeleton(&self) -> &Skeleton
+    {
+        &self.skeleton
+    }
+
+    pub fn get_pose(&self) -> &Vec<Matrix4<f32>>
+    {
+        &self.pose
+    }
+
+    pub fn set_pose(&mut self, pose: Vec<Matrix4<f32>>)
+    {
+        assert_eq!(pose.len(), self.skeleton.get_bone_count(), "pose needs one transform per bone");
+        self.pose = pose;
+    }
+
+    pub fn set_bone_transform(&mut self, bone: usize, local_transform: Matrix4<f32>)
+    {
+        self.pose[bone] = local_transform;
+    }
+
+    pub fn reset_pose(&mut self)
+    {
+        self.pose = self.skeleton.get_rest_pose();
+    }
+
+    pub fn get_skinning_matrices(&self) -> Vec<Matrix4<f32>>
+    {
+        self.skeleton.get_skinning_matrices(&self.pose)
+    }
+
+    /// The base mesh morphed with the morph weights of the render mesh, then skinned with the current pose on the CPU.
+    pub fn get_skinned_mesh(&self) -> Result<Mesh, MeshError>
+    {
+        self.render_mesh.get_morphed_mesh().skin(&self.get_skinning_matrices())
+    }
+
+    /// Recomputes the bind pose bounds of the mesh and its bones, needed after the vertices or the skeleton changed.
+    pub fn update_bounds(&mut self) -> Result<(), MeshError>
+    {
+        self.bone_bounds = self.get_mesh().compute_bone_bounds(self.skeleton.get_bone_count())?;
+        self.render_mesh.get_mesh_mut().update_bounds(false);
+        Ok(())
+    }
+
+    pub fn generate_gpu_resource(&mut self, g_device: &Device) -> Result<(), StaticMeshError>
+    {
+        self.render_mesh.generate_gpu_resource(g_device)
+    }
+}
+
+impl SceneProxy for SkeletalMesh
+{
+    // The bind pose, GPU skinning does not exist yet.
+    fn generate_mesh_batches<'a>(&'a self) -> Vec<MeshBatch<'a>>
+    {
+        self.render_mesh.generate_mesh_batches()
+    }
+
+    // Bounds of the current pose from the bone boxes, looser than the skinned vertices but without skinning them.
+    // They include the drawn bind pose so that culling never drops it.
+    fn get_bounds(&self) -> MeshBounds
+    {
+        let morph_extent = self.render_mesh.get_morph_extent();
+        let morph_offset = Vector3::new(morph_extent, morph_extent, morph_extent);
+        let bind_pose_bounds = self.render_mesh.get_bounds();
+        let mut bounding_box = bind_pose_bounds.bounding_box;
+        for (bone_box, skinning_matrix) in self.bone_bounds.iter().zip(self.get_skinning_matrices())
+        {
+            if !bone_box.is_empty()
+            {
+                let morphed_box = BoundingBox { min: bone_box.min - morph_offset, max: bone_box.max + morph_offset };
+                bounding_box = bounding_box.union(&morphed_box.transform(&skinning_matrix));
+            }
+        }
+        if bounding_box == bind_pose_bounds.bounding_box
+        {
+            return bind_pose_bounds;
+        }
+        let bounding_sphere = BoundingSphere { center: bounding_box.get_center(), radius: bounding_box.get_half_extents().magnitude() };
+        MeshBounds { bounding_box, bounding_sphere, oriented_bounding_box: None }
+    }
+
+    // Skins the mesh on the CPU and tests every triangle, the pose changes too often to keep a hierarchy.
+    fn intersect_ray(&self, ray: &Ray, max_distance: f32) -> Option<MeshRayHit>
+    {
+        self.get_skinned_mesh().ok()?.intersect_ray(ray, max_distance)
+    }
+}
@@ -1,6 +1,7 @@
 use crate::d3d12_buffer::IndexBufferView;
 use crate::d3d12_buffer::VertexBufferView;
 use crate::d3d12_common::ByteCount;
+use crate::d3d12_common::DxError;
 use crate::d3d12_enum::Format;
 use crate::d3d12_enum::ResourceDimension;
 use crate::d3d12_enum::ResourceStates;
@@ -8,35 +9,116 @@ use crate::d3d12_texture::TextureLayout;
 use crate::d3d12_window::*;
 use crate::scene::scene_proxy::*;
 use crate::scene::mesh::*;
+use crate::scene::mesh::simplify::*;
+use crate::scene::mesh::bounds::*;
+use crate::scene::mesh::picking::*;
+use crate::scene::mesh::validation::*;
+use crate::scene::mesh::vertex_format::*;
+use crate::scene::mesh_material::*;
 use crate::d3d12_resource::*;
 use crate::D3D12_HEAP_PROPERTIES;
 use crate::d3d12_wrapper::d3d12_device::*;
 use crate::d3d12_wrapper::d3d12_command::*;
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use cgmath::{Matrix4, SquareMatrix, Vector3};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum StaticMeshError
+{
+    #[error("invalid mesh data: {0}")]
+    Mesh(#[from] MeshError),
+    #[error(transparent)]
+    Device(#[from] DxError)
+}
+
+// Where a level of detail lives in the uploaded buffers, its indices are relative to base_vertex.
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct StaticMeshLodRange
+{
+    pub first_index: u32,
+    pub index_count: u32,
+    pub base_vertex: i32,
+    pub vertex_count: u32
+}
+
+/// Buffer contents of every level of detail, borrowed when they come straight from a mesh cache file.
+#[derive(Clone, Debug, Default)]
+pub struct StaticMeshGpuData<'a>
+{
+    // The vertex streams one after another, stream_sizes bytes each.
+    pub vertex_data: Cow<'a, [u8]>,
+    pub stream_sizes: Vec<u32>,
+    pub strides: Vec<u32>,
+    pub index_data: Cow<'a, [u8]>,
+    // 2 for R16Uint indices, 4 for R32Uint.
+    pub index_stride: u32,
+    pub lod_ranges: Vec<StaticMeshLodRange>
+}
 
 #[derive(Default)]
 pub struct StaticMesh
 {
-    name: &'static str,
+    name: String,
     mesh: Mesh,
+    // Indexed by the material slot of the mesh sections.
+    materials: Vec<MeshMaterial>,
+    // Levels after the base mesh, which is LOD 0. Their sections index into the level's own index buffer.
+    lods: Vec<MeshLod>,
+    lod_ranges: Vec<StaticMeshLodRange>,
+    // One per morph target of the base mesh, missing weights count as zero.
+    morph_weights: Vec<f32>,
+    // Built by the first intersect_ray, dropped whenever the base mesh can change.
+    triangle_bvh: OnceCell<MeshTriangleBvh>,
+    // Bounds of the base mesh without morphing, kept by the first get_bounds and dropped with triangle_bvh.
+    bounds: OnceCell<MeshBounds>,
 
     vertex_buffer_resource: Resource,
     index_buffer_resource: Resource,
-    vertex_buffer_view: VertexBufferView,
+    // One view per vertex stream, bound to the input slot of its index.
+    vertex_buffer_views: Vec<VertexBufferView>,
     index_buffer_view: IndexBufferView
 }
 
 impl StaticMesh
 {
-    pub fn new(name: &'static str) -> Self
+    pub fn new(name: &str) -> Self
     {
         let mut static_mesh = StaticMesh::default();
-        static_mesh.name = name;
+        static_mesh.name = name.to_string();
         static_mesh        
     }
 
-    pub fn add_channel_data(&mut self, channel: MeshDataChannel, mut data: Vec<f32>)
+    pub fn get_name(&self) -> &str
     {
-        let channel_val = channel as usize;
+        &self.name
+    }
+
+    pub fn get_mesh(&self) -> &Mesh
+    {
+        &self.mesh
+    }
+
+    // Drops everything derived from the geometry of the base mesh, including the bounds cached in the mesh itself.
+    fn invalidate_geometry(&mut self)
+    {
+        self.triangle_bvh.take();
+        self.bounds.take();
+        self.mesh.mesh_bounds = None;
+    }
+
+    // The cached bounds of the mesh are dropped, call Mesh::update_bounds after the changes to keep an oriented box.
+    pub fn get_mesh_mut(&mut self) -> &mut Mesh
+    {
+        self.invalidate_geometry();
+        &mut self.mesh
+    }
+
+    pub fn add_channel_data(&mut self, channel: impl Into<usize>, mut data: Vec<f32>)
+    {
+        let channel_val = channel.into();
+        self.invalidate_geometry();
         if self.mesh.mesh_channel_data.contains_key(&channel_val)
         {
             self.mesh.mesh_channel_data.get_mut(&channel_val).unwrap().append(&mut data);
@@ -49,22 +131,206 @@ impl StaticMesh
 
     pub fn set_index_buffer(&mut self, index_buffer: Vec<u32>)
     {
+        self.invalidate_geometry();
         self.mesh.mesh_index_data = index_buffer;
     }
 
-    pub fn generate_gpu_resource(&mut self, g_device: &Device)
+    pub fn add_section(&mut self, section: MeshSection)
+    {
+        self.mesh.add_section(section);
+    }
+
+    pub fn get_sections(&self) -> Vec<MeshSection>
+    {
+        self.mesh.get_sections()
+    }
+
+    pub fn add_material(&mut self, material: MeshMaterial) -> usize
+    {
+        self.materials.push(material);
+        self.materials.len() - 1
+    }
+
+    pub fn get_materials(&self) -> &Vec<MeshMaterial>
+    {
+        &self.materials
+    }
+
+    /// Replaces the reduced levels with lod_count - 1 simplified copies of the base mesh, each with triangle_ratio
+    /// times the triangles of the previous level. Sections are simplified together with their borders kept in place.
+    pub fn generate_lods(&mut self, lod_count: usize, triangle_ratio: f32)
+    {
+        let mut lods = self.mesh.generate_lod_chain(lod_count, triangle_ratio);
+        lods.remove(0);
+        self.lods = lods;
+    }
+
+    pub fn add_lod(&mut self, lod: MeshLod)
+    {
+        self.lods.push(lod);
+    }
+
+    pub fn get_lod_count(&self) -> usize
+    {
+        self.lods.len() + 1
+    }
+
+    pub fn get_lod_mesh(&self, lod: usize) -> &Mesh
+    {
+        if lod == 0 { &self.mesh } else { &self.lods[lod - 1].mesh }
+    }
+
+    pub fn get_lod_sections(&self, lod: usize) -> Vec<MeshSection>
+    {
+        self.get_lod_mesh(lod).get_sections()
+    }
+
+    // Sections of a level with the offsets of the uploaded buffers, as passed to draw_indexed_instanced.
+    pub fn get_lod_draw_sections(&self, lod: usize) -> Vec<MeshSection>
+    {
+        let mut sections = self.get_lod_sections(lod);
+        if let Some(lod_range) = self.lod_ranges.get(lod)
+        {
+            for section in sections.iter_mut()
+            {
+                section.first_index += lod_range.first_index;
+                section.base_vertex += lod_range.base_vertex;
+            }
+        }
+        sections
+    }
+
+    pub fn get_lod_error(&self, lod: usize) -> f32
+    {
+        if lod == 0 { 0.0 } else { self.lods[lod - 1].error }
+    }
+
+    pub fn set_morph_weights(&mut self, weights: Vec<f32>)
+    {
+        self.morph_weights = weights;
+    }
+
+    pub fn set_morph_weight(&mut self, target: usize, weight: f32)
+    {
+        if self.morph_weights.len() <= target
+        {
+            self.morph_weights.resize(target + 1, 0.0);
+        }
+        self.morph_weights[target] = weight;
+    }
+
+    pub fn get_morph_weights(&self) -> &Vec<f32>
+    {
+        &self.morph_weights
+    }
+
+    /// The base mesh with the morph weights applied on the CPU.
+    pub fn get_morphed_mesh(&self) -> Mesh
+    {
+        self.mesh.apply_morph_targets(&self.morph_weights)
+    }
+
+    // Upper bound of how far the current morph weights move any vertex.
+    pub fn get_morph_extent(&self) -> f32
+    {
+        self.mesh.mesh_morph_targets.iter()
+            .zip(&self.morph_weights)
+            .filter(|(_, weight)| **weight != 0.0)
+            .map(|(target, weight)| weight.abs() * target.get_max_position_delta())
+            .sum()
+    }
+
+    // Call before generate_gpu_resource, the vertex factory input layout has to use the same streams.
+    pub fn set_vertex_stream_layout(&mut self, stream_layout: VertexStreamLayout)
+    {
+        self.mesh.set_vertex_stream_layout(stream_layout);
+    }
+
+    // Every level of detail is packed with the channel formats and streams of the base mesh.
+    pub fn get_vertex_layout(&self) -> VertexLayout
+    {
+        self.mesh.get_vertex_layout()
+    }
+
+    // Filled by generate_gpu_resource, one range per level of detail.
+    pub fn get_lod_ranges(&self) -> &Vec<StaticMeshLodRange>
+    {
+        &self.lod_ranges
+    }
+
+    /// Packs the vertex and index buffers of every level of detail the way generate_gpu_resource uploads them.
+    pub fn get_gpu_data(&self) -> Result<StaticMeshGpuData<'static>, MeshError>
+    {
+        // All levels of detail share one vertex and one index buffer, the vertex streams are stored one after another.
+        let vertex_layout = self.get_vertex_layout();
+        let mut stream_data: Vec<Vec<u8>> = vec![vec![]; vertex_layout.get_stream_count()];
+        let mut vertex_size = 0;
+        let mut max_lod_vertex_count = 0;
+        let mut index_buffer_data_32 = vec![];
+        let mut lod_ranges = vec![];
+        for lod in 0..self.get_lod_count()
+        {
+            let mesh = self.get_lod_mesh(lod);
+            let (lod_vertex_data, lod_vertex_count) = mesh.get_vertex_buffer_data_with_layout(&vertex_layout)?;
+            // Triangle soups are drawn indexed like every other mesh, their sections count one index per vertex.
+            let lod_indices = mesh.get_triangle_indices();
+            lod_ranges.push(StaticMeshLodRange
+            {
+                first_index: index_buffer_data_32.len() as u32,
+                index_count: lod_indices.len() as u32,
+                base_vertex: vertex_size as i32,
+                vertex_count: lod_vertex_count
+            });
+            for (data, lod_data) in stream_data.iter_mut().zip(lod_vertex_data)
+            {
+                data.extend(lod_data);
+            }
+            vertex_size += lod_vertex_count;
+            max_lod_vertex_count = max_lod_vertex_count.max(lod_vertex_count);
+            index_buffer_data_32.extend(lod_indices);
+        }
+
+        // Indices are relative to the base vertex of their level, so 16 bits suffice while every level does.
+        let (index_data, index_stride) = if max_lod_vertex_count < u16::MAX as u32
+        {
+            (index_buffer_data_32.iter().flat_map(|index| (*index as u16).to_le_bytes()).collect(), 2)
+        }
+        else
+        {
+            (index_buffer_data_32.iter().flat_map(|index| index.to_le_bytes()).collect(), 4)
+        };
+        Ok(StaticMeshGpuData
+        {
+            stream_sizes: stream_data.iter().map(|data| data.len() as u32).collect(),
+            strides: vertex_layout.strides,
+            vertex_data: Cow::Owned(stream_data.concat()),
+            index_data: Cow::Owned(index_data),
+            index_stride,
+            lod_ranges
+        })
+    }
+
+    pub fn generate_gpu_resource(&mut self, g_device: &Device) -> Result<(), StaticMeshError>
+    {
+        let gpu_data = self.get_gpu_data()?;
+        self.upload_gpu_data(g_device, &gpu_data)
+    }
+
+    /// Uploads buffers packed by get_gpu_data, for example ones loaded from a mesh cache. They have to match the
+    /// vertex layout and levels of detail of this mesh.
+    pub fn upload_gpu_data(&mut self, g_device: &Device, gpu_data: &StaticMeshGpuData) -> Result<(), StaticMeshError>
     {
         let copy_comand_list = G_COPY_COMMAND_LIST.lock().unwrap();
-        let (vertex_data, vertex_size) = self.mesh.get_vertex_buffer_data();
-        
+        self.lod_ranges = gpu_data.lod_ranges.clone();
+        let vertex_data = &gpu_data.vertex_data;
+
         let vertex_buffer_size = ByteCount::from(
-            vertex_data.len() * std::mem::size_of::<f32>(),
+            vertex_data.len(),
         );
 
-        let vertex_staging_buffer = g_device.create_staging_buffer(vertex_buffer_size).expect("Cannot create staging buffer");
+        let vertex_staging_buffer = g_device.create_staging_buffer(vertex_buffer_size)?;
         let data = vertex_staging_buffer
-            .map(0, None)
-            .expect("Cannot map staging buffer");
+            .map(0, None)?;
 
         unsafe {
             std::ptr::copy_nonoverlapping(
@@ -75,7 +341,7 @@ impl StaticMesh
         }
         vertex_staging_buffer.unmap(0, None);
 
-        let vertex_default_buffer = g_device.create_default_buffer(vertex_buffer_size).expect("Cannot create default buffer");
+        let vertex_default_buffer = g_device.create_default_buffer(vertex_buffer_size)?;
         copy_comand_list.add_resource_barrier(&vertex_default_buffer, ResourceStates::Common, ResourceStates::CopyDest);
 
         copy_comand_list.copy_buffer_region(
@@ -87,53 +353,34 @@ impl StaticMesh
         );
         //copy_comand_list.add_resource_barrier(&vertex_default_buffer, ResourceStates::CopyDest, ResourceStates::Common);
 
-        self.vertex_buffer_view = VertexBufferView::default();
-        self.vertex_buffer_view.0.BufferLocation = vertex_default_buffer.get_gpu_virtual_address().0;
-        self.vertex_buffer_view.0.SizeInBytes = vertex_buffer_size.0 as u32;
-        self.vertex_buffer_view.0.StrideInBytes = vertex_buffer_size.0 as u32 / vertex_size as u32;
-        self.vertex_buffer_resource = vertex_default_buffer;
-
-        let index_buffer_data_32 = self.mesh.mesh_index_data.clone();
-        let index_buffer_data_16 = self.mesh.get_index_buffer_data_u16();
-        let mut index_buffer_size = ByteCount::from(index_buffer_data_32.len() * std::mem::size_of::<u32>());
-        let mut index_buffer_stride = 4;
-        if (vertex_size as u32) < (u16::max_value() as u32)
+        self.vertex_buffer_views.clear();
+        let mut stream_offset = 0;
+        for (size, stride) in gpu_data.stream_sizes.iter().zip(&gpu_data.strides)
         {
-            index_buffer_size = ByteCount::from(
-                index_buffer_data_16.len() * std::mem::size_of::<u16>(),
-            );
-
-            index_buffer_stride = 2;
+            let mut vertex_buffer_view = VertexBufferView::default();
+            vertex_buffer_view.0.BufferLocation = vertex_default_buffer.get_gpu_virtual_address().0 + stream_offset;
+            vertex_buffer_view.0.SizeInBytes = *size;
+            vertex_buffer_view.0.StrideInBytes = *stride;
+            self.vertex_buffer_views.push(vertex_buffer_view);
+            stream_offset += *size as u64;
         }
-        
-        let index_staging_buffer = g_device.create_staging_buffer(index_buffer_size).expect("Cannot create staging buffer");
+        self.vertex_buffer_resource = vertex_default_buffer;
+
+        let index_buffer_size = ByteCount::from(gpu_data.index_data.len());
+        let index_staging_buffer = g_device.create_staging_buffer(index_buffer_size)?;
         let data = index_staging_buffer
-            .map(0, None)
-            .expect("Cannot map staging buffer");
-        if (vertex_size as u32) < (u16::max_value() as u32)
-        {
-            unsafe {
-                std::ptr::copy_nonoverlapping(
-                    index_buffer_data_16.as_ptr() as *const u8,
-                    data,
-                    vertex_buffer_size.0 as usize,
-                );
-            }
-        }
-        else
-        {
-            unsafe {
-                std::ptr::copy_nonoverlapping(
-                    index_buffer_data_32.as_ptr() as *const u8,
-                    data,
-                    vertex_buffer_size.0 as usize,
-                );
-            }    
+            .map(0, None)?;
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                gpu_data.index_data.as_ptr() as *const u8,
+                data,
+                index_buffer_size.0 as usize,
+            );
         }
         index_staging_buffer.unmap(0, None);
 
     
-        let index_default_buffer = g_device.create_default_buffer(index_buffer_size).expect("Cannot create default buffer");
+        let index_default_buffer = g_device.create_default_buffer(index_buffer_size)?;
         copy_comand_list.add_resource_barrier(&index_default_buffer, ResourceStates::Common, ResourceStates::CopyDest);
 
         copy_comand_list.copy_buffer_region(
@@ -148,31 +395,108 @@ impl StaticMesh
         self.index_buffer_view = IndexBufferView::default();
         self.index_buffer_view.0.BufferLocation = index_default_buffer.get_gpu_virtual_address().0;
         self.index_buffer_view.0.SizeInBytes = index_buffer_size.0 as u32;
-        self.index_buffer_view.0.Format = Format::R32Uint as i32;
-        if (vertex_size as u32) < (u16::max_value() as u32)
-        {
-            self.index_buffer_view.0.Format = Format::R16Uint as i32;
-        }
+        self.index_buffer_view.0.Format = if gpu_data.index_stride == 2 { Format::R16Uint as i32 } else { Format::R32Uint as i32 };
         self.index_buffer_resource = index_default_buffer;
 
 
-        copy_comand_list.close().expect("Cannot close command list");
+        copy_comand_list.close()?;
         let command_queue = G_COPY_COMMAND_QUEUE.lock().unwrap();
         command_queue
             .execute_command_lists(std::slice::from_ref(&copy_comand_list));
-
+        Ok(())
     }
 
-    pub fn get_gpu_resource(&self) -> (&Resource, &Resource, &VertexBufferView, &IndexBufferView)
+    // Bind all vertex buffer views from slot 0, a depth only pass of a SeparatePosition mesh binds just the first.
+    pub fn get_gpu_resource(&self) -> (&Resource, &Resource, &[VertexBufferView], &IndexBufferView)
     {
-        (&self.vertex_buffer_resource, &self.index_buffer_resource, &self.vertex_buffer_view, &self.index_buffer_view)
+        (&self.vertex_buffer_resource, &self.index_buffer_resource, &self.vertex_buffer_views, &self.index_buffer_view)
     }
 }
 
 impl SceneProxy for StaticMesh
 {
-    fn generate_mesh_batch<'a>(&'a self) -> MeshBatch<'a>
+    fn generate_mesh_batches<'a>(&'a self) -> Vec<MeshBatch<'a>>
+    {
+        self.get_lod_draw_sections(0).into_iter()
+            .map(|section| MeshBatch
+            {
+                mesh: &self.mesh,
+                section,
+                material: section.material_slot.and_then(|slot| self.materials.get(slot)),
+                mesh_index_in_gpu_scene: 0, // fix
+                world_transform: Matrix4::identity()
+            })
+            .collect()
+    }
+
+    // Lower levels of detail only ever lose vertices, the bounds of the base mesh cover all of them. Morph targets
+    // grow them by the largest distance they can move a vertex.
+    fn get_bounds(&self) -> MeshBounds
+    {
+        let bounds = *self.bounds.get_or_init(|| match self.mesh.get_bounds()
+        {
+            Some(bounds) => *bounds,
+            None => self.mesh.compute_bounds(false)
+        });
+        let extent = self.get_morph_extent();
+        if extent == 0.0
+        {
+            return bounds;
+        }
+        let offset = Vector3::new(extent, extent, extent);
+        MeshBounds
+        {
+            bounding_box: BoundingBox { min: bounds.bounding_box.min - offset, max: bounds.bounding_box.max + offset },
+            bounding_sphere: BoundingSphere { center: bounds.bounding_sphere.center, radius: bounds.bounding_sphere.radius + extent },
+            oriented_bounding_box: None
+        }
+    }
+
+    // Tests the base mesh, through a cached hierarchy unless morph weights move its vertices.
+    fn intersect_ray(&self, ray: &Ray, max_distance: f32) -> Option<MeshRayHit>
+    {
+        if self.get_morph_extent() != 0.0
+        {
+            return self.get_morphed_mesh().intersect_ray(ray, max_distance);
+        }
+        let bvh = self.triangle_bvh.get_or_init(|| self.mesh.build_triangle_bvh());
+        self.mesh.intersect_ray_with_bvh(bvh, ray, max_distance)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn gpu_data_of_triangle_soup_matches_sections()
     {
-        MeshBatch{mesh: &self.mesh, mesh_index_in_gpu_scene: 0} // fix
+        let mut static_mesh = StaticMesh::new("soup");
+        static_mesh.add_channel_data(MeshDataChannel::Position, vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 1.0, 0.0, 1.0, 1.0]);
+        let sections = static_mesh.get_sections();
+        let gpu_data = static_mesh.get_gpu_data().unwrap();
+
+        assert_eq!(gpu_data.lod_ranges[0].index_count, 6);
+        assert_eq!(gpu_data.index_data.len() as u32, gpu_data.lod_ranges[0].index_count * gpu_data.index_stride);
+        assert!(sections.iter().all(|section| section.first_index + section.index_count <= gpu_data.lod_ranges[0].index_count));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn bounds_follow_changes_of_the_mesh()
+    {
+        let mut static_mesh = StaticMesh::new("box");
+        *static_mesh.get_mesh_mut() = Mesh::create_box(Vector3::new(2.0, 2.0, 2.0), 1);
+        assert_eq!(static_mesh.get_bounds().bounding_box.max, Vector3::new(1.0, 1.0, 1.0));
+
+        for position in static_mesh.get_mesh_mut().mesh_channel_data.get_mut(&(MeshDataChannel::Position as usize)).unwrap()
+        {
+            *position *= 2.0;
+        }
+        assert_eq!(static_mesh.get_mesh().get_bounds(), None);
+        assert_eq!(static_mesh.get_bounds().bounding_box.max, Vector3::new(2.0, 2.0, 2.0));
+
+        static_mesh.add_channel_data(MeshDataChannel::Position, vec![0.0, 5.0, 0.0]);
+        assert_eq!(static_mesh.get_bounds().bounding_box.max, Vector3::new(2.0, 5.0, 2.0));
+    }
+}
@@ -0,0 +1,111 @@
+use cgmath::{InnerSpace, Matrix3, Matrix4, One, Quaternion, SquareMatrix, Vector3};
+
+/// Translation, rotation and scale, applied to points in the order scale, rotation, translation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform
+{
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>
+}
+
+impl Default for Transform
+{
+    fn default() -> Self
+    {
+        Transform { translation: Vector3::new(0.0, 0.0, 0.0), rotation: Quaternion::one(), scale: Vector3::new(1.0, 1.0, 1.0) }
+    }
+}
+
+impl Transform
+{
+    pub fn new(translation: Vector3<f32>, rotation: Quaternion<f32>, scale: Vector3<f32>) -> Self
+    {
+        Transform { translation, rotation, scale }
+    }
+
+    pub fn from_translation(translation: Vector3<f32>) -> Self
+    {
+        Transform { translation, ..Default::default() }
+    }
+
+    /// Splits an affine matrix into translation, rotation and scale. Shear cannot be represented and is lost, a
+    /// mirroring matrix gets a negative x scale.
+    pub fn from_matrix(matrix: &Matrix4<f32>) -> Self
+    {
+        let columns = [matrix.x.truncate(), matrix.y.truncate(), matrix.z.truncate()];
+        let mut scale = Vector3::new(columns[0].magnitude(), columns[1].magnitude(), columns[2].magnitude());
+        if Matrix3::from_cols(columns[0], columns[1], columns[2]).determinant() < 0.0
+        {
+            scale.x = -scale.x;
+        }
+        let rotation = if scale.x != 0.0 && scale.y != 0.0 && scale.z != 0.0
+        {
+            Quaternion::from(Matrix3::from_cols(columns[0] / scale.x, columns[1] / scale.y, columns[2] / scale.z)).normalize()
+        }
+        else
+        {
+            Quaternion::one()
+        };
+        Transform { translation: matrix.w.truncate(), rotation, scale }
+    }
+
+    pub fn to_matrix(&self) -> Matrix4<f32>
+    {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use cgmath::{Deg, Rotation3};
+
+    use super::*;
+
+    fn assert_matrix_near(a: Matrix4<f32>, b: Matrix4<f32>)
+    {
+        let difference: f32 = (0..4).map(|column| (a[column] - b[column]).magnitude()).sum();
+        assert!(difference < 1e-5, "{:?} differs from {:?}", a, b);
+    }
+
+    #[test]
+    fn matrices_split_into_translation_rotation_and_scale()
+    {
+        let transform = Transform::new(Vector3::new(1.0, -2.0, 3.0), Quaternion::from_axis_angle(Vector3::new(1.0, 1.0, 0.0).normalize(), Deg(40.0)), Vector3::new(2.0, 3.0, 0.5));
+        let split = Transform::from_matrix(&transform.to_matrix());
+        assert!((split.translation - transform.translation).magnitude() < 1e-6);
+        assert!((split.scale - transform.scale).magnitude() < 1e-5);
+        // q and -q are the same rotation.
+        assert!((split.rotation.dot(transform.rotation).abs() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn mirroring_becomes_a_negative_x_scale()
+    {
+        let rotation = Matrix4::from_angle_z(Deg(30.0));
+        for mirror in [Vector3::new(-1.0, 1.0, 1.0), Vector3::new(1.0, -2.0, 1.0), Vector3::new(1.0, 1.0, -3.0)]
+        {
+            let matrix = Matrix4::from_translation(Vector3::new(4.0, 5.0, 6.0)) * rotation * Matrix4::from_nonuniform_scale(mirror.x, mirror.y, mirror.z);
+            let transform = Transform::from_matrix(&matrix);
+            assert!(transform.scale.x < 0.0 && transform.scale.y > 0.0 && transform.scale.z > 0.0);
+            assert!((transform.rotation.magnitude() - 1.0).abs() < 1e-5);
+            assert_matrix_near(transform.to_matrix(), matrix);
+        }
+    }
+
+    #[test]
+    fn zero_scale_keeps_translation_and_scale()
+    {
+        let matrix = Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0)) * Matrix4::from_angle_x(Deg(45.0)) * Matrix4::from_nonuniform_scale(2.0, 0.0, 2.0);
+        let transform = Transform::from_matrix(&matrix);
+        assert_eq!(transform.translation, Vector3::new(1.0, 2.0, 3.0));
+        assert!((transform.scale - Vector3::new(2.0, 0.0, 2.0)).magnitude() < 1e-6);
+        // The rotation cannot be recovered from a flattened matrix.
+        assert_eq!(transform.rotation, Quaternion::one());
+        assert_eq!(Transform::from_matrix(&Matrix4::from_scale(0.0)).scale, Vector3::new(0.0, 0.0, 0.0));
+    }
+}
+